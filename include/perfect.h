@@ -0,0 +1,41 @@
+/* The C interface to perfect, from src/ffi.rs. Build the crate with the
+ * `ffi` feature to get these symbols. */
+
+#ifndef PERFECT_H
+#define PERFECT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A table from byte strings to uint64_t values. */
+typedef struct perfect_table perfect_table;
+
+/* Maps each of the `count` keys, the `lens[i]` bytes at `keys[i]`, to
+ * `values[i]`. The keys are copied, and the build is seeded from the OS's
+ * randomness. Returns NULL if two keys are equal or there's no randomness. */
+perfect_table *perfect_build(const uint8_t *const *keys, const size_t *lens,
+                             const uint64_t *values, size_t count);
+
+/* Like perfect_build, but seeded with `seed`, so the same keys always build
+ * the same table, without the OS's randomness. */
+perfect_table *perfect_build_seeded(const uint8_t *const *keys, const size_t *lens,
+                                    const uint64_t *values, size_t count,
+                                    uint64_t seed);
+
+/* Stores the value for the `len` bytes at `key` in `*value` and returns 1,
+ * or returns 0 if they aren't one of the table's keys. */
+int perfect_lookup(const perfect_table *table, const uint8_t *key, size_t len,
+                   uint64_t *value);
+
+/* Frees a table from perfect_build. NULL is ignored. */
+void perfect_free(perfect_table *table);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
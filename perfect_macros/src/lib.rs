@@ -0,0 +1,190 @@
+//! Perfect hash tables built while compiling.
+//!
+//! `perfect_map!` and `perfect_set!` run construction at expansion time and
+//! expand to a `perfect::StaticMap` or `perfect::StaticSet` literal, so
+//! there's no setup at runtime at all:
+//!
+//! ```ignore
+//! #![feature(phase)]
+//! #[phase(plugin)] extern crate perfect_macros;
+//! extern crate perfect;
+//!
+//! static KEYWORDS: perfect::StaticMap<&'static str, Token> = perfect_map! {
+//!   "if"   => If,
+//!   "else" => Else,
+//! };
+//!
+//! static RESERVED: perfect::StaticSet<&'static str> = perfect_set! {
+//!   "abstract", "final", "override",
+//! };
+//! ```
+//!
+//! Keys must be string literals. Values can be any constant expression.
+#![crate_name = "perfect_macros"]
+#![crate_type = "dylib"]
+#![feature(plugin_registrar, quote)]
+#![deny(warnings, missing_doc)]
+
+extern crate perfect;
+extern crate rustc;
+extern crate syntax;
+
+use perfect::codegen::Tables;
+use rustc::plugin::Registry;
+use syntax::ast;
+use syntax::codemap::Span;
+use syntax::ext::base::{DummyResult, ExtCtxt, MacExpr, MacResult};
+use syntax::ext::build::AstBuilder;
+use syntax::parse::token;
+use syntax::ptr::P;
+
+/// Registers `perfect_map!` and `perfect_set!` with the compiler.
+#[plugin_registrar]
+pub fn plugin_registrar(reg: &mut Registry) {
+  reg.register_macro("perfect_map", expand_perfect_map);
+  reg.register_macro("perfect_set", expand_perfect_set);
+}
+
+struct Entry {
+  key:   String,
+  span:  Span,
+  value: Option<P<ast::Expr>>,
+}
+
+/// Parses `key => value, ...`, or `key, ...` if `with_values` is false.
+fn parse_entries(cx: &mut ExtCtxt, tts: &[ast::TokenTree], with_values: bool)
+    -> Option<Vec<Entry>> {
+  let mut parser  = cx.new_parser_from_tts(tts);
+  let mut entries = Vec::new();
+
+  while parser.token != token::EOF {
+    let key = parser.parse_expr();
+
+    let name =
+      match key.node {
+        ast::ExprLit(ref lit) =>
+          match lit.node {
+            ast::LitStr(ref s, _) => s.get().to_string(),
+            _                     => {
+              cx.span_err(key.span, "expected a string literal");
+              return None;
+            }
+          },
+        _ => {
+          cx.span_err(key.span, "expected a string literal");
+          return None;
+        }
+      };
+
+    let value =
+      if with_values {
+        parser.expect(&token::FAT_ARROW);
+        Some(parser.parse_expr())
+      } else {
+        None
+      };
+
+    entries.push(Entry { key: name, span: key.span, value: value });
+
+    if !parser.eat(&token::COMMA) {
+      break;
+    }
+  }
+
+  if parser.token != token::EOF {
+    cx.span_err(parser.span, "expected `,`");
+    return None;
+  }
+
+  Some(entries)
+}
+
+fn build(cx: &mut ExtCtxt, sp: Span, entries: &[Entry]) -> Option<Tables<String>> {
+  let keys = entries.iter().map(|e| e.key.clone()).collect();
+
+  match perfect::codegen::build_tables(keys) {
+    Ok(tables) => Some(tables),
+    Err(perfect::DuplicateKey(_, second)) => {
+      cx.span_err(entries[second].span, "duplicate key");
+      None
+    }
+    Err(e) => {
+      cx.span_err(sp, format!("couldn't build a perfect table: {}", e).as_slice());
+      None
+    }
+  }
+}
+
+fn uint_slice(cx: &ExtCtxt, sp: Span, xs: &[uint]) -> P<ast::Expr> {
+  cx.expr_vec_slice(sp, xs.iter().map(|&x| cx.expr_uint(sp, x)).collect())
+}
+
+fn u32_lit(cx: &ExtCtxt, sp: Span, x: u32) -> P<ast::Expr> {
+  cx.expr_lit(sp, ast::LitInt(x as u64, ast::UnsignedIntLit(ast::TyU32)))
+}
+
+fn weights_slice(cx: &ExtCtxt, sp: Span, xs: &[(u32, u32)]) -> P<ast::Expr> {
+  cx.expr_vec_slice(sp, xs.iter().map(|&(w1, w2)| {
+      cx.expr_tuple(sp, vec![u32_lit(cx, sp, w1), u32_lit(cx, sp, w2)])
+    }).collect())
+}
+
+fn static_map(cx: &ExtCtxt, sp: Span, tables: Tables<String>, values: Vec<P<ast::Expr>>)
+    -> P<ast::Expr> {
+  let nodes   = uint_slice(cx, sp, tables.nodes.as_slice());
+  let weights = weights_slice(cx, sp, tables.weights.as_slice());
+
+  let keys =
+    cx.expr_vec_slice(sp, tables.keys.iter().map(|k| {
+        cx.expr_str(sp, token::intern_and_get_ident(k.as_slice()))
+      }).collect());
+
+  // Slot i holds the ith key, so the values are already in slot order.
+  let values = cx.expr_vec_slice(sp, values);
+
+  quote_expr!(cx, ::perfect::StaticMap {
+    nodes:   $nodes,
+    weights: $weights,
+    keys:    $keys,
+    values:  $values,
+  })
+}
+
+fn expand_perfect_map<'cx>(cx: &'cx mut ExtCtxt, sp: Span, tts: &[ast::TokenTree])
+    -> Box<MacResult + 'cx> {
+  let entries =
+    match parse_entries(cx, tts, true) {
+      Some(entries) => entries,
+      None          => return DummyResult::expr(sp),
+    };
+
+  let tables =
+    match build(cx, sp, entries.as_slice()) {
+      Some(tables) => tables,
+      None         => return DummyResult::expr(sp),
+    };
+
+  let values = entries.into_iter().map(|e| e.value.unwrap()).collect();
+
+  MacExpr::new(static_map(cx, sp, tables, values))
+}
+
+fn expand_perfect_set<'cx>(cx: &'cx mut ExtCtxt, sp: Span, tts: &[ast::TokenTree])
+    -> Box<MacResult + 'cx> {
+  let entries =
+    match parse_entries(cx, tts, false) {
+      Some(entries) => entries,
+      None          => return DummyResult::expr(sp),
+    };
+
+  let tables =
+    match build(cx, sp, entries.as_slice()) {
+      Some(tables) => tables,
+      None         => return DummyResult::expr(sp),
+    };
+
+  let values = entries.iter().map(|_| quote_expr!(cx, ())).collect();
+  let map    = static_map(cx, sp, tables, values);
+
+  MacExpr::new(quote_expr!(cx, ::perfect::StaticSet { map: $map }))
+}
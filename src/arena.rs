@@ -0,0 +1,279 @@
+//! Tables whose keys and values live in memory the caller manages.
+//!
+//! A `Region` hands out memory that stays put for as long as it's borrowed.
+//! `BumpArena` is one over any buffer, say a `HugeBuffer` or a block that's
+//! freed all at once. `HashMapBuilder::build_in` builds an `ArenaHashMap`
+//! whose key and value arrays are moved into a region, and
+//! `Region::alloc_str` copies key strings in too, so a map of `&str` keys
+//! keeps nothing on the global heap but the hash function's own tables.
+//!
+//! Construction still sorts the pairs out on the heap before moving them
+//! in. Dropping the map runs the keys' and values' destructors, and the
+//! region gets its memory back whenever its owner frees it.
+
+use std::cell::Cell;
+use std::hash;
+use std::kinds::marker;
+use std::mem;
+use std::ptr;
+use std::raw;
+use std::slice;
+
+use super::{PerfectHashFn, KeyHasher, Positional};
+
+/// Memory that lasts as long as the region is borrowed.
+pub trait Region {
+  /// `size` bytes aligned to `align`, which nothing else is handed, or
+  /// `None` if the region is out of room.
+  fn alloc<'a>(&'a self, size: uint, align: uint) -> Option<&'a mut [u8]>;
+
+  /// A copy of `s` in the region, or `None` if it doesn't fit.
+  fn alloc_str<'a>(&'a self, s: &str) -> Option<&'a str> {
+    match self.alloc(s.len(), 1) {
+      Some(bytes) if bytes.len() >= s.len() => {
+        let bytes = bytes.slice_to_mut(s.len());
+        slice::bytes::copy_memory(bytes, s.as_bytes());
+        Some(unsafe { mem::transmute::<&'a [u8], &'a str>(bytes) })
+      }
+      _ => None,
+    }
+  }
+}
+
+/// Hands out a buffer from front to back, and only takes it all back at
+/// once, with `reset`.
+pub struct BumpArena<'b> {
+  start:  *mut u8,
+  len:    uint,
+  used:   Cell<uint>,
+  marker: marker::ContravariantLifetime<'b>,
+}
+
+impl<'b> BumpArena<'b> {
+  /// An arena handing out `buf`, which it borrows for as long as it lives.
+  pub fn new(buf: &'b mut [u8]) -> BumpArena<'b> {
+    BumpArena {
+      start:  buf.as_mut_ptr(),
+      len:    buf.len(),
+      used:   Cell::new(0),
+      marker: marker::ContravariantLifetime,
+    }
+  }
+
+  /// The bytes handed out so far, counting padding for alignment.
+  pub fn used(&self) -> uint {
+    self.used.get()
+  }
+
+  /// Takes back everything handed out. Nothing can still be using it, since
+  /// that would borrow the arena.
+  pub fn reset(&mut self) {
+    self.used.set(0);
+  }
+}
+
+impl<'b> Region for BumpArena<'b> {
+  fn alloc<'a>(&'a self, size: uint, align: uint) -> Option<&'a mut [u8]> {
+    let used  = self.used.get();
+    let addr  = self.start as uint + used;
+    let first = used + (align - addr % align) % align;
+    match first.checked_add(&size) {
+      Some(end) if end <= self.len => {
+        self.used.set(end);
+        Some(unsafe {
+          mem::transmute(raw::Slice { data: self.start.offset(first as int) as *const u8,
+                                      len:  size })
+        })
+      }
+      _ => None,
+    }
+  }
+}
+
+/// Moves `items` into `region`, in order, or drops them and returns `None`
+/// if they don't fit.
+fn move_into<'a, R: Region, T>(region: &'a R, items: Vec<T>) -> Option<&'a mut [T]> {
+  let align = mem::min_align_of::<T>();
+  let size  =
+    match items.len().checked_mul(&mem::size_of::<T>()) {
+      Some(size) => size,
+      None       => return None,
+    };
+
+  let bytes =
+    match region.alloc(size, align) {
+      Some(bytes) if bytes.len() >= size && bytes.as_ptr() as uint % align == 0 => bytes,
+      _                                                                        => return None,
+    };
+
+  let len = items.len();
+  unsafe {
+    let data = bytes.as_mut_ptr() as *mut T;
+    for (i, item) in items.into_iter().enumerate() {
+      ptr::write(data.offset(i as int), item);
+    }
+    Some(mem::transmute(raw::Slice { data: data as *const T, len: len }))
+  }
+}
+
+/// A hashtable from a fixed set of keys to values, with both arrays in a
+/// `Region`. Lookups check the key, so unknown ones get `None`; nothing can
+/// be inserted or removed.
+pub struct ArenaHashMap<'a, K: 'a, V: 'a, H = Positional> {
+  func:   PerfectHashFn<K, H>,
+  /// The key in each slot.
+  keys:   &'a mut [K],
+  values: &'a mut [V],
+}
+
+impl<'a, K: Eq + hash::Hash, V, H: KeyHasher<K>> ArenaHashMap<'a, K, V, H> {
+  /// Puts each pair in the slot `func` gives its key, in arrays moved into
+  /// `region`. Returns `None` if they don't fit.
+  ///
+  /// Fails unless `func` was built from exactly the keys of `pairs`.
+  pub fn from_fn_in<R: Region>(region: &'a R, func: PerfectHashFn<K, H>, pairs: Vec<(K, V)>)
+      -> Option<ArenaHashMap<'a, K, V, H>> {
+    let m = func.len();
+    if pairs.len() != m {
+      fail!("perfect::ArenaHashMap::from_fn_in: {} pairs for {} keys", pairs.len(), m);
+    }
+
+    let mut slots: Vec<Option<(K, V)>> = Vec::from_fn(m, |_| None);
+    for (k, v) in pairs.into_iter() {
+      let slot = slots.get_mut(func.hash(&k));
+      if slot.is_some() {
+        fail!("perfect::ArenaHashMap::from_fn_in: two keys share a slot");
+      }
+      *slot = Some((k, v));
+    }
+    let mut keys   = Vec::with_capacity(m);
+    let mut values = Vec::with_capacity(m);
+    for slot in slots.into_iter() {
+      let (k, v) = slot.unwrap();
+      keys.push(k);
+      values.push(v);
+    }
+
+    // If the values don't fit, the keys are dropped where they are.
+    let keys =
+      match move_into(region, keys) {
+        Some(keys) => keys,
+        None       => return None,
+      };
+    match move_into(region, values) {
+      Some(values) => Some(ArenaHashMap { func: func, keys: keys, values: values }),
+      None         => {
+        unsafe { drop_all(keys) };
+        None
+      }
+    }
+  }
+
+  /// The slot of `k`, if it's a known key.
+  fn find(&self, k: &K) -> Option<uint> {
+    match self.func.try_hash(k) {
+      Some(i) if self.keys[i] == *k => Some(i),
+      _                             => None,
+    }
+  }
+
+  /// A reference to the value for `k`.
+  pub fn get(&self, k: &K) -> Option<&V> {
+    self.find(k).map(|i| &self.values[i])
+  }
+
+  /// A mutable reference to the value for `k`.
+  pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+    match self.find(k) {
+      Some(i) => Some(&mut self.values[i]),
+      None    => None,
+    }
+  }
+
+  /// True if `k` is one of the keys.
+  pub fn contains_key(&self, k: &K) -> bool {
+    self.find(k).is_some()
+  }
+}
+
+impl<'a, K, V, H> ArenaHashMap<'a, K, V, H> {
+  /// Returns the number of elements in the map.
+  pub fn len(&self) -> uint {
+    self.keys.len()
+  }
+
+  /// Returns true if the map contains no elements.
+  pub fn is_empty(&self) -> bool {
+    self.keys.is_empty()
+  }
+
+  /// The keys, in slot order.
+  pub fn keys(&self) -> &[K] {
+    &*self.keys
+  }
+
+  /// The values, in slot order.
+  pub fn values(&self) -> &[V] {
+    &*self.values
+  }
+
+  /// The values, in slot order, by mutable reference.
+  pub fn values_mut(&mut self) -> &mut [V] {
+    &mut *self.values
+  }
+}
+
+/// Drops every element of `items`, which nothing may use afterward.
+unsafe fn drop_all<T>(items: &mut [T]) {
+  for item in items.iter() {
+    drop(ptr::read(item));
+  }
+}
+
+#[unsafe_destructor]
+impl<'a, K, V, H> Drop for ArenaHashMap<'a, K, V, H> {
+  /// The region only holds the arrays, so their elements are dropped here.
+  fn drop(&mut self) {
+    unsafe {
+      drop_all(&mut *self.keys);
+      drop_all(&mut *self.values);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{BumpArena, Region};
+  use super::super::{HashMapBuilder, RegionFull};
+  use super::super::testing::keys;
+
+  #[test]
+  fn keeps_keys_and_values_in_the_region() {
+    let keys = keys(100);
+    let mut buf = Vec::from_elem(1 << 16, 0u8);
+    let arena = BumpArena::new(buf.as_mut_slice());
+
+    let pairs: Vec<(&str, String)> =
+      keys.iter().map(|k| (arena.alloc_str(k.as_slice()).unwrap(), k.clone())).collect();
+    let map = HashMapBuilder::new().seed(1).build_in(&arena, pairs).unwrap();
+
+    let (start, end) = (arena.start as uint, arena.start as uint + arena.used());
+    for k in keys.iter() {
+      let key = map.keys()[map.find(&k.as_slice()).unwrap()];
+      assert!(start <= key.as_ptr() as uint && key.as_ptr() as uint + key.len() <= end);
+      assert!(map.get(&k.as_slice()) == Some(k));
+    }
+    let values = map.values().as_ptr() as uint;
+    assert!(start <= values && values < end);
+    assert!(map.get(&"not a key").is_none());
+  }
+
+  #[test]
+  fn fails_to_build_when_the_region_is_full() {
+    let keys = keys(100);
+    let mut buf = Vec::from_elem(64, 0u8);
+    let arena = BumpArena::new(buf.as_mut_slice());
+    let pairs: Vec<(String, uint)> = keys.into_iter().zip(range(0u, 100)).collect();
+    assert!(HashMapBuilder::new().seed(1).build_in(&arena, pairs).err() == Some(RegionFull));
+  }
+}
@@ -0,0 +1,861 @@
+//! Where a `HashMap` keeps keys it wasn't built for.
+//!
+//! By default that's a `SeededBackup`, but what to do with unknown keys
+//! depends a lot on the application, so any `BackupStore` can take its place
+//! with `HashMap::with_backup`:
+//!
+//! * `SeededBackup` keeps everything, in a std `HashMap` keyed from the seed
+//!   the map was built with.
+//! * `collections::HashMap` keeps everything too, keyed from the task's rng.
+//! * `LruBackup` keeps only the most recently used few.
+//! * `RandomBackup` keeps a fixed number, evicting at random, in constant
+//!   time.
+//! * `SortedBackup` keeps everything in a sorted `Vec`, which is smaller
+//!   but slower than a hashtable.
+//! * `RejectUnknown` keeps nothing, and refuses every insertion.
+
+use std::cell::Cell;
+use std::collections;
+use std::fmt;
+use std::hash;
+use std::hash::sip;
+use std::mem;
+use std::rand;
+
+use super::mix;
+
+/// Storage for the entries whose keys aren't known keys.
+pub trait BackupStore<K, V> {
+  /// The number of entries in the store.
+  fn len(&self) -> uint;
+
+  /// A reference to the value for `k`.
+  fn get<'a>(&'a self, k: &K) -> Option<&'a V>;
+
+  /// A mutable reference to the value for `k`.
+  fn get_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V>;
+
+  /// Like `get`, but for any type that hashes the same way as `K`.
+  fn get_equiv<'a, Q: hash::Hash + Equiv<K>>(&'a self, k: &Q) -> Option<&'a V>;
+
+  /// Like `get`, but with the stored key too.
+  fn get_key_value<'a>(&'a self, k: &K) -> Option<(&'a K, &'a V)>;
+
+  /// Like `get_equiv`, but with the stored key too. By default, keys that
+  /// `get_equiv` finds are then looked for by scanning every entry, so
+  /// stores that can do better should.
+  fn get_key_value_equiv<'a, Q: hash::Hash + Equiv<K>>(&'a self, k: &Q)
+      -> Option<(&'a K, &'a V)> {
+    match self.get_equiv(k) {
+      Some(_) => self.iter().find(|&(key, _)| k.equiv(key)),
+      None    => None,
+    }
+  }
+
+  /// Inserts a pair, returning the old value for the key. If the store won't
+  /// take the pair, it hands it back instead.
+  fn insert(&mut self, k: K, v: V) -> Result<Option<V>, (K, V)>;
+
+  /// True if `insert` would take a pair for `k`, which isn't in the store.
+  /// By default every key is taken.
+  fn accepts(&self, _k: &K) -> bool {
+    true
+  }
+
+  /// Removes `k`, returning its value.
+  fn remove(&mut self, k: &K) -> Option<V>;
+
+  /// The value for `k`, inserting the result of `f` first if there isn't
+  /// one. Returns `None` if the store won't take the key.
+  fn get_or_insert_with<'a>(&'a mut self, k: K, f: || -> V) -> Option<&'a mut V>;
+
+  /// Visits every entry, in any order.
+  fn iter<'a>(&'a self) -> Box<Iterator<(&'a K, &'a V)> + 'a>;
+
+  /// Visits every entry, in any order, with mutable references to the values.
+  fn iter_mut<'a>(&'a mut self) -> Box<Iterator<(&'a K, &'a mut V)> + 'a>;
+
+  /// Takes every entry out of the store, leaving it empty.
+  fn drain(&mut self) -> Vec<(K, V)>;
+
+  /// Removes every entry for which `f` returns false, keeping the others
+  /// just where they are.
+  fn retain(&mut self, f: |&K, &mut V| -> bool);
+
+  /// About how many bytes the store takes up, not counting anything the
+  /// keys or values own.
+  fn heap_size(&self) -> uint {
+    self.len() * mem::size_of::<(K, V)>()
+  }
+}
+
+impl<K: Eq + hash::Hash, V> BackupStore<K, V> for collections::HashMap<K, V> {
+  fn len(&self) -> uint {
+    self.len()
+  }
+
+  fn get<'a>(&'a self, k: &K) -> Option<&'a V> {
+    self.find(k)
+  }
+
+  fn get_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V> {
+    self.find_mut(k)
+  }
+
+  fn get_equiv<'a, Q: hash::Hash + Equiv<K>>(&'a self, k: &Q) -> Option<&'a V> {
+    self.find_equiv(k)
+  }
+
+  /// There's no lookup that returns the stored key, so keys that are found
+  /// are then looked for by scanning every entry.
+  fn get_key_value<'a>(&'a self, k: &K) -> Option<(&'a K, &'a V)> {
+    match self.find(k) {
+      Some(_) => self.iter().find(|&(key, _)| *key == *k),
+      None    => None,
+    }
+  }
+
+  fn insert(&mut self, k: K, v: V) -> Result<Option<V>, (K, V)> {
+    Ok(self.swap(k, v))
+  }
+
+  fn remove(&mut self, k: &K) -> Option<V> {
+    self.pop(k)
+  }
+
+  fn get_or_insert_with<'a>(&'a mut self, k: K, f: || -> V) -> Option<&'a mut V> {
+    Some(self.find_or_insert_with(k, |_| f()))
+  }
+
+  fn iter<'a>(&'a self) -> Box<Iterator<(&'a K, &'a V)> + 'a> {
+    box self.iter()
+  }
+
+  fn iter_mut<'a>(&'a mut self) -> Box<Iterator<(&'a K, &'a mut V)> + 'a> {
+    box self.iter_mut()
+  }
+
+  fn drain(&mut self) -> Vec<(K, V)> {
+    mem::replace(self, collections::HashMap::with_capacity(0)).into_iter().collect()
+  }
+
+  /// A hashtable always has room for the entries it just held.
+  fn retain(&mut self, f: |&K, &mut V| -> bool) {
+    for (k, mut v) in self.drain().into_iter() {
+      if f(&k, &mut v) {
+        self.insert(k, v);
+      }
+    }
+  }
+
+  /// Each bucket holds a key, a value, and a word of hash.
+  fn heap_size(&self) -> uint {
+    self.capacity() * (mem::size_of::<(K, V)>() + mem::size_of::<u64>())
+  }
+}
+
+/// Keeps every entry in a std `HashMap` whose SipHash keys come from a seed
+/// rather than the task's rng, so making one takes no randomness. It's the
+/// default store, keyed from the seed the map was built with: a build with
+/// `HashMapBuilder::seed` or `entropy` never needs the task's rng, and the
+/// keys are as secret as the seed.
+pub struct SeededBackup<K, V> {
+  k0:  u64,
+  k1:  u64,
+  map: collections::HashMap<K, V, sip::SipHasher>,
+}
+
+impl<K: Eq + hash::Hash, V> SeededBackup<K, V> {
+  /// An empty store keyed from `seed`.
+  pub fn new(seed: u64) -> SeededBackup<K, V> {
+    let (k0, k1) = (mix(seed), mix(!seed));
+    SeededBackup {
+      k0:  k0,
+      k1:  k1,
+      map: collections::HashMap::with_hasher(sip::SipHasher::new_with_keys(k0, k1)),
+    }
+  }
+}
+
+impl<K: Clone + Eq + hash::Hash, V: Clone> Clone for SeededBackup<K, V> {
+  fn clone(&self) -> SeededBackup<K, V> {
+    SeededBackup {
+      k0:  self.k0,
+      k1:  self.k1,
+      map: self.map.clone(),
+    }
+  }
+}
+
+impl<K: Eq + hash::Hash, V> BackupStore<K, V> for SeededBackup<K, V> {
+  fn len(&self) -> uint {
+    self.map.len()
+  }
+
+  fn get<'a>(&'a self, k: &K) -> Option<&'a V> {
+    self.map.find(k)
+  }
+
+  fn get_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V> {
+    self.map.find_mut(k)
+  }
+
+  fn get_equiv<'a, Q: hash::Hash + Equiv<K>>(&'a self, k: &Q) -> Option<&'a V> {
+    self.map.find_equiv(k)
+  }
+
+  /// There's no lookup that returns the stored key, so keys that are found
+  /// are then looked for by scanning every entry.
+  fn get_key_value<'a>(&'a self, k: &K) -> Option<(&'a K, &'a V)> {
+    match self.map.find(k) {
+      Some(_) => self.map.iter().find(|&(key, _)| *key == *k),
+      None    => None,
+    }
+  }
+
+  fn insert(&mut self, k: K, v: V) -> Result<Option<V>, (K, V)> {
+    Ok(self.map.swap(k, v))
+  }
+
+  fn remove(&mut self, k: &K) -> Option<V> {
+    self.map.pop(k)
+  }
+
+  fn get_or_insert_with<'a>(&'a mut self, k: K, f: || -> V) -> Option<&'a mut V> {
+    Some(self.map.find_or_insert_with(k, |_| f()))
+  }
+
+  fn iter<'a>(&'a self) -> Box<Iterator<(&'a K, &'a V)> + 'a> {
+    box self.map.iter()
+  }
+
+  fn iter_mut<'a>(&'a mut self) -> Box<Iterator<(&'a K, &'a mut V)> + 'a> {
+    box self.map.iter_mut()
+  }
+
+  /// The emptied table keeps the store's keys.
+  fn drain(&mut self) -> Vec<(K, V)> {
+    let empty = collections::HashMap::with_hasher(sip::SipHasher::new_with_keys(self.k0, self.k1));
+    mem::replace(&mut self.map, empty).into_iter().collect()
+  }
+
+  /// A hashtable always has room for the entries it just held.
+  fn retain(&mut self, f: |&K, &mut V| -> bool) {
+    for (k, mut v) in self.drain().into_iter() {
+      if f(&k, &mut v) {
+        self.map.insert(k, v);
+      }
+    }
+  }
+
+  /// Each bucket holds a key, a value, and a word of hash.
+  fn heap_size(&self) -> uint {
+    self.map.capacity() * (mem::size_of::<(K, V)>() + mem::size_of::<u64>())
+  }
+}
+
+/// Keeps at most `capacity` entries, evicting one that hasn't been used
+/// lately to make room for a new key.
+///
+/// Recency is tracked the CLOCK way, which approximates LRU: each entry has
+/// a place on a ring of keys and a bit that's set when it's used, and a
+/// hand sweeps the ring clearing bits until it finds an entry whose bit was
+/// already clear. Every bit it clears was set by an earlier lookup or
+/// insertion, so eviction is amortized constant time however big the store.
+pub struct LruBackup<K, V> {
+  capacity: uint,
+  /// Each entry's value, its place on the ring, and whether it's been used
+  /// since the hand last passed it.
+  entries:  collections::HashMap<K, (V, uint, Cell<bool>)>,
+  /// The key in each place, or `None` for a place that's free.
+  ring:     Vec<Option<K>>,
+  /// The free places, which only exist while the store isn't full.
+  free:     Vec<uint>,
+  /// The next place the hand looks at.
+  hand:     uint,
+}
+
+impl<K: Clone + Eq + hash::Hash, V> LruBackup<K, V> {
+  /// An empty store that holds up to `capacity` entries.
+  pub fn new(capacity: uint) -> LruBackup<K, V> {
+    LruBackup {
+      capacity: capacity,
+      entries:  collections::HashMap::with_capacity(capacity),
+      ring:     Vec::with_capacity(capacity),
+      free:     Vec::new(),
+      hand:     0,
+    }
+  }
+
+  /// A free place for a new key, evicting an entry if there isn't one.
+  fn make_room(&mut self) -> uint {
+    match self.free.pop() {
+      Some(place) => return place,
+      None        => {}
+    }
+
+    if self.ring.len() < self.capacity {
+      self.ring.push(None);
+      return self.ring.len() - 1;
+    }
+
+    loop {
+      let place = self.hand;
+      self.hand = (self.hand + 1) % self.ring.len();
+
+      let used =
+        match self.ring[place] {
+          Some(ref k) => {
+            let &(_, _, ref used) = self.entries.find(k).unwrap();
+            let was = used.get();
+            used.set(false);
+            was
+          }
+          None => false,
+        };
+
+      if !used {
+        match self.ring.get_mut(place).take() {
+          Some(k) => { self.entries.pop(&k); }
+          None    => {}
+        }
+        return place;
+      }
+    }
+  }
+
+  /// Puts `k` in `place`, returning the key for `entries`.
+  fn occupy(&mut self, place: uint, k: K) -> K {
+    *self.ring.get_mut(place) = Some(k.clone());
+    k
+  }
+}
+
+impl<K: Clone + Eq + hash::Hash, V: Clone> Clone for LruBackup<K, V> {
+  fn clone(&self) -> LruBackup<K, V> {
+    LruBackup {
+      capacity: self.capacity,
+      entries:  self.entries.clone(),
+      ring:     self.ring.clone(),
+      free:     self.free.clone(),
+      hand:     self.hand,
+    }
+  }
+}
+
+impl<K: Clone + Eq + hash::Hash, V> BackupStore<K, V> for LruBackup<K, V> {
+  fn len(&self) -> uint {
+    self.entries.len()
+  }
+
+  fn get<'a>(&'a self, k: &K) -> Option<&'a V> {
+    self.entries.find(k).map(|&(ref v, _, ref used)| {
+      used.set(true);
+      v
+    })
+  }
+
+  fn get_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V> {
+    self.entries.find_mut(k).map(|&(ref mut v, _, ref used)| {
+      used.set(true);
+      v
+    })
+  }
+
+  fn get_equiv<'a, Q: hash::Hash + Equiv<K>>(&'a self, k: &Q) -> Option<&'a V> {
+    self.entries.find_equiv(k).map(|&(ref v, _, ref used)| {
+      used.set(true);
+      v
+    })
+  }
+
+  /// The ring's copy of the key is the one returned.
+  fn get_key_value<'a>(&'a self, k: &K) -> Option<(&'a K, &'a V)> {
+    self.entries.find(k).map(|&(ref v, place, ref used)| {
+      used.set(true);
+      (self.ring[place].as_ref().unwrap(), v)
+    })
+  }
+
+  fn get_key_value_equiv<'a, Q: hash::Hash + Equiv<K>>(&'a self, k: &Q)
+      -> Option<(&'a K, &'a V)> {
+    self.entries.find_equiv(k).map(|&(ref v, place, ref used)| {
+      used.set(true);
+      (self.ring[place].as_ref().unwrap(), v)
+    })
+  }
+
+  fn insert(&mut self, k: K, v: V) -> Result<Option<V>, (K, V)> {
+    if self.capacity == 0 {
+      return Err((k, v));
+    }
+
+    match self.entries.find_mut(&k) {
+      Some(&(ref mut old, _, ref used)) => {
+        used.set(true);
+        return Ok(Some(mem::replace(old, v)));
+      }
+      None => {}
+    }
+
+    let place = self.make_room();
+    let k     = self.occupy(place, k);
+    self.entries.insert(k, (v, place, Cell::new(true)));
+    Ok(None)
+  }
+
+  fn accepts(&self, _k: &K) -> bool {
+    self.capacity > 0
+  }
+
+  fn remove(&mut self, k: &K) -> Option<V> {
+    match self.entries.pop(k) {
+      Some((v, place, _)) => {
+        *self.ring.get_mut(place) = None;
+        self.free.push(place);
+        Some(v)
+      }
+      None => None,
+    }
+  }
+
+  fn get_or_insert_with<'a>(&'a mut self, k: K, f: || -> V) -> Option<&'a mut V> {
+    if self.capacity == 0 {
+      return None;
+    }
+
+    let k =
+      if self.entries.contains_key(&k) {
+        k
+      } else {
+        let place = self.make_room();
+        let k     = self.occupy(place, k);
+        self.entries.insert(k.clone(), (f(), place, Cell::new(true)));
+        k
+      };
+
+    let &(ref mut v, _, ref used) = self.entries.find_mut(&k).unwrap();
+    used.set(true);
+    Some(v)
+  }
+
+  fn iter<'a>(&'a self) -> Box<Iterator<(&'a K, &'a V)> + 'a> {
+    box self.entries.iter().map(|(k, &(ref v, _, _))| (k, v))
+  }
+
+  fn iter_mut<'a>(&'a mut self) -> Box<Iterator<(&'a K, &'a mut V)> + 'a> {
+    box self.entries.iter_mut().map(|(k, &(ref mut v, _, _))| (k, v))
+  }
+
+  fn drain(&mut self) -> Vec<(K, V)> {
+    let entries = mem::replace(&mut self.entries, collections::HashMap::new());
+    self.ring.clear();
+    self.free.clear();
+    self.hand = 0;
+    entries.into_iter().map(|(k, (v, _, _))| (k, v)).collect()
+  }
+
+  /// Entries keep their places on the ring and their recency.
+  fn retain(&mut self, f: |&K, &mut V| -> bool) {
+    let empty   = collections::HashMap::with_capacity(self.capacity);
+    let entries = mem::replace(&mut self.entries, empty);
+    for (k, (mut v, place, used)) in entries.into_iter() {
+      if f(&k, &mut v) {
+        self.entries.insert(k, (v, place, used));
+      } else {
+        *self.ring.get_mut(place) = None;
+        self.free.push(place);
+      }
+    }
+  }
+
+  /// The ring holds a second copy of each key.
+  fn heap_size(&self) -> uint {
+    self.entries.capacity()
+      * (mem::size_of::<(K, (V, uint, Cell<bool>))>() + mem::size_of::<u64>())
+      + self.ring.capacity() * mem::size_of::<Option<K>>()
+      + self.free.capacity() * mem::size_of::<uint>()
+  }
+}
+
+/// Keeps at most `capacity` entries in a fixed table, evicting a random one
+/// to make room for a new key. Everything is constant time, so it suits
+/// backups that untrusted traffic can fill.
+///
+/// Each key can live in one of two buckets, picked by a SipHash with keys
+/// chosen at random when the store is made, so nobody can aim new keys at a
+/// particular entry. A new key takes an empty one of its buckets, or evicts
+/// the entry in either.
+#[deriving(Clone)]
+pub struct RandomBackup<K, V> {
+  k0:      u64,
+  k1:      u64,
+  buckets: Vec<Option<(K, V)>>,
+  /// The number of full buckets.
+  len:     uint,
+}
+
+impl<K: Eq + hash::Hash, V> RandomBackup<K, V> {
+  /// An empty store that holds up to `capacity` entries. The table is
+  /// allocated up front.
+  pub fn new(capacity: uint) -> RandomBackup<K, V> {
+    RandomBackup {
+      k0:      rand::random(),
+      k1:      rand::random(),
+      buckets: Vec::from_fn(capacity, |_| None),
+      len:     0,
+    }
+  }
+
+  /// The two buckets where `k` can live.
+  fn choices<Q: hash::Hash>(&self, k: &Q) -> (uint, uint) {
+    let h = sip::hash_with_keys(self.k0, self.k1, k);
+    let n = self.buckets.len() as u64;
+    ((h as u32 as u64 % n) as uint, ((h >> 32) % n) as uint)
+  }
+
+  /// The bucket holding the key that hashes like `k` and satisfies `eq`.
+  fn find_by<Q: hash::Hash>(&self, k: &Q, eq: |&K| -> bool) -> Option<uint> {
+    if self.buckets.is_empty() {
+      return None;
+    }
+
+    let (b1, b2) = self.choices(k);
+    for &b in [b1, b2].iter() {
+      match self.buckets[b] {
+        Some((ref key, _)) if eq(key) => return Some(b),
+        _                             => {}
+      }
+    }
+    None
+  }
+
+  fn find(&self, k: &K) -> Option<uint> {
+    self.find_by(k, |key| *key == *k)
+  }
+
+  /// Puts `k` and `v`, whose key isn't in the store, in one of its buckets.
+  fn place(&mut self, k: K, v: V) -> uint {
+    let (b1, b2) = self.choices(&k);
+    let b =
+      if self.buckets[b1].is_none() {
+        b1
+      } else if self.buckets[b2].is_none() {
+        b2
+      } else if rand::random() {
+        b1
+      } else {
+        b2
+      };
+
+    if self.buckets[b].is_none() {
+      self.len += 1;
+    }
+    *self.buckets.get_mut(b) = Some((k, v));
+    b
+  }
+}
+
+impl<K: Eq + hash::Hash, V> BackupStore<K, V> for RandomBackup<K, V> {
+  fn len(&self) -> uint {
+    self.len
+  }
+
+  fn get<'a>(&'a self, k: &K) -> Option<&'a V> {
+    self.find(k).map(|b| self.buckets[b].as_ref().unwrap().ref1())
+  }
+
+  fn get_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V> {
+    match self.find(k) {
+      Some(b) => self.buckets.get_mut(b).as_mut().map(|e| e.mut1()),
+      None    => None,
+    }
+  }
+
+  fn get_equiv<'a, Q: hash::Hash + Equiv<K>>(&'a self, k: &Q) -> Option<&'a V> {
+    self.find_by(k, |key| k.equiv(key)).map(|b| self.buckets[b].as_ref().unwrap().ref1())
+  }
+
+  fn get_key_value<'a>(&'a self, k: &K) -> Option<(&'a K, &'a V)> {
+    self.find(k).map(|b| {
+      let &(ref key, ref v) = self.buckets[b].as_ref().unwrap();
+      (key, v)
+    })
+  }
+
+  fn get_key_value_equiv<'a, Q: hash::Hash + Equiv<K>>(&'a self, k: &Q)
+      -> Option<(&'a K, &'a V)> {
+    self.find_by(k, |key| k.equiv(key)).map(|b| {
+      let &(ref key, ref v) = self.buckets[b].as_ref().unwrap();
+      (key, v)
+    })
+  }
+
+  fn insert(&mut self, k: K, v: V) -> Result<Option<V>, (K, V)> {
+    if self.buckets.is_empty() {
+      return Err((k, v));
+    }
+
+    match self.find(&k) {
+      Some(b) => {
+        let entry = self.buckets.get_mut(b).as_mut().unwrap();
+        Ok(Some(mem::replace(entry.mut1(), v)))
+      }
+      None => {
+        self.place(k, v);
+        Ok(None)
+      }
+    }
+  }
+
+  fn accepts(&self, _k: &K) -> bool {
+    !self.buckets.is_empty()
+  }
+
+  fn remove(&mut self, k: &K) -> Option<V> {
+    match self.find(k) {
+      Some(b) => {
+        self.len -= 1;
+        self.buckets.get_mut(b).take().map(|(_, v)| v)
+      }
+      None => None,
+    }
+  }
+
+  fn get_or_insert_with<'a>(&'a mut self, k: K, f: || -> V) -> Option<&'a mut V> {
+    if self.buckets.is_empty() {
+      return None;
+    }
+
+    let b =
+      match self.find(&k) {
+        Some(b) => b,
+        None    => self.place(k, f()),
+      };
+
+    self.buckets.get_mut(b).as_mut().map(|e| e.mut1())
+  }
+
+  fn iter<'a>(&'a self) -> Box<Iterator<(&'a K, &'a V)> + 'a> {
+    box self.buckets.iter().filter_map(|e| e.as_ref().map(|&(ref k, ref v)| (k, v)))
+  }
+
+  fn iter_mut<'a>(&'a mut self) -> Box<Iterator<(&'a K, &'a mut V)> + 'a> {
+    box self.buckets.iter_mut().filter_map(|e| e.as_mut().map(|&(ref k, ref mut v)| (k, v)))
+  }
+
+  fn drain(&mut self) -> Vec<(K, V)> {
+    let n       = self.buckets.len();
+    let buckets = mem::replace(&mut self.buckets, Vec::from_fn(n, |_| None));
+    self.len = 0;
+    buckets.into_iter().filter_map(|e| e).collect()
+  }
+
+  fn retain(&mut self, f: |&K, &mut V| -> bool) {
+    for e in self.buckets.iter_mut() {
+      let keep =
+        match *e {
+          Some((ref k, ref mut v)) => f(k, v),
+          None                     => continue,
+        };
+      if !keep {
+        *e = None;
+        self.len -= 1;
+      }
+    }
+  }
+
+  fn heap_size(&self) -> uint {
+    self.buckets.capacity() * mem::size_of::<Option<(K, V)>>()
+  }
+}
+
+/// Keeps every entry in a `Vec` sorted by key, and finds them by binary
+/// search. It has no hashtable overhead, but inserting is linear.
+#[deriving(Clone)]
+pub struct SortedBackup<K, V> {
+  entries: Vec<(K, V)>,
+}
+
+impl<K: Ord, V> SortedBackup<K, V> {
+  /// An empty store.
+  pub fn new() -> SortedBackup<K, V> {
+    SortedBackup { entries: Vec::new() }
+  }
+
+  /// The position of `k`, or where it would go.
+  fn search(&self, k: &K) -> Result<uint, uint> {
+    let mut lo = 0;
+    let mut hi = self.entries.len();
+
+    while lo < hi {
+      let mid = lo + (hi - lo) / 2;
+      match self.entries[mid].ref0().cmp(k) {
+        Less    => lo = mid + 1,
+        Greater => hi = mid,
+        Equal   => return Ok(mid),
+      }
+    }
+
+    Err(lo)
+  }
+}
+
+impl<K: Ord, V> BackupStore<K, V> for SortedBackup<K, V> {
+  fn len(&self) -> uint {
+    self.entries.len()
+  }
+
+  fn get<'a>(&'a self, k: &K) -> Option<&'a V> {
+    match self.search(k) {
+      Ok(i)  => Some(self.entries[i].ref1()),
+      Err(_) => None,
+    }
+  }
+
+  fn get_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V> {
+    match self.search(k) {
+      Ok(i)  => Some(self.entries.get_mut(i).mut1()),
+      Err(_) => None,
+    }
+  }
+
+  fn get_key_value<'a>(&'a self, k: &K) -> Option<(&'a K, &'a V)> {
+    match self.search(k) {
+      Ok(i)  => {
+        let &(ref key, ref v) = &self.entries[i];
+        Some((key, v))
+      }
+      Err(_) => None,
+    }
+  }
+
+  /// `Equiv` says nothing about order, so this is a linear scan.
+  fn get_equiv<'a, Q: hash::Hash + Equiv<K>>(&'a self, k: &Q) -> Option<&'a V> {
+    self.entries.iter().find(|&&(ref key, _)| k.equiv(key)).map(|&(_, ref v)| v)
+  }
+
+  fn get_key_value_equiv<'a, Q: hash::Hash + Equiv<K>>(&'a self, k: &Q)
+      -> Option<(&'a K, &'a V)> {
+    self.entries.iter().find(|&&(ref key, _)| k.equiv(key)).map(|&(ref key, ref v)| (key, v))
+  }
+
+  fn insert(&mut self, k: K, v: V) -> Result<Option<V>, (K, V)> {
+    match self.search(&k) {
+      Ok(i)  => Ok(Some(mem::replace(self.entries.get_mut(i).mut1(), v))),
+      Err(i) => {
+        self.entries.insert(i, (k, v));
+        Ok(None)
+      }
+    }
+  }
+
+  fn remove(&mut self, k: &K) -> Option<V> {
+    match self.search(k) {
+      Ok(i)  => self.entries.remove(i).map(|(_, v)| v),
+      Err(_) => None,
+    }
+  }
+
+  fn get_or_insert_with<'a>(&'a mut self, k: K, f: || -> V) -> Option<&'a mut V> {
+    let i =
+      match self.search(&k) {
+        Ok(i)  => i,
+        Err(i) => {
+          self.entries.insert(i, (k, f()));
+          i
+        }
+      };
+
+    Some(self.entries.get_mut(i).mut1())
+  }
+
+  fn iter<'a>(&'a self) -> Box<Iterator<(&'a K, &'a V)> + 'a> {
+    box self.entries.iter().map(|&(ref k, ref v)| (k, v))
+  }
+
+  fn iter_mut<'a>(&'a mut self) -> Box<Iterator<(&'a K, &'a mut V)> + 'a> {
+    box self.entries.iter_mut().map(|&(ref k, ref mut v)| (k, v))
+  }
+
+  fn drain(&mut self) -> Vec<(K, V)> {
+    mem::replace(&mut self.entries, Vec::new())
+  }
+
+  fn retain(&mut self, f: |&K, &mut V| -> bool) {
+    for (k, mut v) in mem::replace(&mut self.entries, Vec::new()).into_iter() {
+      if f(&k, &mut v) {
+        self.entries.push((k, v));
+      }
+    }
+  }
+
+  fn heap_size(&self) -> uint {
+    self.entries.capacity() * mem::size_of::<(K, V)>()
+  }
+}
+
+/// Keeps nothing. Every insertion of an unknown key is refused.
+#[deriving(Clone)]
+pub struct RejectUnknown;
+
+/// The pair a `BackupStore` refused, from `HashMap::insert_checked`.
+pub struct UnknownKey<K, V>(pub K, pub V);
+
+impl<K, V> fmt::Show for UnknownKey<K, V> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "the key isn't known and the backup store refused it")
+  }
+}
+
+impl<K, V> BackupStore<K, V> for RejectUnknown {
+  fn len(&self) -> uint {
+    0
+  }
+
+  fn get<'a>(&'a self, _k: &K) -> Option<&'a V> {
+    None
+  }
+
+  fn get_mut<'a>(&'a mut self, _k: &K) -> Option<&'a mut V> {
+    None
+  }
+
+  fn get_equiv<'a, Q: hash::Hash + Equiv<K>>(&'a self, _k: &Q) -> Option<&'a V> {
+    None
+  }
+
+  fn get_key_value<'a>(&'a self, _k: &K) -> Option<(&'a K, &'a V)> {
+    None
+  }
+
+  fn insert(&mut self, k: K, v: V) -> Result<Option<V>, (K, V)> {
+    Err((k, v))
+  }
+
+  fn accepts(&self, _k: &K) -> bool {
+    false
+  }
+
+  fn remove(&mut self, _k: &K) -> Option<V> {
+    None
+  }
+
+  fn get_or_insert_with<'a>(&'a mut self, _k: K, _f: || -> V) -> Option<&'a mut V> {
+    None
+  }
+
+  fn iter<'a>(&'a self) -> Box<Iterator<(&'a K, &'a V)> + 'a> {
+    box None.into_iter()
+  }
+
+  fn iter_mut<'a>(&'a mut self) -> Box<Iterator<(&'a K, &'a mut V)> + 'a> {
+    box None.into_iter()
+  }
+
+  fn drain(&mut self) -> Vec<(K, V)> {
+    Vec::new()
+  }
+
+  fn retain(&mut self, _f: |&K, &mut V| -> bool) {}
+}
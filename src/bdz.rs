@@ -0,0 +1,166 @@
+//! The BDZ construction, after Botelho, Pagh, and Ziviani.
+//!
+//! Each key is an edge joining three vertices, one from each of three equal
+//! parts, and the hypergraph only has to be peelable rather than acyclic.
+//! Random 3-hypergraphs are peelable with high probability once there are
+//! more than about 1.222 vertices per key.
+//!
+//! Every vertex has a two-bit label `g`. A key picks the `j`th of its
+//! vertices, where `j = (g[v0] + g[v1] + g[v2]) % 3`, and its index is the
+//! number of picked vertices before that one. With a bit per vertex marking
+//! which are picked, and two words per 512 vertices of precomputed ranks,
+//! that's about four bits per key.
+
+use std::rand;
+
+use super::KeyHasher;
+use super::{BuildError, Overflow};
+use super::{Corruption, BadLength, BadCount};
+use super::rank::RankedBits;
+
+/// The labels for one set of keys.
+#[deriving(Clone)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+pub struct Labeling {
+  /// Vertices per part. Part `j` is vertices `[j * r, (j + 1) * r)`.
+  r:    uint,
+  /// Two bits per vertex.
+  g:    Vec<u64>,
+  /// One bit per vertex, set if some key picks it.
+  used: RankedBits,
+}
+
+/// The three vertices of the edge for hash values `a` and `b`, both less than
+/// `r`. Deriving all three from two values lets BDZ use any `KeyHasher`.
+fn edge(a: uint, b: uint, r: uint) -> [uint, ..3] {
+  [a, r + (a + b) % r, 2 * r + (a + 2 * b) % r]
+}
+
+fn get_label(g: &[u64], v: uint) -> uint {
+  ((g[v / 32] >> (2 * (v % 32))) & 3) as uint
+}
+
+fn set_label(g: &mut [u64], v: uint, label: uint) {
+  g[v / 32] |= (label as u64) << (2 * (v % 32));
+}
+
+/// Reseeds `hasher` and labels the hypergraph on `3 * r` vertices, or returns
+/// `None` if it can't be peeled.
+pub fn attempt<R: rand::Rng, K, H: KeyHasher<K>>(
+    rng: &mut R, hasher: &mut H, keys: &[K], r: uint)
+    -> Result<Option<Labeling>, BuildError> {
+  hasher.reseed(rng, keys, r);
+
+  let n = 3 * r;
+  let m = keys.len();
+
+  let mut pairs = Vec::with_capacity(m);
+  if !hasher.vertices_batch(keys, r, &mut pairs) {
+    return Err(Overflow);
+  }
+  let edges: Vec<[uint, ..3]> = pairs.into_iter().map(|(a, b)| edge(a, b, r)).collect();
+
+  // A vertex of degree one knows its only edge from the xor of the indices of
+  // all the edges it's on, so there's no need for adjacency lists.
+  let mut degree = Vec::from_elem(n, 0u);
+  let mut xored  = Vec::from_elem(n, 0u);
+  for (i, e) in edges.iter().enumerate() {
+    for &v in e.iter() {
+      *degree.get_mut(v) += 1;
+      *xored.get_mut(v) ^= i;
+    }
+  }
+
+  let mut queue: Vec<uint> = range(0, n).filter(|&v| degree[v] == 1).collect();
+  let mut peeled = Vec::with_capacity(m);
+
+  loop {
+    let v = match queue.pop() { Some(v) => v, None => break };
+
+    // It may have lost its last edge since it was queued.
+    if degree[v] != 1 {
+      continue;
+    }
+
+    let i = xored[v];
+    peeled.push((i, v));
+
+    for &w in edges[i].iter() {
+      *degree.get_mut(w) -= 1;
+      *xored.get_mut(w) ^= i;
+      if degree[w] == 1 {
+        queue.push(w);
+      }
+    }
+  }
+
+  if peeled.len() != m {
+    return Ok(None);
+  }
+
+  // In reverse peeling order, each edge's other two vertices already have
+  // their final labels, so its own vertex can be labeled to pick itself.
+  let mut g    = Vec::from_elem((n + 31) / 32, 0u64);
+  let mut used = Vec::from_elem(n, false);
+
+  for &(i, v) in peeled.iter().rev() {
+    let e   = edges[i];
+    let j   = e.iter().position(|&w| w == v).unwrap();
+    let sum = e.iter().filter(|&&w| w != v).fold(0, |sum, &w| sum + get_label(g.as_slice(), w));
+    set_label(g.as_mut_slice(), v, (j + 6 - sum) % 3);
+    *used.get_mut(v) = true;
+  }
+
+  Ok(Some(Labeling { r: r, g: g, used: RankedBits::new(used.as_slice()) }))
+}
+
+impl Labeling {
+  /// The index `k` would have if it were a known key, or `None` if it
+  /// certainly isn't.
+  pub fn index<K, H: KeyHasher<K>>(&self, hasher: &H, k: &K) -> Option<uint> {
+    let e =
+      match hasher.vertices(k, self.r) {
+        Some((a, b)) => edge(a, b, self.r),
+        None         => return None,
+      };
+
+    let g = self.g.as_slice();
+    let v = e[(get_label(g, e[0]) + get_label(g, e[1]) + get_label(g, e[2])) % 3];
+
+    // No known key picks an unused vertex.
+    self.used.rank(v)
+  }
+
+  /// Checks that there are labels and ranks for all `3 * r` vertices, and
+  /// that `m` of them are picked, so `index` stays in bounds.
+  pub fn verify(&self, m: uint) -> Result<(), Corruption> {
+    let n = self.used.len();
+    if !self.used.is_consistent() || n % 3 != 0 || n / 3 != self.r {
+      return Err(BadLength("used", n));
+    }
+    if self.g.len() != (n + 31) / 32 {
+      return Err(BadLength("g", self.g.len()));
+    }
+
+    let picked = self.used.count_ones();
+    if picked != m {
+      return Err(BadCount(m, picked));
+    }
+    Ok(())
+  }
+
+  /// The range `hasher.vertices` is asked for.
+  pub fn part_size(&self) -> uint {
+    self.r
+  }
+
+  /// The number of vertices, in all three parts.
+  pub fn num_vertices(&self) -> uint {
+    3 * self.r
+  }
+
+  /// The bytes of the labels and ranks.
+  pub fn heap_size(&self) -> uint {
+    self.g.len() * 8 + self.used.heap_size()
+  }
+}
@@ -0,0 +1,104 @@
+//! Two-way maps between known keys and dense ids.
+//!
+//! A `PerfectBiMap` gives each of its keys the id `i` for its position `i`
+//! among the keys it was built from, and looks ids up both ways: a key's id
+//! through an order-preserving perfect hash function, and an id's key by
+//! indexing the keys, which are stored in id order anyway. That's what a
+//! symbol table for interned names needs, in one structure.
+
+use std::hash;
+use std::iter;
+use std::slice;
+
+use super::{HashMapBuilder, BuildError, PerfectHashFn, PerfectHashState, ByteCounter};
+use super::{KeyHasher, Positional};
+
+/// A map from each known key to its id, and back, from
+/// `HashMapBuilder::build_bimap`.
+pub struct PerfectBiMap<K, H = Positional> {
+  func: PerfectHashFn<K, H>,
+  /// The key with each id.
+  keys: Vec<K>,
+}
+
+/// PerfectBiMap iterator, over ids and their keys.
+pub type BiEntries<'a, K> = iter::Enumerate<slice::Items<'a, K>>;
+
+impl HashMapBuilder {
+  /// Builds a bimap giving the `i`th of `keys`, which must not contain
+  /// duplicates, the id `i`. Whatever the algorithm, this preserves order.
+  pub fn build_bimap<'a,
+                     K: Eq
+                      + hash::Hash
+                      + hash::Hash<PerfectHashState<'a>>
+                      + hash::Hash<ByteCounter>>
+      (&self, keys: Vec<K>) -> Result<PerfectBiMap<K>, BuildError> {
+    self.build_bimap_with_hasher(Positional::new(), keys)
+  }
+
+  /// Like `build_bimap`, but hashes the keys with `hasher` instead of the
+  /// default `Positional` hasher.
+  pub fn build_bimap_with_hasher<K: Eq + hash::Hash, H: KeyHasher<K>>
+      (&self, hasher: H, keys: Vec<K>) -> Result<PerfectBiMap<K, H>, BuildError> {
+    let builder = self.clone().order_preserving(true);
+    let func    = try!(builder.build_fn_with_hasher(hasher, keys.as_slice()));
+    Ok(PerfectBiMap { func: func, keys: keys })
+  }
+}
+
+impl<'a,
+     K: Eq
+      + hash::Hash
+      + hash::Hash<PerfectHashState<'a>>
+      + hash::Hash<ByteCounter>>
+    PerfectBiMap<K> {
+  /// Builds a bimap over `keys` with the default `HashMapBuilder` settings.
+  pub fn new(keys: Vec<K>) -> PerfectBiMap<K> {
+    match HashMapBuilder::new().build_bimap(keys) {
+      Ok(map)  => map,
+      Err(err) => fail!("perfect::PerfectBiMap::new: {}", err),
+    }
+  }
+}
+
+impl<K: Eq + hash::Hash, H: KeyHasher<K>> PerfectBiMap<K, H> {
+  /// The id of `k`, or `None` if `k` isn't a known key.
+  pub fn id_of(&self, k: &K) -> Option<uint> {
+    match self.func.try_hash(k) {
+      Some(i) if self.keys[i] == *k => Some(i),
+      _                             => None,
+    }
+  }
+
+  /// Returns true if `k` is one of the known keys.
+  pub fn contains_key(&self, k: &K) -> bool {
+    self.id_of(k).is_some()
+  }
+}
+
+impl<K, H> PerfectBiMap<K, H> {
+  /// The key with id `id`, or `None` if there's no such id.
+  pub fn key_of(&self, id: uint) -> Option<&K> {
+    if id < self.keys.len() { Some(&self.keys[id]) } else { None }
+  }
+
+  /// The number of keys, which is also the number of ids.
+  pub fn len(&self) -> uint {
+    self.keys.len()
+  }
+
+  /// Returns true if there are no keys.
+  pub fn is_empty(&self) -> bool {
+    self.keys.is_empty()
+  }
+
+  /// Every key, in id order.
+  pub fn keys(&self) -> &[K] {
+    self.keys.as_slice()
+  }
+
+  /// An iterator visiting every id with its key, in id order.
+  pub fn iter<'a>(&'a self) -> BiEntries<'a, K> {
+    self.keys.iter().enumerate()
+  }
+}
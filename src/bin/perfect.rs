@@ -0,0 +1,167 @@
+//! Builds a perfect table from a word list, for build pipelines that aren't
+//! written in Rust.
+//!
+//! ```text
+//! perfect [--rust NAME | --c NAME | --binary] [-o OUT] WORDS
+//! ```
+//!
+//! `WORDS` holds one key per line; blank lines are skipped. Each word maps
+//! to its position among the words, counting from zero. `--binary`, the
+//! default, writes a `HashMap<String, uint>` in the format `read_from`
+//! loads. `--rust NAME` writes a `StaticMap<&'static str, uint>` called
+//! `NAME`, as `codegen::write_map` does. `--c NAME` writes a C header with
+//! `uint64_t` values and a `NAME_get` function, as `codegen::write_c_header`
+//! does. The output goes to `OUT`, or to stdout if there's no `-o`.
+//!
+//! Only built with the `cli` feature.
+
+#![cfg_attr(not(feature = "cli"), allow(dead_code, unused_imports))]
+
+extern crate perfect;
+
+use std::io;
+use std::io::{BufferedReader, File, IoResult};
+use std::os;
+
+use perfect::{HashMap, HashMapBuilder, Chm};
+use perfect::codegen;
+
+/// What to write.
+enum Format {
+  Binary,
+  Rust(String),
+  C(String),
+}
+
+struct Options {
+  format: Format,
+  input:  String,
+  output: Option<String>,
+}
+
+static USAGE: &'static str = "usage: perfect [--rust NAME | --c NAME | --binary] [-o OUT] WORDS";
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+  let mut format = Binary;
+  let mut input  = None;
+  let mut output = None;
+
+  let mut args = args.iter();
+  loop {
+    let arg =
+      match args.next() {
+        Some(arg) => arg.as_slice(),
+        None      => break,
+      };
+
+    match arg {
+      "--binary" => format = Binary,
+      "--rust"   => match args.next() {
+        Some(name) => format = Rust(name.clone()),
+        None       => return Err("--rust needs a name".to_string()),
+      },
+      "--c"      => match args.next() {
+        Some(name) => format = C(name.clone()),
+        None       => return Err("--c needs a name".to_string()),
+      },
+      "-o"       => match args.next() {
+        Some(path) => output = Some(path.clone()),
+        None       => return Err("-o needs a path".to_string()),
+      },
+      _ if arg.starts_with("-") => return Err(format!("unknown option {}", arg)),
+      _ if input.is_some()      => return Err("more than one word list".to_string()),
+      _                         => input = Some(arg.to_string()),
+    }
+  }
+
+  match input {
+    Some(input) => Ok(Options { format: format, input: input, output: output }),
+    None        => Err(USAGE.to_string()),
+  }
+}
+
+/// The non-blank lines of `path`, without their line endings.
+fn read_words(path: &str) -> IoResult<Vec<String>> {
+  let file = try!(File::open(&Path::new(path)));
+  let mut words = Vec::new();
+
+  for line in BufferedReader::new(file).lines() {
+    let line = try!(line);
+    let word = line.as_slice().trim_right_chars(|c: char| c == '\n' || c == '\r');
+    if !word.is_empty() {
+      words.push(word.to_string());
+    }
+  }
+
+  Ok(words)
+}
+
+fn write_table<W: Writer>(w: &mut W, format: &Format, words: Vec<String>) -> Result<(), String> {
+  match *format {
+    Binary => {
+      let mut map: HashMap<String, uint> =
+        match HashMapBuilder::new().algorithm(Chm).build(words.clone()) {
+          Ok(map)  => map,
+          Err(err) => return Err(err.to_string()),
+        };
+
+      for (i, word) in words.into_iter().enumerate() {
+        map.insert(word, i);
+      }
+
+      map.write_to(w).map_err(|err| err.to_string())
+    }
+    Rust(ref name) => {
+      let entries =
+        words.iter().enumerate().map(|(i, word)| (word.as_slice(), i.to_string())).collect();
+      codegen::write_map(w, name.as_slice(), "&'static str", "uint", entries)
+        .map_err(|err| err.to_string())
+    }
+    C(ref name) => {
+      let entries =
+        words.iter().enumerate().map(|(i, word)| (word.as_slice(), i.to_string())).collect();
+      codegen::write_c_header(w, name.as_slice(), "uint64_t", entries)
+        .map_err(|err| err.to_string())
+    }
+  }
+}
+
+fn run(options: Options) -> Result<(), String> {
+  let words =
+    match read_words(options.input.as_slice()) {
+      Ok(words) => words,
+      Err(err)  => return Err(format!("{}: {}", options.input, err)),
+    };
+
+  match options.output {
+    Some(ref path) => {
+      let mut file =
+        match File::create(&Path::new(path.as_slice())) {
+          Ok(file) => file,
+          Err(err) => return Err(format!("{}: {}", path, err)),
+        };
+      write_table(&mut file, &options.format, words)
+    }
+    None => write_table(&mut io::stdout(), &options.format, words),
+  }
+}
+
+#[cfg(feature = "cli")]
+fn main() {
+  let args = os::args();
+  let result = parse_args(args.slice_from(1)).and_then(run);
+
+  match result {
+    Ok(())   => {}
+    Err(err) => {
+      let _ = writeln!(io::stderr(), "perfect: {}", err);
+      os::set_exit_status(1);
+    }
+  }
+}
+
+#[cfg(not(feature = "cli"))]
+fn main() {
+  let _ = writeln!(io::stderr(), "perfect: built without the cli feature");
+  os::set_exit_status(1);
+}
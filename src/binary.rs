@@ -0,0 +1,612 @@
+//! A compact, versioned binary format for prebuilt tables.
+//!
+//! Everything is written little-endian, and every word is 64 bits regardless
+//! of the target, so a table built on one machine can be loaded on another.
+//! The file ends with a checksum of everything before it. Only tables built
+//! with `Chm` can be written.
+//!
+//! Lookups do their arithmetic in 64 bits too, so a table gives the same
+//! slots on targets of every word size, as long as its keys hash the same
+//! bytes on all of them. `String`s and `&str`s do. But std hashes `uint`s,
+//! `int`s, and the lengths of `Vec`s at the target's width, so `Positional`
+//! tables of keys containing them only load on targets with the width they
+//! were built on. Elsewhere `read_from` rejects them, as below. A
+//! `StableHashMap` always encodes those in 8 bytes, and loads everywhere.
+//!
+//! Keys are stored by their `Persist` encoding, but looked up by their
+//! hasher. `Positional` weighs the bytes of std's `Hash` impls, which may
+//! change between Rust releases; `read_from` then rejects the table, since
+//! its keys no longer hash to their slots. Tables of a `StableHashMap`
+//! weigh a `StableKey` encoding this crate owns, and keep loading.
+//!
+//! The layout is:
+//!
+//! ```text
+//! magic       8 bytes, "PERFECT\0"
+//! version     u32
+//! hasher      u8, 0 for `Positional`, 1 for `Stable`
+//! n           u64, the number of graph nodes
+//! m           u64, the number of known keys
+//! max_length  u64
+//! t1, t2      max_length u64s each
+//! nodes       n u64s
+//! order       u8, 1 if there's one, then m u64s
+//! fps         u8, 0 for none, 1 for fingerprints, 2 for digests, then m
+//!             u16s or u64s
+//! seed        u8, 1 if it's known, then u64
+//! keys        m keys
+//! table       m (u8 present, value if present)
+//! backup      u64 length, then that many (key, value) pairs
+//! checksum    u64, FNV-1a of all the bytes above
+//! ```
+
+use std::cmp;
+use std::hash;
+use std::io;
+use std::io::{IoError, IoResult};
+use std::kinds::marker;
+use std::u32;
+use std::uint;
+
+use super::{HashMap, PerfectHashFn, KeyHasher, Positional, Stable};
+use super::{ChmLabels, CheckBackup, Fingerprints, Narrow, Wide};
+use super::{BackupStore, SeededBackup};
+use super::bits::PackedInts;
+use super::slots::Slots;
+
+static MAGIC: &'static [u8] = b"PERFECT\0";
+
+/// The format version written by `write_to`, and the only one `read_from`
+/// reads.
+pub static VERSION: u32 = 1;
+
+/// The most elements set aside up front for any array read from a file:
+/// lengths are read before the checksum can vouch for them, so a corrupt
+/// one has to run out of input before it runs out of memory.
+static MAX_PREALLOC: uint = 4096;
+
+/// Types that can be written to and read back from the binary format.
+///
+/// The encoding must not depend on the target: fixed widths, little-endian.
+pub trait Persist {
+  /// Writes `self` to `w`.
+  fn persist<W: Writer>(&self, w: &mut W) -> IoResult<()>;
+  /// Reads back a value written by `persist`.
+  fn restore<R: Reader>(r: &mut R) -> IoResult<Self>;
+}
+
+macro_rules! persist_int(
+  ($t:ty, $write:ident, $read:ident) => (
+    impl Persist for $t {
+      fn persist<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+        w.$write(*self)
+      }
+
+      fn restore<R: Reader>(r: &mut R) -> IoResult<$t> {
+        r.$read()
+      }
+    }
+  )
+)
+
+persist_int!(u8,  write_u8,     read_u8)
+persist_int!(u16, write_le_u16, read_le_u16)
+persist_int!(u32, write_le_u32, read_le_u32)
+persist_int!(u64, write_le_u64, read_le_u64)
+persist_int!(i8,  write_i8,     read_i8)
+persist_int!(i16, write_le_i16, read_le_i16)
+persist_int!(i32, write_le_i32, read_le_i32)
+persist_int!(i64, write_le_i64, read_le_i64)
+
+impl Persist for uint {
+  fn persist<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+    w.write_le_u64(*self as u64)
+  }
+
+  fn restore<R: Reader>(r: &mut R) -> IoResult<uint> {
+    read_uint(r)
+  }
+}
+
+impl Persist for int {
+  fn persist<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+    w.write_le_i64(*self as i64)
+  }
+
+  fn restore<R: Reader>(r: &mut R) -> IoResult<int> {
+    let x = try!(r.read_le_i64());
+    if x as int as i64 != x {
+      return Err(invalid("int doesn't fit on this target"));
+    }
+    Ok(x as int)
+  }
+}
+
+impl Persist for () {
+  fn persist<W: Writer>(&self, _w: &mut W) -> IoResult<()> {
+    Ok(())
+  }
+
+  fn restore<R: Reader>(_r: &mut R) -> IoResult<()> {
+    Ok(())
+  }
+}
+
+impl Persist for bool {
+  fn persist<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+    w.write_u8(*self as u8)
+  }
+
+  fn restore<R: Reader>(r: &mut R) -> IoResult<bool> {
+    match try!(r.read_u8()) {
+      0 => Ok(false),
+      1 => Ok(true),
+      _ => Err(invalid("bad bool")),
+    }
+  }
+}
+
+impl Persist for char {
+  fn persist<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+    w.write_le_u32(*self as u32)
+  }
+
+  fn restore<R: Reader>(r: &mut R) -> IoResult<char> {
+    match ::std::char::from_u32(try!(r.read_le_u32())) {
+      Some(c) => Ok(c),
+      None    => Err(invalid("bad char")),
+    }
+  }
+}
+
+impl Persist for String {
+  fn persist<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+    try!(w.write_le_u64(self.len() as u64));
+    w.write_str(self.as_slice())
+  }
+
+  fn restore<R: Reader>(r: &mut R) -> IoResult<String> {
+    let len = try!(read_uint(r));
+    let bytes = try!(read_bytes(r, len));
+    match String::from_utf8(bytes) {
+      Ok(s)  => Ok(s),
+      Err(_) => Err(invalid("string isn't utf-8")),
+    }
+  }
+}
+
+impl<T: Persist> Persist for Vec<T> {
+  fn persist<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+    try!(w.write_le_u64(self.len() as u64));
+    for x in self.iter() {
+      try!(x.persist(w));
+    }
+    Ok(())
+  }
+
+  fn restore<R: Reader>(r: &mut R) -> IoResult<Vec<T>> {
+    let len = try!(read_uint(r));
+    let mut v = Vec::with_capacity(prealloc(len));
+    for _ in range(0, len) {
+      v.push(try!(Persist::restore(r)));
+    }
+    Ok(v)
+  }
+}
+
+fn invalid(desc: &'static str) -> IoError {
+  IoError {
+    kind:   io::InvalidInput,
+    desc:   desc,
+    detail: None,
+  }
+}
+
+/// The capacity to start an array of `len` elements read from a file with.
+fn prealloc(len: uint) -> uint {
+  cmp::min(len, MAX_PREALLOC)
+}
+
+/// Reads `len` bytes, at most `MAX_PREALLOC` at a time.
+fn read_bytes<R: Reader>(r: &mut R, len: uint) -> IoResult<Vec<u8>> {
+  let mut bytes = Vec::with_capacity(prealloc(len));
+  while bytes.len() < len {
+    let chunk = try!(r.read_exact(prealloc(len - bytes.len())));
+    bytes.push_all(chunk.as_slice());
+  }
+  Ok(bytes)
+}
+
+fn read_uint<R: Reader>(r: &mut R) -> IoResult<uint> {
+  let x = try!(r.read_le_u64());
+  if x > uint::MAX as u64 {
+    return Err(invalid("length doesn't fit on this target"));
+  }
+  Ok(x as uint)
+}
+
+fn write_uints<W: Writer>(w: &mut W, xs: &[uint]) -> IoResult<()> {
+  for &x in xs.iter() {
+    try!(w.write_le_u64(x as u64));
+  }
+  Ok(())
+}
+
+fn read_flag<R: Reader>(r: &mut R) -> IoResult<bool> {
+  Persist::restore(r)
+}
+
+fn read_uints<R: Reader>(r: &mut R, len: uint) -> IoResult<Vec<uint>> {
+  let mut v = Vec::with_capacity(prealloc(len));
+  for _ in range(0, len) {
+    v.push(try!(read_uint(r)));
+  }
+  Ok(v)
+}
+
+/// The positional weights are interleaved `u32`s in memory, but t1 and t2
+/// are separate runs of words in the file.
+fn write_weights<W: Writer, I: Iterator<u32>>(w: &mut W, mut xs: I) -> IoResult<()> {
+  for x in xs {
+    try!(w.write_le_u64(x as u64));
+  }
+  Ok(())
+}
+
+fn read_weights<R: Reader>(r: &mut R, len: uint) -> IoResult<Vec<u32>> {
+  let mut v = Vec::with_capacity(prealloc(len));
+  for _ in range(0, len) {
+    let x = try!(r.read_le_u64());
+    if x > u32::MAX as u64 {
+      return Err(invalid("positional weight doesn't fit in 32 bits"));
+    }
+    v.push(x as u32);
+  }
+  Ok(v)
+}
+
+/// 64-bit FNV-1a. It's not cryptographic, but it catches truncation and the
+/// usual bit rot.
+struct Fnv {
+  hash: u64,
+}
+
+impl Fnv {
+  fn new() -> Fnv {
+    Fnv { hash: 0xcbf29ce484222325 }
+  }
+
+  fn update(&mut self, bytes: &[u8]) {
+    for &b in bytes.iter() {
+      self.hash = (self.hash ^ b as u64) * 0x100000001b3;
+    }
+  }
+}
+
+/// Passes writes through, checksumming them on the way.
+struct ChecksumWriter<'a, W: 'a> {
+  inner: &'a mut W,
+  sum:   Fnv,
+}
+
+impl<'a, W: Writer> Writer for ChecksumWriter<'a, W> {
+  fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+    self.sum.update(buf);
+    self.inner.write(buf)
+  }
+}
+
+/// Passes reads through, checksumming them on the way.
+struct ChecksumReader<'a, R: 'a> {
+  inner: &'a mut R,
+  sum:   Fnv,
+}
+
+impl<'a, R: Reader> Reader for ChecksumReader<'a, R> {
+  fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+    let n = try!(self.inner.read(buf));
+    self.sum.update(buf.slice_to(n));
+    Ok(n)
+  }
+}
+
+/// Hashers whose tables the binary format can store, `Positional` and
+/// `Stable`. The `hasher` byte says which wrote a file.
+pub trait PersistHasher {
+  /// The `hasher` byte for this kind of hasher.
+  fn tag(&self) -> u8;
+  /// The weights for each byte position.
+  fn weights(&self) -> &[(u32, u32)];
+  /// A hasher with `weights`, as read back from a file.
+  fn from_weights(weights: Vec<(u32, u32)>) -> Self;
+}
+
+impl PersistHasher for Positional {
+  fn tag(&self) -> u8 {
+    0
+  }
+
+  fn weights(&self) -> &[(u32, u32)] {
+    self.weights.as_slice()
+  }
+
+  fn from_weights(weights: Vec<(u32, u32)>) -> Positional {
+    Positional { weights: weights }
+  }
+}
+
+impl PersistHasher for Stable {
+  fn tag(&self) -> u8 {
+    1
+  }
+
+  fn weights(&self) -> &[(u32, u32)] {
+    self.weights.as_slice()
+  }
+
+  fn from_weights(weights: Vec<(u32, u32)>) -> Stable {
+    Stable { weights: weights }
+  }
+}
+
+impl<K: Persist + Eq + hash::Hash, V: Persist, H: PersistHasher> HashMap<K, V, H> {
+  /// Writes the table in the binary format described in the module docs.
+  /// Fails with `InvalidInput` for tables built with any algorithm but
+  /// `Chm`, whose labels the format has no room for.
+  pub fn write_to<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+    let nodes =
+      match self.func.chm_nodes() {
+        Some(nodes) => nodes,
+        None        => return Err(invalid("only CHM tables can be written")),
+      };
+
+    let mut cw = ChecksumWriter { inner: w, sum: Fnv::new() };
+
+    try!(cw.write(MAGIC));
+    try!(cw.write_le_u32(VERSION));
+    try!(cw.write_u8(self.func.hasher.tag()));
+    try!(cw.write_le_u64(nodes.len() as u64));
+    try!(cw.write_le_u64(self.keys.len() as u64));
+    let weights = self.func.hasher.weights();
+    try!(cw.write_le_u64(weights.len() as u64));
+    try!(write_weights(&mut cw, weights.iter().map(|&(w1, _)| w1)));
+    try!(write_weights(&mut cw, weights.iter().map(|&(_, w2)| w2)));
+    try!(write_uints(&mut cw, nodes.to_vec().as_slice()));
+
+    match self.func.order {
+      Some(ref order) => { try!(cw.write_u8(1)); try!(write_uints(&mut cw, order.as_slice())); }
+      None            => { try!(cw.write_u8(0)); }
+    }
+
+    match self.func.fingerprints {
+      Some(Narrow(ref fps)) => {
+        try!(cw.write_u8(1));
+        for &fp in fps.iter() {
+          try!(cw.write_le_u16(fp));
+        }
+      }
+      Some(Wide(ref fps)) => {
+        try!(cw.write_u8(2));
+        for &fp in fps.iter() {
+          try!(cw.write_le_u64(fp));
+        }
+      }
+      None => { try!(cw.write_u8(0)); }
+    }
+
+    match self.func.seed {
+      Some(seed) => { try!(cw.write_u8(1)); try!(cw.write_le_u64(seed)); }
+      None       => { try!(cw.write_u8(0)); }
+    }
+
+    for k in self.keys.iter() {
+      try!(k.persist(&mut cw));
+    }
+
+    for v in self.table.iter() {
+      match v {
+        Some(v) => { try!(cw.write_u8(1)); try!(v.persist(&mut cw)); }
+        None    => { try!(cw.write_u8(0)); }
+      }
+    }
+
+    try!(cw.write_le_u64(self.backup.len() as u64));
+    for (k, v) in self.backup.iter() {
+      try!(k.persist(&mut cw));
+      try!(v.persist(&mut cw));
+    }
+
+    let sum = cw.sum.hash;
+    cw.inner.write_le_u64(sum)
+  }
+}
+
+impl<K: Persist + Eq + hash::Hash, V: Persist, H: PersistHasher + KeyHasher<K>>
+    HashMap<K, V, H> {
+  /// Reads a table written by `write_to` with the same kind of hasher.
+  ///
+  /// Fails if the version is unknown, the checksum doesn't match, or the
+  /// tables don't hash every known key back to its own slot. That last
+  /// check is what catches a `Positional` table whose keys' `Hash` impls
+  /// have changed since it was written.
+  pub fn read_from<R: Reader>(r: &mut R) -> IoResult<HashMap<K, V, H>> {
+    let mut cr = ChecksumReader { inner: r, sum: Fnv::new() };
+
+    if try!(cr.read_exact(MAGIC.len())).as_slice() != MAGIC {
+      return Err(invalid("not a perfect::HashMap"));
+    }
+
+    if try!(cr.read_le_u32()) != VERSION {
+      return Err(invalid("unsupported format version"));
+    }
+    let tag = try!(cr.read_u8());
+
+    let n          = try!(read_uint(&mut cr));
+    let m          = try!(read_uint(&mut cr));
+    let max_length = try!(read_uint(&mut cr));
+
+    // A key graph has more nodes than keys, unless it has neither.
+    if n < m {
+      return Err(invalid("fewer nodes than keys"));
+    }
+
+    let t1    = try!(read_weights(&mut cr, max_length));
+    let t2    = try!(read_weights(&mut cr, max_length));
+    let nodes = try!(read_uints(&mut cr, n));
+
+    let order =
+      if try!(read_flag(&mut cr)) { Some(try!(read_uints(&mut cr, m))) } else { None };
+
+    let fingerprints: Option<Fingerprints> =
+      match try!(cr.read_u8()) {
+        0 => None,
+        1 => {
+          let mut fps = Vec::with_capacity(prealloc(m));
+          for _ in range(0, m) {
+            fps.push(try!(cr.read_le_u16()));
+          }
+          Some(Narrow(fps))
+        }
+        2 => {
+          let mut fps = Vec::with_capacity(prealloc(m));
+          for _ in range(0, m) {
+            fps.push(try!(cr.read_le_u64()));
+          }
+          Some(Wide(fps))
+        }
+        _ => return Err(invalid("bad fingerprint tag")),
+      };
+
+    let seed = if try!(read_flag(&mut cr)) { Some(try!(cr.read_le_u64())) } else { None };
+
+    let hasher: H = PersistHasher::from_weights(t1.into_iter().zip(t2.into_iter()).collect());
+    if hasher.tag() != tag {
+      return Err(invalid("table was written with another kind of hasher"));
+    }
+
+    let mut keys = Vec::with_capacity(prealloc(m));
+    for _ in range(0, m) {
+      keys.push(try!(Persist::restore(&mut cr)));
+    }
+
+    let mut size  = 0;
+    let mut table = Vec::with_capacity(prealloc(m));
+    for _ in range(0, m) {
+      match try!(cr.read_u8()) {
+        0 => table.push(None),
+        1 => { table.push(Some(try!(Persist::restore(&mut cr)))); size += 1; }
+        _ => return Err(invalid("bad slot tag")),
+      }
+    }
+
+    let backup_len = try!(read_uint(&mut cr));
+    let mut backup = SeededBackup::new(seed.unwrap_or(0));
+    for _ in range(0, backup_len) {
+      let k = try!(Persist::restore(&mut cr));
+      let v = try!(Persist::restore(&mut cr));
+      // A `SeededBackup` takes every pair.
+      let _ = backup.insert(k, v);
+    }
+
+    let expected = cr.sum.hash;
+    if try!(cr.inner.read_le_u64()) != expected {
+      return Err(invalid("checksum mismatch"));
+    }
+
+    let func = PerfectHashFn {
+      labels:       ChmLabels(PackedInts::new(nodes.as_slice())),
+      hasher:       hasher,
+      m:            m,
+      order:        order,
+      fingerprints: fingerprints,
+      seed:         seed,
+      stats:        None,
+      marker:       marker::CovariantType,
+    };
+
+    let settings = func.builder();
+    let map = HashMap {
+      func:       func,
+      keys:       keys,
+      table:      Slots::from_options(table),
+      size:       size,
+      backup:     backup,
+      rebuild_at: None,
+      unknown:    CheckBackup,
+      sorted:     None,
+      resort:     None,
+      counters:   None,
+      sampler:    None,
+      settings:   settings,
+    };
+
+    if map.verify().is_err() {
+      return Err(invalid("tables are inconsistent"));
+    }
+
+    Ok(map)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::io::{MemReader, MemWriter};
+
+  use super::super::{HashMap, HashMapBuilder};
+  use super::super::testing::keys;
+
+  /// Half the known keys with values, and one unknown key in the backup.
+  fn map(builder: HashMapBuilder) -> HashMap<String, uint> {
+    let keys = keys(100);
+    let mut map = builder.seed(1).build(keys.clone()).unwrap();
+    for (i, k) in keys.into_iter().enumerate().filter(|&(i, _)| i % 2 == 0) {
+      map.insert(k, i);
+    }
+    map.insert("unknown".to_string(), 1000);
+    map
+  }
+
+  fn write(map: &HashMap<String, uint>) -> Vec<u8> {
+    let mut w = MemWriter::new();
+    map.write_to(&mut w).unwrap();
+    w.unwrap()
+  }
+
+  fn read(bytes: Vec<u8>) -> Option<HashMap<String, uint>> {
+    HashMap::read_from(&mut MemReader::new(bytes)).ok()
+  }
+
+  #[test]
+  fn round_trip() {
+    let builders = [
+      HashMapBuilder::new(),
+      HashMapBuilder::new().fingerprints(true),
+      HashMapBuilder::new().digests(true),
+    ];
+    for builder in builders.iter() {
+      let map    = map(builder.clone());
+      let loaded = read(write(&map)).unwrap();
+      assert_eq!(loaded, map);
+      assert_eq!(loaded.get(&"unknown".to_string()), Some(&1000));
+      assert!(loaded.verify().is_ok());
+    }
+  }
+
+  #[test]
+  fn rejects_a_corrupted_byte() {
+    let bytes = write(&map(HashMapBuilder::new()));
+    for i in range(0, bytes.len()) {
+      let mut corrupted = bytes.clone();
+      *corrupted.get_mut(i) ^= 0x10;
+      assert!(read(corrupted).is_none(), "flipping a bit of byte {} went unnoticed", i);
+    }
+  }
+
+  #[test]
+  fn rejects_a_truncated_file() {
+    let bytes = write(&map(HashMapBuilder::new()));
+    for len in range(0, bytes.len()) {
+      assert!(read(bytes.slice_to(len).to_vec()).is_none(), "{} bytes were read", len);
+    }
+  }
+}
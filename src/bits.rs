@@ -0,0 +1,239 @@
+//! Packing fields of any width into words.
+//!
+//! The algorithms that store codes rather than whole words build their
+//! tables with a `BitWriter`, and read them back with `read_bits`. CHM's
+//! node table, whose labels are all less than `m`, is a `PackedInts`.
+
+use std::num::CheckedMul;
+
+/// Bits appended one field at a time.
+pub struct BitWriter {
+  /// The bits, from the low end of each word.
+  pub words: Vec<u64>,
+  /// The number of bits appended.
+  pub len:   uint,
+}
+
+impl BitWriter {
+  /// No bits.
+  pub fn new() -> BitWriter {
+    BitWriter { words: Vec::new(), len: 0 }
+  }
+
+  /// Appends the low `width` bits of `value`.
+  pub fn push(&mut self, value: u64, width: uint) {
+    if width == 0 {
+      return;
+    }
+
+    let value = if width == 64 { value } else { value & ((1 << width) - 1) };
+    let bit   = self.len % 64;
+    if bit == 0 {
+      self.words.push(value);
+    } else {
+      *self.words.last_mut().unwrap() |= value << bit;
+      if bit + width > 64 {
+        self.words.push(value >> (64 - bit));
+      }
+    }
+    self.len += width;
+  }
+
+  /// Appends `zeros` zeros and then a one.
+  pub fn push_unary(&mut self, mut zeros: u64) {
+    while zeros >= 32 {
+      self.push(0, 32);
+      zeros -= 32;
+    }
+    self.push(1 << zeros as uint, zeros as uint + 1);
+  }
+}
+
+/// The `width` bits at `pos`.
+pub fn read_bits(words: &[u64], pos: uint, width: uint) -> u64 {
+  if width == 0 {
+    return 0;
+  }
+
+  let (w, bit) = (pos / 64, pos % 64);
+  let mut value = words[w] >> bit;
+  if bit + width > 64 {
+    value |= words[w + 1] << (64 - bit);
+  }
+  if width == 64 { value } else { value & ((1 << width) - 1) }
+}
+
+/// True if `words` holds `count` fields of `width` bits, and the word past
+/// them that `read_bits` may read along with the last.
+pub fn holds_fields(words: &[u64], count: uint, width: uint) -> bool {
+  width <= 64 && count.checked_mul(&width).map_or(false, |bits| words.len() >= (bits + 63) / 64 + 1)
+}
+
+/// The low `width` bits.
+fn mask(width: uint) -> u64 {
+  if width == 64 { !0 } else { (1 << width) - 1 }
+}
+
+/// Unsigned integers packed end to end, in as many bits each as the largest
+/// takes.
+#[deriving(Clone)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+pub struct PackedInts {
+  len:   uint,
+  width: uint,
+  mask:  u64,
+  /// Padded with a word past the last value's, so `get` can always read
+  /// two.
+  words: Vec<u64>,
+}
+
+impl PackedInts {
+  /// Packs `values`.
+  pub fn new(values: &[uint]) -> PackedInts {
+    let max   = values.iter().map(|&x| x as u64).max().unwrap_or(0);
+    let width = 64 - max.leading_zeros();
+
+    let mut packed = BitWriter::new();
+    for &x in values.iter() {
+      packed.push(x as u64, width);
+    }
+    while packed.words.len() < values.len() * width / 64 + 2 {
+      packed.words.push(0);
+    }
+
+    PackedInts {
+      len:   values.len(),
+      width: width,
+      mask:  mask(width),
+      words: packed.words,
+    }
+  }
+
+  /// Checks that the width and mask agree, and that there are words for
+  /// every value and the padding, so `get` stays in bounds.
+  pub fn is_consistent(&self) -> bool {
+    let bits = self.len.checked_mul(&self.width);
+    self.width <= 64
+      && self.mask == mask(self.width)
+      && bits.map_or(false, |bits| self.words.len() >= bits / 64 + 2)
+  }
+
+  /// The `i`th value. There are no branches but the bounds checks.
+  #[inline]
+  pub fn get(&self, i: uint) -> uint {
+    let pos      = i * self.width;
+    let (w, bit) = (pos / 64, pos % 64);
+
+    // `<< (64 - bit)` would overflow when `bit` is zero, so it's split.
+    let joined = self.words[w] >> bit | (self.words[w + 1] << 1) << (63 - bit);
+    (joined & self.mask) as uint
+  }
+
+  /// The number of values.
+  pub fn len(&self) -> uint {
+    self.len
+  }
+
+  /// The values, unpacked.
+  pub fn to_vec(&self) -> Vec<uint> {
+    range(0, self.len).map(|i| self.get(i)).collect()
+  }
+
+  /// The bytes of the packed words.
+  pub fn heap_size(&self) -> uint {
+    self.words.len() * 8
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::cmp;
+
+  use super::{BitWriter, PackedInts, mask, read_bits};
+
+  /// A hundred values that fill all 64 bits.
+  fn values() -> Vec<u64> {
+    range(0u64, 100).map(|i| (i + 1) * 0x9e3779b97f4a7c15).collect()
+  }
+
+  fn round_trip(width: uint) {
+    let values = values();
+    let mut packed = BitWriter::new();
+    for &x in values.iter() {
+      packed.push(x, width);
+    }
+    assert_eq!(packed.len, values.len() * width);
+
+    for (i, &x) in values.iter().enumerate() {
+      assert_eq!(read_bits(packed.words.as_slice(), i * width, width), x & mask(width));
+    }
+  }
+
+  #[test]
+  fn width_0() {
+    round_trip(0);
+  }
+
+  #[test]
+  fn width_1() {
+    round_trip(1);
+  }
+
+  #[test]
+  fn width_63() {
+    round_trip(63);
+  }
+
+  #[test]
+  fn width_64() {
+    round_trip(64);
+  }
+
+  #[test]
+  fn mixed_widths() {
+    let widths = [1u, 64, 0, 63, 64, 1, 63];
+    let values = values();
+
+    let mut packed = BitWriter::new();
+    for (&x, &width) in values.iter().zip(widths.iter().cycle()) {
+      packed.push(x, width);
+    }
+
+    let mut pos = 0;
+    for (&x, &width) in values.iter().zip(widths.iter().cycle()) {
+      assert_eq!(read_bits(packed.words.as_slice(), pos, width), x & mask(width));
+      pos += width;
+    }
+    assert_eq!(pos, packed.len);
+  }
+
+  #[test]
+  fn unary() {
+    let mut packed = BitWriter::new();
+    for &zeros in [0u64, 1, 31, 32, 33, 100].iter() {
+      packed.push_unary(zeros);
+    }
+
+    let mut pos = 0;
+    for &zeros in [0u, 1, 31, 32, 33, 100].iter() {
+      for _ in range(0, zeros) {
+        assert_eq!(read_bits(packed.words.as_slice(), pos, 1), 0);
+        pos += 1;
+      }
+      assert_eq!(read_bits(packed.words.as_slice(), pos, 1), 1);
+      pos += 1;
+    }
+    assert_eq!(pos, packed.len);
+  }
+
+  #[test]
+  fn packed_ints() {
+    for &max in [0u, 1, 1000, !0].iter() {
+      let values: Vec<uint> =
+        range(0u, 100).map(|i| if i % 7 == 0 { max } else { cmp::min(i, max) }).collect();
+      let packed = PackedInts::new(values.as_slice());
+      assert!(packed.is_consistent());
+      assert_eq!(packed.to_vec(), values);
+    }
+  }
+}
@@ -0,0 +1,939 @@
+//! Tunable construction of perfect hashtables.
+//!
+//! `HashMap::new` picks sensible defaults, but construction is randomized and
+//! the space it uses is a tradeoff against how long it takes. The builder
+//! exposes those knobs.
+
+use std::cmp;
+use std::fmt;
+use std::hash;
+use std::io::IoResult;
+use std::mem;
+use std::os;
+use std::rand;
+use std::rand::{Isaac64Rng, Rng, SeedableRng};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{ArenaHashMap, Region};
+use super::{HashMap, PerfectHashFn, PerfectHashState, ByteCounter, FksLabels};
+use super::{KeyHasher, Positional, TrustedHashMap, Persist, ShardedHashFn};
+use super::{fks, parallel};
+use super::sharded;
+use super::progress::{Progress, CancelToken, Monitor};
+
+/// The default ratio of graph vertices to keys.
+///
+/// The paper suggests c = 2.09. As long as it's greater than 2, the expected
+/// number of attempts before finding an acyclic graph stays constant.
+pub static DEFAULT_SPACE_FACTOR: f64 = 2.0 + 1.0 / 12.0;
+
+/// The default ratio of vertices to keys for `Bdz`.
+///
+/// Random 3-hypergraphs become peelable with high probability above about
+/// 1.222 vertices per edge.
+pub static BDZ_SPACE_FACTOR: f64 = 1.23;
+
+/// The default ratio of slots to keys for `Chd`.
+///
+/// CHD can fill every slot, but the last few buckets take many tries to
+/// place when there's no slack.
+pub static CHD_SPACE_FACTOR: f64 = 1.01;
+
+/// The default average number of keys per bucket for `Chd`. Larger buckets
+/// mean fewer displacements to store, but slower construction.
+pub static DEFAULT_CHD_LAMBDA: f64 = 5.0;
+
+/// The default ratio of slots to keys for `PtHash`.
+///
+/// The last few buckets try many pilots before they find free slots, and
+/// the slots past the keys cost a word each to remap.
+pub static PTHASH_SPACE_FACTOR: f64 = 1.01;
+
+/// The default average number of keys per bucket for `PtHash`. Larger
+/// buckets mean fewer pilots to store, but slower construction.
+pub static DEFAULT_PTHASH_LAMBDA: f64 = 5.0;
+
+/// The default ratio of slots to keys for `Fks`.
+///
+/// A bucket of `b` keys takes `b * b` slots, which comes to about two per
+/// key in all, so a little more than that rarely runs out.
+pub static FKS_SPACE_FACTOR: f64 = 2.5;
+
+/// The default number of keys per leaf for `RecSplit`. Each one more takes
+/// about 0.1 fewer bits per key, and about `e` times as long to build.
+pub static RECSPLIT_LEAF_SIZE: uint = 8;
+
+/// The default average number of keys per bucket for `RecSplit`. Larger
+/// buckets take fewer bits for the bucket directory, but longer lookups.
+pub static RECSPLIT_BUCKET_SIZE: uint = 100;
+
+/// The largest leaf `RecSplit` accepts. Leaves this big already take
+/// billions of tries each to build.
+pub static MAX_RECSPLIT_LEAF_SIZE: uint = 24;
+
+/// The construction algorithm.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Algorithm {
+  /// Czech, Havas, and Majewski: two vertices per key, and an acyclic graph.
+  /// The `i`th key hashes to `i`, but the node table takes about 2.09
+  /// labels of `log2(m)` bits per key.
+  Chm,
+  /// Botelho, Pagh, and Ziviani: three vertices per key, and a peelable
+  /// hypergraph. The tables take about four bits per key, but keys hash to
+  /// indices in no particular order.
+  Bdz,
+  /// Belazzougui, Botelho, and Dietzfelbinger: buckets of about `lambda`
+  /// keys each, displaced into free slots. Takes `16 / lambda` bits per key
+  /// plus about 1.25 for ranking, and keys hash in no particular order.
+  Chd(f64),
+  /// Esposito, Müller Graf, and Vigna: buckets of about `bucket` keys, split
+  /// recursively down to leaves of at most `leaf`, as `RecSplit(leaf,
+  /// bucket)`. Leaves of eight keys take under two bits per key, but
+  /// construction is far slower, and lookups slower, than any other
+  /// algorithm's. Keys hash in no particular order.
+  RecSplit(uint, uint),
+  /// Pibiri and Trani: uneven buckets of about `lambda` keys on average,
+  /// each with a pilot that moves its keys to free slots. Lookups hash
+  /// once and touch one pilot, so they're the fastest of the compact
+  /// algorithms, and the pilots take a few bits per key. Keys hash in no
+  /// particular order.
+  PtHash(f64),
+  /// Fredman, Komlós, and Szemerédi: a bucket per key on average, each with
+  /// its own slots and seed. A bucket that doesn't fit is retried alone,
+  /// rather than the whole table, so construction is cheap and predictable,
+  /// but the tables take about 20 bits per key. Keys hash in no particular
+  /// order.
+  Fks,
+}
+
+/// What a map's lookups do with a key that isn't one of its known keys.
+///
+/// The choice is made once per map, so the branch on it always goes the
+/// same way. Only lookups follow it: `insert` and `remove` always use the
+/// backup.
+#[deriving(Clone, PartialEq, Show)]
+pub enum UnknownKeys {
+  /// Look the key up in the backup store. This is the default.
+  CheckBackup,
+  /// Report the key absent without touching the backup. With fingerprints,
+  /// most such lookups never compare keys either.
+  ReturnNone,
+  /// Fail when debug assertions are on, for maps that should only ever be
+  /// asked about their known keys. Otherwise, check the backup.
+  FailUnknown,
+}
+
+/// The number of attempts `HashMap::try_new` makes before giving up.
+///
+/// With the default space factor each attempt succeeds with probability
+/// around 1/5, so running out of attempts means something else is wrong.
+pub static DEFAULT_MAX_ITERATIONS: uint = 1000;
+
+/// Reasons that building a table can fail.
+#[deriving(Clone, PartialEq)]
+pub enum BuildError {
+  /// The space factor must be greater than 1, or no acyclic graph can exist.
+  InvalidSpaceFactor(f64),
+  /// The CHD or PTHash bucket size must be positive.
+  InvalidLambda(f64),
+  /// The RecSplit leaf size must be between 1 and `MAX_RECSPLIT_LEAF_SIZE`,
+  /// and its bucket size positive.
+  InvalidRecSplit(uint, uint),
+  /// No acyclic graph was found within the given number of attempts.
+  TooManyIterations(uint),
+  /// The `KeyHasher` couldn't hash one of the keys. The built-in hashers
+  /// accept every key.
+  Overflow,
+  /// The keys at these two positions of the input are equal. Every known
+  /// key must be distinct.
+  DuplicateKey(uint, uint),
+  /// The builder's `CancelToken` was cancelled.
+  Cancelled,
+  /// The builder's deadline passed after this many attempts.
+  DeadlineExceeded(uint),
+  /// The `Region` given to `build_in` had no room for the keys or values.
+  RegionFull,
+}
+
+impl fmt::Show for BuildError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      InvalidSpaceFactor(c) =>
+        write!(f, "space factor {} must be greater than 1", c),
+      InvalidLambda(lambda) =>
+        write!(f, "bucket size {} must be positive", lambda),
+      InvalidRecSplit(leaf, bucket) =>
+        write!(f, "leaf size {} must be between 1 and {}, and bucket size {} positive",
+               leaf, MAX_RECSPLIT_LEAF_SIZE, bucket),
+      TooManyIterations(iters) =>
+        write!(f, "no acyclic graph found after {} iterations", iters),
+      Overflow =>
+        write!(f, "a key couldn't be hashed"),
+      DuplicateKey(first, second) =>
+        write!(f, "keys {} and {} are duplicates", first, second),
+      Cancelled =>
+        write!(f, "construction was cancelled"),
+      DeadlineExceeded(iters) =>
+        write!(f, "no table found before the deadline, after {} iterations", iters),
+      RegionFull =>
+        write!(f, "the region has no room for the table"),
+    }
+  }
+}
+
+/// How a construction went, from `HashMap::build_stats`.
+#[deriving(Clone, Show)]
+pub struct BuildStats {
+  /// The number of random tables tried, counting the one that worked.
+  pub iterations:  uint,
+  /// The time spent finding a table that works.
+  pub search_time: Duration,
+  /// The time spent afterward ordering and fingerprinting the keys.
+  pub finish_time: Duration,
+  /// The number of vertices in the key graph, or slots for the other
+  /// algorithms.
+  pub vertices:    uint,
+  /// Keys per vertex: the inverse of the space factor.
+  pub load_factor: f64,
+  /// The bytes of everything but the keys and values: the labels, the
+  /// hasher's tables, and any order or fingerprints.
+  pub heap_size:   uint,
+  /// True if `build_with_deadline` ran out of time, and built a map with no
+  /// known keys instead.
+  pub fell_back:   bool,
+}
+
+/// Configures the construction of a `HashMap` or a `PerfectHashFn`.
+///
+/// ```ignore
+/// let map: HashMap<&str, uint> =
+///   HashMapBuilder::new()
+///     .space_factor(3.0)
+///     .max_iterations(100)
+///     .build(vec!["if", "else", "while"])
+///     .unwrap();
+/// ```
+#[deriving(Clone)]
+pub struct HashMapBuilder {
+  algorithm:      Algorithm,
+  space_factor:   Option<f64>,
+  max_iterations: Option<uint>,
+  seed:           Option<u64>,
+  entropy:        Option<fn() -> u64>,
+  fingerprints:   bool,
+  digests:        bool,
+  ordered:        bool,
+  unknown_keys:   UnknownKeys,
+  counters:       bool,
+  box_over:       Option<uint>,
+  sample_one_in:  Option<uint>,
+  progress:       Option<Arc<Box<Progress + Send + Sync>>>,
+  cancel:         Option<CancelToken>,
+  deadline:       Option<Duration>,
+  spill_dir:      Option<Path>,
+}
+
+impl HashMapBuilder {
+  /// A builder with the same settings `HashMap::new` uses: CHM with the
+  /// default space factor, unlimited retries, and a random seed.
+  pub fn new() -> HashMapBuilder {
+    HashMapBuilder {
+      algorithm:      Chm,
+      space_factor:   None,
+      max_iterations: None,
+      seed:           None,
+      entropy:        None,
+      fingerprints:   false,
+      digests:        false,
+      ordered:        false,
+      unknown_keys:   CheckBackup,
+      counters:       false,
+      box_over:       None,
+      sample_one_in:  None,
+      progress:       None,
+      cancel:         None,
+      deadline:       None,
+      spill_dir:      None,
+    }
+  }
+
+  /// Picks the construction algorithm.
+  pub fn algorithm(mut self, algorithm: Algorithm) -> HashMapBuilder {
+    self.algorithm = algorithm;
+    self
+  }
+
+  /// Sets c, the number of graph vertices per key. It defaults to
+  /// `DEFAULT_SPACE_FACTOR` for `Chm`, `BDZ_SPACE_FACTOR` for `Bdz`, and
+  /// `CHD_SPACE_FACTOR` for `Chd`, `PTHASH_SPACE_FACTOR` for `PtHash`, and
+  /// `FKS_SPACE_FACTOR` for `Fks`. `RecSplit` ignores it: it fills exactly
+  /// as many slots as there are keys.
+  ///
+  /// Larger values use more memory for the node table, but find an acyclic
+  /// graph in fewer attempts.
+  pub fn space_factor(mut self, c: f64) -> HashMapBuilder {
+    self.space_factor = Some(c);
+    self
+  }
+
+  /// Gives up after trying `iters` random tables.
+  pub fn max_iterations(mut self, iters: uint) -> HashMapBuilder {
+    self.max_iterations = Some(iters);
+    self
+  }
+
+  /// Seeds the random number generator used to pick tables, instead of
+  /// seeding it from the task's rng. The same seed, settings, and keys always
+  /// build the same tables.
+  pub fn seed(mut self, seed: u64) -> HashMapBuilder {
+    self.seed = Some(seed);
+    self
+  }
+
+  /// Draws the seed of each unseeded build from `source`, instead of from
+  /// the task's rng, for targets without OS randomness. With this or `seed`,
+  /// building a function or a `HashMap` never touches `task_rng`: duplicate
+  /// keys are found by sorting, and the default `SeededBackup` is keyed from
+  /// the build's seed. Other backup stores, and containers that keep a std
+  /// `HashMap` of their own, such as `PerfectMultiMap`, still draw from it.
+  ///
+  /// Lookups never need randomness, so prebuilt tables such as `StaticMap`s
+  /// don't need either.
+  pub fn entropy(mut self, source: fn() -> u64) -> HashMapBuilder {
+    self.entropy = Some(source);
+    self
+  }
+
+  /// Stores a 16-bit fingerprint per key, indexed by slot, so `try_hash` can
+  /// reject keys that weren't in the set.
+  ///
+  /// Maps check the fingerprint before comparing against the stored key, so
+  /// all but about one in 65536 misses never touch the keys. Hits still
+  /// compare the keys, which is worth it for long ones like `String`s.
+  pub fn fingerprints(mut self, enabled: bool) -> HashMapBuilder {
+    self.fingerprints = enabled;
+    self
+  }
+
+  /// Like `fingerprints`, but stores each key's whole 64-bit `hash::hash`
+  /// instead of 16 bits of it, for eight bytes per key.
+  ///
+  /// Misses then essentially never compare keys, and hits compare only
+  /// keys that are already known to match, which matters when comparing
+  /// them is expensive. This takes precedence over `fingerprints`.
+  pub fn digests(mut self, enabled: bool) -> HashMapBuilder {
+    self.digests = enabled;
+    self
+  }
+
+  /// Makes the `i`th key hash to `i`, and so live in slot `i` of a map, with
+  /// every algorithm. `Chm` always does this. The others need an extra word
+  /// per key to remember the order.
+  pub fn order_preserving(mut self, enabled: bool) -> HashMapBuilder {
+    self.ordered = enabled;
+    self
+  }
+
+  /// Sets what the built map's lookups do with keys that aren't known. It
+  /// defaults to `CheckBackup`.
+  pub fn unknown_keys(mut self, policy: UnknownKeys) -> HashMapBuilder {
+    self.unknown_keys = policy;
+    self
+  }
+
+  /// Makes the built map count where its lookups are served from, for
+  /// `HashMap::lookup_stats`. Each lookup then costs an atomic increment.
+  pub fn lookup_counters(mut self, enabled: bool) -> HashMapBuilder {
+    self.counters = enabled;
+    self
+  }
+
+  /// Makes the built map time one in every `one_in` of its `get`s, and note
+  /// the lengths of their keys, for `HashMap::sample_stats`. Sampled lookups
+  /// read the clock twice, and every lookup costs an atomic increment.
+  pub fn sample_lookups(mut self, one_in: uint) -> HashMapBuilder {
+    self.sample_one_in = Some(one_in);
+    self
+  }
+
+  /// Makes the built map keep its values out of line, each in a box of its
+  /// own, if they take more than `bytes`. The slots then hold a word each,
+  /// so lookups that miss or only check the keys stay within fewer cache
+  /// lines, at the cost of a load on every hit.
+  pub fn box_values_over(mut self, bytes: uint) -> HashMapBuilder {
+    self.box_over = Some(bytes);
+    self
+  }
+
+  /// Reports to `progress` before each attempt, and once more when one works.
+  pub fn progress<P: Progress + Send + Sync>(mut self, progress: P) -> HashMapBuilder {
+    self.progress = Some(Arc::new(box progress as Box<Progress + Send + Sync>));
+    self
+  }
+
+  /// Gives up with `Cancelled` at the first attempt after `token` is
+  /// cancelled.
+  pub fn cancel_token(mut self, token: CancelToken) -> HashMapBuilder {
+    self.cancel = Some(token);
+    self
+  }
+
+  /// Gives up with `DeadlineExceeded` at the first attempt after `timeout`
+  /// has passed since construction started. Attempts aren't interrupted, so
+  /// construction can run over by as long as one takes.
+  pub fn deadline(mut self, timeout: Duration) -> HashMapBuilder {
+    self.deadline = Some(timeout);
+    self
+  }
+
+  /// Spills keys into `dir` during `build_sharded`, instead of the system's
+  /// temporary directory.
+  pub fn spill_dir(mut self, dir: Path) -> HashMapBuilder {
+    self.spill_dir = Some(dir);
+    self
+  }
+
+  /// Builds a table with a dedicated slot for each of `known_vals`, which
+  /// must not contain duplicates.
+  pub fn build<'a,
+               K: Eq
+                + hash::Hash
+                + hash::Hash<PerfectHashState<'a>>
+                + hash::Hash<ByteCounter>,
+               V>
+      (&self, known_vals: Vec<K>) -> Result<HashMap<K, V>, BuildError> {
+    self.build_with_hasher(Positional::new(), known_vals)
+  }
+
+  /// Like `build`, but hashes the keys with `hasher` instead of the default
+  /// `Positional` hasher.
+  pub fn build_with_hasher<K: Eq + hash::Hash, V, H: KeyHasher<K>>
+      (&self, hasher: H, known_vals: Vec<K>) -> Result<HashMap<K, V, H>, BuildError> {
+    let func = try!(self.construct_fn(hasher, known_vals.as_slice()));
+    let mut map = HashMap::with_fn(func, known_vals);
+    self.configure(&mut map);
+    Ok(map)
+  }
+
+  /// Like `build`, but if no table is found within `timeout`, returns a map
+  /// with no known keys instead, which keeps every entry in its backup
+  /// store. Its `build_stats` say it fell back. Any other error is still
+  /// returned.
+  pub fn build_with_deadline<'a,
+                             K: Eq
+                              + hash::Hash
+                              + hash::Hash<PerfectHashState<'a>>
+                              + hash::Hash<ByteCounter>,
+                             V>
+      (&self, timeout: Duration, known_vals: Vec<K>) -> Result<HashMap<K, V>, BuildError> {
+    self.build_with_deadline_with_hasher(Positional::new(), timeout, known_vals)
+  }
+
+  /// Like `build_with_deadline`, but hashes the keys with `hasher` instead of
+  /// the default `Positional` hasher.
+  pub fn build_with_deadline_with_hasher<K: Eq + hash::Hash, V, H: KeyHasher<K>>
+      (&self, hasher: H, timeout: Duration, known_vals: Vec<K>)
+      -> Result<HashMap<K, V, H>, BuildError> {
+    let monitor = self.monitor();
+    let iters =
+      match self.clone().deadline(timeout).build_with_hasher(hasher.clone(), known_vals) {
+        Err(DeadlineExceeded(iters)) => iters,
+        result                       => return result,
+      };
+
+    // Only the keys have to be in the backup, so there's nothing to place.
+    let mut plain = self.clone();
+    plain.deadline = None;
+    let mut func = try!(plain.construct_fn(hasher, [].as_slice()));
+    match func.stats {
+      Some(ref mut stats) => {
+        stats.iterations  = iters;
+        stats.search_time = monitor.elapsed();
+        stats.fell_back   = true;
+      }
+      None => {}
+    }
+
+    let mut map = HashMap::with_fn(func, Vec::new());
+    self.configure(&mut map);
+    // Every key is unknown now, and only the backup has them.
+    map.set_unknown_keys(CheckBackup);
+    Ok(map)
+  }
+
+  /// Like `build`, but copies the known keys out of a slice. That's meant
+  /// for borrowed keys, whose copies are only pointers, so a lexer's table
+  /// can point straight at its static keyword list:
+  ///
+  /// ```ignore
+  /// static KEYWORDS: [&'static str, ..3] = ["if", "else", "while"];
+  /// let map: HashMap<&'static str, Token> =
+  ///   HashMapBuilder::new().build_from_slice(KEYWORDS.as_slice()).unwrap();
+  /// ```
+  ///
+  /// A map is covariant in its keys, so a `&HashMap<&'static str, V>` can
+  /// look up a `&str` borrowed from the input being lexed, however short
+  /// its borrow.
+  pub fn build_from_slice<'a,
+                          K: Clone
+                           + Eq
+                           + hash::Hash
+                           + hash::Hash<PerfectHashState<'a>>
+                           + hash::Hash<ByteCounter>,
+                          V>
+      (&self, keys: &[K]) -> Result<HashMap<K, V>, BuildError> {
+    self.build(keys.to_vec())
+  }
+
+  /// Like `build`, but sorts the known keys as `HashMap::sort_keys` does, so
+  /// `iter_sorted` works from the start.
+  pub fn build_sorted<'a,
+                      K: Ord
+                       + Eq
+                       + hash::Hash
+                       + hash::Hash<PerfectHashState<'a>>
+                       + hash::Hash<ByteCounter>,
+                      V>
+      (&self, known_vals: Vec<K>) -> Result<HashMap<K, V>, BuildError> {
+    let mut map = try!(self.build(known_vals));
+    map.sort_keys();
+    Ok(map)
+  }
+
+  /// Builds a table holding `entries`, whose keys must not contain
+  /// duplicates. Each value goes straight into its key's slot.
+  pub fn build_entries<'a,
+                       K: Eq
+                        + hash::Hash
+                        + hash::Hash<PerfectHashState<'a>>
+                        + hash::Hash<ByteCounter>,
+                       V>
+      (&self, entries: Vec<(K, V)>) -> Result<HashMap<K, V>, BuildError> {
+    self.build_entries_with_hasher(Positional::new(), entries)
+  }
+
+  /// Like `build_entries`, but hashes the keys with `hasher` instead of the
+  /// default `Positional` hasher.
+  pub fn build_entries_with_hasher<K: Eq + hash::Hash, V, H: KeyHasher<K>>
+      (&self, hasher: H, entries: Vec<(K, V)>) -> Result<HashMap<K, V, H>, BuildError> {
+    let mut keys   = Vec::with_capacity(entries.len());
+    let mut values = Vec::with_capacity(entries.len());
+    for (k, v) in entries.into_iter() {
+      keys.push(k);
+      values.push(v);
+    }
+
+    let func = try!(self.construct_fn(hasher, keys.as_slice()));
+    let entries = keys.into_iter().zip(values.into_iter()).collect();
+    let mut map = HashMap::with_fn_entries(func, entries);
+    self.configure(&mut map);
+    Ok(map)
+  }
+
+  /// Like `build_entries`, but sorts the keys as `build_sorted` does.
+  pub fn build_entries_sorted<'a,
+                              K: Ord
+                               + Eq
+                               + hash::Hash
+                               + hash::Hash<PerfectHashState<'a>>
+                               + hash::Hash<ByteCounter>,
+                              V>
+      (&self, entries: Vec<(K, V)>) -> Result<HashMap<K, V>, BuildError> {
+    let mut map = try!(self.build_entries(entries));
+    map.sort_keys();
+    Ok(map)
+  }
+
+  /// Builds a `TrustedHashMap` holding `pairs`, whose keys must not contain
+  /// duplicates.
+  pub fn build_trusted<'a,
+                       K: Eq
+                        + hash::Hash
+                        + hash::Hash<PerfectHashState<'a>>
+                        + hash::Hash<ByteCounter>,
+                       V>
+      (&self, pairs: Vec<(K, V)>) -> Result<TrustedHashMap<K, V>, BuildError> {
+    self.build_trusted_with_hasher(Positional::new(), pairs)
+  }
+
+  /// Like `build_trusted`, but hashes the keys with `hasher` instead of the
+  /// default `Positional` hasher.
+  pub fn build_trusted_with_hasher<K: Eq + hash::Hash, V, H: KeyHasher<K>>
+      (&self, hasher: H, pairs: Vec<(K, V)>) -> Result<TrustedHashMap<K, V, H>, BuildError> {
+    let mut keys   = Vec::with_capacity(pairs.len());
+    let mut values = Vec::with_capacity(pairs.len());
+    for (k, v) in pairs.into_iter() {
+      keys.push(k);
+      values.push(v);
+    }
+
+    let func = try!(self.construct_fn(hasher, keys.as_slice()));
+    Ok(TrustedHashMap::from_fn(func, keys.into_iter().zip(values.into_iter()).collect()))
+  }
+
+  /// Builds a map of `pairs` whose key and value arrays are moved into
+  /// `region`, and which checks keys on lookup. Keys that own heap memory,
+  /// such as `String`s, can be copied into the region first, as with
+  /// `Region::alloc_str`.
+  ///
+  /// Returns `RegionFull` if the region doesn't have room for both arrays.
+  pub fn build_in<'a,
+                  'b,
+                  R: Region,
+                  K: Eq
+                   + hash::Hash
+                   + hash::Hash<PerfectHashState<'b>>
+                   + hash::Hash<ByteCounter>,
+                  V>
+      (&self, region: &'a R, pairs: Vec<(K, V)>) -> Result<ArenaHashMap<'a, K, V>, BuildError> {
+    self.build_in_with_hasher(Positional::new(), region, pairs)
+  }
+
+  /// Like `build_in`, but hashes the keys with `hasher` instead of the
+  /// default `Positional` hasher.
+  pub fn build_in_with_hasher<'a, R: Region, K: Eq + hash::Hash, V, H: KeyHasher<K>>
+      (&self, hasher: H, region: &'a R, pairs: Vec<(K, V)>)
+      -> Result<ArenaHashMap<'a, K, V, H>, BuildError> {
+    let mut keys   = Vec::with_capacity(pairs.len());
+    let mut values = Vec::with_capacity(pairs.len());
+    for (k, v) in pairs.into_iter() {
+      keys.push(k);
+      values.push(v);
+    }
+
+    let func  = try!(self.construct_fn(hasher, keys.as_slice()));
+    let pairs = keys.into_iter().zip(values.into_iter()).collect();
+    match ArenaHashMap::from_fn_in(region, func, pairs) {
+      Some(map) => Ok(map),
+      None      => Err(RegionFull),
+    }
+  }
+
+  /// Builds a function mapping the `i`th of `keys` to `i`. The keys must not
+  /// contain duplicates.
+  pub fn build_fn<'a,
+                  K: Eq
+                   + hash::Hash
+                   + hash::Hash<PerfectHashState<'a>>
+                   + hash::Hash<ByteCounter>>
+      (&self, keys: &[K]) -> Result<PerfectHashFn<K>, BuildError> {
+    self.build_fn_with_hasher(Positional::new(), keys)
+  }
+
+  /// Like `build_fn`, but hashes the keys with `hasher` instead of the
+  /// default `Positional` hasher.
+  pub fn build_fn_with_hasher<K: Eq + hash::Hash, H: KeyHasher<K>>
+      (&self, hasher: H, keys: &[K]) -> Result<PerfectHashFn<K, H>, BuildError> {
+    self.construct_fn(hasher, keys)
+  }
+
+  /// Like `build_fn_with_hasher`, but starts from `func`, a function these
+  /// settings built for other keys. An `Fks` function keeps its hasher and
+  /// its buckets, and only the buckets whose keys changed look for new
+  /// seeds. If they don't fit, or for any other algorithm, this builds a new
+  /// function from scratch with `func`'s hasher.
+  pub fn rebuild_fn<K: Eq + hash::Hash, H: KeyHasher<K>>
+      (&self, func: &PerfectHashFn<K, H>, keys: &[K]) -> Result<PerfectHashFn<K, H>, BuildError> {
+    match (self.algorithm, &func.labels) {
+      (Fks, &FksLabels(ref old)) => {
+        let n = try!(self.num_vertices(keys.len()));
+        let monitor = self.monitor();
+        match fks::patch(old, &func.hasher, keys, n) {
+          Some(labeling) => {
+            let mut patched =
+              PerfectHashFn::from_parts(FksLabels(labeling), func.hasher.clone(), keys.len());
+            patched.seed = func.seed;
+            self.finish_fn(&mut patched, keys, n, 1, &monitor);
+            return Ok(patched);
+          }
+          None => {}
+        }
+      }
+      _ => {}
+    }
+
+    self.construct_fn(func.hasher.clone(), keys)
+  }
+
+  /// Like `build`, but takes the known keys from an iterator. They're
+  /// collected once, into the map's own storage.
+  pub fn build_from_iter<'a,
+                         K: Eq
+                          + hash::Hash
+                          + hash::Hash<PerfectHashState<'a>>
+                          + hash::Hash<ByteCounter>,
+                         V,
+                         I: Iterator<K>>
+      (&self, keys: I) -> Result<HashMap<K, V>, BuildError> {
+    self.build(keys.collect())
+  }
+
+  /// Builds a function over the keys `keys` streams, without ever holding
+  /// them: each call of `keys` must iterate over the same keys, in the same
+  /// order. It's called once to count them, once per attempt, and once more
+  /// for fingerprints or digests. The `i`th key hashes to `i`.
+  ///
+  /// This always uses `Chm`, which holds two words per key while it looks
+  /// for a table. Duplicate keys can't be checked for up front, but the
+  /// first attempt to reach the second of a pair fails with `DuplicateKey`.
+  pub fn build_fn_streamed<'a,
+                           K: Eq
+                            + hash::Hash
+                            + hash::Hash<PerfectHashState<'a>>
+                            + hash::Hash<ByteCounter>,
+                           I: Iterator<K>>
+      (&self, keys: || -> I) -> Result<PerfectHashFn<K>, BuildError> {
+    self.build_fn_streamed_with_hasher(Positional::new(), keys)
+  }
+
+  /// Like `build_fn_streamed`, but hashes the keys with `hasher` instead of
+  /// the default `Positional` hasher.
+  pub fn build_fn_streamed_with_hasher<K: Eq + hash::Hash, H: KeyHasher<K>, I: Iterator<K>>
+      (&self, hasher: H, keys: || -> I) -> Result<PerfectHashFn<K, H>, BuildError> {
+    let m = keys().count();
+    let n = try!(self.clone().algorithm(Chm).num_vertices(m));
+    let (seed, mut rng) = self.rng();
+    let monitor = self.monitor();
+
+    let (mut func, iters) =
+      try!(PerfectHashFn::construct_streamed(&mut rng, hasher, || keys(), m, n,
+                                             self.max_iterations, &monitor));
+    func.seed = Some(seed);
+
+    let search_time = monitor.elapsed();
+    func.finish_streamed(|| keys(), self.fingerprints, self.digests);
+
+    func.stats = Some(BuildStats {
+      iterations:  iters,
+      search_time: search_time,
+      finish_time: monitor.elapsed() - search_time,
+      vertices:    n,
+      load_factor: if n == 0 { 0.0 } else { m as f64 / n as f64 },
+      heap_size:   func.heap_size(),
+      fell_back:   false,
+    });
+    Ok(func)
+  }
+
+  /// Like `build`, but runs `workers` attempts at a time, each on its own
+  /// task. The keys are copied so the workers can share them.
+  ///
+  /// The tables depend on which worker finishes first, so a seed alone
+  /// doesn't fix them. The seed the map reports is the winning worker's, and
+  /// a single-task build from it reproduces the same tables.
+  pub fn build_parallel<'a,
+                        K: Clone
+                         + Send
+                         + Sync
+                         + Eq
+                         + hash::Hash
+                         + hash::Hash<PerfectHashState<'a>>
+                         + hash::Hash<ByteCounter>,
+                        V>
+      (&self, workers: uint, known_vals: Vec<K>) -> Result<HashMap<K, V>, BuildError> {
+    self.build_parallel_with_hasher(Positional::new(), workers, known_vals)
+  }
+
+  /// Like `build_parallel`, but hashes the keys with `hasher` instead of the
+  /// default `Positional` hasher.
+  pub fn build_parallel_with_hasher<K: Clone + Send + Sync + Eq + hash::Hash,
+                                    V,
+                                    H: KeyHasher<K> + Send>
+      (&self, hasher: H, workers: uint, known_vals: Vec<K>)
+      -> Result<HashMap<K, V, H>, BuildError> {
+    let n            = try!(self.num_vertices(known_vals.len()));
+    let (_, mut rng) = self.rng();
+    let shared       = Arc::new(known_vals.clone());
+    let monitor      = self.monitor();
+
+    let (mut func, iters) =
+      try!(parallel::construct(&mut rng, hasher, shared, self.algorithm, n,
+                               self.max_iterations, cmp::max(workers, 1), &monitor));
+    self.finish_fn(&mut func, known_vals.as_slice(), n, iters, &monitor);
+    let mut map = HashMap::with_fn(func, known_vals);
+    self.configure(&mut map);
+    Ok(map)
+  }
+
+  /// Builds a function over `keys`, which must not contain duplicates, as
+  /// `shards` independent functions, `workers` of them at a time.
+  ///
+  /// Lookups hash once more to find the shard, but each shard's graph is
+  /// far smaller than one over every key, and a failed attempt only has to
+  /// be retried for its own shard. With a seed, the same keys always build
+  /// the same function, whatever the number of workers.
+  pub fn build_fn_sharded<'a,
+                          K: Clone
+                           + Send
+                           + Eq
+                           + hash::Hash
+                           + hash::Hash<PerfectHashState<'a>>
+                           + hash::Hash<ByteCounter>>
+      (&self, keys: &[K], shards: uint, workers: uint)
+      -> Result<ShardedHashFn<K>, BuildError> {
+    self.build_fn_sharded_with_hasher(Positional::new(), keys, shards, workers)
+  }
+
+  /// Like `build_fn_sharded`, but hashes the keys with `hasher` instead of
+  /// the default `Positional` hasher.
+  pub fn build_fn_sharded_with_hasher<K: Clone + Send + Eq + hash::Hash,
+                                      H: KeyHasher<K> + Send>
+      (&self, hasher: H, keys: &[K], shards: uint, workers: uint)
+      -> Result<ShardedHashFn<K, H>, BuildError> {
+    let (_, mut rng) = self.rng();
+    sharded::construct(&mut rng, self, hasher, keys, shards, workers)
+  }
+
+  /// Builds a function over `keys`, which must not contain duplicates, while
+  /// holding only as many of them in memory as fit in `budget` bytes.
+  ///
+  /// The keys are streamed once into temporary files, then read back a
+  /// shard at a time, and the files are deleted afterward. Every other
+  /// setting applies to each shard. The keys are split 256 ways before
+  /// they're grouped into shards, so this fails if any one of those 256
+  /// parts would need more than `budget` bytes.
+  pub fn build_sharded<'a,
+                       K: Persist
+                        + Eq
+                        + hash::Hash
+                        + hash::Hash<PerfectHashState<'a>>
+                        + hash::Hash<ByteCounter>,
+                       I: Iterator<K>>
+      (&self, keys: I, budget: uint) -> IoResult<ShardedHashFn<K>> {
+    self.build_sharded_with_hasher(Positional::new(), keys, budget)
+  }
+
+  /// Like `build_sharded`, but hashes the keys with `hasher` instead of the
+  /// default `Positional` hasher.
+  pub fn build_sharded_with_hasher<K: Persist + Eq + hash::Hash,
+                                   H: KeyHasher<K>,
+                                   I: Iterator<K>>
+      (&self, hasher: H, keys: I, budget: uint) -> IoResult<ShardedHashFn<K, H>> {
+    let dir =
+      match self.spill_dir {
+        Some(ref dir) => dir.clone(),
+        None          => os::tmpdir(),
+      };
+
+    let (_, mut rng) = self.rng();
+    sharded::construct_spilled(&mut rng, self, hasher, keys, budget, &dir)
+  }
+
+  /// Applies the settings that belong to the map rather than the function,
+  /// and keeps the rest for its rebuilds.
+  fn configure<K: Eq + hash::Hash, V, H: KeyHasher<K>>(&self, map: &mut HashMap<K, V, H>) {
+    map.settings = self.for_rebuilds();
+    map.set_unknown_keys(self.unknown_keys);
+    map.set_lookup_counters(self.counters);
+    map.set_lookup_sampling(self.sample_one_in);
+    match self.box_over {
+      Some(bytes) => map.set_boxed_values(mem::size_of::<V>() > bytes),
+      None        => {}
+    }
+  }
+
+  /// These settings without the ones that only make sense for one build:
+  /// rebuilds happen in the middle of an insertion, so they can't be cut
+  /// short or reported on.
+  fn for_rebuilds(&self) -> HashMapBuilder {
+    HashMapBuilder {
+      max_iterations: None,
+      progress:       None,
+      cancel:         None,
+      deadline:       None,
+      spill_dir:      None,
+      ..self.clone()
+    }
+  }
+
+  fn construct_fn<K: Eq + hash::Hash, H: KeyHasher<K>>
+      (&self, hasher: H, keys: &[K])
+      -> Result<PerfectHashFn<K, H>, BuildError> {
+    let n = try!(self.num_vertices(keys.len()));
+    let (seed, mut rng) = self.rng();
+    let monitor = self.monitor();
+
+    let (mut func, iters) =
+      try!(PerfectHashFn::construct(&mut rng, hasher, keys, self.algorithm, n,
+                                    self.max_iterations, &monitor));
+    func.seed = Some(seed);
+    self.finish_fn(&mut func, keys, n, iters, &monitor);
+    Ok(func)
+  }
+
+  /// Finishes a function found on `n` vertices after `iters` attempts, and
+  /// records how its construction went.
+  fn finish_fn<K: Eq + hash::Hash, H: KeyHasher<K>>
+      (&self, func: &mut PerfectHashFn<K, H>, keys: &[K], n: uint, iters: uint,
+       monitor: &Monitor) {
+    let search_time = monitor.elapsed();
+    func.finish(keys, self.ordered, self.fingerprints, self.digests);
+
+    func.stats = Some(BuildStats {
+      iterations:  iters,
+      search_time: search_time,
+      finish_time: monitor.elapsed() - search_time,
+      vertices:    n,
+      load_factor: if n == 0 { 0.0 } else { keys.len() as f64 / n as f64 },
+      heap_size:   func.heap_size(),
+      fell_back:   false,
+    });
+  }
+
+  /// The number of graph vertices to use for `m` keys.
+  fn num_vertices(&self, m: uint) -> Result<uint, BuildError> {
+    let c =
+      match (self.space_factor, self.algorithm) {
+        // RecSplit has no slack to size.
+        (_,       RecSplit(leaf, bucket)) => return check_recsplit(leaf, bucket).map(|()| m),
+        (Some(c), _)                      => c,
+        (None,    Chm)                    => DEFAULT_SPACE_FACTOR,
+        (None,    Bdz)                    => BDZ_SPACE_FACTOR,
+        (None,    Chd(_))                 => CHD_SPACE_FACTOR,
+        (None,    PtHash(_))              => PTHASH_SPACE_FACTOR,
+        (None,    Fks)                    => FKS_SPACE_FACTOR,
+      };
+
+    if !(c > 1.0) {
+      return Err(InvalidSpaceFactor(c));
+    }
+
+    match self.algorithm {
+      Chd(lambda)    if !(lambda > 0.0) => return Err(InvalidLambda(lambda)),
+      PtHash(lambda) if !(lambda > 0.0) => return Err(InvalidLambda(lambda)),
+      _                                 => {}
+    }
+
+    Ok((c * m as f64).ceil() as uint)
+  }
+
+  /// Starts watching a construction.
+  fn monitor(&self) -> Monitor {
+    Monitor::new(self.progress.clone(), self.cancel.clone(), self.deadline)
+  }
+
+  /// The seed to build from, and an rng seeded with it.
+  ///
+  /// Even unseeded builds draw a seed, so any table can be rebuilt exactly
+  /// from the seed it reports.
+  fn rng(&self) -> (u64, Isaac64Rng) {
+    let seed =
+      match (self.seed, self.entropy) {
+        (Some(seed), _)          => seed,
+        (None,       Some(draw)) => draw(),
+        (None,       None)       => rand::task_rng().gen(),
+      };
+
+    (seed, SeedableRng::from_seed([seed].as_slice()))
+  }
+}
+
+/// Checks the `RecSplit` leaf and bucket sizes.
+fn check_recsplit(leaf: uint, bucket: uint) -> Result<(), BuildError> {
+  if 0 < leaf && leaf <= MAX_RECSPLIT_LEAF_SIZE && bucket > 0 {
+    Ok(())
+  } else {
+    Err(InvalidRecSplit(leaf, bucket))
+  }
+}
@@ -0,0 +1,188 @@
+//! The CHD construction, after Belazzougui, Botelho, and Dietzfelbinger.
+//!
+//! Keys are split into buckets of about `lambda` keys each. Every key has two
+//! hash values `f1` and `f2` into `n` slots, and each bucket gets a
+//! displacement `(d0, d1)` moving all its keys to `(f1 + d0 * f2 + d1) % n`.
+//! Buckets are placed largest first, each taking the first displacement that
+//! puts all its keys in free slots.
+//!
+//! Displacements are stored as a `u16` index per bucket, so they cost
+//! `16 / lambda` bits per key, plus 1.25 bits per slot to rank the
+//! occupied slots down to `[0, m)`.
+
+use std::num::CheckedMul;
+use std::rand;
+
+use super::KeyHasher;
+use super::{BuildError, Overflow};
+use super::{Corruption, BadLength, BadCount};
+use super::rank::RankedBits;
+
+/// Buckets that find no home among this many displacements fail the attempt.
+static MAX_DISPLACEMENTS: uint = 1 << 16;
+
+/// The displacements for one set of keys.
+#[deriving(Clone)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+pub struct Labeling {
+  /// The number of slots.
+  n:             uint,
+  /// The index of each bucket's displacement.
+  displacements: Vec<u16>,
+  /// One bit per slot, set if a key lives there.
+  used:          RankedBits,
+}
+
+/// A key's bucket, `f1`, and `f2`, from hash values `u < n * buckets` and
+/// `v < n * buckets`.
+fn split(u: uint, v: uint, n: uint) -> (uint, uint, uint) {
+  (u / n, u % n, v % n)
+}
+
+/// The slot that displacement number `l` moves a key to.
+fn displace(f1: uint, f2: uint, l: uint, n: uint) -> uint {
+  let (d0, d1) = (l / n, l % n);
+  (f1 + (d0 * f2) % n + d1) % n
+}
+
+/// Reseeds `hasher` and places the keys in `n` slots, `buckets` buckets at a
+/// time, or returns `None` if some bucket doesn't fit.
+pub fn attempt<R: rand::Rng, K, H: KeyHasher<K>>(
+    rng: &mut R, hasher: &mut H, keys: &[K], n: uint, buckets: uint)
+    -> Result<Option<Labeling>, BuildError> {
+  let span =
+    match n.checked_mul(&buckets) {
+      Some(span) => span,
+      None       => return Err(Overflow),
+    };
+  hasher.reseed(rng, keys, span);
+
+  let mut pairs = Vec::with_capacity(keys.len());
+  if !hasher.vertices_batch(keys, span, &mut pairs) {
+    return Err(Overflow);
+  }
+
+  let mut members: Vec<Vec<(uint, uint)>> = Vec::from_fn(buckets, |_| Vec::new());
+  for &(u, v) in pairs.iter() {
+    let (b, f1, f2) = split(u, v, n);
+    members.get_mut(b).push((f1, f2));
+  }
+
+  let mut order: Vec<uint> = range(0, buckets).collect();
+  order.sort_by(|&a, &b| members[b].len().cmp(&members[a].len()));
+
+  let mut taken         = Vec::from_elem(n, false);
+  let mut displacements = Vec::from_elem(buckets, 0u16);
+  let mut slots         = Vec::new();
+
+  for &b in order.iter() {
+    let bucket = members[b].as_slice();
+
+    // The rest are empty too.
+    if bucket.is_empty() {
+      break;
+    }
+
+    let mut placed = false;
+
+    for l in range(0, MAX_DISPLACEMENTS) {
+      slots.clear();
+
+      for &(f1, f2) in bucket.iter() {
+        let slot = displace(f1, f2, l, n);
+        if taken[slot] || slots.contains(&slot) {
+          break;
+        }
+        slots.push(slot);
+      }
+
+      if slots.len() == bucket.len() {
+        for &slot in slots.iter() {
+          *taken.get_mut(slot) = true;
+        }
+        *displacements.get_mut(b) = l as u16;
+        placed = true;
+        break;
+      }
+    }
+
+    if !placed {
+      return Ok(None);
+    }
+  }
+
+  Ok(Some(Labeling {
+    n:             n,
+    displacements: displacements,
+    used:          RankedBits::new(taken.as_slice()),
+  }))
+}
+
+impl Labeling {
+  /// The index `k` would have if it were a known key, or `None` if it
+  /// certainly isn't.
+  pub fn index<K, H: KeyHasher<K>>(&self, hasher: &H, k: &K) -> Option<uint> {
+    let (b, f1, f2) =
+      match hasher.vertices(k, self.hash_range()) {
+        Some((u, v)) => split(u, v, self.n),
+        None         => return None,
+      };
+
+    let l = self.displacements[b] as uint;
+
+    // No known key lands in an empty slot.
+    self.used.rank(displace(f1, f2, l, self.n))
+  }
+
+  /// Checks that there are ranks for all `n` slots, `m` of them full, and
+  /// that the range keys are hashed onto fits in a word, so `index` stays in
+  /// bounds.
+  pub fn verify(&self, m: uint) -> Result<(), Corruption> {
+    if !self.used.is_consistent() || self.used.len() != self.n {
+      return Err(BadLength("used", self.used.len()));
+    }
+    if m > 0 && self.displacements.is_empty()
+    || self.n.checked_mul(&self.displacements.len()).is_none() {
+      return Err(BadLength("displacements", self.displacements.len()));
+    }
+
+    let full = self.used.count_ones();
+    if full != m {
+      return Err(BadCount(m, full));
+    }
+    Ok(())
+  }
+
+  /// The range `hasher.vertices` is asked for.
+  pub fn hash_range(&self) -> uint {
+    self.n * self.displacements.len()
+  }
+
+  /// The number of slots keys are displaced into, before ranking.
+  pub fn num_slots(&self) -> uint {
+    self.n
+  }
+
+  /// The bytes of the displacements and ranks.
+  pub fn heap_size(&self) -> uint {
+    self.displacements.len() * 2 + self.used.heap_size()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::rand::XorShiftRng;
+  use std::uint;
+
+  use super::attempt;
+  use super::super::{Overflow, Positional};
+  use super::super::testing::keys;
+
+  #[test]
+  fn overflowing_hash_range_fails() {
+    let keys = keys(10);
+    let mut rng = XorShiftRng::new_unseeded();
+    let result = attempt(&mut rng, &mut Positional::new(), keys.as_slice(), uint::MAX / 2, 3);
+    assert!(result.err() == Some(Overflow));
+  }
+}
@@ -0,0 +1,317 @@
+//! Generates Rust source for tables that are built at compile time.
+//!
+//! Call this from a build script, write the output into `OUT_DIR`, and
+//! `include!()` it. The result is a `StaticMap` in a static, so looking
+//! things up costs nothing at startup:
+//!
+//! ```ignore
+//! // build.rs
+//! let mut out = File::create(&Path::new(os::getenv("OUT_DIR").unwrap()).join("keywords.rs"));
+//! perfect::codegen::write_map(&mut out, "KEYWORDS", "&'static str", "Token",
+//!                             vec![("if", "If"), ("else", "Else")]).unwrap();
+//!
+//! // lib.rs
+//! include!(concat!(env!("OUT_DIR"), "/keywords.rs"))
+//!
+//! fn keyword(s: &str) -> Option<Token> {
+//!   KEYWORDS.get_equiv(&s).map(|&t| t)
+//! }
+//! ```
+//!
+//! Tables are built from a fixed seed, so the same keys always produce the
+//! same source. The build machine must have the same word size as the
+//! target, since the tables are written out as `uint`s.
+//!
+//! `write_c_header` writes the same kind of table for string keys as a C
+//! header, with an inline lookup function, so C code can use it without
+//! linking this crate.
+
+use std::fmt;
+use std::hash;
+use std::io;
+use std::io::{IoError, IoResult};
+
+use super::{HashMapBuilder, BuildError, PerfectHashState, ByteCounter};
+use super::{DEFAULT_MAX_ITERATIONS, Chm, ChmLabels};
+
+/// The seed used for every generated table.
+pub static SEED: u64 = 0x5045524645435421;
+
+/// Keys that can be written out as a Rust literal.
+pub trait Literal {
+  /// Writes `self` as an expression that evaluates to an equal value.
+  fn write_literal<W: Writer>(&self, w: &mut W) -> IoResult<()>;
+}
+
+impl<'a> Literal for &'a str {
+  fn write_literal<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+    write!(w, "\"{}\"", self.escape_default())
+  }
+}
+
+macro_rules! literal_int(
+  ($t:ty) => (
+    impl Literal for $t {
+      fn write_literal<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+        write!(w, "{}{}", *self, stringify!($t))
+      }
+    }
+  )
+)
+
+literal_int!(u8)
+literal_int!(u16)
+literal_int!(u32)
+literal_int!(u64)
+literal_int!(uint)
+literal_int!(i8)
+literal_int!(i16)
+literal_int!(i32)
+literal_int!(i64)
+literal_int!(int)
+
+fn write_ints<W: Writer, T: fmt::Show>(w: &mut W, xs: &[T]) -> IoResult<()> {
+  try!(w.write_str("&["));
+  for (i, x) in xs.iter().enumerate() {
+    if i % 16 == 0 {
+      try!(w.write_str("\n    "));
+    }
+    try!(write!(w, "{}, ", x));
+  }
+  w.write_str("\n  ]")
+}
+
+/// The raw tables behind a `StaticMap`, for code generators that emit them
+/// some other way, such as the `perfect_macros` syntax extension.
+pub struct Tables<K> {
+  /// The graph labeling.
+  pub nodes:   Vec<uint>,
+  /// The positional weights, interleaved.
+  pub weights: Vec<(u32, u32)>,
+  /// The keys, in slot order. This is always the order they were given in.
+  pub keys:    Vec<K>,
+}
+
+/// Builds the tables for `keys` from the fixed `SEED`.
+pub fn build_tables<'a,
+                    K: Eq
+                     + hash::Hash
+                     + hash::Hash<PerfectHashState<'a>>
+                     + hash::Hash<ByteCounter>>
+    (keys: Vec<K>) -> Result<Tables<K>, BuildError> {
+  let func =
+    try!(HashMapBuilder::new()
+           .algorithm(Chm)
+           .seed(SEED)
+           .max_iterations(DEFAULT_MAX_ITERATIONS)
+           .build_fn(keys.as_slice()));
+
+  let nodes =
+    match func.labels {
+      ChmLabels(ref nodes) => nodes.to_vec(),
+      _                    => unreachable!(),
+    };
+
+  Ok(Tables {
+    nodes:   nodes,
+    weights: func.hasher.weights,
+    keys:    keys,
+  })
+}
+
+/// Like `build_tables`, but fails with an `IoError`, for the writers.
+fn build_tables_io<'a,
+                   K: Eq
+                    + hash::Hash
+                    + hash::Hash<PerfectHashState<'a>>
+                    + hash::Hash<ByteCounter>>
+    (keys: Vec<K>) -> IoResult<Tables<K>> {
+  build_tables(keys).map_err(|e| IoError {
+    kind:   io::OtherIoError,
+    desc:   "couldn't build a perfect table",
+    detail: Some(e.to_string()),
+  })
+}
+
+/// Writes a declaration of a `StaticMap<key_type, value_type>` called
+/// `name`. Each entry pairs a key with the source of a constant expression
+/// for its value.
+pub fn write_map<'a,
+                 W: Writer,
+                 K: Literal
+                  + Eq
+                  + hash::Hash
+                  + hash::Hash<PerfectHashState<'a>>
+                  + hash::Hash<ByteCounter>,
+                 S: Str>
+    (w: &mut W, name: &str, key_type: &str, value_type: &str,
+     entries: Vec<(K, S)>) -> IoResult<()> {
+  let mut keys   = Vec::with_capacity(entries.len());
+  let mut values = Vec::with_capacity(entries.len());
+  for (k, v) in entries.into_iter() {
+    keys.push(k);
+    values.push(v);
+  }
+
+  let tables = try!(build_tables_io(keys));
+
+  try!(writeln!(w, "// Generated by perfect::codegen. Do not edit."));
+  try!(writeln!(w, "static {}: ::perfect::StaticMap<{}, {}> = ::perfect::StaticMap {{",
+                name, key_type, value_type));
+
+  try!(w.write_str("  nodes: "));
+  try!(write_ints(w, tables.nodes.as_slice()));
+  try!(w.write_str(",\n  weights: "));
+  try!(write_ints(w, tables.weights.as_slice()));
+
+  // Slot i holds the ith key, so the keys and values go out in input order.
+  try!(w.write_str(",\n  keys: &[\n"));
+  for k in tables.keys.iter() {
+    try!(w.write_str("    "));
+    try!(k.write_literal(w));
+    try!(w.write_str(",\n"));
+  }
+
+  try!(w.write_str("  ],\n  values: &[\n"));
+  for v in values.iter() {
+    try!(writeln!(w, "    {},", v.as_slice()));
+  }
+
+  w.write_str("  ],\n};\n")
+}
+
+/// Writes `s` as a C string literal. Anything but printable ASCII is an
+/// octal escape, which unlike a hex one can't swallow the next character.
+fn write_c_string<W: Writer>(w: &mut W, s: &str) -> IoResult<()> {
+  try!(w.write_str("\""));
+  for &b in s.as_bytes().iter() {
+    match b {
+      b'"' | b'\\' => try!(write!(w, "\\{}", b as char)),
+      0x20 ... 0x7e => try!(write!(w, "{}", b as char)),
+      _             => try!(write!(w, "\\{:03o}", b)),
+    }
+  }
+  w.write_str("\"")
+}
+
+/// Writes a C header declaring the table for `entries` as `const` arrays
+/// prefixed with `name`, with a `static inline` function
+/// `const value_type *name_get(const char *key, size_t len)` that returns
+/// the value of the `len` bytes at `key`, or `NULL` for unknown keys. Each
+/// entry pairs a key with the source of a C constant expression for its
+/// value.
+///
+/// The header also defines the table's parameters: `name_N` vertices,
+/// `name_M` keys, `name_MAX_LENGTH` weights, and the `name_SEED` it was
+/// built from. The lookup hashes exactly as `StaticMap::get_equiv` does for
+/// a `&str`.
+pub fn write_c_header<'a, W: Writer, S: Str>
+    (w: &mut W, name: &str, value_type: &str, entries: Vec<(&'a str, S)>) -> IoResult<()> {
+  let mut keys   = Vec::with_capacity(entries.len());
+  let mut values = Vec::with_capacity(entries.len());
+  for (k, v) in entries.into_iter() {
+    keys.push(k);
+    values.push(v);
+  }
+
+  let tables = try!(build_tables_io(keys));
+  let n      = tables.nodes.len();
+  let m      = tables.keys.len();
+
+  try!(writeln!(w, "/* Generated by perfect::codegen. Do not edit. */"));
+  try!(writeln!(w, "#ifndef {}_H", name));
+  try!(writeln!(w, "#define {}_H\n", name));
+  try!(writeln!(w, "#include <stddef.h>"));
+  try!(writeln!(w, "#include <stdint.h>"));
+  try!(writeln!(w, "#include <string.h>\n"));
+
+  try!(writeln!(w, "#define {}_N          {}u", name, n));
+  try!(writeln!(w, "#define {}_M          {}u", name, m));
+  try!(writeln!(w, "#define {}_MAX_LENGTH {}u", name, tables.weights.len()));
+  try!(writeln!(w, "#define {}_SEED       0x{:016x}ull\n", name, SEED));
+
+  // C has no empty arrays, so empty tables get one unused element.
+  try!(write!(w, "static const uint64_t {}_nodes[] = {{", name));
+  for (i, &g) in tables.nodes.iter().enumerate() {
+    try!(write!(w, "{}{}u,", if i % 8 == 0 { "\n  " } else { " " }, g));
+  }
+  if n == 0 {
+    try!(w.write_str(" 0"));
+  }
+
+  try!(write!(w, "\n}};\n\nstatic const uint32_t {}_weights[][2] = {{", name));
+  for (i, &(w1, w2)) in tables.weights.iter().enumerate() {
+    try!(write!(w, "{}{{{}u, {}u}},", if i % 4 == 0 { "\n  " } else { " " }, w1, w2));
+  }
+  if tables.weights.is_empty() {
+    try!(w.write_str(" {0, 0}"));
+  }
+
+  // Slot i holds the ith key, so the keys and values go out in input order.
+  try!(write!(w, "\n}};\n\nstatic const char *const {}_keys[] = {{\n", name));
+  for k in tables.keys.iter() {
+    try!(w.write_str("  "));
+    try!(write_c_string(w, *k));
+    try!(w.write_str(",\n"));
+  }
+  if m == 0 {
+    try!(w.write_str("  \"\",\n"));
+  }
+
+  try!(write!(w, "}};\n\nstatic const size_t {}_key_lengths[] = {{", name));
+  for (i, k) in tables.keys.iter().enumerate() {
+    try!(write!(w, "{}{}u,", if i % 8 == 0 { "\n  " } else { " " }, k.len()));
+  }
+  if m == 0 {
+    try!(w.write_str(" 0"));
+  }
+
+  try!(write!(w, "\n}};\n\nstatic const {} {}_values[] = {{\n", value_type, name));
+  for v in values.iter() {
+    try!(writeln!(w, "  {},", v.as_slice()));
+  }
+  if m == 0 {
+    try!(w.write_str("  0,\n"));
+  }
+  try!(w.write_str("};\n\n"));
+
+  // The same sums as `PerfectHashState`, over the key's bytes and then the
+  // 0xff that ends every `str`, with the same weights for keys longer than
+  // the tables, and then the length.
+  try!(writeln!(w, "static inline uint64_t {}_mix(uint64_t z) {{", name));
+  try!(writeln!(w, "  z = (z ^ (z >> 30)) * 0xbf58476d1ce4e5b9ull;"));
+  try!(writeln!(w, "  z = (z ^ (z >> 27)) * 0x94d049bb133111ebull;"));
+  try!(writeln!(w, "  return z ^ (z >> 31);"));
+  try!(writeln!(w, "}}
+"));
+  try!(writeln!(w, "static inline const {} *{}_get(const char *key, size_t len) {{",
+                value_type, name));
+  try!(writeln!(w, "  uint64_t u = 0, v = 0, slot;"));
+  try!(writeln!(w, "  size_t i;\n"));
+  try!(writeln!(w, "  if ({}_M == 0) return NULL;\n", name));
+  try!(writeln!(w, "  if ({}_MAX_LENGTH > 0) {{", name));
+  try!(writeln!(w, "    for (i = 0; i <= len; i++) {{"));
+  try!(writeln!(w, "      uint64_t b = i < len ? (uint8_t) key[i] : 0xff;"));
+  try!(writeln!(w, "      uint64_t round = i / {}_MAX_LENGTH;", name));
+  try!(writeln!(w, "      const uint32_t *wt = {}_weights[i % {}_MAX_LENGTH];", name, name));
+  try!(writeln!(w, "      uint64_t w1 = wt[0], w2 = wt[1];"));
+  try!(writeln!(w, "      if (round > 0) {{"));
+  try!(writeln!(w, "        w1 = (uint32_t) ({0}_mix(w1 ^ round << 32) % {0}_N);", name));
+  try!(writeln!(w, "        w2 = (uint32_t) ({0}_mix(w2 ^ round << 32) % {0}_N);", name));
+  try!(writeln!(w, "      }}"));
+  try!(writeln!(w, "      u = (u + w1 * b % {0}_N) % {0}_N;", name));
+  try!(writeln!(w, "      v = (v + w2 * b % {0}_N) % {0}_N;", name));
+  try!(writeln!(w, "    }}"));
+  try!(writeln!(w, "    u = (u + {0}_mix({0}_mix({0}_weights[0][0]) + len + 1) % {0}_N) % {0}_N;",
+                name));
+  try!(writeln!(w, "    v = (v + {0}_mix({0}_mix({0}_weights[0][1]) + len + 1) % {0}_N) % {0}_N;",
+                name));
+  try!(writeln!(w, "  }}\n"));
+  try!(writeln!(w, "  slot = ({0}_nodes[u] + {0}_nodes[v]) % {0}_M;", name));
+  try!(writeln!(w, "  if ({0}_key_lengths[slot] != len \
+                    || memcmp({0}_keys[slot], key, len) != 0) return NULL;", name));
+  try!(writeln!(w, "  return &{}_values[slot];", name));
+  try!(writeln!(w, "}}\n"));
+
+  writeln!(w, "#endif /* {}_H */", name)
+}
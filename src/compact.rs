@@ -0,0 +1,149 @@
+//! A map of small `Copy` values that doesn't store its keys.
+//!
+//! For a `HashMap<String, u32>`, the keys and the hash function take many
+//! times the space of the values. A `CompactHashMap` keeps only the
+//! function, a bit per slot saying whether it has a value, and the values
+//! themselves, densely. Built with `Bdz`, a `u32` value comes to under five
+//! bytes per key:
+//!
+//! ```ignore
+//! let ids: CompactHashMap<String, u32> =
+//!   HashMapBuilder::new().algorithm(Bdz).build_compact(names.as_slice()).unwrap();
+//! ```
+//!
+//! Without the keys there's no telling an unknown key from the known key
+//! that shares its slot, unless the function was built with `fingerprints`
+//! or `digests`, which cost two or eight more bytes a key. Without them,
+//! unknown keys get some arbitrary known key's value. The map holds only
+//! known keys: nothing else can be inserted.
+
+use std::hash;
+
+use super::{PerfectHashFn, PerfectHashState, ByteCounter, KeyHasher, Positional};
+use super::{HashMapBuilder, BuildError, MemoryBreakdown};
+use super::slots::Slots;
+
+/// A hashtable from a fixed set of keys to `Copy` values, without the keys.
+pub struct CompactHashMap<K, V, H = Positional> {
+  func:  PerfectHashFn<K, H>,
+  table: Slots<V>,
+  /// The number of full slots.
+  size:  uint,
+}
+
+impl HashMapBuilder {
+  /// Builds an empty compact map whose known keys are `keys`.
+  pub fn build_compact<'a,
+                       K: Eq
+                        + hash::Hash
+                        + hash::Hash<PerfectHashState<'a>>
+                        + hash::Hash<ByteCounter>,
+                       V: Copy>
+      (&self, keys: &[K]) -> Result<CompactHashMap<K, V>, BuildError> {
+    self.build_compact_with_hasher(Positional::new(), keys)
+  }
+
+  /// Like `build_compact`, but hashes the keys with `hasher` instead of the
+  /// default `Positional` hasher.
+  pub fn build_compact_with_hasher<K: Eq + hash::Hash, V: Copy, H: KeyHasher<K>>
+      (&self, hasher: H, keys: &[K]) -> Result<CompactHashMap<K, V, H>, BuildError> {
+    let func = try!(self.build_fn_with_hasher(hasher, keys));
+    Ok(CompactHashMap::from_fn(func))
+  }
+}
+
+impl<K: Eq + hash::Hash, V: Copy, H: KeyHasher<K>> CompactHashMap<K, V, H> {
+  /// An empty map over the keys `func` was built from.
+  pub fn from_fn(func: PerfectHashFn<K, H>) -> CompactHashMap<K, V, H> {
+    let m = func.len();
+    CompactHashMap { func: func, table: Slots::new(m), size: 0 }
+  }
+
+  /// Returns the value corresponding to the key.
+  pub fn get(&self, k: &K) -> Option<V> {
+    match self.func.try_hash(k) {
+      Some(i) => self.table.get(i).map(|&v| v),
+      None    => None,
+    }
+  }
+
+  /// Returns true if the map contains a value for the specified key.
+  pub fn contains_key(&self, k: &K) -> bool {
+    match self.func.try_hash(k) {
+      Some(i) => self.table.is_full(i),
+      None    => false,
+    }
+  }
+
+  /// Sets the value of `k`, returning the old one. Keys the function can
+  /// tell aren't known are handed back, along with `v`.
+  pub fn insert(&mut self, k: K, v: V) -> Result<Option<V>, (K, V)> {
+    match self.func.try_hash(&k) {
+      Some(i) => {
+        let old = self.table.replace(i, Some(v));
+        if old.is_none() {
+          self.size += 1;
+        }
+        Ok(old)
+      }
+      None => Err((k, v)),
+    }
+  }
+
+  /// Removes a key's value, returning it if there was one.
+  pub fn remove(&mut self, k: &K) -> Option<V> {
+    match self.func.try_hash(k) {
+      Some(i) => {
+        let old = self.table.take(i);
+        if old.is_some() {
+          self.size -= 1;
+        }
+        old
+      }
+      None => None,
+    }
+  }
+
+  /// The bytes each part of the map takes up. There are no keys or backup
+  /// store, so those are always zero.
+  pub fn memory_usage(&self) -> MemoryBreakdown {
+    MemoryBreakdown {
+      hasher: self.func.hasher.heap_size(),
+      labels: self.func.labels_size(),
+      slots:  self.table.heap_size(),
+      keys:   0,
+      backup: 0,
+    }
+  }
+}
+
+impl<K, V, H> CompactHashMap<K, V, H> {
+  /// Returns the number of elements in the map.
+  pub fn len(&self) -> uint {
+    self.size
+  }
+
+  /// Returns true if the map contains no elements.
+  pub fn is_empty(&self) -> bool {
+    self.size == 0
+  }
+
+  /// The number of known keys, which is how many values the map can hold.
+  pub fn capacity(&self) -> uint {
+    self.table.len()
+  }
+
+  /// The hash function, for mapping keys to the slots `get_index` takes.
+  pub fn hash_fn(&self) -> &PerfectHashFn<K, H> {
+    &self.func
+  }
+
+  /// The value in slot `i`, if it has one.
+  pub fn get_index(&self, i: uint) -> Option<V> where V: Copy {
+    if i < self.table.len() {
+      self.table.get(i).map(|&v| v)
+    } else {
+      None
+    }
+  }
+}
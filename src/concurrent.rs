@@ -0,0 +1,133 @@
+//! A map for read-mostly caches shared between tasks.
+//!
+//! A `ConcurrentHashMap` serves known keys from a `FrozenHashMap`, which
+//! nothing ever writes, so looking them up takes no locks at all. Unknown
+//! keys go into a backup table split into `BACKUP_SHARDS` std `HashMap`s,
+//! each behind its own `Mutex`, so tasks inserting different keys rarely
+//! wait on each other. Share one through an `Arc`:
+//!
+//! ```ignore
+//! let cache = Arc::new(ConcurrentHashMap::new(map));
+//! let other = cache.clone();
+//! spawn(proc() { other.insert("unknown".to_string(), 3); });
+//! cache.get(&"known".to_string());
+//! ```
+//!
+//! The values of known keys are as fixed as the keys themselves: `insert`
+//! only takes unknown ones.
+
+use std::collections;
+use std::hash;
+use std::hash::sip;
+use std::rand;
+use std::sync::Mutex;
+
+use super::{HashMap, FrozenHashMap, KeyHasher, BackupStore, Positional};
+
+/// The number of separately locked parts of the backup table.
+pub static BACKUP_SHARDS: uint = 16;
+
+/// Known keys without locks, and unknown keys behind sharded locks.
+pub struct ConcurrentHashMap<K, V, H = Positional> {
+  table:  FrozenHashMap<K, V, H>,
+  /// The keys of the SipHash that picks each unknown key's shard.
+  k0:     u64,
+  k1:     u64,
+  shards: Vec<Mutex<collections::HashMap<K, V>>>,
+}
+
+impl<K: Send + Eq + hash::Hash, V: Send, H: KeyHasher<K>> ConcurrentHashMap<K, V, H> {
+  /// Freezes `map`'s known keys, and moves its backup entries into the
+  /// sharded table.
+  pub fn new<B: BackupStore<K, V>>(mut map: HashMap<K, V, H, B>) -> ConcurrentHashMap<K, V, H> {
+    let entries = map.backup.drain();
+
+    let concurrent = ConcurrentHashMap {
+      table:  map.freeze(),
+      k0:     rand::random(),
+      k1:     rand::random(),
+      shards: Vec::from_fn(BACKUP_SHARDS, |_| Mutex::new(collections::HashMap::new())),
+    };
+
+    for (k, v) in entries.into_iter() {
+      concurrent.shard(&k).lock().insert(k, v);
+    }
+    concurrent
+  }
+
+  /// The part of the backup table `k` belongs in.
+  fn shard(&self, k: &K) -> &Mutex<collections::HashMap<K, V>> {
+    let h = sip::hash_with_keys(self.k0, self.k1, k);
+    &self.shards[(h % BACKUP_SHARDS as u64) as uint]
+  }
+
+  /// The known keys, and their values, without the backup table.
+  pub fn known(&self) -> &FrozenHashMap<K, V, H> {
+    &self.table
+  }
+
+  /// Returns a reference to the value of a known key, without taking any
+  /// locks. Unknown keys get `None`, even if the backup table has them.
+  pub fn get_known(&self, k: &K) -> Option<&V> {
+    if self.is_known(k) {
+      self.table.get(k)
+    } else {
+      None
+    }
+  }
+
+  /// Returns true if `k` is a known key, without taking any locks.
+  pub fn is_known(&self, k: &K) -> bool {
+    self.table.as_map().index_of(k).is_some()
+  }
+
+  /// Returns a copy of the value corresponding to the key. Only unknown
+  /// keys take a lock, on their shard of the backup table.
+  pub fn get(&self, k: &K) -> Option<V> where V: Clone {
+    if self.is_known(k) {
+      self.table.get(k).map(|v| v.clone())
+    } else {
+      self.shard(k).lock().find(k).map(|v| v.clone())
+    }
+  }
+
+  /// Returns true if the map contains a value for the specified key.
+  pub fn contains_key(&self, k: &K) -> bool {
+    if self.is_known(k) {
+      self.table.contains_key(k)
+    } else {
+      self.shard(k).lock().contains_key(k)
+    }
+  }
+
+  /// Inserts an unknown key's value, returning its old value if it had one.
+  /// Known keys can't change, so they're handed back.
+  pub fn insert(&self, k: K, v: V) -> Result<Option<V>, (K, V)> {
+    if self.is_known(&k) {
+      return Err((k, v));
+    }
+
+    Ok(self.shard(&k).lock().swap(k, v))
+  }
+
+  /// Removes an unknown key, returning its value if it had one. Known keys
+  /// stay put.
+  pub fn remove(&self, k: &K) -> Option<V> {
+    if self.is_known(k) {
+      None
+    } else {
+      self.shard(k).lock().pop(k)
+    }
+  }
+
+  /// Returns the number of elements in the map. Each shard of the backup
+  /// table is counted under its lock, but they aren't all counted at once.
+  pub fn len(&self) -> uint {
+    self.table.len() + self.shards.iter().fold(0, |n, s| n + s.lock().len())
+  }
+
+  /// Returns true if the map contains no elements.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
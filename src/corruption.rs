@@ -0,0 +1,48 @@
+//! What `verify` found wrong with a table.
+//!
+//! Loaders reject inconsistent tables outright, but a table that's been
+//! mmapped, or read by hand from some other store, is only as good as the
+//! bytes behind it. `HashMap::verify` and `HashMapRef::verify` walk every
+//! table and say exactly where the first problem is.
+
+#[cfg(not(feature = "std"))] use core::prelude::*;
+
+use std::fmt;
+
+/// The first problem `verify` found.
+#[deriving(Clone, PartialEq)]
+pub enum Corruption {
+  /// The named table can't have this many entries, given the others.
+  BadLength(&'static str, uint),
+  /// Entry `i` of the named table is this value, which is out of bounds.
+  OutOfBounds(&'static str, uint, uint),
+  /// The `KeyHasher`'s tables don't fit the number of vertices.
+  BadHasher,
+  /// The key stored in this slot hashes to this other slot, or to none.
+  MisplacedKey(uint, Option<uint>),
+  /// The map counts this many full slots, but this many are full.
+  BadCount(uint, uint),
+  /// A key in the backup store is also a known key.
+  KnownKeyInBackup,
+}
+
+impl fmt::Show for Corruption {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      BadLength(table, len) =>
+        write!(f, "table {} can't have {} entries", table, len),
+      OutOfBounds(table, i, value) =>
+        write!(f, "entry {} of table {} is out of bounds at {}", i, table, value),
+      BadHasher =>
+        write!(f, "the hasher doesn't fit the node table"),
+      MisplacedKey(slot, Some(found)) =>
+        write!(f, "the key in slot {} hashes to slot {}", slot, found),
+      MisplacedKey(slot, None) =>
+        write!(f, "the key in slot {} doesn't hash to any slot", slot),
+      BadCount(expected, found) =>
+        write!(f, "{} slots should be full, but {} are", expected, found),
+      KnownKeyInBackup =>
+        write!(f, "a known key is in the backup store"),
+    }
+  }
+}
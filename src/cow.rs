@@ -0,0 +1,175 @@
+//! Maps whose snapshots share everything that hasn't changed since.
+//!
+//! A `CowHashMap` keeps the function and the known keys, which never change,
+//! behind `Arc`s, and splits the values into pages of `PAGE_SLOTS` slots,
+//! each behind its own `Arc`. `snapshot` copies only those pointers. The
+//! first write to a shared page copies that page, and the backup table the
+//! first time it's written, so a snapshot costs a pointer per page up
+//! front and a page per slot written after:
+//!
+//! ```ignore
+//! let mut live = map.into_cow();
+//! let before   = live.snapshot();
+//! live.insert("timeout", 30);
+//! assert!(before.get(&"timeout") != live.get(&"timeout"));
+//! ```
+
+use std::collections;
+use std::hash;
+use std::mem;
+use std::sync::Arc;
+
+use super::{HashMap, PerfectHashFn, KeyHasher, BackupStore, Positional};
+
+/// The number of slots in each separately copied page of values.
+pub static PAGE_SLOTS: uint = 64;
+
+/// A map with cheap snapshots, from `HashMap::into_cow`.
+pub struct CowHashMap<K, V, H = Positional> {
+  func:   Arc<PerfectHashFn<K, H>>,
+  /// The key in each slot.
+  keys:   Arc<Vec<K>>,
+  /// The values, `PAGE_SLOTS` slots to a page.
+  pages:  Vec<Arc<Vec<Option<V>>>>,
+  /// The number of full slots.
+  size:   uint,
+  backup: Arc<collections::HashMap<K, V>>,
+}
+
+impl<K: Send + Sync + Eq + hash::Hash, V: Send + Sync, H: Send + Sync, B: BackupStore<K, V>>
+    HashMap<K, V, H, B> {
+  /// Moves the map's contents into a `CowHashMap`, leaving the backup store
+  /// for a std `HashMap`.
+  pub fn into_cow(mut self) -> CowHashMap<K, V, H> {
+    let mut backup = collections::HashMap::with_capacity(self.backup.len());
+    for (k, v) in self.backup.drain().into_iter() {
+      backup.insert(k, v);
+    }
+
+    let mut pages = Vec::with_capacity((self.keys.len() + PAGE_SLOTS - 1) / PAGE_SLOTS);
+    let mut page  = Vec::with_capacity(PAGE_SLOTS);
+    for v in self.table.into_options().into_iter() {
+      page.push(v);
+      if page.len() == PAGE_SLOTS {
+        pages.push(Arc::new(page));
+        page = Vec::with_capacity(PAGE_SLOTS);
+      }
+    }
+    if !page.is_empty() {
+      pages.push(Arc::new(page));
+    }
+
+    CowHashMap {
+      func:   Arc::new(self.func),
+      keys:   Arc::new(self.keys),
+      pages:  pages,
+      size:   self.size,
+      backup: Arc::new(backup),
+    }
+  }
+}
+
+impl<K: Send + Sync, V: Send + Sync, H: Send + Sync> CowHashMap<K, V, H> {
+  /// A copy of the map as it is now, which later writes to either map
+  /// don't affect. Nothing is copied but a pointer per page.
+  pub fn snapshot(&self) -> CowHashMap<K, V, H> {
+    CowHashMap {
+      func:   self.func.clone(),
+      keys:   self.keys.clone(),
+      pages:  self.pages.clone(),
+      size:   self.size,
+      backup: self.backup.clone(),
+    }
+  }
+
+  /// Returns the number of elements in the map.
+  pub fn len(&self) -> uint {
+    self.size + self.backup.len()
+  }
+
+  /// Returns true if the map contains no elements.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+impl<K: Send + Sync, V: Send + Sync, H: Send + Sync> Clone for CowHashMap<K, V, H> {
+  fn clone(&self) -> CowHashMap<K, V, H> {
+    self.snapshot()
+  }
+}
+
+impl<K: Send + Sync + Clone + Eq + hash::Hash,
+     V: Send + Sync + Clone,
+     H: Send + Sync + KeyHasher<K>>
+    CowHashMap<K, V, H> {
+  fn find_slot(&self, k: &K) -> Option<uint> {
+    match self.func.try_hash(k) {
+      Some(i) if self.keys[i] == *k => Some(i),
+      _                             => None,
+    }
+  }
+
+  /// The slot `i` of a page that isn't shared, copying it first if it was.
+  fn slot_mut<'a>(&'a mut self, i: uint) -> &'a mut Option<V> {
+    self.pages.get_mut(i / PAGE_SLOTS).make_unique().get_mut(i % PAGE_SLOTS)
+  }
+
+  /// Returns a reference to the value corresponding to the key.
+  pub fn get<'a>(&'a self, k: &K) -> Option<&'a V> {
+    match self.find_slot(k) {
+      Some(i) => self.pages[i / PAGE_SLOTS][i % PAGE_SLOTS].as_ref(),
+      None    => self.backup.find(k),
+    }
+  }
+
+  /// Returns a mutable reference to the value corresponding to the key,
+  /// copying its page first if a snapshot shares it.
+  pub fn get_mut<'a>(&'a mut self, k: &K) -> Option<&'a mut V> {
+    match self.find_slot(k) {
+      Some(i) => self.slot_mut(i).as_mut(),
+      None    =>
+        if self.backup.contains_key(k) {
+          self.backup.make_unique().find_mut(k)
+        } else {
+          None
+        },
+    }
+  }
+
+  /// Returns true if the map contains a value for the specified key.
+  pub fn contains_key(&self, k: &K) -> bool {
+    self.get(k).is_some()
+  }
+
+  /// Inserts a key-value pair into the map, returning the old value if the
+  /// key was already present.
+  pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+    match self.find_slot(&k) {
+      Some(i) => {
+        let old = mem::replace(self.slot_mut(i), Some(v));
+        if old.is_none() {
+          self.size += 1;
+        }
+        old
+      }
+      None => self.backup.make_unique().swap(k, v),
+    }
+  }
+
+  /// Removes a key from the map, returning its value if it was present.
+  /// Nothing is copied if it wasn't.
+  pub fn remove(&mut self, k: &K) -> Option<V> {
+    if !self.contains_key(k) {
+      return None;
+    }
+
+    match self.find_slot(k) {
+      Some(i) => {
+        self.size -= 1;
+        self.slot_mut(i).take()
+      }
+      None => self.backup.make_unique().pop(k),
+    }
+  }
+}
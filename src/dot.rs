@@ -0,0 +1,73 @@
+//! Graphviz output of the CHM key graph, behind the `dot` feature.
+//!
+//! CHM succeeds when the graph with an edge `(f1, f2)` per key is acyclic.
+//! When it keeps failing, looking at the cycles usually says why: a few
+//! keys that always collide, or a hasher that spreads the keys badly.
+//! `write_attempt` draws one candidate's graph with each edge labeled by
+//! its key, and the edges that close a cycle in red:
+//!
+//! ```ignore
+//! let mut file = File::create(&Path::new("attempt.dot"));
+//! let n = (perfect::builder::DEFAULT_SPACE_FACTOR * keys.len() as f64).ceil() as uint;
+//! dot::write_attempt(&mut file, &mut rand::task_rng(), Positional::new(), keys, n);
+//! ```
+//!
+//! Then `dot -Tsvg attempt.dot` renders it. Only vertices with an edge are
+//! drawn.
+
+use std::fmt;
+use std::io;
+use std::io::{IoError, IoResult};
+use std::rand::Rng;
+
+use super::{DisjointSets, KeyHasher};
+
+/// `s` inside a DOT string literal.
+fn escape(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '"' | '\\' => { out.push('\\'); out.push(c); }
+      '\n'       => out.push_str("\\n"),
+      _          => out.push(c),
+    }
+  }
+  out
+}
+
+/// Reseeds `hasher` as a CHM attempt over `keys` with `n` vertices would,
+/// and writes the resulting graph to `w` as DOT. Returns true if the graph
+/// came out acyclic, so the attempt would have succeeded.
+pub fn write_attempt<W: Writer, R: Rng, K: fmt::Show, H: KeyHasher<K>>(
+    w: &mut W, rng: &mut R, mut hasher: H, keys: &[K], n: uint) -> IoResult<bool> {
+  hasher.reseed(rng, keys, n);
+
+  let mut sets    = DisjointSets::new(n);
+  let mut acyclic = true;
+
+  try!(writeln!(w, "graph perfect {{"));
+  try!(writeln!(w, "  // {} keys on {} vertices", keys.len(), n));
+
+  for k in keys.iter() {
+    let (f1, f2) =
+      match hasher.vertices(k, n) {
+        Some(uv) => uv,
+        None     => return Err(IoError {
+          kind:   io::OtherIoError,
+          desc:   "a key can't be hashed onto this many vertices",
+          detail: Some(k.to_string()),
+        }),
+      };
+
+    let label = escape(k.to_string().as_slice());
+    if sets.union(f1, f2) {
+      try!(writeln!(w, "  v{} -- v{} [label=\"{}\"];", f1, f2, label));
+    } else {
+      acyclic = false;
+      try!(writeln!(w, "  v{} -- v{} [label=\"{}\", color=red];", f1, f2, label));
+    }
+  }
+
+  try!(writeln!(w, "}}"));
+  Ok(acyclic)
+}
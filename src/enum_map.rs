@@ -0,0 +1,199 @@
+//! Maps keyed by fieldless enums.
+//!
+//! An enum with `n` variants numbered `0` to `n - 1` is already a minimal
+//! perfect hash of itself, so an `EnumMap` skips construction and hashing
+//! and indexes its slots by discriminant. It has the same interface as
+//! `HashMap`, less everything about unknown keys, since there are none.
+//!
+//! `enum_key!` declares the enum and implements `EnumKey` for it, so the
+//! numbering can't go wrong:
+//!
+//! ```ignore
+//! #[phase(plugin, link)] extern crate perfect;
+//!
+//! enum_key! {
+//!   #[deriving(Show)]
+//!   pub enum Color { Red, Green, Blue }
+//! }
+//!
+//! let mut names = perfect::EnumMap::new();
+//! names.insert(Green, "green");
+//! ```
+
+use std::iter;
+use std::kinds::marker;
+use std::ops;
+use std::slice;
+
+use slots;
+use slots::Slots;
+
+/// Fieldless enums whose discriminants run from zero up, without gaps.
+/// Implement it with `enum_key!`.
+pub trait EnumKey {
+  /// Every variant, in discriminant order.
+  fn variants() -> &'static [Self];
+
+  /// The discriminant of `self`, which is its index in `variants`.
+  fn discriminant(&self) -> uint;
+}
+
+/// Declares a fieldless enum and implements `EnumKey` for it.
+#[macro_export]
+macro_rules! enum_key(
+  ($(#[$attr:meta])* pub enum $name:ident { $($variant:ident),+ }) => (
+    $(#[$attr])*
+    pub enum $name { $($variant),+ }
+    enum_key!(impl $name { $($variant),+ })
+  );
+  ($(#[$attr:meta])* enum $name:ident { $($variant:ident),+ }) => (
+    $(#[$attr])*
+    enum $name { $($variant),+ }
+    enum_key!(impl $name { $($variant),+ })
+  );
+  (impl $name:ident { $($variant:ident),+ }) => (
+    impl ::perfect::EnumKey for $name {
+      fn variants() -> &'static [$name] {
+        static VARIANTS: &'static [$name] = &[$($variant),+];
+        VARIANTS
+      }
+
+      fn discriminant(&self) -> uint {
+        *self as uint
+      }
+    }
+  );
+)
+
+/// A map with a slot for every variant of `K`.
+pub struct EnumMap<K, V> {
+  table:  Slots<V>,
+  /// The number of full slots.
+  size:   uint,
+  marker: marker::CovariantType<K>,
+}
+
+impl<K: EnumKey + 'static, V> EnumMap<K, V> {
+  /// An empty map.
+  pub fn new() -> EnumMap<K, V> {
+    let variants: &'static [K] = EnumKey::variants();
+
+    EnumMap {
+      table:  Slots::new(variants.len()),
+      size:   0,
+      marker: marker::CovariantType,
+    }
+  }
+
+  /// Returns a reference to the value corresponding to the key.
+  pub fn get(&self, k: &K) -> Option<&V> {
+    self.table.get(k.discriminant())
+  }
+
+  /// Returns a mutable reference to the value corresponding to the key.
+  pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+    self.table.get_mut(k.discriminant())
+  }
+
+  /// Returns true if the map contains a value for the specified key.
+  pub fn contains_key(&self, k: &K) -> bool {
+    self.table.is_full(k.discriminant())
+  }
+
+  /// Inserts a key-value pair into the map, returning the old value if the
+  /// key was already present.
+  pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+    let old = self.table.replace(k.discriminant(), Some(v));
+    if old.is_none() {
+      self.size += 1;
+    }
+    old
+  }
+
+  /// Removes a key from the map, returning its value if it was present.
+  pub fn remove(&mut self, k: &K) -> Option<V> {
+    let old = self.table.take(k.discriminant());
+    if old.is_some() {
+      self.size -= 1;
+    }
+    old
+  }
+
+  /// Removes every value.
+  pub fn clear(&mut self) {
+    self.table.clear();
+    self.size = 0;
+  }
+
+  /// An iterator visiting all key-value pairs in discriminant order.
+  pub fn iter<'a>(&'a self) -> Entries<'a, K, V> {
+    let variants: &'static [K] = EnumKey::variants();
+    Entries { inner: variants.iter().zip(self.table.iter()) }
+  }
+
+  /// An iterator visiting all keys in discriminant order.
+  pub fn keys<'a>(&'a self) -> Keys<'a, K, V> {
+    self.iter().map(|(k, _v)| k)
+  }
+
+  /// An iterator visiting all values in discriminant order.
+  pub fn values<'a>(&'a self) -> Values<'a, K, V> {
+    self.iter().map(|(_k, v)| v)
+  }
+}
+
+impl<K, V> EnumMap<K, V> {
+  /// Returns the number of elements in the map.
+  pub fn len(&self) -> uint {
+    self.size
+  }
+
+  /// Returns true if the map contains no elements.
+  pub fn is_empty(&self) -> bool {
+    self.size == 0
+  }
+}
+
+impl<K: EnumKey + 'static, V> ops::Index<K, V> for EnumMap<K, V> {
+  fn index<'a>(&'a self, k: &K) -> &'a V {
+    match self.get(k) {
+      Some(v) => v,
+      None    => fail!("perfect::EnumMap: key not present"),
+    }
+  }
+}
+
+impl<K: EnumKey + 'static, V> ops::IndexMut<K, V> for EnumMap<K, V> {
+  fn index_mut<'a>(&'a mut self, k: &K) -> &'a mut V {
+    match self.get_mut(k) {
+      Some(v) => v,
+      None    => fail!("perfect::EnumMap: key not present"),
+    }
+  }
+}
+
+/// EnumMap iterator.
+pub struct Entries<'a, K: 'static, V: 'a> {
+  inner: iter::Zip<slice::Items<'static, K>, slots::Items<'a, V>>,
+}
+
+/// EnumMap keys iterator.
+pub type Keys<'a, K, V> =
+  iter::Map<'a, (&'a K, &'a V), &'a K, Entries<'a, K, V>>;
+
+/// EnumMap values iterator.
+pub type Values<'a, K, V> =
+  iter::Map<'a, (&'a K, &'a V), &'a V, Entries<'a, K, V>>;
+
+impl<'a, K, V> Iterator<(&'a K, &'a V)> for Entries<'a, K, V> {
+  fn next(&mut self) -> Option<(&'a K, &'a V)> {
+    for (k, v) in self.inner.by_ref() {
+      match v {
+        Some(v) => return Some((k, v)),
+        None    => {}
+      }
+    }
+
+    None
+  }
+}
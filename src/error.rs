@@ -0,0 +1,106 @@
+//! One error type for everything the crate can fail at.
+//!
+//! Construction fails with a `BuildError`, loading a table with an
+//! `IoError`, and verifying one with a `Corruption`. An application that
+//! does all three can `try!` each into a `perfect::Error`, which wraps
+//! whichever it was and implements `std::error::Error`:
+//!
+//! ```ignore
+//! fn load(path: &Path) -> Result<HashMap<String, uint>, perfect::Error> {
+//!   let map: HashMap<String, uint> = try!(HashMap::read_from(&mut try!(File::open(path))));
+//!   try!(map.verify());
+//!   Ok(map)
+//! }
+//! ```
+
+use std::error;
+use std::error::FromError;
+use std::fmt;
+use std::io::IoError;
+
+use super::{BuildError, Corruption};
+
+/// A failure anywhere in the crate.
+#[deriving(Clone, PartialEq)]
+pub enum Error {
+  /// Construction failed.
+  Build(BuildError),
+  /// Reading or writing a table failed, or what was read isn't a table.
+  Io(IoError),
+  /// A table's parts don't agree with each other.
+  Corrupt(Corruption),
+}
+
+impl fmt::Show for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Build(ref err)   => write!(f, "building a perfect hash table: {}", err),
+      Io(ref err)      => write!(f, "loading a perfect hash table: {}", err),
+      Corrupt(ref err) => write!(f, "corrupt perfect hash table: {}", err),
+    }
+  }
+}
+
+impl error::Error for Error {
+  fn description(&self) -> &str {
+    match *self {
+      Build(_)    => "construction failed",
+      Io(ref err) => err.desc,
+      Corrupt(_)  => "the table is corrupt",
+    }
+  }
+
+  fn detail(&self) -> Option<String> {
+    match *self {
+      Build(ref err)   => Some(err.to_string()),
+      Io(ref err)      => err.detail.clone(),
+      Corrupt(ref err) => Some(err.to_string()),
+    }
+  }
+
+  fn cause(&self) -> Option<&error::Error> {
+    match *self {
+      Build(ref err)   => Some(err as &error::Error),
+      Io(ref err)      => Some(err as &error::Error),
+      Corrupt(ref err) => Some(err as &error::Error),
+    }
+  }
+}
+
+impl error::Error for BuildError {
+  fn description(&self) -> &str {
+    "construction failed"
+  }
+
+  fn detail(&self) -> Option<String> {
+    Some(self.to_string())
+  }
+}
+
+impl error::Error for Corruption {
+  fn description(&self) -> &str {
+    "the table is corrupt"
+  }
+
+  fn detail(&self) -> Option<String> {
+    Some(self.to_string())
+  }
+}
+
+impl FromError<BuildError> for Error {
+  fn from_error(err: BuildError) -> Error {
+    Build(err)
+  }
+}
+
+impl FromError<IoError> for Error {
+  fn from_error(err: IoError) -> Error {
+    Io(err)
+  }
+}
+
+impl FromError<Corruption> for Error {
+  fn from_error(err: Corruption) -> Error {
+    Corrupt(err)
+  }
+}
@@ -0,0 +1,108 @@
+//! A C interface to tables with byte string keys, behind the `ffi` feature.
+//!
+//! Tables map byte strings to `uint64_t`s and are hashed with `Bytes`, so C
+//! and C++ callers get the same functions as Rust ones without
+//! reimplementing anything. Build the crate as a `staticlib` or `dylib`
+//! with the `ffi` feature and include `include/perfect.h`:
+//!
+//! ```text
+//! const uint8_t *keys[] = { (const uint8_t *) "if", (const uint8_t *) "else" };
+//! size_t         lens[] = { 2, 4 };
+//! uint64_t     values[] = { 1, 2 };
+//!
+//! perfect_table *table = perfect_build(keys, lens, values, 2);
+//! uint64_t value;
+//! if (perfect_lookup(table, (const uint8_t *) "else", 4, &value)) { ... }
+//! perfect_free(table);
+//! ```
+//!
+//! Construction errors come back as a null table, and none of these
+//! functions fail on valid arguments. Failures aren't caught, though, since
+//! `task::try` needs a Rust runtime the C caller may not have: a bug that
+//! fails here aborts a thread without a runtime, and unwinding into C from
+//! one with a runtime is undefined.
+
+use libc::{c_int, size_t};
+use std::mem;
+use std::ptr;
+use std::raw;
+use std::rand::{OsRng, Rng};
+
+use super::{HashMap, HashMapBuilder, Bytes};
+
+/// The table behind a `perfect_table *`.
+pub type Table = HashMap<Vec<u8>, u64, Bytes>;
+
+/// The `len` bytes at `ptr`, borrowed for as long as the caller says.
+unsafe fn bytes<'a>(ptr: *const u8, len: size_t) -> &'a [u8] {
+  mem::transmute(raw::Slice { data: ptr, len: len as uint })
+}
+
+/// Builds a table mapping each of the `count` keys, the `lens[i]` bytes at
+/// `keys[i]`, to `values[i]`. The keys are copied. The build is seeded from
+/// the OS's randomness rather than the task's rng, which needs a runtime.
+///
+/// Returns null if two keys are equal, or if there's no OS randomness: see
+/// `perfect_build_seeded`. Free the table with `perfect_free`.
+#[no_mangle]
+pub unsafe extern "C" fn perfect_build(keys: *const *const u8, lens: *const size_t,
+                                       values: *const u64, count: size_t) -> *mut Table {
+  let seed =
+    match OsRng::new() {
+      Ok(mut rng) => rng.gen(),
+      Err(_)      => return ptr::null_mut(),
+    };
+
+  perfect_build_seeded(keys, lens, values, count, seed)
+}
+
+/// Like `perfect_build`, but seeded with `seed`, so the same keys always
+/// build the same table, and targets without OS randomness can build one.
+/// The table's backup store is keyed from the seed too, so nothing here
+/// touches task-local state.
+#[no_mangle]
+pub unsafe extern "C" fn perfect_build_seeded(keys: *const *const u8, lens: *const size_t,
+                                              values: *const u64, count: size_t, seed: u64)
+    -> *mut Table {
+  let count = count as uint;
+  let mut known = Vec::with_capacity(count);
+  for i in range(0, count) {
+    let i = i as int;
+    known.push(bytes(*keys.offset(i), *lens.offset(i)).to_vec());
+  }
+
+  let mut map: Table =
+    match HashMapBuilder::new().seed(seed).build_with_hasher(Bytes::new(), known.clone()) {
+      Ok(map) => map,
+      Err(_)  => return ptr::null_mut(),
+    };
+
+  for (i, k) in known.into_iter().enumerate() {
+    map.insert(k, *values.offset(i as int));
+  }
+
+  mem::transmute(box map)
+}
+
+/// Looks up the `len` bytes at `key`. If they're one of the table's keys,
+/// stores its value in `*value` and returns 1. Otherwise returns 0 and
+/// leaves `*value` alone.
+#[no_mangle]
+pub unsafe extern "C" fn perfect_lookup(table: *const Table, key: *const u8, len: size_t,
+                                        value: *mut u64) -> c_int {
+  match (*table).get_equiv(&bytes(key, len)) {
+    Some(&v) => {
+      *value = v;
+      1
+    }
+    None => 0,
+  }
+}
+
+/// Frees a table from `perfect_build`. Does nothing if `table` is null.
+#[no_mangle]
+pub unsafe extern "C" fn perfect_free(table: *mut Table) {
+  if !table.is_null() {
+    let _: Box<Table> = mem::transmute(table);
+  }
+}
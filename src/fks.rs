@@ -0,0 +1,344 @@
+//! The two-level construction of Fredman, Komlós, and Szemerédi.
+//!
+//! Keys are split into about as many buckets as there are keys. A bucket of
+//! `b` keys gets `b * b` slots of its own, and a seed that sends its keys to
+//! distinct ones, which the first seed tried does more often than not. The
+//! slots are ranked down to `[0, m)`, like CHD's.
+//!
+//! Every bucket's seed is found independently of the others, so a bucket
+//! that doesn't fit is retried on its own, and the whole attempt only fails
+//! if the buckets need more than `n` slots between them. For the same reason
+//! a rebuild can keep the hasher, and the seed of every bucket whose keys it
+//! still sends to distinct slots, and search again only for the others. That
+//! locality costs space: sixteen bits per bucket to find its slots, a couple
+//! of bits per key for the seeds, and about two and a half for ranking the
+//! slots.
+
+use std::mem;
+use std::rand;
+
+use super::{KeyHasher, mix};
+use super::{BuildError, Overflow};
+use super::{Corruption, BadLength, BadCount};
+use super::bits::{BitWriter, read_bits, holds_fields};
+use super::rank::RankedBits;
+
+/// The range keys are hashed onto. Both halves of a key's vertices make up
+/// its 62-bit fingerprint, and the first also picks its bucket.
+static HASH_RANGE: uint = 1 << 31;
+
+/// Buckets are grouped this many at a time, with a word for the first slot
+/// of each group and sixteen bits for each bucket after that.
+static GROUP: uint = 64;
+
+/// The most keys a bucket can have, so a group's slots fit in sixteen bits.
+static MAX_BUCKET: uint = 31;
+
+/// Buckets that find no seed below this fail the attempt.
+static MAX_SEED: u64 = 1 << 16;
+
+/// The seeds for one set of keys.
+#[deriving(Clone)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+pub struct Labeling {
+  buckets: uint,
+  /// The first slot of each group of buckets.
+  groups:  Vec<uint>,
+  /// The first slot of each bucket, after its group's. There's one past the
+  /// last bucket, for its end.
+  starts:  Vec<u16>,
+  /// The bits of each seed.
+  width:   uint,
+  seeds:   Vec<u64>,
+  /// One bit per slot, set if a key lives there.
+  used:    RankedBits,
+}
+
+/// A key's fingerprint and bucket, from hash values `u` and `v`.
+fn split(u: uint, v: uint, buckets: uint) -> (u64, uint) {
+  ((u as u64) << 31 | v as u64, ((u as u64 * buckets as u64) >> 31) as uint)
+}
+
+/// The slot, of a bucket's `size`, that seed `s` sends fingerprint `x` to.
+fn place(x: u64, s: u64, size: uint) -> uint {
+  (mix(x ^ s * 0x9e3779b97f4a7c15) % size as u64) as uint
+}
+
+/// Reseeds `hasher` and gives each bucket its own slots, out of no more than
+/// `n`, or returns `None` if the buckets need more.
+pub fn attempt<R: rand::Rng, K, H: KeyHasher<K>>(
+    rng: &mut R, hasher: &mut H, keys: &[K], n: uint)
+    -> Result<Option<Labeling>, BuildError> {
+  hasher.reseed(rng, keys, HASH_RANGE);
+
+  let mut pairs = Vec::with_capacity(keys.len());
+  if !hasher.vertices_batch(keys, HASH_RANGE, &mut pairs) {
+    return Err(Overflow);
+  }
+
+  Ok(label(pairs.as_slice(), keys.len(), n, None))
+}
+
+/// Labels `keys` again without reseeding `hasher`, which `old` was built
+/// with. The keys keep `old`'s buckets, and a bucket whose seed still sends
+/// its keys to distinct slots keeps it, so only the buckets whose keys
+/// changed are searched again. Returns `None` if the buckets need more than
+/// `n` slots, or `hasher` can't hash the new keys without a reseed.
+pub fn patch<K, H: KeyHasher<K>>(old: &Labeling, hasher: &H, keys: &[K], n: uint)
+    -> Option<Labeling> {
+  let mut pairs = Vec::with_capacity(keys.len());
+  if old.buckets == 0 || !hasher.vertices_batch(keys, HASH_RANGE, &mut pairs) {
+    return None;
+  }
+
+  label(pairs.as_slice(), old.buckets, n, Some(old))
+}
+
+/// Splits the keys with hash values `pairs` into `buckets`, and gives each
+/// its own slots out of no more than `n`, trying `old`'s seed for a bucket
+/// first if it has as many slots there.
+fn label(pairs: &[(uint, uint)], buckets: uint, n: uint, old: Option<&Labeling>)
+    -> Option<Labeling> {
+  let mut hashed: Vec<(uint, u64)> =
+    pairs.iter().map(|&(u, v)| { let (x, b) = split(u, v, buckets); (b, x) }).collect();
+  hashed.sort();
+
+  // Keys with the same fingerprint always land together.
+  if hashed.windows(2).any(|w| w[0] == w[1]) {
+    return None;
+  }
+
+  // Bucket `b` is `hashed[offsets[b]..offsets[b + 1]]`.
+  let mut offsets = Vec::from_elem(buckets + 1, 0u);
+  for &(b, _) in hashed.iter() {
+    *offsets.get_mut(b + 1) += 1;
+  }
+  for b in range(0, buckets) {
+    if offsets[b + 1] > MAX_BUCKET {
+      return None;
+    }
+    *offsets.get_mut(b + 1) += offsets[b];
+  }
+
+  let mut groups = Vec::with_capacity(buckets / GROUP + 1);
+  let mut starts = Vec::with_capacity(buckets + 1);
+  let mut slots  = 0;
+  for b in range(0, buckets + 1) {
+    if b % GROUP == 0 {
+      groups.push(slots);
+    }
+    starts.push((slots - groups[b / GROUP]) as u16);
+    if b < buckets {
+      let size = offsets[b + 1] - offsets[b];
+      slots += size * size;
+    }
+  }
+
+  if slots > n {
+    return None;
+  }
+
+  let mut taken = Vec::from_elem(slots, false);
+  let mut seeds = Vec::from_elem(buckets, 0u64);
+  for b in range(0, buckets) {
+    let bucket = hashed.slice(offsets[b], offsets[b + 1]);
+    let size   = bucket.len() * bucket.len();
+    let first  = groups[b / GROUP] + starts[b] as uint;
+
+    let kept =
+      match old {
+        Some(old) if old.start(b + 1) - old.start(b) == size => Some(old.seed(b)),
+        _                                                   => None,
+      };
+
+    let mut placed = false;
+
+    // No other bucket's keys can be in this one's slots, so only its own
+    // can collide.
+    for s in kept.into_iter().chain(range(0, MAX_SEED)) {
+      for &(_, x) in bucket.iter() {
+        *taken.get_mut(first + place(x, s, size)) = true;
+      }
+      if range(first, first + size).filter(|&i| taken[i]).count() == bucket.len() {
+        *seeds.get_mut(b) = s;
+        placed = true;
+        break;
+      }
+      for i in range(first, first + size) {
+        *taken.get_mut(i) = false;
+      }
+    }
+
+    if !placed {
+      return None;
+    }
+  }
+
+  let max   = seeds.iter().map(|&s| s).max().unwrap_or(0);
+  let width = 64 - max.leading_zeros();
+  let mut packed = BitWriter::new();
+  for &s in seeds.iter() {
+    packed.push(s, width);
+  }
+  // Keep one word past the end, so reads never run off it.
+  packed.words.push(0);
+
+  Some(Labeling {
+    buckets: buckets,
+    groups:  groups,
+    starts:  starts,
+    width:   width,
+    seeds:   packed.words,
+    used:    RankedBits::new(taken.as_slice()),
+  })
+}
+
+impl Labeling {
+  /// The first slot of bucket `b`.
+  fn start(&self, b: uint) -> uint {
+    self.groups[b / GROUP] + self.starts[b] as uint
+  }
+
+  /// The index `k` would have if it were a known key, or `None` if it
+  /// certainly isn't.
+  pub fn index<K, H: KeyHasher<K>>(&self, hasher: &H, k: &K) -> Option<uint> {
+    let (x, b) =
+      match hasher.vertices(k, HASH_RANGE) {
+        Some((u, v)) => split(u, v, self.buckets),
+        None         => return None,
+      };
+
+    let first = self.start(b);
+    let size  = self.start(b + 1) - first;
+
+    // No known key lands in an empty bucket.
+    if size == 0 {
+      return None;
+    }
+
+    // Nor in an empty slot.
+    self.used.rank(first + place(x, self.seed(b), size))
+  }
+
+  /// The seed of bucket `b`.
+  fn seed(&self, b: uint) -> u64 {
+    read_bits(self.seeds.as_slice(), b * self.width, self.width)
+  }
+
+  /// Checks that every bucket has a seed and its own run of slots, in order,
+  /// and that there are ranks for all of them, `m` full, so `index` stays in
+  /// bounds.
+  pub fn verify(&self, m: uint) -> Result<(), Corruption> {
+    if self.starts.len() != self.buckets + 1 || self.groups.len() != self.buckets / GROUP + 1 {
+      return Err(BadLength("starts", self.starts.len()));
+    }
+    if !holds_fields(self.seeds.as_slice(), self.buckets, self.width) {
+      return Err(BadLength("seeds", self.seeds.len()));
+    }
+    if self.start(0) != 0 || range(0, self.buckets).any(|b| self.start(b) > self.start(b + 1)) {
+      return Err(BadLength("groups", self.groups.len()));
+    }
+    if !self.used.is_consistent() || self.used.len() != self.num_slots() {
+      return Err(BadLength("used", self.used.len()));
+    }
+
+    let full = self.used.count_ones();
+    if full != m {
+      return Err(BadCount(m, full));
+    }
+    Ok(())
+  }
+
+  /// The range `hasher.vertices` is asked for.
+  pub fn hash_range(&self) -> uint {
+    HASH_RANGE
+  }
+
+  /// The number of slots the buckets have between them, before ranking.
+  pub fn num_slots(&self) -> uint {
+    self.start(self.buckets)
+  }
+
+  /// The bytes of the bucket directory, the seeds, and the ranks.
+  pub fn heap_size(&self) -> uint {
+    self.groups.len() * mem::size_of::<uint>() + self.starts.len() * 2 + self.seeds.len() * 8
+      + self.used.heap_size()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{GROUP, HASH_RANGE, Labeling, split};
+  use super::super::{HashMapBuilder, KeyHasher, PerfectHashFn, Fks, FksLabels};
+  use super::super::testing::keys;
+
+  fn labeling<'a>(func: &'a PerfectHashFn<String>) -> &'a Labeling {
+    match func.labels {
+      FksLabels(ref labeling) => labeling,
+      _                       => fail!("not an FKS table"),
+    }
+  }
+
+  #[test]
+  fn gives_each_bucket_the_square_of_its_size_in_slots() {
+    let keys = keys(1000);
+    let func =
+      HashMapBuilder::new()
+        .algorithm(Fks)
+        .seed(1)
+        .build_fn(keys.as_slice())
+        .unwrap();
+    let labeling = labeling(&func);
+
+    let mut sizes = Vec::from_elem(labeling.buckets, 0u);
+    for k in keys.iter() {
+      let (u, v) = func.hasher.vertices(k, HASH_RANGE).unwrap();
+      let (_, b) = split(u, v, labeling.buckets);
+      *sizes.get_mut(b) += 1;
+    }
+
+    for b in range(0, labeling.buckets) {
+      assert_eq!(labeling.start(b + 1) - labeling.start(b), sizes[b] * sizes[b]);
+    }
+    // Each group's starts count from its own first slot.
+    for g in range(0, labeling.groups.len()) {
+      assert_eq!(labeling.starts[g * GROUP], 0);
+    }
+  }
+
+  #[test]
+  fn rebuilds_only_the_buckets_whose_keys_changed() {
+    let keys    = keys(1010);
+    let builder = HashMapBuilder::new().algorithm(Fks).seed(1);
+    let old     = builder.build_fn(keys.slice_to(1000)).unwrap();
+    let patched = builder.rebuild_fn(&old, keys.as_slice()).unwrap();
+    let full    = builder.build_fn(keys.as_slice()).unwrap();
+
+    // The new keys went into the old buckets, and every bucket they missed
+    // kept its seed.
+    let (before, after) = (labeling(&old), labeling(&patched));
+    assert_eq!(after.buckets, before.buckets);
+    let mut changed = Vec::from_elem(before.buckets, false);
+    for k in keys.slice_from(1000).iter() {
+      let (u, v) = patched.hasher.vertices(k, HASH_RANGE).unwrap();
+      let (_, b) = split(u, v, after.buckets);
+      *changed.get_mut(b) = true;
+    }
+    for b in range(0, before.buckets) {
+      if !changed[b] {
+        assert_eq!(after.seed(b), before.seed(b));
+      }
+    }
+
+    // Either way, every key has an index of its own.
+    for func in [&patched, &full].iter() {
+      let mut seen = Vec::from_elem(keys.len(), false);
+      for k in keys.iter() {
+        let i = func.hash(k);
+        assert!(!seen[i], "{} hashed to {}, as another key did", k, i);
+        *seen.get_mut(i) = true;
+      }
+      assert!(func.verify().is_ok());
+    }
+  }
+}
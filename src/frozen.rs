@@ -0,0 +1,151 @@
+//! A map that can no longer change, for sharing between tasks.
+//!
+//! A `FrozenHashMap` is a `HashMap` with only the `&self` half of its
+//! interface. Nothing behind a shared reference is ever written, so it's
+//! `Send` and `Sync` whenever its keys, values, and hasher are, and one
+//! `Arc<FrozenHashMap<..>>` can serve any number of readers without locks.
+//! Backup stores that write on lookup, like `LruBackup`, are traded for a
+//! `SeededBackup` when the map is frozen.
+
+use std::hash;
+use std::ops;
+
+use super::{HashMap, Positional, KeyHasher, BackupStore, Entries, Keys, Values};
+use super::{LookupStats, SortedEntries};
+use super::{MemoryBreakdown, seeded_backup};
+
+/// A read-only `HashMap`, from `HashMap::freeze`.
+pub struct FrozenHashMap<K, V, H = Positional> {
+  map: HashMap<K, V, H>,
+}
+
+impl<K: Eq + hash::Hash, V, H: KeyHasher<K>, B: BackupStore<K, V>> HashMap<K, V, H, B> {
+  /// Gives up the ability to change the map, so it can be shared freely.
+  pub fn freeze(self) -> FrozenHashMap<K, V, H> {
+    let backup = seeded_backup(&self.func);
+    FrozenHashMap { map: self.with_backup(backup) }
+  }
+
+  /// Like `freeze`, but first `rebuild`s the map, so the backup's entries
+  /// move into slots of their own, and trims every table to size. That's
+  /// the smallest a map holding these entries gets without dropping the
+  /// known keys that have no value.
+  pub fn into_frozen(mut self) -> FrozenHashMap<K, V, H> {
+    self.rebuild();
+    self.shrink_to_fit();
+    self.freeze()
+  }
+}
+
+impl<K: Eq + hash::Hash, V, H: KeyHasher<K>> FrozenHashMap<K, V, H> {
+  /// Returns a reference to the value corresponding to the key.
+  pub fn get(&self, k: &K) -> Option<&V> {
+    self.map.get(k)
+  }
+
+  /// Like `get`, but looks up any type that hashes the same way as `K`.
+  pub fn get_equiv<Q: hash::Hash + Equiv<K>>(&self, k: &Q) -> Option<&V>
+      where H: KeyHasher<Q> {
+    self.map.get_equiv(k)
+  }
+
+  /// Like `get`, but also returns the stored key.
+  pub fn get_key_value(&self, k: &K) -> Option<(&K, &V)> {
+    self.map.get_key_value(k)
+  }
+
+  /// Like `get_key_value`, but looks up any type that hashes the same way as
+  /// `K`.
+  pub fn get_key_value_equiv<Q: hash::Hash + Equiv<K>>(&self, k: &Q) -> Option<(&K, &V)>
+      where H: KeyHasher<Q> {
+    self.map.get_key_value_equiv(k)
+  }
+
+  /// Looks up every key in `keys`, returning their values in the same order.
+  pub fn get_many<'a>(&'a self, keys: &[K]) -> Vec<Option<&'a V>> {
+    self.map.get_many(keys)
+  }
+
+  /// Returns true if the map contains a value for the specified key.
+  pub fn contains_key(&self, k: &K) -> bool {
+    self.map.contains_key(k)
+  }
+
+  /// The slot of `k`, or `None` if `k` isn't a known key.
+  pub fn index_of(&self, k: &K) -> Option<uint> {
+    self.map.index_of(k)
+  }
+
+  /// Another name for `index_of`, to pair with `get_index`.
+  pub fn get_index_of(&self, k: &K) -> Option<uint> {
+    self.map.index_of(k)
+  }
+
+  /// The known key in slot `i` and its value, or `None` if the slot is
+  /// empty or there's no slot `i`.
+  pub fn get_index(&self, i: uint) -> Option<(&K, &V)> {
+    self.map.get_index(i)
+  }
+
+  /// The bytes each part of the map takes up.
+  pub fn memory_usage(&self) -> MemoryBreakdown {
+    self.map.memory_usage()
+  }
+
+  /// The map as the `HashMap` it was, for the `&self` methods this doesn't
+  /// have.
+  pub fn as_map(&self) -> &HashMap<K, V, H> {
+    &self.map
+  }
+
+  /// Makes the map mutable again.
+  pub fn thaw(self) -> HashMap<K, V, H> {
+    self.map
+  }
+
+  /// Returns the number of elements in the map.
+  pub fn len(&self) -> uint {
+    self.map.len()
+  }
+
+  /// Returns true if the map contains no elements.
+  pub fn is_empty(&self) -> bool {
+    self.map.is_empty()
+  }
+
+  /// The number of known keys, which is the number of slots.
+  pub fn capacity(&self) -> uint {
+    self.map.capacity()
+  }
+
+  /// An iterator visiting all key-value pairs in arbitrary order.
+  pub fn iter<'a>(&'a self) -> Entries<'a, K, V> {
+    self.map.iter()
+  }
+
+  /// Like `HashMap::lookup_stats`. Lookups from every task are counted.
+  pub fn lookup_stats(&self) -> Option<LookupStats> {
+    self.map.lookup_stats()
+  }
+
+  /// Like `HashMap::iter_sorted`. Call `sort_keys` before freezing the map.
+  pub fn iter_sorted<'a>(&'a self) -> SortedEntries<'a, K, V> {
+    self.map.iter_sorted()
+  }
+
+  /// An iterator visiting all keys in arbitrary order.
+  pub fn keys<'a>(&'a self) -> Keys<'a, K, V> {
+    self.map.keys()
+  }
+
+  /// An iterator visiting all values in arbitrary order.
+  pub fn values<'a>(&'a self) -> Values<'a, K, V> {
+    self.map.values()
+  }
+}
+
+impl<K: Eq + hash::Hash, V, H: KeyHasher<K>> ops::Index<K, V> for FrozenHashMap<K, V, H> {
+  fn index<'a>(&'a self, k: &K) -> &'a V {
+    ops::Index::index(&self.map, k)
+  }
+}
@@ -0,0 +1,111 @@
+//! Hugepage-aligned buffers for table images, behind the `hugepages`
+//! feature. Unix only.
+//!
+//! Lookups in a table of a gigabyte or more touch a different page almost
+//! every time, so with 4KB pages they're dominated by TLB misses. A
+//! `HugeBuffer` is mapped on a 2MB boundary, and on Linux advised to be
+//! backed by transparent hugepages, so the same table needs 512 times fewer
+//! TLB entries. Write an image into it with `HashMap::write_image_in`:
+//!
+//! ```ignore
+//! let mut buf = try!(HugeBuffer::new(map.image_len().unwrap()));
+//! let view    = try!(map.write_image_in(buf.as_mut_slice()));
+//! ```
+//!
+//! The advice is only a hint. The kernel may back the buffer with small
+//! pages anyway, if hugepages are disabled or memory is fragmented.
+
+use libc;
+use libc::{c_int, c_void, size_t};
+use std::io::{IoError, IoResult};
+use std::mem;
+use std::raw;
+
+/// The size, and alignment, of a hugepage.
+pub static HUGEPAGE_SIZE: uint = 2 * 1024 * 1024;
+
+#[cfg(target_os = "linux")]
+static MADV_HUGEPAGE: c_int = 14;
+
+/// Rounds `len` up to a whole number of hugepages.
+fn round_up(len: uint) -> uint {
+  (len + HUGEPAGE_SIZE - 1) & !(HUGEPAGE_SIZE - 1)
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn advise(ptr: *mut u8, len: uint) {
+  // Failing just means small pages, so the result doesn't matter.
+  libc::madvise(ptr as *mut c_void, len as size_t, MADV_HUGEPAGE);
+}
+
+#[cfg(not(target_os = "linux"))]
+unsafe fn advise(_ptr: *mut u8, _len: uint) {}
+
+/// Zeroed memory mapped on a hugepage boundary, unmapped on drop.
+pub struct HugeBuffer {
+  ptr: *mut u8,
+  /// The requested length. The mapping is rounded up to whole hugepages.
+  len: uint,
+}
+
+impl HugeBuffer {
+  /// Maps a zeroed buffer of `len` bytes, starting on a hugepage boundary.
+  pub fn new(len: uint) -> IoResult<HugeBuffer> {
+    let mapped = round_up(len);
+    if mapped == 0 {
+      return Ok(HugeBuffer { ptr: HUGEPAGE_SIZE as *mut u8, len: 0 });
+    }
+
+    unsafe {
+      // mmap only promises small page alignment, so map a hugepage extra
+      // and trim both ends down to an aligned run.
+      let raw =
+        libc::mmap(0 as *mut c_void,
+                   (mapped + HUGEPAGE_SIZE) as size_t,
+                   libc::PROT_READ | libc::PROT_WRITE,
+                   libc::MAP_PRIVATE | libc::MAP_ANON,
+                   -1,
+                   0);
+      if raw == libc::MAP_FAILED {
+        return Err(IoError::last_error());
+      }
+
+      let start = raw as uint;
+      let ptr   = round_up(start);
+      let end   = start + mapped + HUGEPAGE_SIZE;
+      if ptr > start {
+        libc::munmap(start as *mut c_void, (ptr - start) as size_t);
+      }
+      if end > ptr + mapped {
+        libc::munmap((ptr + mapped) as *mut c_void, (end - ptr - mapped) as size_t);
+      }
+
+      advise(ptr as *mut u8, mapped);
+      Ok(HugeBuffer { ptr: ptr as *mut u8, len: len })
+    }
+  }
+
+  /// The buffer's length, as requested.
+  pub fn len(&self) -> uint {
+    self.len
+  }
+
+  /// The buffer's contents.
+  pub fn as_slice<'a>(&'a self) -> &'a [u8] {
+    unsafe { mem::transmute(raw::Slice { data: self.ptr as *const u8, len: self.len }) }
+  }
+
+  /// The buffer's contents, mutably.
+  pub fn as_mut_slice<'a>(&'a mut self) -> &'a mut [u8] {
+    unsafe { mem::transmute(raw::Slice { data: self.ptr as *const u8, len: self.len }) }
+  }
+}
+
+impl Drop for HugeBuffer {
+  fn drop(&mut self) {
+    let mapped = round_up(self.len);
+    if mapped > 0 {
+      unsafe { libc::munmap(self.ptr as *mut c_void, mapped as size_t); }
+    }
+  }
+}
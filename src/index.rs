@@ -0,0 +1,127 @@
+//! Known keys and their slots, with the values left to the caller.
+//!
+//! A `HashMap` keeps one value per key. A `PerfectIndex` keeps only the keys
+//! and the function, and hands out each key's slot, so any number of value
+//! columns can be kept alongside it as plain `Vec`s, one element per slot:
+//!
+//! ```ignore
+//! let index = HashMapBuilder::new().build_index(names).unwrap();
+//! let mut ages    = index.column(0u);
+//! let mut heights = index.column_from_fn(|name| default_height(name));
+//! *index.get_mut(ages.as_mut_slice(), &name).unwrap() += 1;
+//! ```
+//!
+//! Every column shares the one function and the one copy of the keys. A
+//! column must be `len()` long: `column` and `column_from_fn` make them so.
+
+use std::hash;
+
+use super::{PerfectHashFn, PerfectHashState, ByteCounter, KeyHasher, Positional, KeyId};
+use super::{HashMapBuilder, BuildError};
+
+/// A fixed set of keys, each with its own slot in `[0, len())`.
+pub struct PerfectIndex<K, H = Positional> {
+  func: PerfectHashFn<K, H>,
+  /// The keys, each in its own slot.
+  keys: Vec<K>,
+}
+
+impl HashMapBuilder {
+  /// Builds an index of `keys`, which must not contain duplicates.
+  pub fn build_index<'a,
+                     K: Eq
+                      + hash::Hash
+                      + hash::Hash<PerfectHashState<'a>>
+                      + hash::Hash<ByteCounter>>
+      (&self, keys: Vec<K>) -> Result<PerfectIndex<K>, BuildError> {
+    self.build_index_with_hasher(Positional::new(), keys)
+  }
+
+  /// Like `build_index`, but hashes the keys with `hasher` instead of the
+  /// default `Positional` hasher.
+  pub fn build_index_with_hasher<K: Eq + hash::Hash, H: KeyHasher<K>>
+      (&self, hasher: H, keys: Vec<K>) -> Result<PerfectIndex<K, H>, BuildError> {
+    let func = try!(self.build_fn_with_hasher(hasher, keys.as_slice()));
+    Ok(PerfectIndex::from_fn(func, keys))
+  }
+}
+
+impl<K: Eq + hash::Hash, H: KeyHasher<K>> PerfectIndex<K, H> {
+  /// An index of `keys`, which `func` must have been built from.
+  pub fn from_fn(func: PerfectHashFn<K, H>, keys: Vec<K>) -> PerfectIndex<K, H> {
+    let mut slots: Vec<Option<K>> = Vec::from_fn(keys.len(), |_| None);
+    for k in keys.into_iter() {
+      let i = func.hash(&k);
+      *slots.get_mut(i) = Some(k);
+    }
+
+    PerfectIndex { func: func, keys: slots.into_iter().map(|k| k.unwrap()).collect() }
+  }
+
+  /// The slot of `k`, or `None` if `k` isn't a known key.
+  pub fn index_of(&self, k: &K) -> Option<uint> {
+    match self.func.try_hash(k) {
+      Some(i) if self.keys[i] == *k => Some(i),
+      _                             => None,
+    }
+  }
+
+  /// The handle of `k`, or `None` if `k` isn't a known key.
+  pub fn id_of(&self, k: &K) -> Option<KeyId> {
+    self.index_of(k).map(KeyId)
+  }
+
+  /// The element of `column` for `k`, or `None` if `k` isn't a known key.
+  pub fn get<'c, V>(&self, column: &'c [V], k: &K) -> Option<&'c V> {
+    match self.index_of(k) {
+      Some(i) => column.get(i),
+      None    => None,
+    }
+  }
+
+  /// Like `get`, but with a mutable reference.
+  pub fn get_mut<'c, V>(&self, column: &'c mut [V], k: &K) -> Option<&'c mut V> {
+    match self.index_of(k) {
+      Some(i) => column.get_mut(i),
+      None    => None,
+    }
+  }
+}
+
+impl<K, H> PerfectIndex<K, H> {
+  /// The number of keys, and so of elements in each column.
+  pub fn len(&self) -> uint {
+    self.keys.len()
+  }
+
+  /// Returns true if there are no keys.
+  pub fn is_empty(&self) -> bool {
+    self.keys.is_empty()
+  }
+
+  /// The known key with handle `id`, or `None` if no known key has it.
+  pub fn key_of(&self, id: KeyId) -> Option<&K> {
+    let KeyId(i) = id;
+    if i < self.keys.len() { Some(&self.keys[i]) } else { None }
+  }
+
+  /// The keys, each at its own slot.
+  pub fn keys(&self) -> &[K] {
+    self.keys.as_slice()
+  }
+
+  /// The hash function, for mapping keys to slots without checking them.
+  pub fn hash_fn(&self) -> &PerfectHashFn<K, H> {
+    &self.func
+  }
+
+  /// A new column with `v` for every key.
+  pub fn column<V: Clone>(&self, v: V) -> Vec<V> {
+    Vec::from_elem(self.keys.len(), v)
+  }
+
+  /// A new column with `f(k)` for every key `k`.
+  pub fn column_from_fn<V>(&self, f: |&K| -> V) -> Vec<V> {
+    self.keys.iter().map(f).collect()
+  }
+}
@@ -0,0 +1,157 @@
+//! Owned string and byte string keys, looked up by borrowed ones.
+//!
+//! A table can own its keys in whatever form is cheapest to keep, such as
+//! an `Rc<String>` shared with the rest of the program, or a `MaybeOwned`
+//! that only allocates for keys that aren't static, and still be queried
+//! with a plain `&str` borrowed from the input:
+//!
+//! ```ignore
+//! let map: HashMap<Rc<String>, Symbol> = HashMapBuilder::new().build(names).unwrap();
+//! map.get_str(line.slice(start, end))
+//! ```
+//!
+//! Lookups go through `get_equiv`, with a `StrQuery` or `BytesQuery` that
+//! hashes its borrowed data exactly as the key it stands for hashes its own.
+//! That holds for every key type here, as long as the `KeyHasher` goes
+//! through `Hash`, as `Positional` does.
+//!
+//! There are no `Box<str>` or `Rc<str>` keys, since neither can be built;
+//! `Box<String>` and `Rc<String>` are the nearest owned forms.
+
+use std::hash;
+use std::rc::Rc;
+use std::str::MaybeOwned;
+
+use super::{HashMap, KeyHasher, BackupStore};
+
+/// Keys that are a string, and hash just as that `str` does.
+pub trait StrKey {
+  /// The string.
+  fn as_str<'a>(&'a self) -> &'a str;
+}
+
+impl StrKey for String {
+  fn as_str<'a>(&'a self) -> &'a str {
+    self.as_slice()
+  }
+}
+
+impl<'b> StrKey for &'b str {
+  fn as_str<'a>(&'a self) -> &'a str {
+    *self
+  }
+}
+
+impl<'b> StrKey for MaybeOwned<'b> {
+  fn as_str<'a>(&'a self) -> &'a str {
+    self.as_slice()
+  }
+}
+
+impl<S: StrKey> StrKey for Rc<S> {
+  fn as_str<'a>(&'a self) -> &'a str {
+    (**self).as_str()
+  }
+}
+
+impl<S: StrKey> StrKey for Box<S> {
+  fn as_str<'a>(&'a self) -> &'a str {
+    (**self).as_str()
+  }
+}
+
+/// Keys that are a byte string, and hash just as that `[u8]` does.
+pub trait BytesKey {
+  /// The bytes.
+  fn as_bytes<'a>(&'a self) -> &'a [u8];
+}
+
+impl BytesKey for Vec<u8> {
+  fn as_bytes<'a>(&'a self) -> &'a [u8] {
+    self.as_slice()
+  }
+}
+
+impl<'b> BytesKey for &'b [u8] {
+  fn as_bytes<'a>(&'a self) -> &'a [u8] {
+    *self
+  }
+}
+
+impl<S: BytesKey> BytesKey for Rc<S> {
+  fn as_bytes<'a>(&'a self) -> &'a [u8] {
+    (**self).as_bytes()
+  }
+}
+
+impl<S: BytesKey> BytesKey for Box<S> {
+  fn as_bytes<'a>(&'a self) -> &'a [u8] {
+    (**self).as_bytes()
+  }
+}
+
+/// A borrowed string, to look up any `StrKey`.
+pub struct StrQuery<'a>(pub &'a str);
+
+impl<'a, K: StrKey> Equiv<K> for StrQuery<'a> {
+  fn equiv(&self, other: &K) -> bool {
+    let StrQuery(s) = *self;
+    s == other.as_str()
+  }
+}
+
+impl<'a, W: hash::Writer> hash::Hash<W> for StrQuery<'a> {
+  fn hash(&self, w: &mut W) {
+    let StrQuery(s) = *self;
+    s.hash(w)
+  }
+}
+
+/// A borrowed byte string, to look up any `BytesKey`.
+pub struct BytesQuery<'a>(pub &'a [u8]);
+
+impl<'a, K: BytesKey> Equiv<K> for BytesQuery<'a> {
+  fn equiv(&self, other: &K) -> bool {
+    let BytesQuery(s) = *self;
+    s == other.as_bytes()
+  }
+}
+
+impl<'a, W: hash::Writer> hash::Hash<W> for BytesQuery<'a> {
+  fn hash(&self, w: &mut W) {
+    let BytesQuery(s) = *self;
+    s.hash(w)
+  }
+}
+
+impl<K: Eq + hash::Hash + StrKey, V, H: KeyHasher<K>, B: BackupStore<K, V>>
+    HashMap<K, V, H, B> {
+  /// Returns the value for the key that is the string `k`.
+  pub fn get_str<'a>(&self, k: &'a str) -> Option<&V>
+      where H: KeyHasher<StrQuery<'a>> {
+    self.get_equiv(&StrQuery(k))
+  }
+
+  /// Returns true if the map has a value for the key that is the string
+  /// `k`.
+  pub fn contains_str<'a>(&self, k: &'a str) -> bool
+      where H: KeyHasher<StrQuery<'a>> {
+    self.get_str(k).is_some()
+  }
+}
+
+impl<K: Eq + hash::Hash + BytesKey, V, H: KeyHasher<K>, B: BackupStore<K, V>>
+    HashMap<K, V, H, B> {
+  /// Returns the value for the key that is the byte string `k`.
+  pub fn get_bytes<'a>(&self, k: &'a [u8]) -> Option<&V>
+      where H: KeyHasher<BytesQuery<'a>> {
+    self.get_equiv(&BytesQuery(k))
+  }
+
+  /// Returns true if the map has a value for the key that is the byte
+  /// string `k`.
+  pub fn contains_bytes<'a>(&self, k: &'a [u8]) -> bool
+      where H: KeyHasher<BytesQuery<'a>> {
+    self.get_bytes(k).is_some()
+  }
+}
@@ -0,0 +1,234 @@
+//! A map shared between tasks whose known keys grow as it's used.
+//!
+//! A `LiveHashMap` serves lookups from a `FrozenHashMap`, with new entries
+//! collected in a small std `HashMap` on the side. When its `RebuildPolicy`
+//! says the side table has grown enough, a background task builds a new
+//! frozen map over every entry and swaps it in. Locks are only ever held to
+//! copy an `Arc` or touch the side table, never during construction, so
+//! lookups don't wait for rebuilds. Lookups only read-lock the side table,
+//! and skip it entirely while it's empty.
+//!
+//! While a rebuild runs, the entries it's folding in stay visible, and new
+//! inserts go to a fresh side table, so nothing goes missing in between:
+//!
+//! ```ignore
+//! let live = LiveHashMap::new(map.freeze(), RebuildPolicy::new());
+//! let other = live.clone();
+//! spawn(proc() { other.insert("new".to_string(), 7) });
+//! live.get(&"new".to_string());
+//! ```
+
+use std::collections;
+use std::hash;
+use std::mem;
+use std::sync::{Arc, RWLock};
+use std::sync::atomic::{AtomicBool, AtomicUint, SeqCst};
+use std::task;
+
+use super::{FrozenHashMap, KeyHasher, Positional};
+
+/// When a `LiveHashMap` rebuilds.
+#[deriving(Clone, Show)]
+pub struct RebuildPolicy {
+  /// Never rebuild for fewer new entries than this.
+  pub min_new:      uint,
+  /// Rebuild once the new entries are at least this fraction of the ones
+  /// the current table knows.
+  pub new_fraction: f64,
+}
+
+impl RebuildPolicy {
+  /// Rebuilds once there are 1024 new entries, or a tenth as many as the
+  /// table already has, whichever is more. That keeps the work amortized:
+  /// each rebuild costs about as much as the inserts since the last one.
+  pub fn new() -> RebuildPolicy {
+    RebuildPolicy { min_new: 1024, new_fraction: 0.1 }
+  }
+
+  /// Returns true if a table with `known` entries should be rebuilt for
+  /// `new` more.
+  pub fn should_rebuild(&self, known: uint, new: uint) -> bool {
+    new >= self.min_new && new as f64 >= self.new_fraction * known as f64
+  }
+}
+
+/// The tables lookups go through, swapped as a whole.
+struct Tables<K, V, H> {
+  current: Arc<FrozenHashMap<K, V, H>>,
+  /// The new entries a running rebuild is folding into `current`.
+  folding: Option<Arc<collections::HashMap<K, V>>>,
+}
+
+struct Shared<K, V, H> {
+  tables:     RWLock<Tables<K, V, H>>,
+  /// Entries inserted since the last rebuild started. They shadow the
+  /// entries in `tables`.
+  pending:     RWLock<collections::HashMap<K, V>>,
+  /// The length of `pending`, stored while it's locked, so lookups can skip
+  /// it when it's empty.
+  pending_len: AtomicUint,
+  rebuilding:  AtomicBool,
+  policy:      RebuildPolicy,
+}
+
+/// Clears `rebuilding` when a rebuild ends, even if the task fails.
+struct Rebuilding<K, V, H> {
+  shared: Arc<Shared<K, V, H>>,
+}
+
+#[unsafe_destructor]
+impl<K, V, H> Drop for Rebuilding<K, V, H> {
+  fn drop(&mut self) {
+    self.shared.rebuilding.store(false, SeqCst);
+  }
+}
+
+/// A map that can be inserted into from any task, and rebuilds itself on
+/// another one as it grows. Clones share the same map.
+pub struct LiveHashMap<K, V, H = Positional> {
+  shared: Arc<Shared<K, V, H>>,
+}
+
+impl<K, V, H> Clone for LiveHashMap<K, V, H> {
+  fn clone(&self) -> LiveHashMap<K, V, H> {
+    LiveHashMap { shared: self.shared.clone() }
+  }
+}
+
+impl<K: Send + Sync + Clone + Eq + hash::Hash,
+     V: Send + Sync + Clone,
+     H: KeyHasher<K> + Send + Sync>
+    LiveHashMap<K, V, H> {
+  /// Starts from the entries of `map`, rebuilding as `policy` says.
+  pub fn new(map: FrozenHashMap<K, V, H>, policy: RebuildPolicy) -> LiveHashMap<K, V, H> {
+    let tables = Tables { current: Arc::new(map), folding: None };
+
+    LiveHashMap {
+      shared: Arc::new(Shared {
+        tables:      RWLock::new(tables),
+        pending:     RWLock::new(collections::HashMap::new()),
+        pending_len: AtomicUint::new(0),
+        rebuilding:  AtomicBool::new(false),
+        policy:      policy,
+      }),
+    }
+  }
+
+  /// The table lookups are currently served from, without the entries
+  /// inserted since it was built.
+  pub fn snapshot(&self) -> Arc<FrozenHashMap<K, V, H>> {
+    self.shared.tables.read().current.clone()
+  }
+
+  /// Returns a copy of the value corresponding to the key.
+  pub fn get(&self, k: &K) -> Option<V> {
+    if self.shared.pending_len.load(SeqCst) > 0 {
+      match self.shared.pending.read().find(k) {
+        Some(v) => return Some(v.clone()),
+        None    => {}
+      }
+    }
+
+    let (current, folding) = {
+      let tables = self.shared.tables.read();
+      (tables.current.clone(), tables.folding.clone())
+    };
+
+    match folding {
+      Some(ref folding) => match folding.find(k) {
+        Some(v) => return Some(v.clone()),
+        None    => {}
+      },
+      None => {}
+    }
+
+    current.get(k).map(|v| v.clone())
+  }
+
+  /// Returns true if the map contains a value for the specified key.
+  pub fn contains_key(&self, k: &K) -> bool {
+    self.get(k).is_some()
+  }
+
+  /// Inserts a key-value pair, known key or not, and starts a rebuild if
+  /// that makes enough new entries. Returns true if it started one.
+  pub fn insert(&self, k: K, v: V) -> bool {
+    let new = {
+      let mut pending = self.shared.pending.write();
+      pending.insert(k, v);
+      self.shared.pending_len.store(pending.len(), SeqCst);
+      pending.len()
+    };
+
+    let known = self.snapshot().len();
+    if self.shared.policy.should_rebuild(known, new) {
+      self.rebuild()
+    } else {
+      false
+    }
+  }
+
+  /// Starts folding the new entries into a fresh table on another task,
+  /// unless a rebuild is already running. Returns true if it started one.
+  pub fn rebuild(&self) -> bool {
+    if self.shared.rebuilding.compare_and_swap(false, true, SeqCst) {
+      return false;
+    }
+    let rebuilding = Rebuilding { shared: self.shared.clone() };
+
+    // Lookups check `pending` before `tables`, so the entries are put in
+    // `folding` before they leave `pending`, and are never out of sight.
+    // Whatever a failed rebuild left in `folding` is folded in again.
+    {
+      let mut tables  = self.shared.tables.write();
+      let mut pending = self.shared.pending.write();
+      let mut new = mem::replace(&mut *pending, collections::HashMap::new());
+      self.shared.pending_len.store(0, SeqCst);
+      match tables.folding.take() {
+        Some(old) => for (k, v) in old.iter() {
+          if !new.contains_key(k) {
+            new.insert(k.clone(), v.clone());
+          }
+        },
+        None => {}
+      }
+      tables.folding = Some(Arc::new(new));
+    }
+
+    task::spawn(proc() {
+      LiveHashMap { shared: rebuilding.shared.clone() }.fold();
+    });
+    true
+  }
+
+  /// Builds the table with the folded entries, and swaps it in.
+  fn fold(&self) {
+    let (current, folding) = {
+      let tables = self.shared.tables.read();
+      (tables.current.clone(), tables.folding.clone().unwrap())
+    };
+
+    let mut entries: Vec<(K, V)> =
+      current.iter()
+        .filter(|&(k, _)| !folding.contains_key(k))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    entries.extend(folding.iter().map(|(k, v)| (k.clone(), v.clone())));
+
+    // The entries have distinct keys, and there's no iteration limit, so
+    // this can't fail. If it did anyway, the folded entries stay in
+    // `folding`, where lookups still find them.
+    // Built the way the map itself would rebuild.
+    let map   = current.as_map();
+    let built = map.settings.build_entries_with_hasher(map.func.hasher.clone(), entries);
+
+    match built {
+      Ok(map) => {
+        let mut tables = self.shared.tables.write();
+        tables.current = Arc::new(map.freeze());
+        tables.folding = None;
+      }
+      Err(err) => error!("perfect::LiveHashMap: rebuild failed: {}", err),
+    }
+  }
+}
@@ -0,0 +1,178 @@
+//! Maps from each known key to any number of values.
+//!
+//! A `PerfectMultiMap` is built once from `(key, value)` pairs, where keys
+//! may repeat. The values of every key sit in one shared array, in the order
+//! they were given, and each slot stores where its key's run starts, so a
+//! lookup is one hash and returns a slice. That suits inverted indexes over
+//! a known vocabulary, where one `Vec` per word would mostly be overhead.
+
+use std::collections;
+use std::hash;
+
+use super::{HashMapBuilder, BuildError, PerfectHashFn, PerfectHashState, ByteCounter};
+use super::{KeyHasher, Positional};
+
+/// A map from each known key to a slice of values, from
+/// `HashMapBuilder::build_multi`.
+pub struct PerfectMultiMap<K, V, H = Positional> {
+  func:    PerfectHashFn<K, H>,
+  /// The key in each slot.
+  keys:    Vec<K>,
+  /// Where each slot's values start in `values`, and then the end of the
+  /// last ones.
+  offsets: Vec<uint>,
+  values:  Vec<V>,
+}
+
+impl HashMapBuilder {
+  /// Builds a multimap whose known keys are the distinct keys of `pairs`,
+  /// each mapped to all of its values.
+  pub fn build_multi<'a,
+                     K: Eq
+                      + hash::Hash
+                      + hash::Hash<PerfectHashState<'a>>
+                      + hash::Hash<ByteCounter>,
+                     V>
+      (&self, pairs: Vec<(K, V)>) -> Result<PerfectMultiMap<K, V>, BuildError> {
+    self.build_multi_with_hasher(Positional::new(), pairs)
+  }
+
+  /// Like `build_multi`, but hashes the keys with `hasher` instead of the
+  /// default `Positional` hasher.
+  pub fn build_multi_with_hasher<K: Eq + hash::Hash, V, H: KeyHasher<K>>
+      (&self, hasher: H, pairs: Vec<(K, V)>) -> Result<PerfectMultiMap<K, V, H>, BuildError> {
+    let mut groups: collections::HashMap<K, Vec<V>> = collections::HashMap::new();
+    for (k, v) in pairs.into_iter() {
+      groups.find_or_insert_with(k, |_| Vec::new()).push(v);
+    }
+
+    let mut keys  = Vec::with_capacity(groups.len());
+    let mut lists = Vec::with_capacity(groups.len());
+    for (k, vs) in groups.into_iter() {
+      keys.push(k);
+      lists.push(vs);
+    }
+
+    let func = try!(self.build_fn_with_hasher(hasher, keys.as_slice()));
+    Ok(PerfectMultiMap::from_groups(func, keys, lists))
+  }
+}
+
+impl<'a,
+     K: Eq
+      + hash::Hash
+      + hash::Hash<PerfectHashState<'a>>
+      + hash::Hash<ByteCounter>,
+     V>
+    PerfectMultiMap<K, V> {
+  /// Builds a multimap from `pairs`, with the default `HashMapBuilder`
+  /// settings.
+  pub fn new(pairs: Vec<(K, V)>) -> PerfectMultiMap<K, V> {
+    match HashMapBuilder::new().build_multi(pairs) {
+      Ok(map)  => map,
+      Err(err) => fail!("perfect::PerfectMultiMap::new: {}", err),
+    }
+  }
+}
+
+impl<K: Eq + hash::Hash, V, H: KeyHasher<K>> PerfectMultiMap<K, V, H> {
+  /// Lays out `lists[i]`, the values of `keys[i]`, in slot order.
+  fn from_groups(func: PerfectHashFn<K, H>, keys: Vec<K>, lists: Vec<Vec<V>>)
+      -> PerfectMultiMap<K, V, H> {
+    let m = keys.len();
+
+    let mut slots: Vec<Option<(K, Vec<V>)>> = Vec::from_fn(m, |_| None);
+    for (k, vs) in keys.into_iter().zip(lists.into_iter()) {
+      let i = func.hash(&k);
+      *slots.get_mut(i) = Some((k, vs));
+    }
+
+    let mut keys    = Vec::with_capacity(m);
+    let mut offsets = Vec::with_capacity(m + 1);
+    let mut values  = Vec::new();
+    offsets.push(0u);
+    for slot in slots.into_iter() {
+      let (k, vs) = slot.unwrap();
+      keys.push(k);
+      values.extend(vs.into_iter());
+      offsets.push(values.len());
+    }
+
+    PerfectMultiMap {
+      func:    func,
+      keys:    keys,
+      offsets: offsets,
+      values:  values,
+    }
+  }
+
+  fn find_slot(&self, k: &K) -> Option<uint> {
+    match self.func.try_hash(k) {
+      Some(i) if self.keys[i] == *k => Some(i),
+      _                             => None,
+    }
+  }
+
+  /// The values for `k`, in the order they were given, or an empty slice if
+  /// `k` isn't a known key.
+  pub fn get(&self, k: &K) -> &[V] {
+    match self.find_slot(k) {
+      Some(i) => self.values.slice(self.offsets[i], self.offsets[i + 1]),
+      None    => self.values.slice(0, 0),
+    }
+  }
+
+  /// Like `get`, but with mutable references to the values.
+  pub fn get_mut(&mut self, k: &K) -> &mut [V] {
+    match self.find_slot(k) {
+      Some(i) => self.values.slice_mut(self.offsets[i], self.offsets[i + 1]),
+      None    => self.values.slice_mut(0, 0),
+    }
+  }
+
+  /// Returns true if `k` is one of the known keys.
+  pub fn contains_key(&self, k: &K) -> bool {
+    self.find_slot(k).is_some()
+  }
+}
+
+impl<K, V, H> PerfectMultiMap<K, V, H> {
+  /// The number of distinct keys.
+  pub fn len(&self) -> uint {
+    self.keys.len()
+  }
+
+  /// Returns true if there are no keys.
+  pub fn is_empty(&self) -> bool {
+    self.keys.is_empty()
+  }
+
+  /// The number of values, over all keys.
+  pub fn value_count(&self) -> uint {
+    self.values.len()
+  }
+
+  /// An iterator visiting each key with all its values, in slot order.
+  pub fn iter<'a>(&'a self) -> MultiEntries<'a, K, V, H> {
+    MultiEntries { map: self, next: 0 }
+  }
+}
+
+/// PerfectMultiMap iterator.
+pub struct MultiEntries<'a, K: 'a, V: 'a, H: 'a> {
+  map:  &'a PerfectMultiMap<K, V, H>,
+  next: uint,
+}
+
+impl<'a, K, V, H> Iterator<(&'a K, &'a [V])> for MultiEntries<'a, K, V, H> {
+  fn next(&mut self) -> Option<(&'a K, &'a [V])> {
+    let map = self.map;
+    let i   = self.next;
+    if i >= map.keys.len() {
+      return None;
+    }
+
+    self.next += 1;
+    Some((&map.keys[i], map.values.slice(map.offsets[i], map.offsets[i + 1])))
+  }
+}
@@ -0,0 +1,122 @@
+//! String keys that compare and hash by a normal form, such as ignoring
+//! ASCII case.
+//!
+//! A `Folded` key hashes its normalized bytes and compares equal to any key
+//! with the same normal form, so it works with every hasher that goes
+//! through `Hash`, and with the backup store, with nothing to configure.
+//! Build the table from folded keys, and look up with folded keys too:
+//!
+//! ```ignore
+//! let headers: Vec<Folded<&'static str>> =
+//!   vec!["Content-Type", "Content-Length"].into_iter().map(Folded::new).collect();
+//! let map: HashMap<Folded<&'static str>, uint> = HashMapBuilder::new().build(headers).unwrap();
+//!
+//! map.get_equiv(&Folded::new("content-type"))
+//! ```
+//!
+//! `AsciiCase` folds bytes one at a time without allocating. Any
+//! `fn(&[u8]) -> Vec<u8>` is also a `Normalizer`, for normal forms that
+//! aren't byte for byte, at the cost of allocating on every hash and
+//! comparison.
+
+use std::fmt;
+use std::hash;
+
+/// A normal form for byte strings.
+pub trait Normalizer {
+  /// Feeds the normal form of `bytes` to `out`, in any number of pieces.
+  fn normalize(&self, bytes: &[u8], out: |&[u8]|);
+
+  /// Returns true if `a` and `b` have the same normal form. By default this
+  /// normalizes both in full, and compares the results.
+  fn same(&self, a: &[u8], b: &[u8]) -> bool {
+    let mut x = Vec::with_capacity(a.len());
+    let mut y = Vec::with_capacity(b.len());
+    self.normalize(a, |piece| x.push_all(piece));
+    self.normalize(b, |piece| y.push_all(piece));
+    x == y
+  }
+}
+
+/// Folds ASCII letters to lowercase, and leaves every other byte alone.
+#[deriving(Clone, Show)]
+pub struct AsciiCase;
+
+fn lower(b: u8) -> u8 {
+  if b'A' <= b && b <= b'Z' { b + (b'a' - b'A') } else { b }
+}
+
+impl Normalizer for AsciiCase {
+  fn normalize(&self, bytes: &[u8], out: |&[u8]|) {
+    let mut buf = [0u8, ..64];
+    for chunk in bytes.chunks(buf.len()) {
+      for (i, &b) in chunk.iter().enumerate() {
+        buf[i] = lower(b);
+      }
+      out(buf.slice_to(chunk.len()));
+    }
+  }
+
+  fn same(&self, a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).all(|(&x, &y)| lower(x) == lower(y))
+  }
+}
+
+impl Normalizer for fn(&[u8]) -> Vec<u8> {
+  fn normalize(&self, bytes: &[u8], out: |&[u8]|) {
+    out((*self)(bytes).as_slice())
+  }
+}
+
+/// A string key that hashes and compares by its normal form under `N`.
+#[deriving(Clone)]
+pub struct Folded<S, N = AsciiCase> {
+  /// The key, as given.
+  pub key:        S,
+  /// What it's folded with.
+  pub normalizer: N,
+}
+
+impl<S: Str> Folded<S> {
+  /// Folds `key` ignoring ASCII case.
+  pub fn new(key: S) -> Folded<S> {
+    Folded { key: key, normalizer: AsciiCase }
+  }
+}
+
+impl<S: Str, N: Normalizer> Folded<S, N> {
+  /// Folds `key` with `normalizer`.
+  pub fn with_normalizer(key: S, normalizer: N) -> Folded<S, N> {
+    Folded { key: key, normalizer: normalizer }
+  }
+}
+
+impl<S: Str, N: Normalizer> PartialEq for Folded<S, N> {
+  fn eq(&self, other: &Folded<S, N>) -> bool {
+    self.normalizer.same(self.key.as_slice().as_bytes(), other.key.as_slice().as_bytes())
+  }
+}
+
+impl<S: Str, N: Normalizer> Eq for Folded<S, N> {}
+
+/// Folded `&str`s look up maps of any folded strings, with the lookup's
+/// normalizer.
+impl<'a, S: Str, N: Normalizer> Equiv<Folded<S, N>> for Folded<&'a str, N> {
+  fn equiv(&self, other: &Folded<S, N>) -> bool {
+    self.normalizer.same(self.key.as_bytes(), other.key.as_slice().as_bytes())
+  }
+}
+
+/// The normal form, followed by a `0xff` byte, like a `str`.
+impl<W: hash::Writer, S: Str, N: Normalizer> hash::Hash<W> for Folded<S, N> {
+  fn hash(&self, w: &mut W) {
+    self.normalizer.normalize(self.key.as_slice().as_bytes(), |piece| w.write(piece));
+    w.write([0xff]);
+  }
+}
+
+impl<S: Str, N> fmt::Show for Folded<S, N> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    self.key.as_slice().fmt(f)
+  }
+}
@@ -0,0 +1,161 @@
+//! Map/reduce over a `FrozenHashMap` on several tasks, behind the `par`
+//! feature.
+//!
+//! The values of known keys sit in one flat array, so each worker takes a
+//! contiguous run of slots, and the first also takes the backup store. The
+//! map is shared through an `Arc`, so nothing is copied:
+//!
+//! ```ignore
+//! fn count(_: &String, v: &uint) -> uint { *v }
+//! fn add(a: uint, b: uint) -> uint { a + b }
+//!
+//! let map   = Arc::new(map.freeze());
+//! let total = par::par_iter(&map, 8).map_reduce(count, add).unwrap_or(0);
+//! ```
+//!
+//! Workers are tasks, which can't borrow from their parent, so `f` and
+//! `combine` are plain functions rather than closures.
+
+use std::cmp;
+use std::hash;
+use std::sync::Arc;
+use std::task;
+
+use super::{BackupStore, FrozenHashMap, HashMap, KeyHasher, Positional};
+
+/// Every entry of a map, split between `workers` tasks.
+pub struct ParIter<K, V, H = Positional> {
+  map:     Arc<FrozenHashMap<K, V, H>>,
+  workers: uint,
+}
+
+/// Every value of a map, split between `workers` tasks.
+pub struct ParValues<K, V, H = Positional> {
+  iter: ParIter<K, V, H>,
+}
+
+/// Visits every entry of `map` on `workers` tasks at once.
+pub fn par_iter<K, V, H>(map: &Arc<FrozenHashMap<K, V, H>>, workers: uint) -> ParIter<K, V, H> {
+  ParIter { map: map.clone(), workers: cmp::max(workers, 1) }
+}
+
+/// Visits every value of `map` on `workers` tasks at once.
+pub fn par_values<K, V, H>(map: &Arc<FrozenHashMap<K, V, H>>, workers: uint)
+    -> ParValues<K, V, H> {
+  ParValues { iter: par_iter(map, workers) }
+}
+
+/// Adds `t` to what's been combined so far.
+fn merge<T>(acc: Option<T>, t: Option<T>, combine: fn(T, T) -> T) -> Option<T> {
+  match (acc, t) {
+    (Some(a), Some(b)) => Some(combine(a, b)),
+    (a,       None)    => a,
+    (None,    b)       => b,
+  }
+}
+
+/// Folds `f` of every entry in slots `[lo, hi)`, and of every backup entry
+/// if `backup` is set.
+fn fold_entries<K: Eq + hash::Hash, V, H: KeyHasher<K>, T>(
+    map: &HashMap<K, V, H>, lo: uint, hi: uint, backup: bool,
+    f: fn(&K, &V) -> T, combine: fn(T, T) -> T) -> Option<T> {
+  let mut acc = None;
+
+  for i in range(lo, hi) {
+    acc = merge(acc, map.get_index(i).map(|(k, v)| f(k, v)), combine);
+  }
+
+  if backup {
+    for (k, v) in map.backup.iter() {
+      acc = merge(acc, Some(f(k, v)), combine);
+    }
+  }
+
+  acc
+}
+
+/// Like `fold_entries`, but `f` only sees the values.
+fn fold_values<K: Eq + hash::Hash, V, H: KeyHasher<K>, T>(
+    map: &HashMap<K, V, H>, lo: uint, hi: uint, backup: bool,
+    f: fn(&V) -> T, combine: fn(T, T) -> T) -> Option<T> {
+  let mut acc = None;
+
+  for i in range(lo, hi) {
+    acc = merge(acc, map.get_index(i).map(|(_, v)| f(v)), combine);
+  }
+
+  if backup {
+    for (_, v) in map.backup.iter() {
+      acc = merge(acc, Some(f(v)), combine);
+    }
+  }
+
+  acc
+}
+
+impl<K: Send + Sync + Eq + hash::Hash,
+     V: Send + Sync,
+     H: KeyHasher<K> + Send + Sync>
+    ParIter<K, V, H> {
+  /// Splits the slots into a run per worker, runs `fold` over each run on
+  /// its own task, and combines what they found.
+  fn run<F: Send + Copy, T: Send>(
+      &self, f: F, combine: fn(T, T) -> T,
+      fold: fn(&HashMap<K, V, H>, uint, uint, bool, F, fn(T, T) -> T) -> Option<T>)
+      -> Option<T> {
+    let slots    = self.map.as_map().keys.len();
+    let chunk    = (slots + self.workers - 1) / self.workers;
+    let (tx, rx) = channel();
+
+    for w in range(0, self.workers) {
+      let map = self.map.clone();
+      let tx  = tx.clone();
+      let hi  = cmp::min((w + 1) * chunk, slots);
+      let lo  = cmp::min(w * chunk, hi);
+
+      task::spawn(proc() {
+        tx.send(fold(map.as_map(), lo, hi, w == 0, f, combine));
+      });
+    }
+
+    drop(tx);
+
+    rx.iter().fold(None, |acc, t| merge(acc, t, combine))
+  }
+
+  /// Applies `f` to every entry, and combines the results with `combine`,
+  /// or returns `None` if the map is empty.
+  ///
+  /// Results are combined in no particular order, so `combine` should be
+  /// associative and commutative.
+  pub fn map_reduce<T: Send>(&self, f: fn(&K, &V) -> T, combine: fn(T, T) -> T) -> Option<T> {
+    self.run(f, combine, fold_entries)
+  }
+
+  /// Calls `f` on every entry.
+  pub fn for_each(&self, f: fn(&K, &V)) {
+    fn unit(_: (), _: ()) {}
+    self.map_reduce(f, unit);
+  }
+
+  /// Only the values.
+  pub fn values(self) -> ParValues<K, V, H> {
+    ParValues { iter: self }
+  }
+}
+
+impl<K: Send + Sync + Eq + hash::Hash,
+     V: Send + Sync,
+     H: KeyHasher<K> + Send + Sync>
+    ParValues<K, V, H> {
+  /// Like `ParIter::map_reduce`, but `f` only sees the values.
+  pub fn map_reduce<T: Send>(&self, f: fn(&V) -> T, combine: fn(T, T) -> T) -> Option<T> {
+    self.iter.run(f, combine, fold_values)
+  }
+
+  /// Calls `f` on every value.
+  pub fn for_each(&self, f: fn(&V)) {
+    fn unit(_: (), _: ()) {}
+    self.map_reduce(f, unit);
+  }
+}
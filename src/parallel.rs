@@ -0,0 +1,126 @@
+//! Construction spread across several tasks.
+//!
+//! Attempts are independent, so each worker runs its own stream of them from
+//! its own seed, and the first acyclic graph wins. The winner's seed is the
+//! one recorded, so building from it on a single task gives the same tables.
+
+use std::hash;
+use std::rand::{Isaac64Rng, Rng, SeedableRng};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUint, SeqCst};
+use std::task;
+
+use super::{PerfectHashFn, KeyHasher, Labels, check_duplicates};
+use super::{Algorithm, BuildError, TooManyIterations, Finishing};
+use super::progress::Monitor;
+
+/// How a worker stopped.
+enum Outcome<H> {
+  /// It succeeded, with these labels, hasher, and seed.
+  Found(Labels, H, u64),
+  /// It hit an error that every other worker would hit too.
+  Failed(BuildError),
+  /// Another worker finished first, or the attempts ran out.
+  Stopped,
+}
+
+/// Like `PerfectHashFn::construct`, but with `workers` tasks trying
+/// candidates at once. `max_iterations` bounds the attempts of all of them
+/// together. Returns the function and the number of candidates tried.
+pub fn construct<R: Rng,
+                 K: Eq + hash::Hash + Send + Sync,
+                 H: KeyHasher<K> + Send>(
+    rng: &mut R,
+    hasher: H,
+    keys: Arc<Vec<K>>,
+    algorithm: Algorithm,
+    n: uint,
+    max_iterations: Option<uint>,
+    workers: uint,
+    monitor: &Monitor) -> Result<(PerfectHashFn<K, H>, uint), BuildError> {
+  try!(check_duplicates(keys.as_slice()));
+
+  let done     = Arc::new(AtomicBool::new(false));
+  let attempts = Arc::new(AtomicUint::new(0));
+  let (tx, rx) = channel();
+
+  for _ in range(0, workers) {
+    let seed : u64 = rng.gen();
+    let hasher     = hasher.clone();
+    let keys       = keys.clone();
+    let done       = done.clone();
+    let attempts   = attempts.clone();
+    let tx         = tx.clone();
+    let monitor    = monitor.clone();
+
+    task::spawn(proc() {
+      let outcome =
+        work(seed, hasher, keys.as_slice(), algorithm, n, max_iterations, &*done, &*attempts,
+             &monitor);
+      // The receiver is gone if another worker already won.
+      let _ = tx.send_opt(outcome);
+    });
+  }
+
+  drop(tx);
+
+  for outcome in rx.iter() {
+    match outcome {
+      Found(labels, hasher, seed) => {
+        done.store(true, SeqCst);
+        let iters = attempts.load(SeqCst);
+        monitor.report(iters, Finishing);
+        let mut func = PerfectHashFn::from_parts(labels, hasher, keys.len());
+        func.seed = Some(seed);
+        return Ok((func, iters));
+      }
+      Failed(err) => {
+        done.store(true, SeqCst);
+        return Err(err);
+      }
+      Stopped => {}
+    }
+  }
+
+  // Nobody won and nobody set `done`, so the attempts ran out.
+  match max_iterations {
+    Some(max) => Err(TooManyIterations(max)),
+    None      => fail!("perfect: a construction worker failed"),
+  }
+}
+
+fn work<K: Eq + hash::Hash, H: KeyHasher<K>>(
+    seed: u64,
+    mut hasher: H,
+    keys: &[K],
+    algorithm: Algorithm,
+    n: uint,
+    max_iterations: Option<uint>,
+    done: &AtomicBool,
+    attempts: &AtomicUint,
+    monitor: &Monitor) -> Outcome<H> {
+  let mut rng: Isaac64Rng = SeedableRng::from_seed([seed].as_slice());
+
+  loop {
+    if done.load(SeqCst) {
+      return Stopped;
+    }
+
+    let attempt = attempts.fetch_add(1, SeqCst);
+    match max_iterations {
+      Some(max) if attempt >= max => return Stopped,
+      _                           => {}
+    }
+
+    match monitor.attempt(attempt) {
+      Ok(())   => {}
+      Err(err) => return Failed(err),
+    }
+
+    match PerfectHashFn::attempt(&mut rng, &mut hasher, keys, algorithm, n) {
+      Ok(Some(labels)) => return Found(labels, hasher, seed),
+      Ok(None)         => {}
+      Err(err)         => return Failed(err),
+    }
+  }
+}
@@ -2,84 +2,297 @@
 //!
 //! These tables are slow to initialize, but much faster than traditional
 //! hashtables at lookup/insertion/deletion of elements that it knows about.
-//! Elements that it doesn't know about are thrown into a backup traditional
-//! hashtable. This hashtable is lazily initialized.
+//! Elements that it doesn't know about are thrown into a backup store, a
+//! traditional hashtable unless `HashMap::with_backup` says otherwise.
+//!
+//! The hash function is minimal: `m` known keys map onto exactly `m` slots,
+//! so the value array never has holes reserved for keys that don't exist.
+//! The price is an auxiliary node table of about 2.08 labels per key, each
+//! packed into the `log2(m)` bits it needs, plus eight bytes per byte of
+//! all but the longest 1% of keys. Building with `Bdz` or `Chd`
+//! instead cuts the node table to a few bits per key, and `RecSplit` to
+//! under two.
 #![crate_type = "lib"]
-#![feature(macro_rules, default_type_params, phase)]
+#![feature(macro_rules, default_type_params, phase, unsafe_destructor)]
 #![deny(warnings, missing_doc)]
-#[phase(plugin, link)] extern crate log;
-extern crate graph;
+#![cfg_attr(not(feature = "std"), no_std)]
+#[cfg(feature = "std")] #[phase(plugin, link)] extern crate log;
+#[cfg(feature = "serialize")] extern crate serialize;
+#[cfg(any(feature = "ffi", feature = "hugepages"))] extern crate libc;
+#[cfg(feature = "std")] extern crate time;
+
+// Without `std`, only the read-only tables in `view` are built, on top of
+// `core` and `collections`. The `std` module below stands in for the real
+// one so the shared code can keep its `std::` paths.
+#[cfg(not(feature = "std"))] #[phase(plugin, link)] extern crate core;
+#[cfg(not(feature = "std"))] extern crate collections;
+#[cfg(not(feature = "std"))] use core::prelude::*;
+
+#[cfg(not(feature = "std"))]
+mod std {
+  pub use core::{cmp, fmt, iter, kinds, mem, num, option, raw};
+  pub use collections::{hash, slice};
+}
 
-use std::cmp;
-use std::collections;
+#[cfg(feature = "std")] use std::cmp;
+#[cfg(feature = "std")] use std::collections;
+#[cfg(feature = "std")] use std::default::Default;
+#[cfg(feature = "std")] use std::fmt;
 use std::hash;
-use std::rand;
-use std::vec;
-use graph::Graph;
+#[cfg(feature = "std")] use std::intrinsics;
+#[cfg(feature = "std")] use std::iter;
+#[cfg(feature = "std")] use std::kinds::marker;
+#[cfg(feature = "std")] use std::mem;
+#[cfg(feature = "std")] use std::ops;
+#[cfg(feature = "std")] use std::rand;
+#[cfg(feature = "std")] use std::slice;
+#[cfg(feature = "std")] use std::sync::atomic::{AtomicUint, Relaxed};
+#[cfg(feature = "std")] use std::vec;
+
+#[cfg(feature = "std")] pub use arena::{ArenaHashMap, BumpArena, Region};
+#[cfg(feature = "std")] pub use backup::{BackupStore, LruBackup, SortedBackup, RejectUnknown};
+#[cfg(feature = "std")] pub use backup::{RandomBackup, SeededBackup};
+#[cfg(feature = "std")] pub use backup::UnknownKey;
+#[cfg(feature = "std")] pub use bimap::PerfectBiMap;
+#[cfg(feature = "std")] pub use binary::Persist;
+#[cfg(feature = "std")] pub use builder::{HashMapBuilder, BuildError, DEFAULT_MAX_ITERATIONS};
+#[cfg(feature = "std")] pub use builder::BuildStats;
+#[cfg(feature = "std")] pub use builder::{Algorithm, Chm, Bdz, Chd, RecSplit, PtHash, Fks};
+#[cfg(feature = "std")] pub use builder::{UnknownKeys, CheckBackup, ReturnNone, FailUnknown};
+#[cfg(feature = "std")] pub use builder::{InvalidSpaceFactor, InvalidLambda, InvalidRecSplit};
+#[cfg(feature = "std")] pub use builder::{Overflow, TooManyIterations, DuplicateKey, Cancelled};
+#[cfg(feature = "std")] pub use builder::{DeadlineExceeded, RegionFull};
+pub use corruption::{Corruption, BadLength, OutOfBounds, BadHasher, MisplacedKey, BadCount};
+pub use corruption::KnownKeyInBackup;
+#[cfg(feature = "std")] pub use error::{Error, Build, Io, Corrupt};
+#[cfg(feature = "std")] pub use enum_map::{EnumKey, EnumMap};
+#[cfg(feature = "std")] pub use compact::CompactHashMap;
+#[cfg(feature = "std")] pub use concurrent::ConcurrentHashMap;
+#[cfg(feature = "std")] pub use cow::CowHashMap;
+#[cfg(feature = "std")] pub use frozen::FrozenHashMap;
+#[cfg(feature = "std")] pub use index::PerfectIndex;
+#[cfg(feature = "std")] pub use keys::{StrKey, BytesKey, StrQuery, BytesQuery};
+#[cfg(feature = "std")] pub use live::{LiveHashMap, RebuildPolicy};
+#[cfg(feature = "std")] pub use multimap::PerfectMultiMap;
+#[cfg(feature = "std")] pub use normalize::{Folded, Normalizer, AsciiCase};
+#[cfg(feature = "std")] pub use progress::{Progress, Phase, Searching, Finishing, CancelToken};
+#[cfg(feature = "std")] pub use rank::RankedBits;
+#[cfg(feature = "std")] pub use set::HashSet;
+#[cfg(feature = "std")] pub use sharded::ShardedHashFn;
+#[cfg(feature = "std")] pub use trusted::TrustedHashMap;
+pub use view::{HashMapRef, Pod, StaticMap, StaticSet};
+
+#[cfg(feature = "std")] use bits::PackedInts;
+#[cfg(feature = "std")] use builder::{DEFAULT_CHD_LAMBDA, DEFAULT_PTHASH_LAMBDA};
+#[cfg(feature = "std")] use builder::{RECSPLIT_LEAF_SIZE, RECSPLIT_BUCKET_SIZE};
+#[cfg(feature = "std")] use progress::Monitor;
+#[cfg(feature = "std")] use slots::Slots;
+
+#[cfg(feature = "std")] pub mod arena;
+#[cfg(feature = "std")] pub mod backup;
+#[cfg(feature = "std")] pub mod bimap;
+#[cfg(feature = "std")] pub mod binary;
+#[cfg(feature = "std")] pub mod builder;
+#[cfg(feature = "std")] pub mod codegen;
+#[cfg(feature = "std")] pub mod compact;
+#[cfg(feature = "std")] pub mod concurrent;
+#[cfg(feature = "std")] pub mod cow;
+pub mod corruption;
+#[cfg(feature = "std")] pub mod enum_map;
+#[cfg(feature = "std")] pub mod error;
+#[cfg(feature = "std")] pub mod frozen;
+#[cfg(feature = "std")] pub mod index;
+#[cfg(feature = "std")] pub mod keys;
+#[cfg(feature = "std")] pub mod live;
+#[cfg(feature = "std")] pub mod multimap;
+#[cfg(feature = "std")] pub mod normalize;
+#[cfg(feature = "std")] pub mod rank;
+#[cfg(feature = "std")] pub mod set;
+#[cfg(feature = "std")] pub mod trusted;
+pub mod view;
+
+#[cfg(feature = "std")] mod bdz;
+#[cfg(feature = "std")] mod bits;
+#[cfg(feature = "std")] mod chd;
+#[cfg(feature = "std")] mod fks;
+#[cfg(feature = "std")] mod parallel;
+#[cfg(feature = "std")] mod progress;
+#[cfg(feature = "std")] mod pthash;
+#[cfg(feature = "std")] mod recsplit;
+#[cfg(feature = "std")] mod sharded;
+#[cfg(feature = "std")] mod slots;
+#[cfg(all(test, feature = "std"))] mod testing;
 
-pub struct HashMap<K, V> {
-  nodes:  Vec<uint>,
-  t1:     Vec<uint>,
-  t2:     Vec<uint>,
-  table:  Vec<Option<(K, V)>>,
-  backup: Option<collections::HashMap<K, V>>,
+#[cfg(feature = "dot")] pub mod dot;
+#[cfg(feature = "ffi")] pub mod ffi;
+#[cfg(feature = "hugepages")] pub mod huge;
+#[cfg(feature = "par")] pub mod par;
+#[cfg(feature = "serialize")] mod serialization;
+
+/// A hashtable specialized to a set of keys known at construction time.
+///
+/// Every known key owns exactly one slot in `table`. Keys outside that set
+/// are stored in `backup`, a `BackupStore` of type `B`. The default
+/// `SeededBackup` isn't allocated until the first unknown key is inserted.
+///
+/// Each known key lives in the slot it hashes to, and `table` has one slot
+/// per known key. Only the hash function's labeling is larger than the key
+/// set.
+///
+/// Known keys are hashed with `H`, a `Positional` hasher unless the table
+/// was built with `HashMapBuilder::build_with_hasher`.
+#[cfg(feature = "std")]
+pub struct HashMap<K, V, H = Positional, B = SeededBackup<K, V>> {
+  func:       PerfectHashFn<K, H>,
+  keys:       Vec<K>,
+  table:      Slots<V>,
+  /// The number of filled slots in `table`. The backup counts its own.
+  size:       uint,
+  backup:     B,
+  rebuild_at: Option<uint>,
+  /// What lookups do with keys that miss the slots.
+  unknown:    UnknownKeys,
+  /// Every slot, in the order of its key, from `sort_keys`.
+  sorted:     Option<Vec<uint>>,
+  /// Sorts the known keys again after a rebuild, once `sort_keys` has run.
+  resort:     Option<fn(&[K]) -> Vec<uint>>,
+  /// Where lookups are served from, if anyone asked.
+  counters:   Option<Counters>,
+  /// What a sample of lookups took, if anyone asked.
+  sampler:    Option<Sampler>,
+  /// What `rebuild` builds with.
+  settings:   HashMapBuilder,
 }
 
+/// A map that only ever holds its known keys. `insert_checked` turns every
+/// other key away, and no backup table is ever allocated. Make one with
+/// `HashMap::with_backup(RejectUnknown)`.
+#[cfg(feature = "std")]
+pub type StrictHashMap<K, V, H = Positional> = HashMap<K, V, H, RejectUnknown>;
+
+/// The hash state used to map a key onto its two graph vertices.
 pub struct PerfectHashState<'a> {
-  t1: &'a [uint],
-  t2: &'a [uint],
+  /// The weights of each byte position, for `u` and for `v`.
+  weights: &'a [(u32, u32)],
   max_length: uint,
   n:  uint,
-  m:  uint,
   i:  uint,
+  /// How many times the key has wrapped around the tables.
+  round: uint,
   u:  uint,
   v:  uint,
 }
 
 impl<'a> hash::Writer for PerfectHashState<'a> {
   fn write(&mut self, bytes: &[u8]) {
-    for (&b, i) in bytes.iter().zip(range(self.i, self.max_length)) {
-      let bu = b as uint;
-      self.u = self.u.checked_add(&(self.t1[i] * bu)).expect("should not overflow");
-      self.v = self.v.checked_add(&(self.t2[i] * bu)).expect("should not overflow");
+    // Only empty keys were known, so there's nothing to weigh bytes with.
+    if self.max_length == 0 {
+      return;
+    }
+
+    // Bytes past the end of the tables wrap around to the start, so every
+    // byte of a long key still contributes.
+    for &b in bytes.iter() {
+      let (mut w1, mut w2) = self.weights[self.i];
+      if self.round > 0 {
+        w1 = round_weight(w1, self.round, self.n);
+        w2 = round_weight(w2, self.round, self.n);
+      }
+      self.u = add_weighted(self.u, w1, b, self.n);
+      self.v = add_weighted(self.v, w2, b, self.n);
+
+      self.i += 1;
+      if self.i == self.max_length {
+        self.i      = 0;
+        self.round += 1;
+      }
     }
-    self.i = cmp::min(self.i + bytes.len(), self.max_length);
   }
 }
 
 impl<'a> PerfectHashState<'a> {
-  fn new<'a>(t1: &'a [uint], t2: &'a [uint], n: uint, m: uint) -> PerfectHashState<'a> {
+  fn new<'a>(weights: &'a [(u32, u32)], n: uint) -> PerfectHashState<'a> {
     PerfectHashState {
-      t1: t1,
-      t2: t2,
-      max_length: t1.len(),
+      weights: weights,
+      max_length: weights.len(),
       n: n,
-      m: m,
       i: 0,
+      round: 0,
       u: 0,
-      v: 0
+      v: 0,
     }
   }
 
   fn get_u(&self) -> uint {
-    self.u % self.n
+    self.with_length(self.u, |(w1, _)| w1)
   }
 
   fn get_v(&self) -> uint {
-    self.v % self.n
+    self.with_length(self.v, |(_, w2)| w2)
+  }
+
+  /// `acc` with the number of bytes written mixed in, by the first weight
+  /// `pick` takes from the tables.
+  fn with_length(&self, acc: uint, pick: |(u32, u32)| -> u32) -> uint {
+    // Only empty keys were known, so they all have the same length.
+    if self.max_length == 0 {
+      return acc;
+    }
+
+    let length = self.round * self.max_length + self.i;
+    let w      = length_weight(pick(self.weights[0]), length, self.n);
+    ((acc as u64 + w as u64) % self.n as u64) as uint
   }
 }
 
+/// `(acc + weight * b) % n`, for `acc` and `weight` already reduced mod `n`.
+///
+/// The accumulators are reduced after every byte, so they can't overflow no
+/// matter how long the key is. Doing it in 64 bits keeps the product in range
+/// on 32-bit targets too. On 64-bit ones, it would take n > 2^55 vertices to
+/// overflow.
+fn add_weighted(acc: uint, weight: u32, b: u8, n: uint) -> uint {
+  let n = n as u64;
+  ((acc as u64 + (weight as u64 * b as u64) % n) % n) as uint
+}
+
+/// The weight a byte gets `round` times around the tables, from `w`, the
+/// one its position has the first time. Every round gets weights of its
+/// own, so bytes of a long key can't trade places a table's length apart
+/// without changing its vertices, whatever the tables are.
+fn round_weight(w: u32, round: uint, n: uint) -> u32 {
+  (mix(w as u64 ^ (round as u64) << 32) % n as u64) as u32
+}
+
+/// What a key of `length` bytes adds to a vertex, for a table whose first
+/// weight is `w`. Zero bytes weigh nothing, so without it keys that differ
+/// only by trailing zeros would share their vertices under every table, and
+/// construction would never finish.
+fn length_weight(w: u32, length: uint, n: uint) -> uint {
+  (mix(mix(w as u64) + length as u64) % n as u64) as uint
+}
+
+/// splitmix64's finalizer: every bit of the result depends on every bit of
+/// `z`.
+fn mix(z: u64) -> u64 {
+  let z = (z ^ (z >> 30)) * 0xbf58476d1ce4e5b9;
+  let z = (z ^ (z >> 27)) * 0x94d049bb133111eb;
+  z ^ (z >> 31)
+}
+
+#[cfg(feature = "std")]
 struct ByteCounter {
   i: uint,
 }
 
+#[cfg(feature = "std")]
 impl hash::Writer for ByteCounter {
   fn write(&mut self, bytes: &[u8]) {
     self.i += bytes.len();
   }
 }
 
+#[cfg(feature = "std")]
 impl ByteCounter {
   fn new() -> ByteCounter {
     ByteCounter { i: 0 }
@@ -90,63 +303,2798 @@ impl ByteCounter {
   }
 }
 
-fn gen_table<R: rand::Rng>(rng: &R, n: uint, m: uint) -> Vec<uint> {
-  rng.gen_iter().map(|x: uint| x % n).take(m).collect()
+/// The number of bytes `k` feeds its hasher.
+#[cfg(feature = "std")]
+fn hashed_length<K: hash::Hash<ByteCounter>>(k: &K) -> uint {
+  let mut c = ByteCounter::new();
+  k.hash(&mut c);
+  c.get_count()
 }
 
-impl<'a,
-     K: Eq
-      + hash::Hash
-      + hash::Hash<PerfectHashState<'a>>
-      + hash::Hash<ByteCounter>,
-     V>
-    HashMap<K, V> {
+/// The number of weights to draw for keys of these `lengths`: enough for
+/// all but the longest 1% of them, which wrap around. One very long key
+/// then doesn't size the tables for all the others.
+#[cfg(feature = "std")]
+fn table_length(mut lengths: Vec<uint>) -> uint {
+  if lengths.is_empty() {
+    return 0;
+  }
 
-  pub fn new(known_vals: Vec<K>) -> HashMap<K, V> {
-    let max_length = known_vals.iter().map(|k| {
-        let mut c = ByteCounter::new();
-        k.hash(&mut c);
-        c.get_count()
-      }).max();
+  lengths.sort();
+  match lengths[(lengths.len() - 1) * 99 / 100] {
+    // Any nonempty key needs a weight to wrap around to.
+    0       => cmp::min(lengths[lengths.len() - 1], 1),
+    typical => typical,
+  }
+}
+
+/// Weights are 32 bits to keep the tables small. With more than 2^32
+/// vertices they don't cover the whole range, but every one is still `< n`.
+#[cfg(feature = "std")]
+fn gen_table<R: rand::Rng>(rng: &mut R, n: uint, len: uint) -> Vec<(u32, u32)> {
+  let n = n as u64;
+  rng.gen_iter().map(|(x, y): (u32, u32)| ((x as u64 % n) as u32, (y as u64 % n) as u32))
+    .take(len)
+    .collect()
+}
 
-    let mut rng = rand::task_rng();
+/// Maps keys onto the two graph vertices that construction connects.
+///
+/// Each implementation is a family of hash functions. Construction draws a
+/// fresh member of the family with `reseed` on every attempt, until the
+/// graph it produces is acyclic.
+#[cfg(feature = "std")]
+pub trait KeyHasher<K>: Clone {
+  /// Picks new random functions, suited to hashing `keys` onto `n` vertices.
+  fn reseed<R: rand::Rng>(&mut self, rng: &mut R, keys: &[K], n: uint);
 
-    let m = known_vals.len();
+  /// Like `reseed`, but for keys that are streamed instead of held in a
+  /// slice: each call of `keys` iterates over all of them again. By default
+  /// they're collected for `reseed`. The built-in hashers override this, and
+  /// never hold more than one key at a time.
+  fn reseed_streamed<R: rand::Rng, I: Iterator<K>>(&mut self, rng: &mut R, keys: || -> I,
+                                                   n: uint) {
+    let keys: Vec<K> = keys().collect();
+    self.reseed(rng, keys.as_slice(), n);
+  }
+
+  /// The two vertices in `[0, n)` that `k` connects, or `None` if `k` can't
+  /// be hashed by the current functions.
+  fn vertices(&self, k: &K, n: uint) -> Option<(uint, uint)>;
 
-    // c = 2.08 according to the paper. As long as it's greater than 2,
-    // we're good.
-    let n = 2*m + m/12;
+  /// Appends the vertices of each of `keys` to `out`, as `vertices` would.
+  /// Returns false, with `out` partly filled, if one can't be hashed.
+  ///
+  /// Construction hashes every key this way, so hashers that can hash
+  /// several keys at a time faster than one by one should override it.
+  fn vertices_batch(&self, keys: &[K], n: uint, out: &mut Vec<(uint, uint)>) -> bool {
+    for k in keys.iter() {
+      match self.vertices(k, n) {
+        Some(uv) => out.push(uv),
+        None     => return false,
+      }
+    }
+    true
+  }
 
-    let acyclic_t1 : Vec<uint>;
-    let acyclic_t2 : Vec<uint>;
-    let acyclic_g  : Graph<(), ()>;
+  /// Checks that the functions' internal state is usable with `n` vertices.
+  /// Loaders call this before trusting deserialized state.
+  fn is_consistent(&self, _n: uint) -> bool {
+    true
+  }
 
-    let mut iters : uint = 0;
+  /// The bytes of tables the functions keep on the heap.
+  fn heap_size(&self) -> uint {
+    0
+  }
+
+  /// The number of bytes the functions weigh for `k`, if they weigh bytes.
+  /// Lookup sampling records it.
+  fn hashed_bytes(&self, _k: &K) -> Option<uint> {
+    None
+  }
+}
+
+/// The default `KeyHasher`: every byte position of a key has a random
+/// weight in each of two tables, and a key's vertices are the weighted sums
+/// of its bytes.
+///
+/// The tables have one entry per byte of all but the longest 1% of known
+/// keys. Longer keys wrap around, with weights mixed from the same entries
+/// for each time around. They're stored interleaved, so each byte
+/// reads both its weights from one cache line.
+#[deriving(Clone)]
+#[cfg(feature = "std")]
+pub struct Positional {
+  weights: Vec<(u32, u32)>,
+}
+
+#[cfg(feature = "std")]
+impl Positional {
+  /// A hasher with empty tables. Construction fills them in.
+  pub fn new() -> Positional {
+    Positional { weights: Vec::new() }
+  }
+}
+
+#[cfg(feature = "std")]
+impl<'a, K: hash::Hash<PerfectHashState<'a>> + hash::Hash<ByteCounter>> KeyHasher<K> for Positional {
+  fn reseed<R: rand::Rng>(&mut self, rng: &mut R, keys: &[K], n: uint) {
+    let length = table_length(keys.iter().map(hashed_length).collect());
+    self.weights = gen_table(rng, n, length);
+  }
+
+  fn reseed_streamed<R: rand::Rng, I: Iterator<K>>(&mut self, rng: &mut R, keys: || -> I,
+                                                   n: uint) {
+    let length = table_length(keys().map(|k| hashed_length(&k)).collect());
+    self.weights = gen_table(rng, n, length);
+  }
+
+  fn vertices(&self, k: &K, n: uint) -> Option<(uint, uint)> {
+    let mut state = PerfectHashState::new(self.weights.as_slice(), n);
+    k.hash(&mut state);
+    Some((state.get_u(), state.get_v()))
+  }
+
+  fn is_consistent(&self, n: uint) -> bool {
+    self.weights.iter().all(|&(w1, w2)| (w1 as uint) < n && (w2 as uint) < n)
+  }
+
+  fn heap_size(&self) -> uint {
+    self.weights.len() * mem::size_of::<(u32, u32)>()
+  }
+
+  fn hashed_bytes(&self, k: &K) -> Option<uint> {
+    Some(hashed_length(k))
+  }
+}
+
+/// A `KeyHasher` built on SipHash-2-4 with random keys. Each vertex costs a
+/// full SipHash of the key, but there are no tables to store, and no limit on
+/// key length.
+#[deriving(Clone)]
+#[cfg(feature = "std")]
+pub struct Sip {
+  k0: u64,
+  k1: u64,
+}
+
+#[cfg(feature = "std")]
+impl Sip {
+  /// A hasher with arbitrary keys. Construction replaces them.
+  pub fn new() -> Sip {
+    Sip { k0: 0, k1: 0 }
+  }
+}
+
+#[cfg(feature = "std")]
+impl<K: hash::Hash> KeyHasher<K> for Sip {
+  fn reseed<R: rand::Rng>(&mut self, rng: &mut R, _keys: &[K], _n: uint) {
+    self.k0 = rng.gen();
+    self.k1 = rng.gen();
+  }
+
+  fn reseed_streamed<R: rand::Rng, I: Iterator<K>>(&mut self, rng: &mut R, _keys: || -> I,
+                                                   _n: uint) {
+    self.k0 = rng.gen();
+    self.k1 = rng.gen();
+  }
+
+  fn vertices(&self, k: &K, n: uint) -> Option<(uint, uint)> {
+    let u = hash::sip::hash_with_keys(self.k0, self.k1, k);
+    let v = hash::sip::hash_with_keys(self.k1, self.k0, k);
+    Some(((u % n as u64) as uint, (v % n as u64) as uint))
+  }
+}
+
+/// A `KeyHasher` for byte strings, using the same weights as `Positional`
+/// but reading `as_slice()` directly instead of going through `Hash`.
+///
+/// Keys hash as if they were followed by a `0xff` byte, like `str`s do, so
+/// keys that differ only in trailing zeros still get different vertices.
+#[deriving(Clone)]
+#[cfg(feature = "std")]
+pub struct Bytes {
+  weights: Vec<(u32, u32)>,
+}
+
+#[cfg(feature = "std")]
+impl Bytes {
+  /// A hasher with empty tables. Construction fills them in.
+  pub fn new() -> Bytes {
+    Bytes { weights: Vec::new() }
+  }
+
+  /// Hashes four keys at once, reading the weights of each byte position
+  /// once for all of them. The lanes are independent, so the inner loop
+  /// vectorizes.
+  ///
+  /// The sums are only reduced at the end, which gives the same vertices as
+  /// reducing after every byte. Every term is under 2^40, so that's safe for
+  /// keys shorter than `MAX_UNREDUCED`, which the caller checks, along with
+  /// the keys not being long enough to wrap around the tables. The length
+  /// goes in last, as `PerfectHashState` adds it.
+  fn vertices_lanes(&self, keys: [&[u8], ..4], n: uint, out: &mut Vec<(uint, uint)>) {
+    let mut u = [0u64, ..4];
+    let mut v = [0u64, ..4];
+
+    let common = keys.iter().map(|k| k.len()).min().unwrap_or(0);
+    for j in range(0, common) {
+      let (w1, w2) = self.weights[j];
+      for l in range(0u, 4) {
+        let b = keys[l][j] as u64;
+        u[l] += w1 as u64 * b;
+        v[l] += w2 as u64 * b;
+      }
+    }
+
+    let (first1, first2) = self.weights[0];
+    let n64 = n as u64;
+    for l in range(0u, 4) {
+      let k = keys[l];
+      for j in range(common, k.len() + 1) {
+        let b = if j < k.len() { k[j] as u64 } else { 0xff };
+        let (w1, w2) = self.weights[j];
+        u[l] += w1 as u64 * b;
+        v[l] += w2 as u64 * b;
+      }
+      let length = k.len() + 1;
+      out.push((((u[l] % n64 + length_weight(first1, length, n) as u64) % n64) as uint,
+                ((v[l] % n64 + length_weight(first2, length, n) as u64) % n64) as uint));
+    }
+  }
+}
+
+/// Keys `Bytes::vertices_batch` hashes without reducing the sums along the
+/// way must be shorter than this.
+#[cfg(feature = "std")]
+static MAX_UNREDUCED: uint = 1 << 23;
+
+#[cfg(feature = "std")]
+impl<K: slice::AsSlice<u8>> KeyHasher<K> for Bytes {
+  fn reseed<R: rand::Rng>(&mut self, rng: &mut R, keys: &[K], n: uint) {
+    let length = table_length(keys.iter().map(|k| k.as_slice().len() + 1).collect());
+    self.weights = gen_table(rng, n, length);
+  }
+
+  fn reseed_streamed<R: rand::Rng, I: Iterator<K>>(&mut self, rng: &mut R, keys: || -> I,
+                                                   n: uint) {
+    let length = table_length(keys().map(|k| k.as_slice().len() + 1).collect());
+    self.weights = gen_table(rng, n, length);
+  }
+
+  fn vertices(&self, k: &K, n: uint) -> Option<(uint, uint)> {
+    let mut state = PerfectHashState::new(self.weights.as_slice(), n);
+    hash::Writer::write(&mut state, k.as_slice());
+    hash::Writer::write(&mut state, [0xff]);
+    Some((state.get_u(), state.get_v()))
+  }
+
+  fn vertices_batch(&self, keys: &[K], n: uint, out: &mut Vec<(uint, uint)>) -> bool {
+    let limit = cmp::min(self.weights.len(), MAX_UNREDUCED);
+
+    for group in keys.chunks(4) {
+      let fast = group.len() == 4 && group.iter().all(|k| k.as_slice().len() < limit);
+      if fast {
+        let lanes = [group[0].as_slice(), group[1].as_slice(),
+                     group[2].as_slice(), group[3].as_slice()];
+        self.vertices_lanes(lanes, n, out);
+      } else {
+        for k in group.iter() {
+          out.push(self.vertices(k, n).unwrap());
+        }
+      }
+    }
+    true
+  }
+
+  fn is_consistent(&self, n: uint) -> bool {
+    self.weights.iter().all(|&(w1, w2)| (w1 as uint) < n && (w2 as uint) < n)
+  }
+
+  fn heap_size(&self) -> uint {
+    self.weights.len() * mem::size_of::<(u32, u32)>()
+  }
+
+  fn hashed_bytes(&self, k: &K) -> Option<uint> {
+    Some(k.as_slice().len() + 1)
+  }
+}
+
+/// A map over byte string keys, hashed with `Bytes`. Build one with
+/// `HashMapBuilder::build_with_hasher(Bytes::new(), keys)`.
+#[cfg(feature = "std")]
+pub type BytesHashMap<K, V> = HashMap<K, V, Bytes>;
+
+/// Keys with a byte encoding owned by this crate, which `Stable` hashes.
+///
+/// `Positional` weighs whatever bytes a key's `Hash` impl feeds it, and std
+/// is free to change those between releases. This encoding never changes:
+///
+/// * Integers, `bool`s and `char`s are their value, little-endian, at their
+///   own width. `uint` and `int` are always 8 bytes.
+/// * Strings are their UTF-8 bytes followed by a `0xff` byte.
+/// * `Vec`s are their length as 8 bytes, then each element.
+/// * Pairs are the first element, then the second.
+#[cfg(feature = "std")]
+pub trait StableKey {
+  /// Feeds the encoding of `self` to `w`.
+  fn stable_hash<W: hash::Writer>(&self, w: &mut W);
+}
+
+/// The low `len` bytes of `x`, least significant first.
+#[cfg(feature = "std")]
+fn write_le<W: hash::Writer>(w: &mut W, x: u64, len: uint) {
+  let mut buf = [0u8, ..8];
+  for i in range(0, len) {
+    buf[i] = (x >> (8 * i)) as u8;
+  }
+  hash::Writer::write(w, buf.slice_to(len));
+}
+
+macro_rules! stable_int(
+  ($t:ty, $len:expr) => (
+    #[cfg(feature = "std")]
+    impl StableKey for $t {
+      fn stable_hash<W: hash::Writer>(&self, w: &mut W) {
+        write_le(w, *self as u64, $len)
+      }
+    }
+  )
+)
+
+stable_int!(u8,   1)
+stable_int!(u16,  2)
+stable_int!(u32,  4)
+stable_int!(u64,  8)
+stable_int!(uint, 8)
+stable_int!(i8,   1)
+stable_int!(i16,  2)
+stable_int!(i32,  4)
+stable_int!(i64,  8)
+stable_int!(int,  8)
+stable_int!(bool, 1)
+stable_int!(char, 4)
+
+#[cfg(feature = "std")]
+impl<'a> StableKey for &'a str {
+  fn stable_hash<W: hash::Writer>(&self, w: &mut W) {
+    hash::Writer::write(w, self.as_bytes());
+    hash::Writer::write(w, [0xff]);
+  }
+}
+
+#[cfg(feature = "std")]
+impl StableKey for String {
+  fn stable_hash<W: hash::Writer>(&self, w: &mut W) {
+    self.as_slice().stable_hash(w)
+  }
+}
+
+#[cfg(feature = "std")]
+impl<T: StableKey> StableKey for Vec<T> {
+  fn stable_hash<W: hash::Writer>(&self, w: &mut W) {
+    write_le(w, self.len() as u64, 8);
+    for x in self.iter() {
+      x.stable_hash(w);
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl<A: StableKey, B: StableKey> StableKey for (A, B) {
+  fn stable_hash<W: hash::Writer>(&self, w: &mut W) {
+    let (ref a, ref b) = *self;
+    a.stable_hash(w);
+    b.stable_hash(w);
+  }
+}
+
+/// A `KeyHasher` with the same tables as `Positional`, but weighing each
+/// key's `StableKey` encoding instead of what its `Hash` impl writes. Use
+/// it for tables that are saved with `write_to` and loaded by a program
+/// built with another Rust release.
+#[deriving(Clone)]
+#[cfg(feature = "std")]
+pub struct Stable {
+  weights: Vec<(u32, u32)>,
+}
+
+#[cfg(feature = "std")]
+impl Stable {
+  /// A hasher with empty tables. Construction fills them in.
+  pub fn new() -> Stable {
+    Stable { weights: Vec::new() }
+  }
+}
+
+/// The number of bytes in the `StableKey` encoding of `k`.
+#[cfg(feature = "std")]
+fn stable_length<K: StableKey>(k: &K) -> uint {
+  let mut c = ByteCounter::new();
+  k.stable_hash(&mut c);
+  c.get_count()
+}
+
+#[cfg(feature = "std")]
+impl<K: StableKey> KeyHasher<K> for Stable {
+  fn reseed<R: rand::Rng>(&mut self, rng: &mut R, keys: &[K], n: uint) {
+    let length = table_length(keys.iter().map(stable_length).collect());
+    self.weights = gen_table(rng, n, length);
+  }
+
+  fn reseed_streamed<R: rand::Rng, I: Iterator<K>>(&mut self, rng: &mut R, keys: || -> I,
+                                                   n: uint) {
+    let length = table_length(keys().map(|k| stable_length(&k)).collect());
+    self.weights = gen_table(rng, n, length);
+  }
+
+  fn vertices(&self, k: &K, n: uint) -> Option<(uint, uint)> {
+    let mut state = PerfectHashState::new(self.weights.as_slice(), n);
+    k.stable_hash(&mut state);
+    Some((state.get_u(), state.get_v()))
+  }
+
+  fn is_consistent(&self, n: uint) -> bool {
+    self.weights.iter().all(|&(w1, w2)| (w1 as uint) < n && (w2 as uint) < n)
+  }
+
+  fn heap_size(&self) -> uint {
+    self.weights.len() * mem::size_of::<(u32, u32)>()
+  }
+
+  fn hashed_bytes(&self, k: &K) -> Option<uint> {
+    Some(stable_length(k))
+  }
+}
+
+/// A map whose keys are hashed with `Stable`, so its saved tables load the
+/// same under any Rust release. Build one with
+/// `HashMapBuilder::build_with_hasher(Stable::new(), keys)`.
+#[cfg(feature = "std")]
+pub type StableHashMap<K, V> = HashMap<K, V, Stable>;
+
+/// Fixed-width integer keys, which `Multiplicative` hashes directly.
+#[cfg(feature = "std")]
+pub trait IntKey {
+  /// The key's bits, zero- or sign-extended.
+  fn to_u64(&self) -> u64;
+}
+
+macro_rules! int_key(
+  ($t:ty) => (
+    #[cfg(feature = "std")]
+    impl IntKey for $t {
+      fn to_u64(&self) -> u64 {
+        *self as u64
+      }
+    }
+  )
+)
+
+int_key!(u8)
+int_key!(u16)
+int_key!(u32)
+int_key!(u64)
+int_key!(uint)
+int_key!(i8)
+int_key!(i16)
+int_key!(i32)
+int_key!(i64)
+int_key!(int)
+int_key!(char)
+
+/// A `KeyHasher` for integer keys: each vertex is the key times a random odd
+/// constant, with the high bits folded down. There's no per-byte loop and no
+/// table to store.
+#[deriving(Clone)]
+#[cfg(feature = "std")]
+pub struct Multiplicative {
+  a1: u64,
+  a2: u64,
+}
+
+#[cfg(feature = "std")]
+impl Multiplicative {
+  /// A hasher with arbitrary constants. Construction replaces them.
+  pub fn new() -> Multiplicative {
+    Multiplicative { a1: 1, a2: 1 }
+  }
+}
+
+/// `x * a`, wrapping, with the well-mixed high half xored into the low half.
+#[cfg(feature = "std")]
+fn multiply_shift(x: u64, a: u64) -> u64 {
+  let h = x * a;
+  h ^ (h >> 32)
+}
+
+#[cfg(feature = "std")]
+impl<K: IntKey> KeyHasher<K> for Multiplicative {
+  fn reseed<R: rand::Rng>(&mut self, rng: &mut R, _keys: &[K], _n: uint) {
+    self.a1 = rng.gen::<u64>() | 1;
+    self.a2 = rng.gen::<u64>() | 1;
+  }
+
+  fn reseed_streamed<R: rand::Rng, I: Iterator<K>>(&mut self, rng: &mut R, _keys: || -> I,
+                                                   _n: uint) {
+    self.a1 = rng.gen::<u64>() | 1;
+    self.a2 = rng.gen::<u64>() | 1;
+  }
+
+  fn vertices(&self, k: &K, n: uint) -> Option<(uint, uint)> {
+    let x = k.to_u64();
+    let u = multiply_shift(x, self.a1);
+    let v = multiply_shift(x, self.a2);
+    Some(((u % n as u64) as uint, (v % n as u64) as uint))
+  }
+}
+
+/// A map over integer keys, hashed with `Multiplicative`. Build one with
+/// `HashMapBuilder::build_with_hasher(Multiplicative::new(), keys)`.
+#[cfg(feature = "std")]
+pub type IntHashMap<K, V> = HashMap<K, V, Multiplicative>;
+
+/// The number of keys a CHM attempt hashes at a time.
+#[cfg(feature = "std")]
+static HASH_BATCH: uint = 1024;
+
+/// Union-find over graph vertices, for spotting cycles as edges are added.
+#[cfg(feature = "std")]
+struct DisjointSets {
+  parent: Vec<uint>,
+  rank:   Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl DisjointSets {
+  fn new(n: uint) -> DisjointSets {
+    DisjointSets {
+      parent: Vec::from_fn(n, |i| i),
+      rank:   Vec::from_elem(n, 0u8),
+    }
+  }
+
+  fn find(&mut self, mut x: uint) -> uint {
+    // Path halving: point every other vertex on the way at its grandparent.
+    while self.parent[x] != x {
+      let grandparent = self.parent[self.parent[x]];
+      *self.parent.get_mut(x) = grandparent;
+      x = grandparent;
+    }
+    x
+  }
+
+  /// Joins the sets holding `x` and `y`. Returns false if they were already
+  /// the same set, in which case an edge between them closes a cycle.
+  fn union(&mut self, x: uint, y: uint) -> bool {
+    let x = self.find(x);
+    let y = self.find(y);
+
+    if x == y {
+      return false;
+    }
+
+    if self.rank[x] < self.rank[y] {
+      *self.parent.get_mut(x) = y;
+    } else {
+      *self.parent.get_mut(y) = x;
+      if self.rank[x] == self.rank[y] {
+        *self.rank.get_mut(x) += 1;
+      }
+    }
+
+    true
+  }
+}
+
+/// Labels the vertices of the graph formed by `edges` so that for the `i`th
+/// edge `(u, v)`, `(g[u] + g[v]) % m == i`. Such a labeling exists exactly
+/// when the undirected graph is acyclic, so this returns `None` on a cycle.
+#[cfg(feature = "std")]
+fn assign_nodes(n: uint, edges: &[(uint, uint)]) -> Option<Vec<uint>> {
+  let m = edges.len();
+
+  let mut adjacent: Vec<Vec<(uint, uint)>> = Vec::from_fn(n, |_| Vec::new());
+
+  for (i, &(u, v)) in edges.iter().enumerate() {
+    if u == v {
+      return None;
+    }
+    adjacent.get_mut(u).push((v, i));
+    adjacent.get_mut(v).push((u, i));
+  }
+
+  let mut nodes   = Vec::from_elem(n, 0u);
+  let mut visited = Vec::from_elem(n, false);
+  let mut used    = Vec::from_elem(m, false);
+  let mut stack   = Vec::new();
+
+  for root in range(0, n) {
+    if visited[root] {
+      continue;
+    }
+
+    *visited.get_mut(root) = true;
+    stack.push(root);
 
     loop {
-      let g : Graph<(), ()> = Graph::new();
+      let x = match stack.pop() { Some(x) => x, None => break };
+      let gx = nodes[x];
+
+      for &(y, i) in adjacent[x].iter() {
+        if used[i] {
+          continue;
+        }
+        *used.get_mut(i) = true;
 
-      let t1 = gen_table(&rng, n, m);
-      let t2 = gen_table(&rng, n, m);
+        // We've reached y a second way. That's a cycle.
+        if visited[y] {
+          return None;
+        }
 
-      for w in known_vals.iter() {
-        let mut state = PerfectHashState::new(t1.as_slice(), t2.as_slice(), n, m);
-        w.hash(&mut state);
-        let f1 = state.get_u();
-        let f2 = state.get_v();
-        g.insert_vertex(f1, ());
-        g.insert_vertex(f2, ());
-        g.insert_directed_edge(f1, f2, ());
+        *visited.get_mut(y) = true;
+        *nodes.get_mut(y) = ((i as u64 + m as u64 - gx as u64) % m as u64) as uint;
+        stack.push(y);
       }
+    }
+  }
 
-      iters += 1;
+  Some(nodes)
+}
+
+/// The slot `k` would occupy in a table of `m` keys if it were a known key.
+/// It's up to the caller to check that the key stored there is `k`.
+fn candidate_slot<'a, K: hash::Hash<PerfectHashState<'a>>>(
+    nodes: &'a [uint], weights: &'a [(u32, u32)], m: uint, k: &K) -> Option<uint> {
+  if m == 0 {
+    return None;
+  }
+
+  let mut state = PerfectHashState::new(weights, nodes.len());
+  k.hash(&mut state);
+
+  Some(chm_slot(nodes[state.get_u()], nodes[state.get_v()], m))
+}
+
+/// The slot `(g1 + g2) % m` of a key whose vertices have labels `g1` and
+/// `g2`. It's summed in 64 bits, so a table with over 2^31 keys gives the
+/// same slots on 32-bit targets as on 64-bit ones.
+fn chm_slot(g1: uint, g2: uint, m: uint) -> uint {
+  ((g1 as u64 + g2 as u64) % m as u64) as uint
+}
+
+/// The part of a `PerfectHashFn` that depends on the construction algorithm.
+#[cfg(feature = "std")]
+#[deriving(Clone)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+enum Labels {
+  /// A label per vertex, as in `assign_nodes`.
+  ChmLabels(PackedInts),
+  BdzLabels(bdz::Labeling),
+  ChdLabels(chd::Labeling),
+  RecSplitLabels(recsplit::Labeling),
+  PtHashLabels(pthash::Labeling),
+  FksLabels(fks::Labeling),
+}
+
+/// A perfect hash function over a fixed set of keys.
+///
+/// Each of the `m` keys it was built from hashes to a distinct index in
+/// `[0, m)`. With the default CHM algorithm, or with
+/// `HashMapBuilder::order_preserving`, the `i`th key hashes to `i`.
+/// The keys themselves aren't stored, so any other key hashes to an
+/// arbitrary index. With fingerprints enabled, `try_hash` rejects most such
+/// keys for two extra bytes per key.
+#[cfg(feature = "std")]
+pub struct PerfectHashFn<K, H = Positional> {
+  labels:       Labels,
+  hasher:       H,
+  m:            uint,
+  order:        Option<Vec<uint>>,
+  fingerprints: Option<Fingerprints>,
+  seed:         Option<u64>,
+  stats:        Option<BuildStats>,
+  marker:       marker::CovariantType<K>,
+}
+
+/// Duplicates produce identical edges, so the graph would never be acyclic
+/// and construction would retry forever. Reject them up front.
+///
+/// The keys are sorted by their whole 64-bit SipHash rather than put in a
+/// hashtable, which would need random keys to be safe from collisions: only
+/// keys whose whole hashes match are ever compared.
+#[cfg(feature = "std")]
+fn check_duplicates<K: Eq + hash::Hash>(keys: &[K]) -> Result<(), BuildError> {
+  let mut hashed: Vec<(u64, uint)> =
+    keys.iter().enumerate().map(|(i, k)| (hash::hash(k), i)).collect();
+  hashed.sort();
 
-      if g.is_acyclic() {
-        acyclic_g = g;
-        break;
+  // As a scan in order would, report the first key that repeats an earlier
+  // one, with the latest key before it that it repeats.
+  let mut found: Option<(uint, uint)> = None;
+  let mut start = 0;
+  while start < hashed.len() {
+    let (h, _) = hashed[start];
+    let run = hashed.slice_from(start);
+    let run = run.slice_to(run.iter().take_while(|&&(other, _)| other == h).count());
+
+    for (b, &(_, i)) in run.iter().enumerate().skip(1) {
+      let before = run.slice_to(b).iter().rev().map(|&(_, j)| j).find(|&j| keys[j] == keys[i]);
+      match before {
+        Some(first) if found.map_or(true, |(_, later)| i < later) => found = Some((first, i)),
+        _                                                          => {}
       }
     }
+    start += run.len();
+  }
+
+  match found {
+    Some((first, i)) => Err(DuplicateKey(first, i)),
+    None             => Ok(()),
+  }
+}
+
+/// A 16-bit digest of `k`, independent of the hash function's tables.
+#[cfg(feature = "std")]
+fn fingerprint<K: hash::Hash>(k: &K) -> u16 {
+  (hash::hash(k) >> 48) as u16
+}
+
+/// What `try_hash` compares against the slot a key lands in.
+#[cfg(feature = "std")]
+#[deriving(Clone)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+enum Fingerprints {
+  /// The top 16 bits of each key's `hash::hash`, from `fingerprint`.
+  Narrow(Vec<u16>),
+  /// All 64 bits of it.
+  Wide(Vec<u64>),
+}
+
+#[cfg(feature = "std")]
+impl Fingerprints {
+  fn len(&self) -> uint {
+    match *self {
+      Narrow(ref fps) => fps.len(),
+      Wide(ref fps)   => fps.len(),
+    }
+  }
 
-    debug!("Number of iterations: {}", iters);
+  /// True if `k` could be the key in slot `i`.
+  fn matches<K: hash::Hash>(&self, i: uint, k: &K) -> bool {
+    match *self {
+      Narrow(ref fps) => fps[i] == fingerprint(k),
+      Wide(ref fps)   => fps[i] == hash::hash(k),
+    }
+  }
+
+  fn heap_size(&self) -> uint {
+    match *self {
+      Narrow(ref fps) => fps.len() * mem::size_of::<u16>(),
+      Wide(ref fps)   => fps.len() * mem::size_of::<u64>(),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl<'a,
+     K: Eq
+      + hash::Hash
+      + hash::Hash<PerfectHashState<'a>>
+      + hash::Hash<ByteCounter>>
+    PerfectHashFn<K> {
+
+  /// Builds a function mapping the `i`th of `keys` to `i`.
+  ///
+  /// This uses the default `HashMapBuilder` settings, retrying until it
+  /// succeeds. Use `HashMapBuilder::build_fn` to tune construction or enable
+  /// fingerprints.
+  pub fn new(keys: &[K]) -> PerfectHashFn<K> {
+    match HashMapBuilder::new().build_fn(keys) {
+      Ok(func) => func,
+      Err(err) => fail!("perfect::PerfectHashFn::new: {}", err),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl<K: Eq + hash::Hash, H: KeyHasher<K>> PerfectHashFn<K, H> {
+  /// Reseeds `hasher` until `algorithm` succeeds with about `n` vertices,
+  /// trying at most `max_iterations` candidates, and telling `monitor` about
+  /// each. Returns the function and the number of candidates tried.
+  fn construct<R: rand::Rng>(
+      rng: &mut R,
+      mut hasher: H,
+      keys: &[K],
+      algorithm: Algorithm,
+      n: uint,
+      max_iterations: Option<uint>,
+      monitor: &Monitor) -> Result<(PerfectHashFn<K, H>, uint), BuildError> {
+    try!(check_duplicates(keys));
+
+    let labels : Labels;
+
+    let mut iters : uint = 0;
+
+    loop {
+      match max_iterations {
+        Some(max) if iters >= max => return Err(TooManyIterations(iters)),
+        _                         => {}
+      }
+
+      try!(monitor.attempt(iters));
+
+      let attempt = try!(PerfectHashFn::attempt(rng, &mut hasher, keys, algorithm, n));
+
+      iters += 1;
+
+      match attempt {
+        Some(found) => {
+          labels = found;
+          break;
+        }
+        None => {}
+      }
+    }
+
+    debug!("Number of iterations: {}", iters);
+    monitor.report(iters, Finishing);
+
+    Ok((PerfectHashFn::from_parts(labels, hasher, keys.len()), iters))
+  }
+
+  /// Makes one attempt at construction: reseeds `hasher`, and labels the
+  /// key graph if `algorithm` can.
+  fn attempt<R: rand::Rng>(rng: &mut R, hasher: &mut H, keys: &[K], algorithm: Algorithm,
+                           n: uint) -> Result<Option<Labels>, BuildError> {
+    match algorithm {
+      Chm => {
+        let nodes = try!(PerfectHashFn::attempt_chm(rng, hasher, keys, n));
+        Ok(nodes.map(|nodes| ChmLabels(PackedInts::new(nodes.as_slice()))))
+      }
+      Bdz => {
+        let labeling = try!(bdz::attempt(rng, hasher, keys, (n + 2) / 3));
+        Ok(labeling.map(BdzLabels))
+      }
+      Chd(lambda) => {
+        let buckets  = (keys.len() as f64 / lambda).ceil() as uint;
+        let labeling = try!(chd::attempt(rng, hasher, keys, n, buckets));
+        Ok(labeling.map(ChdLabels))
+      }
+      RecSplit(leaf, bucket) => {
+        let labeling = try!(recsplit::attempt(rng, hasher, keys, leaf, bucket));
+        Ok(labeling.map(RecSplitLabels))
+      }
+      PtHash(lambda) => {
+        let buckets  = (keys.len() as f64 / lambda).ceil() as uint;
+        let labeling = try!(pthash::attempt(rng, hasher, keys, n, buckets));
+        Ok(labeling.map(PtHashLabels))
+      }
+      Fks => {
+        let labeling = try!(fks::attempt(rng, hasher, keys, n));
+        Ok(labeling.map(FksLabels))
+      }
+    }
+  }
+
+  /// One CHM attempt: labels the key graph's `n` vertices if it came out
+  /// acyclic.
+  fn attempt_chm<R: rand::Rng>(rng: &mut R, hasher: &mut H, keys: &[K], n: uint)
+      -> Result<Option<Vec<uint>>, BuildError> {
+    hasher.reseed(rng, keys, n);
+
+    let mut sets  = DisjointSets::new(n);
+    let mut edges = Vec::with_capacity(keys.len());
+
+    // Keys are hashed a batch at a time, so hashers can work on several at
+    // once, and at the first cycle the rest of the batches aren't hashed.
+    // A self-loop is a cycle too.
+    for batch in keys.chunks(HASH_BATCH) {
+      let start = edges.len();
+      if !hasher.vertices_batch(batch, n, &mut edges) {
+        return Err(Overflow);
+      }
+
+      for &(f1, f2) in edges.slice_from(start).iter() {
+        if !sets.union(f1, f2) {
+          return Ok(None);
+        }
+      }
+    }
+
+    Ok(assign_nodes(n, edges.as_slice()))
+  }
+
+  /// Like `construct` with `Chm`, but for the `m` keys streamed by `keys`,
+  /// which are iterated over again on every attempt. Only the graph's edges
+  /// are held, at two words a key.
+  ///
+  /// A key that comes twice gives the same edge twice, closing a cycle, so
+  /// when a cycle is closed by an edge the graph already has, the two keys
+  /// behind it are compared, and if they're equal it's a `DuplicateKey`.
+  fn construct_streamed<R: rand::Rng, I: Iterator<K>>(
+      rng: &mut R,
+      mut hasher: H,
+      keys: || -> I,
+      m: uint,
+      n: uint,
+      max_iterations: Option<uint>,
+      monitor: &Monitor) -> Result<(PerfectHashFn<K, H>, uint), BuildError> {
+    let mut iters : uint = 0;
+
+    loop {
+      match max_iterations {
+        Some(max) if iters >= max => return Err(TooManyIterations(iters)),
+        _                         => {}
+      }
+
+      try!(monitor.attempt(iters));
+
+      hasher.reseed_streamed(rng, || keys(), n);
+
+      let mut sets    = DisjointSets::new(n);
+      let mut edges   = Vec::with_capacity(m);
+      let mut acyclic = true;
+      for (i, k) in keys().enumerate() {
+        let (f1, f2) =
+          match hasher.vertices(&k, n) {
+            Some(uv) => uv,
+            None     => return Err(Overflow),
+          };
+
+        if !sets.union(f1, f2) {
+          match edges.iter().position(|&e| e == (f1, f2)) {
+            Some(first) if keys().nth(first).map_or(false, |other| other == k) =>
+              return Err(DuplicateKey(first, i)),
+            _ => {}
+          }
+          acyclic = false;
+          break;
+        }
+
+        edges.push((f1, f2));
+      }
+
+      iters += 1;
+
+      if acyclic {
+        match assign_nodes(n, edges.as_slice()) {
+          Some(nodes) => {
+            debug!("Number of iterations: {}", iters);
+            monitor.report(iters, Finishing);
+            let labels = ChmLabels(PackedInts::new(nodes.as_slice()));
+            return Ok((PerfectHashFn::from_parts(labels, hasher, m), iters));
+          }
+          None => {}
+        }
+      }
+    }
+  }
+
+  /// Like `finish`, for keys streamed by `keys`. CHM already preserves
+  /// order, so only the fingerprints or digests are left.
+  fn finish_streamed<I: Iterator<K>>(&mut self, keys: || -> I, fingerprints: bool,
+                                     digests: bool) {
+    if digests {
+      let mut fps = Vec::from_elem(self.m, 0u64);
+      for k in keys() {
+        *fps.get_mut(self.hash(&k)) = hash::hash(&k);
+      }
+      self.fingerprints = Some(Wide(fps));
+    } else if fingerprints {
+      let mut fps = Vec::from_elem(self.m, 0u16);
+      for k in keys() {
+        *fps.get_mut(self.hash(&k)) = fingerprint(&k);
+      }
+      self.fingerprints = Some(Narrow(fps));
+    }
+  }
+
+  /// Wraps up a successful attempt over `m` keys.
+  fn from_parts(labels: Labels, hasher: H, m: uint) -> PerfectHashFn<K, H> {
+    PerfectHashFn {
+      labels:       labels,
+      hasher:       hasher,
+      m:            m,
+      order:        None,
+      fingerprints: None,
+      seed:         None,
+      stats:        None,
+      marker:       marker::CovariantType,
+    }
+  }
+
+  /// Makes the `i`th of `keys` hash to `i`, if the algorithm didn't already,
+  /// and then stores their fingerprints, or their whole digests, if asked
+  /// to.
+  fn finish(&mut self, keys: &[K], preserve_order: bool, fingerprints: bool, digests: bool) {
+    let ordered =
+      match self.labels {
+        ChmLabels(_) => true,
+        _            => false,
+      };
+
+    if preserve_order && !ordered {
+      let mut order = Vec::from_elem(keys.len(), 0u);
+      for (i, k) in keys.iter().enumerate() {
+        *order.get_mut(self.hash(k)) = i;
+      }
+      self.order = Some(order);
+    }
+
+    if digests {
+      let mut fps = Vec::from_elem(keys.len(), 0u64);
+      for k in keys.iter() {
+        *fps.get_mut(self.hash(k)) = hash::hash(k);
+      }
+      self.fingerprints = Some(Wide(fps));
+    } else if fingerprints {
+      let mut fps = Vec::from_elem(keys.len(), 0u16);
+      for k in keys.iter() {
+        *fps.get_mut(self.hash(k)) = fingerprint(k);
+      }
+      self.fingerprints = Some(Narrow(fps));
+    }
+  }
+
+  /// The bytes of everything but the keys: the labels, the hasher's tables,
+  /// and the order and fingerprints if there are any.
+  fn heap_size(&self) -> uint {
+    self.labels_size() + self.hasher.heap_size()
+  }
+
+  /// The index `k` would have if it were one of the keys, or `None` if it
+  /// certainly isn't.
+  fn index<Q>(&self, k: &Q) -> Option<uint> where H: KeyHasher<Q> {
+    if self.m == 0 {
+      return None;
+    }
+
+    // No known key fails to hash, or construction would have failed.
+    let i =
+      match self.labels {
+        ChmLabels(ref nodes) =>
+          match self.hasher.vertices(k, nodes.len()) {
+            Some((u, v)) => Some(chm_slot(nodes.get(u), nodes.get(v), self.m)),
+            None         => None,
+          },
+        BdzLabels(ref labeling)      => labeling.index(&self.hasher, k),
+        ChdLabels(ref labeling)      => labeling.index(&self.hasher, k),
+        RecSplitLabels(ref labeling) => labeling.index(&self.hasher, k),
+        PtHashLabels(ref labeling)   => labeling.index(&self.hasher, k),
+        FksLabels(ref labeling)      => labeling.index(&self.hasher, k),
+      };
+
+    match self.order {
+      Some(ref order) => i.map(|i| order[i]),
+      None            => i,
+    }
+  }
+
+  /// Returns the index of `k`, which must be one of the keys this function
+  /// was built from. Other keys get an arbitrary index, or a task failure.
+  pub fn hash(&self, k: &K) -> uint {
+    match self.index(k) {
+      Some(i) => i,
+      None    => fail!("perfect::PerfectHashFn::hash: not a known key"),
+    }
+  }
+
+  /// Returns the index of `k`, or `None` if `k` is known not to be one of the
+  /// keys. Without fingerprints, only keys the hasher rejects are caught.
+  pub fn try_hash(&self, k: &K) -> Option<uint> {
+    self.try_hash_equiv(k)
+  }
+
+  /// Like `try_hash`, but for any type that both `H` and `Hash` treat the
+  /// same way as `K`, such as a `&str` for `String` keys.
+  pub fn try_hash_equiv<Q: hash::Hash>(&self, k: &Q) -> Option<uint> where H: KeyHasher<Q> {
+    match self.index(k) {
+      None    => None,
+      Some(i) =>
+        match self.fingerprints {
+          Some(ref fps) if !fps.matches(i, k) => None,
+          _                                   => Some(i),
+        },
+    }
+  }
+
+  /// Checks that the tables are well-formed: every entry is in bounds, so
+  /// hashing can't index out of range.
+  fn verify(&self) -> Result<(), Corruption> {
+    match self.fingerprints {
+      Some(ref fps) if fps.len() != self.m => return Err(BadLength("fingerprints", fps.len())),
+      _                                    => {}
+    }
+
+    match self.order {
+      Some(ref order) => {
+        if order.len() != self.m {
+          return Err(BadLength("order", order.len()));
+        }
+        match order.iter().position(|&i| i >= self.m) {
+          Some(i) => return Err(OutOfBounds("order", i, order[i])),
+          None    => {}
+        }
+      }
+      None => {}
+    }
+
+    let n =
+      match self.labels {
+        ChmLabels(ref nodes) => {
+          if !nodes.is_consistent() || self.m > 0 && nodes.len() == 0 {
+            return Err(BadLength("nodes", nodes.len()));
+          }
+          match range(0, nodes.len()).find(|&i| nodes.get(i) >= self.m) {
+            Some(i) => return Err(OutOfBounds("nodes", i, nodes.get(i))),
+            None    => {}
+          }
+          nodes.len()
+        }
+        BdzLabels(ref labeling)      => { try!(labeling.verify(self.m)); labeling.part_size() }
+        ChdLabels(ref labeling)      => { try!(labeling.verify(self.m)); labeling.hash_range() }
+        RecSplitLabels(ref labeling) => { try!(labeling.verify(self.m)); labeling.hash_range() }
+        PtHashLabels(ref labeling)   => { try!(labeling.verify(self.m)); labeling.hash_range() }
+        FksLabels(ref labeling)      => { try!(labeling.verify(self.m)); labeling.hash_range() }
+      };
+
+    if self.hasher.is_consistent(n) { Ok(()) } else { Err(BadHasher) }
+  }
+}
+
+#[cfg(feature = "std")]
+impl<K, H> PerfectHashFn<K, H> {
+  /// The number of keys, which is also the number of distinct indices.
+  pub fn len(&self) -> uint {
+    self.m
+  }
+
+  /// The size of the table the keys were placed in: the vertices of the
+  /// graph for CHM and BDZ, or the slots keys are placed in for CHD, PTHash,
+  /// and FKS. Ranking or remapping maps it down to `len` indices, so it's at
+  /// least that. RecSplit places keys straight into their indices.
+  pub fn table_size(&self) -> uint {
+    match self.labels {
+      ChmLabels(ref nodes)         => nodes.len(),
+      BdzLabels(ref labeling)      => labeling.num_vertices(),
+      ChdLabels(ref labeling)      => labeling.num_slots(),
+      RecSplitLabels(ref labeling) => labeling.len(),
+      PtHashLabels(ref labeling)   => labeling.num_slots(),
+      FksLabels(ref labeling)      => labeling.num_slots(),
+    }
+  }
+
+  /// The keys per entry of the table, `len() / table_size()`. Higher is
+  /// smaller, but slower to build. Zero for a function of no keys.
+  pub fn load_factor(&self) -> f64 {
+    match self.table_size() {
+      0    => 0.0,
+      size => self.m as f64 / size as f64,
+    }
+  }
+
+  /// The CHM node table, or `None` if another algorithm built this. The
+  /// binary format and table images only know how to store CHM tables.
+  fn chm_nodes(&self) -> Option<&PackedInts> {
+    match self.labels {
+      ChmLabels(ref nodes) => Some(nodes),
+      _                    => None,
+    }
+  }
+
+  /// True if `try_hash` checks fingerprints, narrow or wide.
+  pub fn has_fingerprints(&self) -> bool {
+    self.fingerprints.is_some()
+  }
+
+  /// True if the fingerprints are whole 64-bit digests.
+  pub fn has_digests(&self) -> bool {
+    match self.fingerprints {
+      Some(Wide(_)) => true,
+      _             => false,
+    }
+  }
+
+  /// The seed this function was built from, or `None` if that isn't known.
+  pub fn seed(&self) -> Option<u64> {
+    self.seed
+  }
+
+  /// The algorithm that built this. The tables don't record CHD's or
+  /// PTHash's bucket size, or RecSplit's sizes, so those are the defaults.
+  pub fn algorithm(&self) -> Algorithm {
+    match self.labels {
+      ChmLabels(_)      => Chm,
+      BdzLabels(_)      => Bdz,
+      ChdLabels(_)      => Chd(DEFAULT_CHD_LAMBDA),
+      RecSplitLabels(_) => RecSplit(RECSPLIT_LEAF_SIZE, RECSPLIT_BUCKET_SIZE),
+      PtHashLabels(_)   => PtHash(DEFAULT_PTHASH_LAMBDA),
+      FksLabels(_)      => Fks,
+    }
+  }
+
+  /// Settings that build functions like this one, for maps that don't have
+  /// the builder they came from, such as loaded ones.
+  fn builder(&self) -> HashMapBuilder {
+    HashMapBuilder::new()
+      .algorithm(self.algorithm())
+      .order_preserving(self.order.is_some())
+      .fingerprints(self.has_fingerprints())
+      .digests(self.has_digests())
+  }
+
+  /// The bytes of the labels, and the order and fingerprints if there are
+  /// any.
+  fn labels_size(&self) -> uint {
+    let labels =
+      match self.labels {
+        ChmLabels(ref nodes)         => nodes.heap_size(),
+        BdzLabels(ref labeling)      => labeling.heap_size(),
+        ChdLabels(ref labeling)      => labeling.heap_size(),
+        RecSplitLabels(ref labeling) => labeling.heap_size(),
+        PtHashLabels(ref labeling)   => labeling.heap_size(),
+        FksLabels(ref labeling)      => labeling.heap_size(),
+      };
+
+    let order        = self.order.as_ref().map_or(0, |o| o.len() * mem::size_of::<uint>());
+    let fingerprints = self.fingerprints.as_ref().map_or(0, |f| f.heap_size());
+
+    labels + order + fingerprints
+  }
+
+  /// How construction went, or `None` if this was loaded from a serialized
+  /// table.
+  pub fn build_stats(&self) -> Option<&BuildStats> {
+    self.stats.as_ref()
+  }
+}
+
+#[cfg(feature = "std")]
+impl<K, H: Clone> Clone for PerfectHashFn<K, H> {
+  fn clone(&self) -> PerfectHashFn<K, H> {
+    PerfectHashFn {
+      labels:       self.labels.clone(),
+      hasher:       self.hasher.clone(),
+      m:            self.m,
+      order:        self.order.clone(),
+      fingerprints: self.fingerprints.clone(),
+      seed:         self.seed,
+      stats:        self.stats.clone(),
+      marker:       marker::CovariantType,
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl<'a,
+     K: Eq
+      + hash::Hash
+      + hash::Hash<PerfectHashState<'a>>
+      + hash::Hash<ByteCounter>,
+     V>
+    HashMap<K, V> {
+
+  /// Builds a table with a dedicated slot for each of `known_vals`.
+  ///
+  /// Fails if `known_vals` contains the same key twice.
+  ///
+  /// This uses the default `HashMapBuilder` settings, retrying until it
+  /// succeeds. Use the builder directly to bound or tune construction.
+  pub fn new(known_vals: Vec<K>) -> HashMap<K, V> {
+    match HashMapBuilder::new().build(known_vals) {
+      Ok(map)  => map,
+      Err(err) => fail!("perfect::HashMap::new: {}", err),
+    }
+  }
+
+  /// Like `new`, but builds from `seed` instead of a random one, so the same
+  /// keys always get the same tables.
+  pub fn new_with_seed(known_vals: Vec<K>, seed: u64) -> HashMap<K, V> {
+    match HashMapBuilder::new().seed(seed).build(known_vals) {
+      Ok(map)  => map,
+      Err(err) => fail!("perfect::HashMap::new_with_seed: {}", err),
+    }
+  }
+
+  /// Like `new`, but draws the seed from `rng` instead of the task's rng,
+  /// for deterministic tests or an entropy pool of your own. The table
+  /// reports the seed drawn, as with any other build.
+  pub fn new_with_rng<R: rand::Rng>(known_vals: Vec<K>, rng: &mut R) -> HashMap<K, V> {
+    match HashMapBuilder::new().seed(rng.gen()).build(known_vals) {
+      Ok(map)  => map,
+      Err(err) => fail!("perfect::HashMap::new_with_rng: {}", err),
+    }
+  }
+
+  /// Builds a table whose known keys are the keys of `entries`, with each
+  /// value already in its key's slot.
+  ///
+  /// Fails if the same key appears twice. Like `new`, this uses the default
+  /// `HashMapBuilder` settings; see `HashMapBuilder::build_entries`.
+  pub fn from_entries(entries: Vec<(K, V)>) -> HashMap<K, V> {
+    match HashMapBuilder::new().build_entries(entries) {
+      Ok(map)  => map,
+      Err(err) => fail!("perfect::HashMap::from_entries: {}", err),
+    }
+  }
+
+  /// Like `new`, but gives up after `DEFAULT_MAX_ITERATIONS` attempts and
+  /// reports failures instead of panicking, so callers can fall back to
+  /// another kind of table.
+  pub fn try_new(known_vals: Vec<K>) -> Result<HashMap<K, V>, BuildError> {
+    HashMapBuilder::new()
+      .max_iterations(DEFAULT_MAX_ITERATIONS)
+      .build(known_vals)
+  }
+}
+
+#[cfg(feature = "std")]
+impl<'a,
+     K: Eq
+      + hash::Hash
+      + hash::Hash<PerfectHashState<'a>>
+      + hash::Hash<ByteCounter>,
+     V>
+    FromIterator<(K, V)> for HashMap<K, V> {
+  /// Builds a map whose known keys are exactly the keys of `iter`. Later
+  /// values for a key replace earlier ones.
+  fn from_iter<T: Iterator<(K, V)>>(iter: T) -> HashMap<K, V> {
+    let pairs: collections::HashMap<K, V> = iter.collect();
+    HashMap::from_entries(pairs.into_iter().collect())
+  }
+}
+
+#[cfg(feature = "std")]
+impl<'a,
+     K: Eq
+      + hash::Hash
+      + hash::Hash<PerfectHashState<'a>>
+      + hash::Hash<ByteCounter>,
+     V>
+    Default for HashMap<K, V> {
+  /// A map with no known keys, which keeps everything in its backup.
+  fn default() -> HashMap<K, V> {
+    HashMap::new(Vec::new())
+  }
+}
+
+#[cfg(feature = "std")]
+impl<K: Clone, V: Clone, H: Clone, B: Clone> Clone for HashMap<K, V, H, B> {
+  fn clone(&self) -> HashMap<K, V, H, B> {
+    HashMap {
+      func:       self.func.clone(),
+      keys:       self.keys.clone(),
+      table:      self.table.clone(),
+      size:       self.size,
+      backup:     self.backup.clone(),
+      rebuild_at: self.rebuild_at,
+      unknown:    self.unknown,
+      sorted:     self.sorted.clone(),
+      resort:     self.resort,
+      counters:   self.counters.clone(),
+      sampler:    self.sampler.clone(),
+      settings:   self.settings.clone(),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl<K: Eq + hash::Hash, V: PartialEq, H: KeyHasher<K>, B: BackupStore<K, V>>
+    PartialEq for HashMap<K, V, H, B> {
+  /// Maps are equal if they hold the same entries, however their keys are
+  /// split between the slots and the backup.
+  fn eq(&self, other: &HashMap<K, V, H, B>) -> bool {
+    self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+  }
+}
+
+#[cfg(feature = "std")]
+impl<K: fmt::Show, V: fmt::Show, H, B: BackupStore<K, V>> fmt::Show for HashMap<K, V, H, B> {
+  /// Shows the entries, as `{k: v, ...}`, and none of the tables behind them.
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    try!(write!(f, "{{"));
+    for (i, (k, v)) in self.iter().enumerate() {
+      if i != 0 {
+        try!(write!(f, ", "));
+      }
+      try!(write!(f, "{}: {}", *k, *v));
+    }
+    write!(f, "}}")
+  }
+}
+
+#[cfg(feature = "std")]
+impl<K: Eq + hash::Hash, V, H: KeyHasher<K>, B: BackupStore<K, V>>
+    Extend<(K, V)> for HashMap<K, V, H, B> {
+  /// Inserts every pair. Keys that aren't known go to the backup store. Use
+  /// `rebuild` to make them known.
+  fn extend<T: Iterator<(K, V)>>(&mut self, mut iter: T) {
+    for (k, v) in iter {
+      self.insert(k, v);
+    }
+  }
+}
+
+/// An empty default backup store for a map with `func`, keyed from the seed
+/// it was built from, or from zero if that isn't known.
+#[cfg(feature = "std")]
+fn seeded_backup<K: Eq + hash::Hash, V, H>(func: &PerfectHashFn<K, H>) -> SeededBackup<K, V> {
+  SeededBackup::new(func.seed.unwrap_or(0))
+}
+
+#[cfg(feature = "std")]
+impl<K: Eq + hash::Hash, V, H: KeyHasher<K>> HashMap<K, V, H> {
+  /// A map with no values yet, whose known keys are those `func` was built
+  /// from.
+  fn with_fn(func: PerfectHashFn<K, H>, keys: Vec<K>) -> HashMap<K, V, H> {
+    let m = keys.len();
+
+    // Put each key in the slot it hashes to. For CHM that's where it
+    // already is.
+    let mut slots: Vec<Option<K>> = Vec::from_fn(m, |_| None);
+    for k in keys.into_iter() {
+      let i = func.hash(&k);
+      *slots.get_mut(i) = Some(k);
+    }
+
+    let settings = func.builder();
+    let backup   = seeded_backup(&func);
+    HashMap {
+      func:       func,
+      keys:       slots.into_iter().map(|k| k.unwrap()).collect(),
+      table:      Slots::new(m),
+      size:       0,
+      // Allocates nothing until the first unknown key arrives.
+      backup:     backup,
+      rebuild_at: None,
+      unknown:    CheckBackup,
+      sorted:     None,
+      resort:     None,
+      counters:   None,
+      sampler:    None,
+      settings:   settings,
+    }
+  }
+
+  /// Like `with_fn`, but fills every slot with the value paired with its
+  /// key.
+  fn with_fn_entries(func: PerfectHashFn<K, H>, entries: Vec<(K, V)>) -> HashMap<K, V, H> {
+    let m = entries.len();
+
+    let mut keys: Vec<Option<K>> = Vec::from_fn(m, |_| None);
+    let mut table = Slots::new(m);
+    for (k, v) in entries.into_iter() {
+      let i = func.hash(&k);
+      *keys.get_mut(i) = Some(k);
+      table.replace(i, Some(v));
+    }
+
+    let settings = func.builder();
+    let backup   = seeded_backup(&func);
+    HashMap {
+      func:       func,
+      keys:       keys.into_iter().map(|k| k.unwrap()).collect(),
+      table:      table,
+      size:       m,
+      backup:     backup,
+      rebuild_at: None,
+      unknown:    CheckBackup,
+      sorted:     None,
+      resort:     None,
+      counters:   None,
+      sampler:    None,
+      settings:   settings,
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl<K: Eq + hash::Hash, V, H: KeyHasher<K>, B: BackupStore<K, V>> HashMap<K, V, H, B> {
+  /// Moves the entries for unknown keys into `store`, which keeps them from
+  /// now on.
+  ///
+  /// Fails if `store` refuses one of the entries already in the backup.
+  pub fn with_backup<C: BackupStore<K, V>>(mut self, mut store: C) -> HashMap<K, V, H, C> {
+    for (k, v) in self.backup.drain().into_iter() {
+      match store.insert(k, v) {
+        Ok(_)  => {}
+        Err(_) => fail!("perfect::HashMap::with_backup: the store refused an entry"),
+      }
+    }
+
+    HashMap {
+      func:       self.func,
+      keys:       self.keys,
+      table:      self.table,
+      size:       self.size,
+      backup:     store,
+      rebuild_at: self.rebuild_at,
+      unknown:    self.unknown,
+      sorted:     self.sorted,
+      resort:     self.resort,
+      counters:   self.counters,
+      sampler:    self.sampler,
+      settings:   self.settings,
+    }
+  }
+  /// Splits the map in two: one whose known keys are the keys `pred`
+  /// accepts, and one with the rest. Every key goes to a side, whether it
+  /// was known or in the backup store, with its value if it had one.
+  ///
+  /// Both sides are built the way `rebuild` builds, and keep this map's
+  /// other settings: the rest gets an empty copy of the backup store, fresh
+  /// counters and samples if this map keeps them, and sorted keys if this
+  /// map's are. Their functions are rebuilt from this one's, as `rebuild`
+  /// would.
+  pub fn partition(mut self, pred: |&K| -> bool) -> (HashMap<K, V, H, B>, HashMap<K, V, H, B>)
+      where B: Clone {
+    let mut keys   = mem::replace(&mut self.keys, Vec::new());
+    let mut values = self.table.drain_options();
+    for (k, v) in self.backup.drain().into_iter() {
+      keys.push(k);
+      values.push(Some(v));
+    }
+
+    let mut our_keys     = Vec::new();
+    let mut our_values   = Vec::new();
+    let mut their_keys   = Vec::new();
+    let mut their_values = Vec::new();
+    for (k, v) in keys.into_iter().zip(values.into_iter()) {
+      if pred(&k) {
+        our_keys.push(k);
+        our_values.push(v);
+      } else {
+        their_keys.push(k);
+        their_values.push(v);
+      }
+    }
+
+    let mut rest = HashMap {
+      func:       self.func.clone(),
+      keys:       Vec::new(),
+      table:      Slots::with_layout(0, self.table.is_boxed()),
+      size:       0,
+      backup:     self.backup.clone(),
+      rebuild_at: self.rebuild_at,
+      unknown:    self.unknown,
+      sorted:     None,
+      resort:     self.resort,
+      counters:   self.counters.as_ref().map(|_| Counters::new()),
+      sampler:    self.sampler.as_ref().map(|s| Sampler::new(s.one_in)),
+      settings:   self.settings.clone(),
+    };
+
+    // The keys were already distinct, so neither build can fail.
+    match (self.rebuild_from(our_keys, our_values), rest.rebuild_from(their_keys, their_values)) {
+      (Ok(_), Ok(_))                 => (self, rest),
+      (Err(err), _) | (_, Err(err)) => fail!("perfect::HashMap::partition: {}", err),
+    }
+  }
+
+
+  /// True if a lookup that missed the slots should go on to the backup.
+  #[inline]
+  fn check_backup(&self) -> bool {
+    match self.unknown {
+      CheckBackup => true,
+      ReturnNone  => false,
+      FailUnknown => {
+        debug_assert!(false, "perfect::HashMap: looked up an unknown key");
+        true
+      }
+    }
+  }
+
+  /// Returns the slot reserved for `k`, or `None` if `k` isn't a known key.
+  /// A fingerprint mismatch rules `k` out without comparing the stored key.
+  fn find_slot(&self, k: &K) -> Option<uint> {
+    match self.func.try_hash(k) {
+      Some(i) if self.keys[i] == *k => Some(i),
+      _                             => None,
+    }
+  }
+
+  /// The slot of `k`, or `None` if `k` isn't a known key.
+  ///
+  /// With `Chm` or an order-preserving build, that's the position of `k` in
+  /// the known keys, and iteration visits the known keys in that order
+  /// before any others.
+  pub fn index_of(&self, k: &K) -> Option<uint> {
+    self.find_slot(k)
+  }
+
+  /// Another name for `index_of`, to pair with `get_index`.
+  pub fn get_index_of(&self, k: &K) -> Option<uint> {
+    self.find_slot(k)
+  }
+
+  /// The handle of `k`, or `None` if `k` isn't a known key. Handles are the
+  /// slots of `index_of`, so they're dense in `[0, capacity())`, and each
+  /// known key keeps its own until a `rebuild`.
+  pub fn id_of(&self, k: &K) -> Option<KeyId> {
+    self.find_slot(k).map(KeyId)
+  }
+
+  /// The value for `k`, without comparing it to the stored key or checking
+  /// its fingerprint.
+  ///
+  /// This is unsafe because the caller must make sure `k` is a known key
+  /// with a value. Anything else reads an arbitrary slot, which may be
+  /// uninitialized, or past the end of an empty table. It never fails, even
+  /// for keys the hasher rejects.
+  pub unsafe fn get_unchecked(&self, k: &K) -> &V {
+    self.table.get_unchecked(self.func.index(k).unwrap_or(0))
+  }
+
+  /// Checks that the tables agree with each other and with the stored keys:
+  /// every entry is in bounds, every known key hashes back to its own slot,
+  /// and no key in the backup store is also a known key.
+  ///
+  /// Loading a table already does this, but it's worth repeating on tables
+  /// that were put together some other way before trusting their lookups.
+  pub fn verify(&self) -> Result<(), Corruption> {
+    let m = self.keys.len();
+
+    if self.func.len() != m {
+      return Err(BadLength("keys", m));
+    }
+    if self.table.len() != m {
+      return Err(BadLength("table", self.table.len()));
+    }
+    try!(self.func.verify());
+
+    for (i, k) in self.keys.iter().enumerate() {
+      match self.find_slot(k) {
+        Some(j) if j == i => {}
+        found             => return Err(MisplacedKey(i, found)),
+      }
+    }
+
+    let full = self.table.count();
+    if full != self.size {
+      return Err(BadCount(self.size, full));
+    }
+
+    if self.backup.iter().any(|(k, _)| self.find_slot(k).is_some()) {
+      return Err(KnownKeyInBackup);
+    }
+
+    Ok(())
+  }
+
+  /// Returns a reference to the value corresponding to the key.
+  pub fn get(&self, k: &K) -> Option<&V> {
+    match self.sampler {
+      Some(ref sampler) if sampler.pick() => {
+        let start = time::precise_time_ns();
+        let v     = self.lookup(k);
+        sampler.record(time::precise_time_ns() - start, self.func.hasher.hashed_bytes(k));
+        v
+      }
+      _ => self.lookup(k),
+    }
+  }
+
+  /// `get`, without sampling.
+  fn lookup(&self, k: &K) -> Option<&V> {
+    match self.find_slot(k) {
+      Some(i)                     => tally(&self.counters, true, self.table.get(i)),
+      None if self.check_backup() => tally(&self.counters, false, self.backup.get(k)),
+      None                        => tally(&self.counters, false, None),
+    }
+  }
+
+  /// Like `get`, but says whether the value came from a known key's slot or
+  /// from the backup store.
+  pub fn get_tiered(&self, k: &K) -> Lookup<&V> {
+    let (from_slots, v) =
+      match self.find_slot(k) {
+        Some(i)                     => (true,  self.table.get(i)),
+        None if self.check_backup() => (false, self.backup.get(k)),
+        None                        => (false, None),
+      };
+
+    match tally(&self.counters, from_slots, v) {
+      Some(v) if from_slots => PerfectHit(v),
+      Some(v)               => BackupHit(v),
+      None                  => Miss,
+    }
+  }
+
+  /// Returns a mutable reference to the value corresponding to the key.
+  pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+    match self.find_slot(k) {
+      Some(i)                     => tally(&self.counters, true, self.table.get_mut(i)),
+      None if self.check_backup() => tally(&self.counters, false, self.backup.get_mut(k)),
+      None                        => tally(&self.counters, false, None),
+    }
+  }
+
+  /// Looks up every key in `keys`, returning their values in the same order.
+  ///
+  /// All the keys are hashed, and their slots prefetched, before any slot is
+  /// read. On a table too big for the cache, the slot reads are then all in
+  /// flight at once, instead of each waiting on its own hash.
+  pub fn get_many<'a>(&'a self, keys: &[K]) -> Vec<Option<&'a V>> {
+    let slots: Vec<Option<uint>> = keys.iter().map(|k| self.func.try_hash(k)).collect();
+    for &slot in slots.iter() {
+      self.prefetch_slot(slot);
+    }
+
+    keys.iter().zip(slots.into_iter())
+      .map(|(k, slot)| self.get_hashed(k, slot))
+      .collect()
+  }
+
+  /// Like `get_many`, but for a stream of keys, which are looked up
+  /// `LOOKUP_BATCH` at a time.
+  pub fn get_iter<'a, I: Iterator<&'a K>>(&'a self, keys: I) -> Lookups<'a, K, V, H, B, I> {
+    Lookups {
+      map:   self,
+      keys:  keys,
+      batch: Vec::with_capacity(LOOKUP_BATCH),
+      next:  0,
+    }
+  }
+
+  /// Prefetches the key and value in `slot`, if there is one.
+  fn prefetch_slot(&self, slot: Option<uint>) {
+    match slot {
+      Some(i) if i < self.keys.len() => {
+        prefetch(&self.keys[i] as *const K);
+        self.table.prefetch(i);
+      }
+      _ => {}
+    }
+  }
+
+  /// The value for `k`, given what `try_hash` said about it.
+  fn get_hashed<'a>(&'a self, k: &K, slot: Option<uint>) -> Option<&'a V> {
+    match slot {
+      Some(i) if self.keys[i] == *k => tally(&self.counters, true, self.table.get(i)),
+      _ if self.check_backup()      => tally(&self.counters, false, self.backup.get(k)),
+      _                             => tally(&self.counters, false, None),
+    }
+  }
+
+  /// Returns true if the map contains a value for the specified key.
+  pub fn contains_key(&self, k: &K) -> bool {
+    self.get(k).is_some()
+  }
+
+  /// Like `get`, but looks up any type that hashes the same way as `K`, such
+  /// as a `&str` in a map of `String`s, in both the slots and the backup.
+  pub fn get_equiv<Q: hash::Hash + Equiv<K>>(&self, k: &Q) -> Option<&V>
+      where H: KeyHasher<Q> {
+    match self.func.try_hash_equiv(k) {
+      Some(i) if k.equiv(&self.keys[i]) => tally(&self.counters, true, self.table.get(i)),
+      _ if self.check_backup()          => tally(&self.counters, false, self.backup.get_equiv(k)),
+      _                                 => tally(&self.counters, false, None),
+    }
+  }
+
+  /// Like `get`, but also returns the stored key, so the map can intern its
+  /// keys: look one up with a short-lived `k`, and keep the one in the map.
+  ///
+  /// Keys in backup stores that can't look up their keys, like a std
+  /// `HashMap` or the default `SeededBackup`, are found by scanning the
+  /// store.
+  pub fn get_key_value(&self, k: &K) -> Option<(&K, &V)> {
+    match self.find_slot(k) {
+      Some(i)                     => tally(&self.counters, true, self.get_index(i)),
+      None if self.check_backup() => tally(&self.counters, false, self.backup.get_key_value(k)),
+      None                        => tally(&self.counters, false, None),
+    }
+  }
+
+  /// Like `get_key_value`, but for any type that hashes the same way as `K`,
+  /// such as a `&str` in a map of `String`s.
+  pub fn get_key_value_equiv<Q: hash::Hash + Equiv<K>>(&self, k: &Q) -> Option<(&K, &V)>
+      where H: KeyHasher<Q> {
+    match self.func.try_hash_equiv(k) {
+      Some(i) if k.equiv(&self.keys[i]) => tally(&self.counters, true, self.get_index(i)),
+      _ if self.check_backup()          =>
+        tally(&self.counters, false, self.backup.get_key_value_equiv(k)),
+      _                                 => tally(&self.counters, false, None),
+    }
+  }
+
+  /// Like `contains_key`, but for any type that hashes the same way as `K`.
+  pub fn contains_key_equiv<Q: hash::Hash + Equiv<K>>(&self, k: &Q) -> bool
+      where H: KeyHasher<Q> {
+    self.get_equiv(k).is_some()
+  }
+
+  /// Inserts a key-value pair into the map, returning the old value if the
+  /// key was already present.
+  ///
+  /// Known keys are written straight into their slot. Unknown keys go into
+  /// the backup store. Fails if the store refuses the key; see
+  /// `insert_checked`.
+  pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+    match self.insert_checked(k, v) {
+      Ok(old) => old,
+      Err(_)  => fail!("perfect::HashMap: the backup store refused a key"),
+    }
+  }
+
+  /// Like `insert`, but hands the pair back in an `UnknownKey` if the backup
+  /// store refuses it. With a `StrictHashMap` that's every unknown key.
+  pub fn insert_checked(&mut self, k: K, v: V) -> Result<Option<V>, UnknownKey<K, V>> {
+    match self.claim(k) {
+      PerfectSlot(table, i, size) => {
+        let old = table.replace(i, Some(v));
+        if old.is_none() {
+          *size += 1;
+        }
+        Ok(old)
+      }
+      BackupSlot(backup, k) =>
+        match backup.insert(k, v) {
+          Ok(old)     => Ok(old),
+          Err((k, v)) => Err(UnknownKey(k, v)),
+        },
+    }
+  }
+
+  /// Inserts `v` only if `k` has no value yet, and returns a reference to
+  /// it. Otherwise leaves the map alone, and returns the value already
+  /// there along with `v`.
+  ///
+  /// Fails if `k` is unknown and the backup store refuses it, like `insert`.
+  pub fn try_insert(&mut self, k: K, v: V) -> Result<&mut V, (&mut V, V)> {
+    // `v` is only taken if the key had no value.
+    let mut v = Some(v);
+    let value = self.entry(k).or_insert_with(|| v.take().unwrap());
+    match v {
+      Some(v) => Err((value, v)),
+      None    => Ok(value),
+    }
+  }
+
+  /// Returns the value for `k`, inserting the result of `f` first if there
+  /// isn't one. Short for `entry(k).or_insert_with(f)`.
+  ///
+  /// Fails if `k` is unknown and the backup store refuses it.
+  pub fn get_or_insert_with(&mut self, k: K, f: || -> V) -> &mut V {
+    self.entry(k).or_insert_with(f)
+  }
+
+  /// Removes a key from the map, returning its value if it was present.
+  pub fn remove(&mut self, k: &K) -> Option<V> {
+    match self.find_slot(k) {
+      Some(i) => {
+        let old = self.table.take(i);
+        if old.is_some() {
+          self.size -= 1;
+        }
+        old
+      }
+      None => self.backup.remove(k),
+    }
+  }
+
+  /// Rebuilds the tables with every key currently in the map, including the
+  /// ones in the backup store, as a known key. Known keys without a value
+  /// stay known.
+  ///
+  /// The new function comes from the map's hasher and the settings of the
+  /// `HashMapBuilder` that built the map: its algorithm, space factor, seed
+  /// or entropy source, order, fingerprints, and digests. Any iteration
+  /// limit, deadline, or progress reporting applied to the first build only.
+  /// Maps that weren't built, such as loaded ones, use their algorithm's
+  /// defaults. An `Fks` map only looks for new seeds for the buckets whose
+  /// keys changed, as `HashMapBuilder::rebuild_fn` does. It's worth calling
+  /// after `extend` or `insert` have put many unknown keys in the backup, or
+  /// see `set_rebuild_threshold` to have it happen automatically.
+  pub fn rebuild(&mut self) {
+    self.rebuild_with(None);
+  }
+
+  /// Like `rebuild`, but also makes `extra`, an unknown key that isn't in
+  /// the backup store, a known key with no value. Returns its slot.
+  fn rebuild_with(&mut self, extra: Option<K>) -> Option<uint> {
+    if self.backup.len() == 0 && extra.is_none() {
+      return None;
+    }
+
+    let mut keys   = mem::replace(&mut self.keys, Vec::new());
+    let mut values = self.table.drain_options();
+    for (k, v) in self.backup.drain().into_iter() {
+      keys.push(k);
+      values.push(Some(v));
+    }
+    let has_extra = extra.is_some();
+    match extra {
+      Some(k) => {
+        keys.push(k);
+        values.push(None);
+      }
+      None => {}
+    }
+
+    // Backup keys are never known keys, so there are no duplicates, and
+    // without an iteration limit nothing else can go wrong.
+    match self.rebuild_from(keys, values) {
+      Ok(last) => if has_extra { last } else { None },
+      Err(err) => fail!("perfect::HashMap::rebuild: {}", err),
+    }
+  }
+
+  /// Combines this map with `other`. The known keys of the result are every
+  /// key either map knows or holds in its backup store, and a key with a
+  /// value in both gets `resolve(k, ours, theirs)`.
+  ///
+  /// The tables are built once, the way `rebuild` builds them, and the
+  /// result keeps this map's other settings.
+  pub fn merge(mut self, other: HashMap<K, V, H, B>, resolve: |&K, V, V| -> V)
+      -> HashMap<K, V, H, B> {
+    let HashMap { keys: other_keys, table: other_table, backup: mut other_backup, .. } = other;
+    let mut theirs: Vec<(K, Option<V>)> =
+      other_keys.into_iter().zip(other_table.into_options().into_iter()).collect();
+    theirs.extend(other_backup.drain().into_iter().map(|(k, v)| (k, Some(v))));
+
+    // Keys only `other` knows, with keys from this map's backup.
+    let mut new_keys   = Vec::new();
+    let mut new_values = Vec::new();
+
+    for (k, theirs) in theirs.into_iter() {
+      let slot = self.find_slot(&k);
+      let ours =
+        match slot {
+          Some(i) => self.table.take(i),
+          None    => self.backup.remove(&k),
+        };
+
+      let merged =
+        match (ours, theirs) {
+          (Some(ours), Some(theirs)) => Some(resolve(&k, ours, theirs)),
+          (ours,       None)         => ours,
+          (None,       theirs)       => theirs,
+        };
+
+      match slot {
+        Some(i) => { self.table.replace(i, merged); }
+        None    => {
+          new_keys.push(k);
+          new_values.push(merged);
+        }
+      }
+    }
+
+    let mut keys   = mem::replace(&mut self.keys, Vec::new());
+    let mut values = self.table.drain_options();
+    keys.extend(new_keys.into_iter());
+    values.extend(new_values.into_iter());
+    for (k, v) in self.backup.drain().into_iter() {
+      keys.push(k);
+      values.push(Some(v));
+    }
+
+    // Each map's known and backup keys are distinct, and every key both
+    // maps have was merged into one.
+    match self.rebuild_from(keys, values) {
+      Ok(_)    => self,
+      Err(err) => fail!("perfect::HashMap::merge: {}", err),
+    }
+  }
+
+  /// Replaces the tables with ones in which `keys` are the known keys, with
+  /// `values` in their slots, built as `rebuild` describes. Returns the slot
+  /// of the last key, if there are any.
+  fn rebuild_from(&mut self, keys: Vec<K>, values: Vec<Option<V>>)
+      -> Result<Option<uint>, BuildError> {
+    let func = try!(self.settings.rebuild_fn(&self.func, keys.as_slice()));
+
+    // Put each key and its value in the slot the key hashes to.
+    let m = keys.len();
+    let mut slots: Vec<Option<K>> = Vec::from_fn(m, |_| None);
+    let mut table: Vec<Option<V>> = Vec::from_fn(m, |_| None);
+    let mut last = None;
+    for (k, v) in keys.into_iter().zip(values.into_iter()) {
+      let i = func.hash(&k);
+      *slots.get_mut(i) = Some(k);
+      *table.get_mut(i) = v;
+      last = Some(i);
+    }
+
+    self.size   = table.iter().filter(|v| v.is_some()).count();
+    self.func   = func;
+    self.keys   = slots.into_iter().map(|k| k.unwrap()).collect();
+    self.table  = Slots::from_options_in(table, self.table.is_boxed());
+    self.sorted = self.resort.map(|sort| sort(self.keys.as_slice()));
+    Ok(last)
+  }
+
+  /// Frees the spare capacity of the known keys and their sorted order,
+  /// which `rebuild` can leave behind. The other tables are built to size.
+  pub fn shrink_to_fit(&mut self) {
+    self.keys.shrink_to_fit();
+    match self.sorted {
+      Some(ref mut order) => order.shrink_to_fit(),
+      None                => {}
+    }
+  }
+
+  /// Makes every kind of insertion call `rebuild` when a new key would bring
+  /// the backup store up to `backup_len` entries, or never if it's `None`,
+  /// which is the default. The key that sets it off becomes a known key
+  /// instead of going into the store, if the store would take it at all: a
+  /// `StrictHashMap` refuses unknown keys whatever the threshold.
+  pub fn set_rebuild_threshold(&mut self, backup_len: Option<uint>) {
+    self.rebuild_at = backup_len;
+  }
+
+  /// Sets what lookups do with unknown keys, as `HashMapBuilder::unknown_keys`
+  /// does for new maps. Loaded maps start out with `CheckBackup`.
+  pub fn set_unknown_keys(&mut self, policy: UnknownKeys) {
+    self.unknown = policy;
+  }
+
+  /// What lookups do with unknown keys.
+  pub fn unknown_keys(&self) -> UnknownKeys {
+    self.unknown
+  }
+
+  /// Moves the values out of line, each into a box of its own, or back into
+  /// the slots, as `HashMapBuilder::box_values_over` does for new maps.
+  pub fn set_boxed_values(&mut self, boxed: bool) {
+    self.table.set_boxed(boxed);
+  }
+
+  /// True if the values are out of line.
+  pub fn has_boxed_values(&self) -> bool {
+    self.table.is_boxed()
+  }
+
+  /// Starts counting where lookups are served from, as
+  /// `HashMapBuilder::lookup_counters` does for new maps, or stops and
+  /// forgets the counts.
+  pub fn set_lookup_counters(&mut self, enabled: bool) {
+    self.counters = if enabled { Some(Counters::new()) } else { None };
+  }
+
+  /// The bytes each part of the map takes up.
+  pub fn memory_usage(&self) -> MemoryBreakdown {
+    MemoryBreakdown {
+      hasher: self.func.hasher.heap_size(),
+      labels: self.func.labels_size(),
+      slots:  self.table.heap_size(),
+      keys:   self.keys.capacity() * mem::size_of::<K>()
+            + self.sorted.as_ref().map_or(0, |s| s.len() * mem::size_of::<uint>()),
+      backup: self.backup.heap_size(),
+    }
+  }
+
+  /// Gets the entry for `k`, to look at or fill in with a single lookup.
+  ///
+  /// Unknown keys go to the backup store.
+  pub fn entry<'a>(&'a mut self, k: K) -> Entry<'a, K, V, B> {
+    Entry { place: self.claim(k) }
+  }
+
+  /// Where `k` goes: its slot, or the backup store. Every insertion comes
+  /// through here, so this is where the rebuild threshold is checked, and a
+  /// rebuild happens before the value goes in, never under a reference to
+  /// it.
+  fn claim<'a>(&'a mut self, k: K) -> Place<'a, K, V, B> {
+    match self.find_slot(&k) {
+      Some(i)                        => PerfectSlot(&mut self.table, i, &mut self.size),
+      None if !self.fills_backup(&k) => BackupSlot(&mut self.backup, k),
+      None                           => {
+        let i = self.rebuild_with(Some(k)).unwrap();
+        PerfectSlot(&mut self.table, i, &mut self.size)
+      }
+    }
+  }
+
+  /// True if `k`, an unknown key, isn't in the backup store yet, and putting
+  /// it there would bring the store up to the rebuild threshold. A key the
+  /// store would refuse never sets off a rebuild, so it's refused as usual.
+  fn fills_backup(&self, k: &K) -> bool {
+    match self.rebuild_at {
+      Some(at) =>
+        self.backup.len() + 1 >= at && self.backup.get(k).is_none() && self.backup.accepts(k),
+      None     => false,
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl<K, V, H, B: BackupStore<K, V>> HashMap<K, V, H, B> {
+  /// Returns the number of elements in the map.
+  pub fn len(&self) -> uint {
+    self.size + self.backup.len()
+  }
+
+  /// Returns true if the map contains no elements.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// The number of known keys, which is the number of slots. Slot indices
+  /// are all less than it, so it sizes arrays indexed by `index_of`.
+  pub fn capacity(&self) -> uint {
+    self.keys.len()
+  }
+
+  /// The size of the hash function's table, from
+  /// `PerfectHashFn::table_size`.
+  pub fn table_size(&self) -> uint {
+    self.func.table_size()
+  }
+
+  /// The hash function's keys per table entry, from
+  /// `PerfectHashFn::load_factor`.
+  pub fn load_factor(&self) -> f64 {
+    self.func.load_factor()
+  }
+
+  /// The seed the tables were built from, or `None` if that isn't known.
+  /// Passing it to `HashMapBuilder::seed` with the same keys and settings
+  /// rebuilds them exactly.
+  pub fn seed(&self) -> Option<u64> {
+    self.func.seed()
+  }
+
+  /// How the hash function's construction went, or `None` if the map was
+  /// loaded from a serialized table.
+  pub fn build_stats(&self) -> Option<&BuildStats> {
+    self.func.build_stats()
+  }
+
+  /// The known key in slot `i` and its value, or `None` if the slot is
+  /// empty or there's no slot `i`. Slot indices never change until a
+  /// `rebuild`, so they make compact handles to keys.
+  pub fn get_index(&self, i: uint) -> Option<(&K, &V)> {
+    if i >= self.keys.len() {
+      return None;
+    }
+
+    self.table.get(i).map(|v| (&self.keys[i], v))
+  }
+
+  /// The value in slot `i`, from `index_of`, with no checks at all.
+  ///
+  /// This is unsafe because slot `i` must exist and be full, and nothing
+  /// checks either.
+  pub unsafe fn value_at_unchecked(&self, i: uint) -> &V {
+    self.table.get_unchecked(i)
+  }
+
+  /// Like `value_at_unchecked`, but with a mutable reference.
+  pub unsafe fn value_at_unchecked_mut(&mut self, i: uint) -> &mut V {
+    self.table.get_unchecked_mut(i)
+  }
+
+  /// The known key with handle `id`, whether or not it has a value, or
+  /// `None` if no known key has it.
+  pub fn key_of(&self, id: KeyId) -> Option<&K> {
+    let KeyId(i) = id;
+    if i < self.keys.len() { Some(&self.keys[i]) } else { None }
+  }
+
+  /// Like `get_index`, but with a mutable reference to the value.
+  pub fn get_index_mut(&mut self, i: uint) -> Option<(&K, &mut V)> {
+    if i >= self.keys.len() {
+      return None;
+    }
+
+    let key = &self.keys[i];
+    self.table.get_mut(i).map(|v| (key, v))
+  }
+
+  /// How many lookups the slots and the backup have served, and how many
+  /// missed both, or `None` if the map isn't counting them. A backup that
+  /// serves a lot of lookups is a sign that a `rebuild` would pay off.
+  pub fn lookup_stats(&self) -> Option<LookupStats> {
+    self.counters.as_ref().map(|c| c.snapshot())
+  }
+
+  /// Sets every count back to zero, if the map is counting lookups.
+  pub fn reset_lookup_stats(&self) {
+    match self.counters {
+      Some(ref c) => c.reset(),
+      None        => {}
+    }
+  }
+
+  /// Times one in every `one_in` calls of `get`, and notes the length of its
+  /// key, as `HashMapBuilder::sample_lookups` does for new maps, or stops
+  /// sampling if it's `None`.
+  pub fn set_lookup_sampling(&mut self, one_in: Option<uint>) {
+    self.sampler = one_in.map(|n| Sampler::new(cmp::max(n, 1)));
+  }
+
+  /// What the sampled lookups took, and how long their keys were, or
+  /// `None` if the map isn't sampling them.
+  ///
+  /// Long lookups into a big table mean cache misses on the node table,
+  /// which `Chd` or `Bdz` would shrink. Long keys with many misses are worth
+  /// `fingerprints`, which spare most misses the key comparison.
+  pub fn sample_stats(&self) -> Option<SampleStats> {
+    self.sampler.as_ref().map(|s| s.snapshot())
+  }
+
+  /// An iterator visiting all key-value pairs in arbitrary order.
+  pub fn iter<'a>(&'a self) -> Entries<'a, K, V> {
+    Entries {
+      perfect: self.keys.iter().zip(self.table.iter()),
+      backup:  self.backup.iter(),
+    }
+  }
+
+  /// An iterator visiting all key-value pairs in arbitrary order, with
+  /// mutable references to the values.
+  pub fn iter_mut<'a>(&'a mut self) -> MutEntries<'a, K, V> {
+    MutEntries {
+      perfect: self.keys.iter().zip(self.table.iter_mut()),
+      backup:  self.backup.iter_mut(),
+    }
+  }
+
+  /// Creates a consuming iterator over all key-value pairs in arbitrary
+  /// order.
+  pub fn into_iter(mut self) -> MoveEntries<K, V> {
+    MoveEntries {
+      backup:  self.backup.drain().into_iter(),
+      perfect: self.keys.into_iter().zip(self.table.into_iter()),
+    }
+  }
+
+  /// An iterator visiting the entries of known keys in key order, and then
+  /// those in the backup, in the backup's own order. With a `SortedBackup`
+  /// that's key order too.
+  ///
+  /// Fails unless the keys have been sorted, by `sort_keys` or
+  /// `HashMapBuilder::build_sorted`. Rebuilds keep them sorted.
+  pub fn iter_sorted<'a>(&'a self) -> SortedEntries<'a, K, V> {
+    let order =
+      match self.sorted {
+        Some(ref order) => order.iter(),
+        None            => fail!("perfect::HashMap::iter_sorted: call sort_keys first"),
+      };
+
+    SortedEntries {
+      keys:   self.keys.as_slice(),
+      table:  &self.table,
+      order:  order,
+      backup: self.backup.iter(),
+    }
+  }
+
+  /// An iterator visiting all keys in arbitrary order.
+  pub fn keys<'a>(&'a self) -> Keys<'a, K, V> {
+    self.iter().map(|(k, _v)| k)
+  }
+
+  /// An iterator visiting all values in arbitrary order.
+  pub fn values<'a>(&'a self) -> Values<'a, K, V> {
+    self.iter().map(|(_k, v)| v)
+  }
+
+  /// An iterator visiting all values in arbitrary order, by mutable
+  /// reference.
+  pub fn values_mut<'a>(&'a mut self) -> MutValues<'a, K, V> {
+    self.iter_mut().map(|(_k, v)| v)
+  }
+
+  /// Removes every entry for which `f` returns false, in the slots and the
+  /// backup. Nothing is rebuilt: known keys whose values are removed stay
+  /// known.
+  pub fn retain(&mut self, f: |&K, &mut V| -> bool) {
+    for i in range(0, self.keys.len()) {
+      let keep =
+        match self.table.get_mut(i) {
+          Some(v) => f(&self.keys[i], v),
+          None    => continue,
+        };
+
+      if !keep {
+        self.table.take(i);
+        self.size -= 1;
+      }
+    }
+
+    self.backup.retain(|k, v| f(k, v));
+  }
+
+  /// Removes every value, in the slots and the backup, but keeps the hash
+  /// function and the slots' buffer. The known keys stay known, so the map
+  /// can be filled again for the same keys without a rebuild.
+  pub fn clear(&mut self) {
+    self.table.clear();
+    self.size = 0;
+    self.backup.drain();
+  }
+}
+
+#[cfg(feature = "std")]
+impl<K: Ord, V, H, B: BackupStore<K, V>> HashMap<K, V, H, B> {
+  /// Sorts the slots by key, once, so that `iter_sorted` can visit the
+  /// known keys in order without sorting them on every call. The order
+  /// takes a word per known key, and every `rebuild` sorts the new keys.
+  pub fn sort_keys(&mut self) {
+    let resort: fn(&[K]) -> Vec<uint> = sort_order;
+    self.resort = Some(resort);
+    self.sorted = Some(sort_order(self.keys.as_slice()));
+  }
+}
+
+/// The slots of `keys`, in the order of their keys.
+#[cfg(feature = "std")]
+fn sort_order<K: Ord>(keys: &[K]) -> Vec<uint> {
+  let mut order: Vec<uint> = range(0, keys.len()).collect();
+  order.sort_by(|&a, &b| keys[a].cmp(&keys[b]));
+  order
+}
+
+#[cfg(feature = "std")]
+impl<K: Clone, V, H, B: BackupStore<K, V>> HashMap<K, V, H, B> {
+  /// Empties the map, returning every key-value pair in arbitrary order.
+  /// The known keys stay known, so the slots are ready to be refilled
+  /// without a rebuild, which is why their keys are cloned.
+  pub fn drain(&mut self) -> MoveEntries<K, V> {
+    let empty = Slots::with_layout(self.keys.len(), self.table.is_boxed());
+    let table = mem::replace(&mut self.table, empty);
+    self.size = 0;
+
+    MoveEntries {
+      backup:  self.backup.drain().into_iter(),
+      perfect: self.keys.clone().into_iter().zip(table.into_iter()),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl<K: Eq + hash::Hash, V, H: KeyHasher<K>, B: BackupStore<K, V>>
+    ops::Index<K, V> for HashMap<K, V, H, B> {
+  fn index<'a>(&'a self, k: &K) -> &'a V {
+    match self.get(k) {
+      Some(v) => v,
+      None    => fail!("perfect::HashMap: key not present"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl<K: Eq + hash::Hash, V, H: KeyHasher<K>, B: BackupStore<K, V>>
+    ops::IndexMut<K, V> for HashMap<K, V, H, B> {
+  fn index_mut<'a>(&'a mut self, k: &K) -> &'a mut V {
+    match self.get_mut(k) {
+      Some(v) => v,
+      None    => fail!("perfect::HashMap: key not present"),
+    }
+  }
+}
+
+/// A known key's handle, from `HashMap::id_of`: a small integer that can
+/// index arrays kept alongside the map.
+#[deriving(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Show)]
+#[cfg(feature = "std")]
+pub struct KeyId(pub uint);
+
+#[cfg(feature = "std")]
+impl KeyId {
+  /// The handle as an index, below the map's `capacity()`.
+  pub fn index(&self) -> uint {
+    let KeyId(i) = *self;
+    i
+  }
+}
+
+/// The bytes used by each part of a map, from `HashMap::memory_usage`.
+///
+/// Only the tables themselves are counted, not anything the keys or values
+/// own, like the contents of a `String`.
+#[deriving(Clone, PartialEq, Show)]
+#[cfg(feature = "std")]
+pub struct MemoryBreakdown {
+  /// The `KeyHasher`'s tables: the weights `t1` and `t2`, for `Positional`.
+  pub hasher: uint,
+  /// The node table `g`, or what the other algorithms keep instead, plus any
+  /// order or fingerprints.
+  pub labels: uint,
+  /// The value slots, the bitmap of which are full, and any boxed values.
+  pub slots:  uint,
+  /// The stored known keys, and their order from `sort_keys` if there is
+  /// one.
+  pub keys:   uint,
+  /// The backup store, as estimated by `BackupStore::heap_size`.
+  pub backup: uint,
+}
+
+#[cfg(feature = "std")]
+impl MemoryBreakdown {
+  /// The sum of every part.
+  pub fn total(&self) -> uint {
+    self.hasher + self.labels + self.slots + self.keys + self.backup
+  }
+}
+
+/// Where a map's lookups were served from, from `HashMap::lookup_stats`.
+#[deriving(Clone, PartialEq, Show)]
+#[cfg(feature = "std")]
+pub struct LookupStats {
+  /// Lookups that found a value in a known key's slot.
+  pub perfect_hits: uint,
+  /// Lookups that found a value in the backup.
+  pub backup_hits:  uint,
+  /// Lookups that found no value at all.
+  pub misses:       uint,
+}
+
+/// Where a lookup was served from, from `HashMap::get_tiered`.
+#[deriving(Clone, PartialEq, Show)]
+#[cfg(feature = "std")]
+pub enum Lookup<V> {
+  /// The value was in a known key's slot.
+  PerfectHit(V),
+  /// The value was in the backup store.
+  BackupHit(V),
+  /// There was no value.
+  Miss,
+}
+
+#[cfg(feature = "std")]
+impl<V> Lookup<V> {
+  /// The value, wherever it came from.
+  pub fn value(self) -> Option<V> {
+    match self {
+      PerfectHit(v) => Some(v),
+      BackupHit(v)  => Some(v),
+      Miss          => None,
+    }
+  }
+}
+
+/// A sample of `HashMap::get`s, from `HashMap::sample_stats`.
+///
+/// Both histograms have a bucket per power of two: bucket `i` counts the
+/// samples of at least `2^(i - 1)`, and less than `2^i`. Bucket 0 counts
+/// those of zero.
+#[deriving(Clone, PartialEq, Show)]
+#[cfg(feature = "std")]
+pub struct SampleStats {
+  /// The number of lookups sampled.
+  pub samples:    uint,
+  /// The sampled lookups by how many nanoseconds they took.
+  pub latency_ns: Vec<uint>,
+  /// The sampled keys by how many bytes they hash. Only the hashers that
+  /// weigh bytes, `Positional`, `Bytes`, and `Stable`, count them.
+  pub key_bytes:  Vec<uint>,
+}
+
+/// The bucket of `x` in a `SampleStats` histogram.
+#[cfg(feature = "std")]
+fn log2_bucket(x: u64) -> uint {
+  64 - x.leading_zeros()
+}
+
+/// The running histograms behind `SampleStats`, relaxed like `Counters`.
+#[cfg(feature = "std")]
+struct Sampler {
+  one_in:     uint,
+  lookups:    AtomicUint,
+  samples:    AtomicUint,
+  latency_ns: Vec<AtomicUint>,
+  key_bytes:  Vec<AtomicUint>,
+}
+
+#[cfg(feature = "std")]
+impl Sampler {
+  fn new(one_in: uint) -> Sampler {
+    Sampler {
+      one_in:     one_in,
+      lookups:    AtomicUint::new(0),
+      samples:    AtomicUint::new(0),
+      latency_ns: Vec::from_fn(65, |_| AtomicUint::new(0)),
+      key_bytes:  Vec::from_fn(65, |_| AtomicUint::new(0)),
+    }
+  }
+
+  /// True if this lookup is one to sample.
+  fn pick(&self) -> bool {
+    self.lookups.fetch_add(1, Relaxed) % self.one_in == 0
+  }
+
+  fn record(&self, ns: u64, bytes: Option<uint>) {
+    self.samples.fetch_add(1, Relaxed);
+    self.latency_ns[log2_bucket(ns)].fetch_add(1, Relaxed);
+    match bytes {
+      Some(b) => { self.key_bytes[log2_bucket(b as u64)].fetch_add(1, Relaxed); }
+      None    => {}
+    }
+  }
+
+  fn snapshot(&self) -> SampleStats {
+    SampleStats {
+      samples:    self.samples.load(Relaxed),
+      latency_ns: self.latency_ns.iter().map(|c| c.load(Relaxed)).collect(),
+      key_bytes:  self.key_bytes.iter().map(|c| c.load(Relaxed)).collect(),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl Clone for Sampler {
+  fn clone(&self) -> Sampler {
+    Sampler {
+      one_in:     self.one_in,
+      lookups:    AtomicUint::new(self.lookups.load(Relaxed)),
+      samples:    AtomicUint::new(self.samples.load(Relaxed)),
+      latency_ns: self.latency_ns.iter().map(|c| AtomicUint::new(c.load(Relaxed))).collect(),
+      key_bytes:  self.key_bytes.iter().map(|c| AtomicUint::new(c.load(Relaxed))).collect(),
+    }
+  }
+}
+
+/// The running counts behind `LookupStats`. Lookups only get `&self`, and
+/// frozen maps are shared between tasks, so they're atomic, but relaxed:
+/// they order nothing else.
+#[cfg(feature = "std")]
+struct Counters {
+  perfect_hits: AtomicUint,
+  backup_hits:  AtomicUint,
+  misses:       AtomicUint,
+}
+
+#[cfg(feature = "std")]
+impl Counters {
+  fn new() -> Counters {
+    Counters {
+      perfect_hits: AtomicUint::new(0),
+      backup_hits:  AtomicUint::new(0),
+      misses:       AtomicUint::new(0),
+    }
+  }
+
+  fn snapshot(&self) -> LookupStats {
+    LookupStats {
+      perfect_hits: self.perfect_hits.load(Relaxed),
+      backup_hits:  self.backup_hits.load(Relaxed),
+      misses:       self.misses.load(Relaxed),
+    }
+  }
+
+  fn reset(&self) {
+    self.perfect_hits.store(0, Relaxed);
+    self.backup_hits.store(0, Relaxed);
+    self.misses.store(0, Relaxed);
+  }
+}
+
+#[cfg(feature = "std")]
+impl Clone for Counters {
+  fn clone(&self) -> Counters {
+    let stats = self.snapshot();
+    Counters {
+      perfect_hits: AtomicUint::new(stats.perfect_hits),
+      backup_hits:  AtomicUint::new(stats.backup_hits),
+      misses:       AtomicUint::new(stats.misses),
+    }
+  }
+}
+
+/// Hints that `p` will be read soon, so it can be on its way into the cache.
+/// A prefetch never faults, wherever `p` points.
+#[inline]
+#[cfg(feature = "std")]
+fn prefetch<T>(p: *const T) {
+  unsafe { intrinsics::prefetch_read_data(p, 3) }
+}
+
+/// Counts a lookup that found `v`, in the slots if `from_slots`, and passes
+/// `v` on.
+#[inline]
+#[cfg(feature = "std")]
+fn tally<T>(counters: &Option<Counters>, from_slots: bool, v: Option<T>) -> Option<T> {
+  match *counters {
+    Some(ref c) => {
+      let counter =
+        match v {
+          None                  => &c.misses,
+          Some(_) if from_slots => &c.perfect_hits,
+          Some(_)               => &c.backup_hits,
+        };
+      counter.fetch_add(1, Relaxed);
+    }
+    None => {}
+  }
+  v
+}
+
+/// HashMap iterator.
+#[cfg(feature = "std")]
+pub struct Entries<'a, K: 'a, V: 'a> {
+  perfect: iter::Zip<slice::Items<'a, K>, slots::Items<'a, V>>,
+  backup:  Box<Iterator<(&'a K, &'a V)> + 'a>,
+}
+
+/// HashMap mutable entries iterator.
+#[cfg(feature = "std")]
+pub struct MutEntries<'a, K: 'a, V: 'a> {
+  perfect: iter::Zip<slice::Items<'a, K>, slots::MutItems<'a, V>>,
+  backup:  Box<Iterator<(&'a K, &'a mut V)> + 'a>,
+}
+
+/// HashMap move iterator.
+#[cfg(feature = "std")]
+pub struct MoveEntries<K, V> {
+  perfect: iter::Zip<vec::MoveItems<K>, slots::MoveItems<V>>,
+  backup:  vec::MoveItems<(K, V)>,
+}
+
+/// HashMap iterator in key order, from `HashMap::iter_sorted`.
+#[cfg(feature = "std")]
+pub struct SortedEntries<'a, K: 'a, V: 'a> {
+  keys:   &'a [K],
+  table:  &'a Slots<V>,
+  order:  slice::Items<'a, uint>,
+  backup: Box<Iterator<(&'a K, &'a V)> + 'a>,
+}
+
+/// HashMap keys iterator.
+#[cfg(feature = "std")]
+pub type Keys<'a, K, V> =
+  iter::Map<'a, (&'a K, &'a V), &'a K, Entries<'a, K, V>>;
+
+/// HashMap values iterator.
+#[cfg(feature = "std")]
+pub type Values<'a, K, V> =
+  iter::Map<'a, (&'a K, &'a V), &'a V, Entries<'a, K, V>>;
+
+/// HashMap mutable values iterator.
+#[cfg(feature = "std")]
+pub type MutValues<'a, K, V> =
+  iter::Map<'a, (&'a K, &'a mut V), &'a mut V, MutEntries<'a, K, V>>;
+
+#[cfg(feature = "std")]
+impl<'a, K, V> Iterator<(&'a K, &'a V)> for Entries<'a, K, V> {
+  fn next(&mut self) -> Option<(&'a K, &'a V)> {
+    for (k, v) in self.perfect.by_ref() {
+      match v {
+        Some(v) => return Some((k, v)),
+        None    => {}
+      }
+    }
+
+    self.backup.next()
+  }
+}
+
+#[cfg(feature = "std")]
+impl<'a, K, V> Iterator<(&'a K, &'a mut V)> for MutEntries<'a, K, V> {
+  fn next(&mut self) -> Option<(&'a K, &'a mut V)> {
+    for (k, v) in self.perfect.by_ref() {
+      match v {
+        Some(v) => return Some((k, v)),
+        None    => {}
+      }
+    }
+
+    self.backup.next()
+  }
+}
+
+#[cfg(feature = "std")]
+impl<K, V> Iterator<(K, V)> for MoveEntries<K, V> {
+  fn next(&mut self) -> Option<(K, V)> {
+    for (k, v) in self.perfect.by_ref() {
+      match v {
+        Some(v) => return Some((k, v)),
+        None    => {}
+      }
+    }
+
+    self.backup.next()
+  }
+}
+
+#[cfg(feature = "std")]
+impl<'a, K, V> Iterator<(&'a K, &'a V)> for SortedEntries<'a, K, V> {
+  fn next(&mut self) -> Option<(&'a K, &'a V)> {
+    let (keys, table) = (self.keys, self.table);
+    for &i in self.order.by_ref() {
+      match table.get(i) {
+        Some(v) => return Some((&keys[i], v)),
+        None    => {}
+      }
+    }
+
+    self.backup.next()
+  }
+}
+
+/// The number of keys `HashMap::get_iter` hashes and prefetches before it
+/// reads any slots.
+#[cfg(feature = "std")]
+pub static LOOKUP_BATCH: uint = 16;
+
+/// Iterator over the values of a stream of keys, from `HashMap::get_iter`.
+#[cfg(feature = "std")]
+pub struct Lookups<'a, K: 'a, V: 'a, H: 'a, B: 'a, I> {
+  map:   &'a HashMap<K, V, H, B>,
+  keys:  I,
+  /// The current batch of keys, with their slots.
+  batch: Vec<(&'a K, Option<uint>)>,
+  /// The position in `batch` of the next key to look up.
+  next:  uint,
+}
+
+#[cfg(feature = "std")]
+impl<'a,
+     K: Eq + hash::Hash,
+     V,
+     H: KeyHasher<K>,
+     B: BackupStore<K, V>,
+     I: Iterator<&'a K>>
+    Iterator<Option<&'a V>> for Lookups<'a, K, V, H, B, I> {
+  fn next(&mut self) -> Option<Option<&'a V>> {
+    if self.next == self.batch.len() {
+      self.batch.clear();
+      self.next = 0;
+
+      for k in self.keys.by_ref().take(LOOKUP_BATCH) {
+        self.batch.push((k, self.map.func.try_hash(k)));
+      }
+      for &(_, slot) in self.batch.iter() {
+        self.map.prefetch_slot(slot);
+      }
+
+      if self.batch.is_empty() {
+        return None;
+      }
+    }
+
+    let (k, slot) = self.batch[self.next];
+    self.next += 1;
+    Some(self.map.get_hashed(k, slot))
+  }
+}
+
+/// A single key's place in a map, from `HashMap::entry`.
+#[cfg(feature = "std")]
+pub struct Entry<'a, K: 'a, V: 'a, B: 'a = SeededBackup<K, V>> {
+  place: Place<'a, K, V, B>,
+}
+
+#[cfg(feature = "std")]
+enum Place<'a, K: 'a, V: 'a, B: 'a> {
+  /// A known key's slot, and the map's count of filled slots.
+  PerfectSlot(&'a mut Slots<V>, uint, &'a mut uint),
+  BackupSlot(&'a mut B, K),
+}
+
+#[cfg(feature = "std")]
+impl<'a, K, V, B: BackupStore<K, V>> Entry<'a, K, V, B> {
+  /// Returns the value for the key, inserting `v` first if there isn't one.
+  pub fn or_insert(self, v: V) -> &'a mut V {
+    // Boxed closures can't move out of their environment.
+    let mut v = Some(v);
+    self.or_insert_with(|| v.take().unwrap())
+  }
+
+  /// Returns the value for the key, inserting the result of `f` first if
+  /// there isn't one. `f` is only called if the value is missing.
+  ///
+  /// Fails if the key is unknown and the backup store refuses it.
+  pub fn or_insert_with(self, f: || -> V) -> &'a mut V {
+    match self.place {
+      PerfectSlot(table, i, size) => {
+        if !table.is_full(i) {
+          table.replace(i, Some(f()));
+          *size += 1;
+        }
+        table.get_mut(i).unwrap()
+      }
+      BackupSlot(backup, k) =>
+        match backup.get_or_insert_with(k, f) {
+          Some(v) => v,
+          None    => fail!("perfect::HashMap: the backup store refused a key"),
+        },
+    }
   }
 }
@@ -0,0 +1,117 @@
+//! Watching, and stopping, a construction in progress.
+//!
+//! Over tens of millions of keys construction can take minutes. A builder
+//! given a `Progress` reports to it before every attempt, and one given a
+//! `CancelToken` checks it just as often, giving up with `Cancelled` once
+//! it's been cancelled from anywhere else. One given a deadline gives up
+//! with `DeadlineExceeded` at the first attempt after it passes.
+
+use std::cmp;
+use std::i64;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, SeqCst};
+use std::time::Duration;
+use time;
+
+use super::{BuildError, Cancelled, DeadlineExceeded};
+
+/// What construction is doing when it reports.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Phase {
+  /// About to try another random table.
+  Searching,
+  /// A table worked. What's left is ordering and fingerprinting the keys.
+  Finishing,
+}
+
+/// Receives reports from a construction, possibly from several tasks at
+/// once.
+pub trait Progress {
+  /// Called with the number of attempts made so far, the time since
+  /// construction started, and what it's doing now.
+  fn report(&self, iteration: uint, elapsed: Duration, phase: Phase);
+}
+
+/// A flag shared between a construction and whoever might want to stop it.
+///
+/// Clones share the flag, so keep one and give another to the builder.
+#[deriving(Clone)]
+pub struct CancelToken {
+  cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+  /// A token that hasn't been cancelled.
+  pub fn new() -> CancelToken {
+    CancelToken { cancelled: Arc::new(AtomicBool::new(false)) }
+  }
+
+  /// Stops every construction watching this token at its next attempt.
+  pub fn cancel(&self) {
+    self.cancelled.store(true, SeqCst);
+  }
+
+  /// True once `cancel` has been called on any clone.
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(SeqCst)
+  }
+}
+
+/// A builder's `Progress`, `CancelToken`, and deadline, and when
+/// construction started.
+#[deriving(Clone)]
+pub struct Monitor {
+  progress: Option<Arc<Box<Progress + Send + Sync>>>,
+  cancel:   Option<CancelToken>,
+  start:    u64,
+  /// When construction has to give up by, in `precise_time_ns`.
+  deadline: Option<u64>,
+}
+
+impl Monitor {
+  /// Starts the clock, with `timeout` to go until the deadline, if any.
+  pub fn new(progress: Option<Arc<Box<Progress + Send + Sync>>>,
+             cancel: Option<CancelToken>,
+             timeout: Option<Duration>) -> Monitor {
+    let start = time::precise_time_ns();
+    Monitor {
+      progress: progress,
+      cancel:   cancel,
+      start:    start,
+      deadline: timeout.map(|t| {
+        start + cmp::max(0, t.num_nanoseconds().unwrap_or(i64::MAX)) as u64
+      }),
+    }
+  }
+
+  /// The time since construction started.
+  pub fn elapsed(&self) -> Duration {
+    Duration::nanoseconds((time::precise_time_ns() - self.start) as i64)
+  }
+
+  /// Reports `phase`, if anyone's listening.
+  pub fn report(&self, iteration: uint, phase: Phase) {
+    match self.progress {
+      Some(ref progress) => progress.report(iteration, self.elapsed(), phase),
+      None               => {}
+    }
+  }
+
+  /// Reports that attempt `iteration` is about to start, unless construction
+  /// has been cancelled or is out of time.
+  pub fn attempt(&self, iteration: uint) -> Result<(), BuildError> {
+    match self.cancel {
+      Some(ref cancel) if cancel.is_cancelled() => return Err(Cancelled),
+      _                                         => {}
+    }
+
+    match self.deadline {
+      Some(deadline) if time::precise_time_ns() >= deadline =>
+        return Err(DeadlineExceeded(iteration)),
+      _ => {}
+    }
+
+    self.report(iteration, Searching);
+    Ok(())
+  }
+}
@@ -0,0 +1,291 @@
+//! The PTHash construction, after Pibiri and Trani.
+//!
+//! Keys are split into buckets unevenly: 60% of them go to 30% of the
+//! buckets, so the big buckets are placed while the table is still mostly
+//! empty. Each bucket gets a pilot, the first that sends all its keys to free
+//! slots of `n`, with a key's slot its fingerprint xored with the pilot's
+//! hash. Buckets are placed largest first.
+//!
+//! Lookups hash once, find the pilot, and land on the slot: no second table
+//! and no ranking. The `n - m` slots past `m` that keys landed in are each
+//! remapped to one of the free slots below `m`, so the function is minimal.
+//! Pilots are packed into as many bits as the largest takes, which is a few
+//! bits per key.
+
+use std::cmp;
+use std::mem;
+use std::rand;
+
+use super::{KeyHasher, mix};
+use super::{BuildError, Overflow};
+use super::{Corruption, BadLength, OutOfBounds};
+use super::bits::{BitWriter, read_bits, holds_fields};
+
+/// The range keys are hashed onto. Both halves of a key's vertices make up
+/// its 62-bit fingerprint, and the first also picks its bucket.
+static HASH_RANGE: uint = 1 << 31;
+
+/// Hash values below this go to the dense buckets: 60% of them.
+static DENSE_CUTOFF: uint = HASH_RANGE / 10 * 6;
+
+/// Buckets that find no home among this many pilots fail the attempt.
+static MAX_PILOT: u64 = 1 << 20;
+
+/// The pilots and the remapped slots for one set of keys.
+#[deriving(Clone)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+pub struct Labeling {
+  /// The number of keys.
+  m:       uint,
+  /// The number of slots keys are placed in.
+  n:       uint,
+  /// The number of dense buckets, among `buckets`.
+  dense:   uint,
+  buckets: uint,
+  /// The bits of each pilot.
+  width:   uint,
+  pilots:  Vec<u64>,
+  /// The slot below `m` that each slot from `m` on stands for.
+  remap:   Vec<uint>,
+}
+
+/// A key's fingerprint and bucket, from hash values `u` and `v`.
+fn split(u: uint, v: uint, dense: uint, buckets: uint) -> (u64, uint) {
+  let x = (u as u64) << 31 | v as u64;
+  let b =
+    if u < DENSE_CUTOFF {
+      (u as u64 * dense as u64 / DENSE_CUTOFF as u64) as uint
+    } else {
+      let sparse = (buckets - dense) as u64;
+      dense + ((u - DENSE_CUTOFF) as u64 * sparse / (HASH_RANGE - DENSE_CUTOFF) as u64) as uint
+    };
+  (x, b)
+}
+
+/// The slot pilot `p` sends fingerprint `x` to.
+fn place(x: u64, p: u64, n: uint) -> uint {
+  ((x ^ mix(p * 0x9e3779b97f4a7c15)) % n as u64) as uint
+}
+
+/// Reseeds `hasher` and places the keys in `n` slots, `buckets` buckets at a
+/// time, or returns `None` if some bucket doesn't fit.
+pub fn attempt<R: rand::Rng, K, H: KeyHasher<K>>(
+    rng: &mut R, hasher: &mut H, keys: &[K], n: uint, buckets: uint)
+    -> Result<Option<Labeling>, BuildError> {
+  hasher.reseed(rng, keys, HASH_RANGE);
+
+  let mut pairs = Vec::with_capacity(keys.len());
+  if !hasher.vertices_batch(keys, HASH_RANGE, &mut pairs) {
+    return Err(Overflow);
+  }
+
+  let m       = keys.len();
+  let n       = cmp::max(n, 1);
+  let buckets = cmp::max(buckets, 2);
+  let dense   = cmp::max(1, buckets * 3 / 10);
+
+  let mut hashed: Vec<(uint, u64)> =
+    pairs.iter().map(|&(u, v)| { let (x, b) = split(u, v, dense, buckets); (b, x) }).collect();
+  hashed.sort();
+
+  // Keys with the same fingerprint always land together.
+  if hashed.windows(2).any(|w| w[0] == w[1]) {
+    return Ok(None);
+  }
+
+  // Bucket `b` is `hashed[offsets[b]..offsets[b + 1]]`.
+  let mut offsets = Vec::from_elem(buckets + 1, 0u);
+  for &(b, _) in hashed.iter() {
+    *offsets.get_mut(b + 1) += 1;
+  }
+  for b in range(0, buckets) {
+    *offsets.get_mut(b + 1) += offsets[b];
+  }
+  let sizes: Vec<uint> = range(0, buckets).map(|b| offsets[b + 1] - offsets[b]).collect();
+
+  let mut order: Vec<uint> = range(0, buckets).collect();
+  order.sort_by(|&a, &b| sizes[b].cmp(&sizes[a]));
+
+  let mut taken  = Vec::from_elem(n, false);
+  let mut pilots = Vec::from_elem(buckets, 0u64);
+  let mut slots  = Vec::new();
+
+  for &b in order.iter() {
+    let bucket = hashed.slice(offsets[b], offsets[b + 1]);
+
+    // The rest are empty too.
+    if bucket.is_empty() {
+      break;
+    }
+
+    let mut placed = false;
+
+    for p in range(0, MAX_PILOT) {
+      slots.clear();
+
+      for &(_, x) in bucket.iter() {
+        let slot = place(x, p, n);
+        if taken[slot] || slots.contains(&slot) {
+          break;
+        }
+        slots.push(slot);
+      }
+
+      if slots.len() == bucket.len() {
+        for &slot in slots.iter() {
+          *taken.get_mut(slot) = true;
+        }
+        *pilots.get_mut(b) = p;
+        placed = true;
+        break;
+      }
+    }
+
+    if !placed {
+      return Ok(None);
+    }
+  }
+
+  // There are as many free slots below `m` as taken ones from `m` on.
+  let mut free  = range(0, m).filter(|&i| !taken[i]);
+  let mut remap = Vec::from_elem(n - m, 0u);
+  for i in range(m, n) {
+    if taken[i] {
+      *remap.get_mut(i - m) = free.next().unwrap();
+    }
+  }
+
+  let max   = pilots.iter().map(|&p| p).max().unwrap_or(0);
+  let width = 64 - max.leading_zeros();
+  let mut packed = BitWriter::new();
+  for &p in pilots.iter() {
+    packed.push(p, width);
+  }
+  // Keep one word past the end, so reads never run off it.
+  packed.words.push(0);
+
+  Ok(Some(Labeling {
+    m:       m,
+    n:       n,
+    dense:   dense,
+    buckets: buckets,
+    width:   width,
+    pilots:  packed.words,
+    remap:   remap,
+  }))
+}
+
+impl Labeling {
+  /// The index `k` would have if it were a known key. Every key gets one.
+  pub fn index<K, H: KeyHasher<K>>(&self, hasher: &H, k: &K) -> Option<uint> {
+    let (x, b) =
+      match hasher.vertices(k, HASH_RANGE) {
+        Some((u, v)) => split(u, v, self.dense, self.buckets),
+        None         => return None,
+      };
+
+    let p    = read_bits(self.pilots.as_slice(), b * self.width, self.width);
+    let slot = place(x, p, self.n);
+    if slot < self.m { Some(slot) } else { Some(self.remap[slot - self.m]) }
+  }
+
+  /// Checks that this places `m` keys, that every bucket has a pilot, that
+  /// some buckets are sparse, and that every slot from `m` on is remapped
+  /// below it, so `index` stays in bounds.
+  pub fn verify(&self, m: uint) -> Result<(), Corruption> {
+    if self.m != m {
+      return Err(BadLength("keys", self.m));
+    }
+    if self.n == 0 || self.n < m || self.remap.len() != self.n - m {
+      return Err(BadLength("remap", self.remap.len()));
+    }
+    match self.remap.iter().position(|&i| i >= m) {
+      Some(i) => return Err(OutOfBounds("remap", i, self.remap[i])),
+      None    => {}
+    }
+    if self.dense == 0 || self.dense >= self.buckets
+    || !holds_fields(self.pilots.as_slice(), self.buckets, self.width) {
+      return Err(BadLength("pilots", self.buckets));
+    }
+    Ok(())
+  }
+
+  /// The range `hasher.vertices` is asked for.
+  pub fn hash_range(&self) -> uint {
+    HASH_RANGE
+  }
+
+  /// The number of slots keys are placed in, before remapping.
+  pub fn num_slots(&self) -> uint {
+    self.n
+  }
+
+  /// The bytes of the pilots and the remapped slots.
+  pub fn heap_size(&self) -> uint {
+    self.pilots.len() * 8 + self.remap.len() * mem::size_of::<uint>()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{HASH_RANGE, split, place};
+  use super::super::{HashMapBuilder, KeyHasher, PerfectHashFn, PtHash, PtHashLabels};
+  use super::super::bits::read_bits;
+  use super::super::builder::DEFAULT_PTHASH_LAMBDA;
+  use super::super::testing::keys;
+
+  /// A function over `keys` with room for some of them past `m`.
+  fn build(keys: &[String]) -> PerfectHashFn<String> {
+    HashMapBuilder::new()
+      .algorithm(PtHash(DEFAULT_PTHASH_LAMBDA))
+      .space_factor(1.2)
+      .seed(1)
+      .build_fn(keys)
+      .unwrap()
+  }
+
+  #[test]
+  fn remaps_slots_past_m_to_the_free_ones_below_it() {
+    let keys = keys(1000);
+    let func = build(keys.as_slice());
+    let labeling =
+      match func.labels {
+        PtHashLabels(ref labeling) => labeling,
+        _                          => fail!("not a PTHash table"),
+      };
+
+    let mut taken = Vec::from_elem(labeling.n, false);
+    for k in keys.iter() {
+      let (u, v) = func.hasher.vertices(k, HASH_RANGE).unwrap();
+      let (x, b) = split(u, v, labeling.dense, labeling.buckets);
+      let p      = read_bits(labeling.pilots.as_slice(), b * labeling.width, labeling.width);
+      let slot   = place(x, p, labeling.n);
+      assert!(!taken[slot], "{} landed in slot {}, as another key did", k, slot);
+      *taken.get_mut(slot) = true;
+    }
+
+    // Each slot a key landed in from `m` on stands for the next free one.
+    let m = keys.len();
+    let free: Vec<uint> = range(0, m).filter(|&i| !taken[i]).collect();
+    let remapped: Vec<uint> =
+      range(m, labeling.n).filter(|&i| taken[i]).map(|i| labeling.remap[i - m]).collect();
+    assert!(!free.is_empty());
+    assert_eq!(remapped, free);
+  }
+
+  #[test]
+  fn verify_rejects_tables_with_no_sparse_buckets() {
+    let keys = keys(100);
+    let func = build(keys.as_slice());
+    let mut labeling =
+      match func.labels {
+        PtHashLabels(ref labeling) => labeling.clone(),
+        _                          => fail!("not a PTHash table"),
+      };
+    assert!(labeling.verify(keys.len()).is_ok());
+
+    // Sparse keys would go to bucket `buckets`, past the pilots.
+    labeling.dense = labeling.buckets;
+    assert!(labeling.verify(keys.len()).is_err());
+  }
+}
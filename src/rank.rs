@@ -0,0 +1,177 @@
+//! A bit vector that can count the set bits below any position.
+//!
+//! The algorithms that spread keys over more slots than there are keys use
+//! this to squeeze the slots back down to `[0, m)`: a key's index is the
+//! number of occupied slots before its own. It works just as well for any
+//! other sparse table that needs dense indices:
+//!
+//! ```ignore
+//! let occupied = RankedBits::new([false, true, true, false, true].as_slice());
+//! assert_eq!(occupied.rank(4), Some(2));
+//! assert_eq!(occupied.rank(3), None);
+//! ```
+//!
+//! The ranks are laid out as in Vigna's rank9: for every 512 bits, one word
+//! of the set bits before them and one of seven 9-bit counts within them.
+//! That's a quarter of a bit of overhead per bit, and a rank is two loads
+//! and no loops.
+
+use std::mem;
+
+/// A bit per slot, plus two words per 512 slots of precomputed ranks.
+#[deriving(Clone)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+pub struct RankedBits {
+  len:    uint,
+  bits:   Vec<u64>,
+  /// For each block of eight words, the set bits before it, and then the
+  /// set bits before each of its words but the first, nine bits apiece.
+  counts: Vec<u64>,
+}
+
+impl RankedBits {
+  /// Packs `set` into bits and ranks them.
+  pub fn new(set: &[bool]) -> RankedBits {
+    let mut bits = Vec::from_elem((set.len() + 63) / 64, 0u64);
+    for (i, &b) in set.iter().enumerate() {
+      if b {
+        *bits.get_mut(i / 64) |= 1 << (i % 64);
+      }
+    }
+
+    let counts = rank_counts(bits.as_slice());
+    RankedBits { len: set.len(), bits: bits, counts: counts }
+  }
+
+  /// Checks that there are bits for `len` slots and no more, and that the
+  /// ranks count them right, so `rank` and `count_below` are exact.
+  pub fn is_consistent(&self) -> bool {
+    let tail = self.len % 64;
+    self.bits.len() == (self.len + 63) / 64
+      && (tail == 0 || self.bits[self.bits.len() - 1] >> tail == 0)
+      && self.counts == rank_counts(self.bits.as_slice())
+  }
+
+  /// The number of bits.
+  pub fn len(&self) -> uint {
+    self.len
+  }
+
+  /// True if bit `i` is set.
+  pub fn get(&self, i: uint) -> bool {
+    self.bits[i / 64] & 1 << (i % 64) != 0
+  }
+
+  /// The number of set bits before `i`, which may be `len()`.
+  pub fn count_below(&self, i: uint) -> uint {
+    if i == self.len {
+      return self.count_ones();
+    }
+
+    let word  = i / 64;
+    let block = word / 8 * 2;
+
+    // For the first word of a block, `t` is -1, which picks the always-zero
+    // top bit of the relative counts.
+    let t        = (word % 8) as i64 - 1;
+    let relative = (self.counts[block + 1] >> ((t + (t >> 60 & 8)) * 9) as uint) & 0x1ff;
+    let partial  = (self.bits[word] & ((1 << (i % 64)) - 1)).count_ones() as u64;
+
+    (self.counts[block] + relative + partial) as uint
+  }
+
+  /// The number of set bits before `i`, or `None` if bit `i` isn't set.
+  pub fn rank(&self, i: uint) -> Option<uint> {
+    if self.get(i) { Some(self.count_below(i)) } else { None }
+  }
+
+  /// The number of set bits.
+  pub fn count_ones(&self) -> uint {
+    match self.bits.len() {
+      0 => 0,
+      n => self.count_below((n - 1) * 64) + self.bits[n - 1].count_ones(),
+    }
+  }
+
+  /// The bytes of the bits and ranks.
+  pub fn heap_size(&self) -> uint {
+    (self.bits.len() + self.counts.len()) * mem::size_of::<u64>()
+  }
+}
+
+/// The `counts` of a `RankedBits` with `bits`.
+fn rank_counts(bits: &[u64]) -> Vec<u64> {
+  let mut counts = Vec::with_capacity((bits.len() + 7) / 8 * 2);
+  let mut rank   = 0u64;
+  for block in bits.chunks(8) {
+    let mut within   = 0u64;
+    let mut relative = 0u64;
+    for (j, &word) in block.iter().enumerate() {
+      if j > 0 {
+        relative |= within << (9 * (j - 1));
+      }
+      within += word.count_ones() as u64;
+    }
+    // Short last blocks count the missing words as empty.
+    for j in range(block.len(), 8) {
+      relative |= within << (9 * (j - 1));
+    }
+
+    counts.push(rank);
+    counts.push(relative);
+    rank += within;
+  }
+  counts
+}
+
+#[cfg(test)]
+mod test {
+  use super::RankedBits;
+
+  /// Checks every rank against a count of the bits before it.
+  fn check(set: &[bool]) {
+    let ranked = RankedBits::new(set);
+    assert!(ranked.is_consistent());
+    assert_eq!(ranked.len(), set.len());
+
+    let mut below = 0;
+    for (i, &b) in set.iter().enumerate() {
+      assert_eq!(ranked.get(i), b);
+      assert_eq!(ranked.count_below(i), below);
+      assert_eq!(ranked.rank(i), if b { Some(below) } else { None });
+      if b {
+        below += 1;
+      }
+    }
+    assert_eq!(ranked.count_below(set.len()), below);
+    assert_eq!(ranked.count_ones(), below);
+  }
+
+  /// `len` bits, scattered, except every third block of 512 is full.
+  fn scattered(len: uint) -> Vec<bool> {
+    range(0, len).map(|i| i * 7919 % 13 < 5 || i / 512 % 3 == 2).collect()
+  }
+
+  #[test]
+  fn lengths_around_word_and_block_boundaries() {
+    for &len in [0u, 1, 63, 64, 65, 511, 512, 513, 1535, 1536, 1537].iter() {
+      check(scattered(len).as_slice());
+    }
+  }
+
+  #[test]
+  fn short_last_block() {
+    // Three full blocks, and three words and a bit of a fourth.
+    check(scattered(3 * 512 + 3 * 64 + 1).as_slice());
+  }
+
+  #[test]
+  fn all_set() {
+    check(Vec::from_elem(2000, true).as_slice());
+  }
+
+  #[test]
+  fn none_set() {
+    check(Vec::from_elem(2000, false).as_slice());
+  }
+}
@@ -0,0 +1,556 @@
+//! The RecSplit construction, after Esposito, Müller Graf, and Vigna.
+//!
+//! Keys are split into buckets of about `bucket` keys each. Each bucket is
+//! split again, recursively, by searching for a seed whose hash sends the
+//! right number of keys to each part, until the parts are leaves of at most
+//! `leaf` keys. Each leaf then gets a seed whose hash is a bijection onto
+//! its slots. Nothing is stored but the seeds, Golomb-Rice coded, so with
+//! leaves of eight keys the tables take under two bits per key.
+//!
+//! The shape of the tree only depends on how many keys a node has, so the
+//! seeds are stored in depth-first order with no pointers: a lookup skips the
+//! subtrees it doesn't go into by their sizes alone. A bucket's fixed-width
+//! halves of the codes come first, then its unary halves, so skipping a
+//! subtree is one addition and a count of its nodes' unary codes.
+//!
+//! Construction takes about `e^leaf` hashes per key, so it's far slower than
+//! the other algorithms, and each extra key per leaf multiplies it by about
+//! `e` again.
+
+use std::cmp;
+use std::rand;
+
+use super::{KeyHasher, mix};
+use super::{BuildError, Overflow};
+use super::{Corruption, BadLength, OutOfBounds};
+use super::bits::{BitWriter, read_bits};
+
+/// The range keys are hashed onto. Both halves of a key's vertices make up
+/// its 62-bit fingerprint, and the first also picks its bucket.
+static HASH_RANGE: uint = 1 << 31;
+
+/// Parts of nodes with up to this many leaves' worth of keys are leaves.
+fn lower_fanout(leaf: uint) -> uint {
+  cmp::max(2, (0.35 * leaf as f64 + 0.5).ceil() as uint)
+}
+
+/// Parts of nodes with up to this many lower nodes' worth of keys are lower
+/// nodes. Bigger nodes split in two.
+fn upper_fanout(leaf: uint) -> uint {
+  cmp::max(2, (0.21 * leaf as f64 + 0.9).ceil() as uint)
+}
+
+/// What a node of some number of keys looks like, and what its subtree
+/// costs.
+#[deriving(Clone)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+struct Shape {
+  /// The size of every part but the last, or zero for a leaf.
+  unit:  uint,
+  /// The fixed-width bits of the node's Golomb-Rice code.
+  rice:  uint,
+  /// The coded nodes in the subtree, counting this one.
+  nodes: uint,
+  /// The fixed-width bits of the whole subtree.
+  fixed: uint,
+}
+
+/// `ln(n!)`.
+fn ln_factorial(n: uint) -> f64 {
+  range(2, n + 1).fold(0.0, |sum, i| sum + (i as f64).ln())
+}
+
+/// The best Golomb-Rice parameter for the number of failed tries before a
+/// search that succeeds with probability `e^ln_p` on each one.
+fn rice_parameter(ln_p: f64) -> uint {
+  let p = ln_p.exp();
+  if p >= 1.0 {
+    return 0;
+  }
+
+  // Gallager and van Voorhis: log2(ln(φ - 1) / ln(1 - p)), rounded up.
+  let bits = (((5.0f64.sqrt() - 1.0) / 2.0).ln() / (-p).ln_1p()).log2().ceil();
+  if bits > 0.0 { bits as uint } else { 0 }
+}
+
+/// The shapes of nodes of every size up to `max`, for leaves of up to `leaf`
+/// keys.
+fn shapes(leaf: uint, max: uint) -> Vec<Shape> {
+  let lower = leaf * lower_fanout(leaf);
+  let upper = lower * upper_fanout(leaf);
+
+  let mut shapes: Vec<Shape> = Vec::with_capacity(max + 1);
+  for m in range(0, max + 1) {
+    let shape =
+      if m <= 1 {
+        // Nothing to search for.
+        Shape { unit: 0, rice: 0, nodes: 0, fixed: 0 }
+      } else if m <= leaf {
+        // Every one of the `m^m` maps onto `m` slots is as likely, and `m!`
+        // of them are bijections.
+        let rice = rice_parameter(ln_factorial(m) - m as f64 * (m as f64).ln());
+        Shape { unit: 0, rice: rice, nodes: 1, fixed: rice }
+      } else {
+        let unit =
+          if m <= lower {
+            leaf
+          } else if m <= upper {
+            lower
+          } else {
+            (((m + 1) / 2 + upper - 1) / upper) * upper
+          };
+
+        // The multinomial probability of the exact part sizes.
+        let mut ln_p  = ln_factorial(m);
+        let mut nodes = 1;
+        let mut fixed = 0;
+        let mut start = 0;
+        while start < m {
+          let s = cmp::min(unit, m - start);
+          ln_p  += s as f64 * (s as f64 / m as f64).ln() - ln_factorial(s);
+          nodes += shapes[s].nodes;
+          fixed += shapes[s].fixed;
+          start += s;
+        }
+
+        let rice = rice_parameter(ln_p);
+        Shape { unit: unit, rice: rice, nodes: nodes, fixed: fixed + rice }
+      };
+    shapes.push(shape);
+  }
+  shapes
+}
+
+/// True if `shapes[m]` describes a node of `m` keys for every `m`: leaves
+/// search for one seed, other nodes split into smaller parts, and each
+/// subtree counts its parts' nodes and bits.
+fn shapes_are_consistent(shapes: &[Shape]) -> bool {
+  range(0, shapes.len()).all(|m| {
+    let shape = &shapes[m];
+    if m <= 1 {
+      shape.unit == 0 && shape.rice == 0 && shape.nodes == 0 && shape.fixed == 0
+    } else if shape.unit == 0 {
+      shape.rice < 64 && shape.nodes == 1 && shape.fixed == shape.rice
+    } else if shape.unit < m && shape.rice < 64 {
+      let mut nodes = 1;
+      let mut fixed = shape.rice;
+      let mut start = 0;
+      while start < m {
+        let s = cmp::min(shape.unit, m - start);
+        nodes += shapes[s].nodes;
+        fixed += shapes[s].fixed;
+        start += s;
+      }
+      shape.nodes == nodes && shape.fixed == fixed
+    } else {
+      false
+    }
+  })
+}
+
+/// The well-mixed 64 bits for fingerprint `x`, seed `seed`, at depth
+/// `depth` of the tree.
+fn remix(x: u64, seed: u64, depth: uint) -> u64 {
+  mix(x + ((depth as u64) << 56 | seed) * 0x9e3779b97f4a7c15)
+}
+
+/// `h` scaled down to `[0, m)`.
+fn reduce(h: u64, m: uint) -> uint {
+  (((h >> 32) * m as u64) >> 32) as uint
+}
+
+/// A key's fingerprint and bucket, from its vertices.
+fn split(u: uint, v: uint, buckets: uint) -> (u64, uint) {
+  ((u as u64) << 31 | v as u64, ((u as u64 * buckets as u64) >> 31) as uint)
+}
+
+/// The number of zeros at `pos` before the next one.
+fn read_unary(words: &[u64], pos: uint) -> uint {
+  let (mut w, bit) = (pos / 64, pos % 64);
+  let mut word  = words[w] >> bit;
+  let mut zeros = 0;
+  if word == 0 {
+    zeros = 64 - bit;
+    w += 1;
+    while words[w] == 0 {
+      zeros += 64;
+      w += 1;
+    }
+    word = words[w];
+  }
+  zeros + word.trailing_zeros()
+}
+
+/// True if there are `count` ones at or after `pos`, within `words`.
+fn has_ones(words: &[u64], mut pos: uint, count: uint) -> bool {
+  let mut found = 0;
+  while found < count {
+    if pos >= words.len() * 64 {
+      return false;
+    }
+    found += (words[pos / 64] >> (pos % 64) & 1) as uint;
+    pos   += 1;
+  }
+  true
+}
+
+/// The position just past the `count`th one at or after `pos`.
+fn skip_ones(words: &[u64], mut pos: uint, mut count: uint) -> uint {
+  while count > 0 {
+    let word = words[pos / 64] >> (pos % 64);
+    let ones = word.count_ones();
+    if ones >= count {
+      let mut word = word;
+      for _ in range(1, count) {
+        word &= word - 1;
+      }
+      return pos + word.trailing_zeros() + 1;
+    }
+    count -= ones;
+    pos    = (pos / 64 + 1) * 64;
+  }
+  pos
+}
+
+/// A nondecreasing sequence in about `2 + log2(max / len)` bits an element,
+/// after Elias and Fano.
+#[deriving(Clone)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+struct EliasFano {
+  /// The bits of each element stored as is.
+  low_bits: uint,
+  low:      Vec<u64>,
+  /// The rest of each element `i`, as a one at bit `(x >> low_bits) + i`.
+  high:     Vec<u64>,
+  /// The position in `high` of every 64th one.
+  samples:  Vec<uint>,
+}
+
+impl EliasFano {
+  fn new(values: &[uint]) -> EliasFano {
+    let n   = values.len();
+    let max = values.last().map_or(0, |&x| x);
+
+    let mut low_bits = 0;
+    while n > 0 && (max / n) >> (low_bits + 1) > 0 {
+      low_bits += 1;
+    }
+
+    let mut low     = BitWriter::new();
+    let mut high    = Vec::from_elem(((max >> low_bits) + n) / 64 + 1, 0u64);
+    let mut samples = Vec::with_capacity(n / 64 + 1);
+    for (i, &x) in values.iter().enumerate() {
+      low.push(x as u64, low_bits);
+      let pos = (x >> low_bits) + i;
+      *high.get_mut(pos / 64) |= 1 << (pos % 64);
+      if i % 64 == 0 {
+        samples.push(pos);
+      }
+    }
+
+    EliasFano { low_bits: low_bits, low: low.words, high: high, samples: samples }
+  }
+
+  fn get(&self, i: uint) -> uint {
+    let pos = skip_ones(self.high.as_slice(), self.samples[i / 64], i % 64 + 1) - 1;
+    (pos - i) << self.low_bits
+      | read_bits(self.low.as_slice(), i * self.low_bits, self.low_bits) as uint
+  }
+
+  fn heap_size(&self) -> uint {
+    (self.low.len() + self.high.len()) * 8 + self.samples.len() * 8
+  }
+
+  /// True if this holds `len` values in order, and `get` stays in bounds
+  /// for each of them.
+  fn is_consistent(&self, len: uint) -> bool {
+    if self.low_bits >= 64 {
+      return false;
+    }
+
+    // Every value has a one in `high`, and every 64th one is sampled.
+    let mut ones = 0;
+    let mut pos  = 0;
+    while ones < len && pos < self.high.len() * 64 {
+      if self.high[pos / 64] >> (pos % 64) & 1 != 0 {
+        if ones % 64 == 0 && self.samples.as_slice().get(ones / 64) != Some(&pos) {
+          return false;
+        }
+        ones += 1;
+      }
+      pos += 1;
+    }
+
+    ones == len
+      && self.samples.len() == (len + 63) / 64
+      && self.low.len() * 64 >= len * self.low_bits
+      && range(1, len).all(|i| self.get(i - 1) <= self.get(i))
+  }
+}
+
+/// The seeds for one set of keys.
+#[deriving(Clone)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+pub struct Labeling {
+  buckets: uint,
+  /// The number of keys before each bucket, and after the last.
+  offsets: EliasFano,
+  /// Where each bucket's codes start in `codes`.
+  starts:  EliasFano,
+  codes:   Vec<u64>,
+  /// Indexed by the number of keys in a node.
+  shapes:  Vec<Shape>,
+}
+
+/// Splits the fingerprints `xs`, of a node at depth `depth`, and its parts,
+/// appending the seeds and their Rice parameters in depth-first order.
+fn build_node(xs: &mut [u64], depth: uint, shapes: &[Shape], scratch: &mut Vec<u64>,
+              seeds: &mut Vec<(u64, uint)>) {
+  let m     = xs.len();
+  let shape = &shapes[m];
+  if m <= 1 {
+    return;
+  }
+
+  if shape.unit == 0 {
+    let full = (1u32 << m) - 1;
+    let mut seed = 0;
+    loop {
+      let mut seen = 0u32;
+      for &x in xs.iter() {
+        seen |= 1 << reduce(remix(x, seed, depth), m);
+      }
+      if seen == full {
+        break;
+      }
+      seed += 1;
+    }
+    seeds.push((seed, shape.rice));
+    return;
+  }
+
+  let unit  = shape.unit;
+  let parts = (m + unit - 1) / unit;
+  let mut counts = Vec::from_elem(parts, 0u);
+  let mut seed   = 0;
+  loop {
+    for c in counts.iter_mut() {
+      *c = 0;
+    }
+    for &x in xs.iter() {
+      *counts.get_mut(reduce(remix(x, seed, depth), m) / unit) += 1;
+    }
+    if counts.iter().enumerate().all(|(j, &c)| c == cmp::min(unit, m - j * unit)) {
+      break;
+    }
+    seed += 1;
+  }
+  seeds.push((seed, shape.rice));
+
+  // Sort the keys into their parts, and split each of those.
+  scratch.clear();
+  for j in range(0, parts) {
+    let part = xs.iter().map(|&x| x).filter(|&x| reduce(remix(x, seed, depth), m) / unit == j);
+    scratch.extend(part);
+  }
+  for (x, &y) in xs.iter_mut().zip(scratch.iter()) {
+    *x = y;
+  }
+  for part in xs.chunks_mut(unit) {
+    build_node(part, depth + 1, shapes, scratch, seeds);
+  }
+}
+
+/// Reseeds `hasher` and splits the keys into buckets of about `bucket` and
+/// leaves of at most `leaf`, or returns `None` if two keys' fingerprints
+/// collide.
+pub fn attempt<R: rand::Rng, K, H: KeyHasher<K>>(
+    rng: &mut R, hasher: &mut H, keys: &[K], leaf: uint, bucket: uint)
+    -> Result<Option<Labeling>, BuildError> {
+  hasher.reseed(rng, keys, HASH_RANGE);
+
+  let mut pairs = Vec::with_capacity(keys.len());
+  if !hasher.vertices_batch(keys, HASH_RANGE, &mut pairs) {
+    return Err(Overflow);
+  }
+
+  let buckets = cmp::max(1, (keys.len() + bucket - 1) / bucket);
+  let mut hashed: Vec<(uint, u64)> =
+    pairs.iter().map(|&(u, v)| { let (x, b) = split(u, v, buckets); (b, x) }).collect();
+  hashed.sort();
+
+  // Equal fingerprints can never be split apart.
+  if hashed.windows(2).any(|w| w[0] == w[1]) {
+    return Ok(None);
+  }
+
+  let mut offsets = Vec::from_elem(buckets + 1, 0u);
+  for &(b, _) in hashed.iter() {
+    *offsets.get_mut(b + 1) += 1;
+  }
+  for b in range(0, buckets) {
+    *offsets.get_mut(b + 1) += offsets[b];
+  }
+
+  let max    = range(0, buckets).map(|b| offsets[b + 1] - offsets[b]).max().unwrap_or(0);
+  let shapes = shapes(leaf, max);
+
+  let mut xs: Vec<u64> = hashed.iter().map(|&(_, x)| x).collect();
+  let mut codes   = BitWriter::new();
+  let mut starts  = Vec::with_capacity(buckets);
+  let mut scratch = Vec::new();
+  let mut seeds   = Vec::new();
+  for b in range(0, buckets) {
+    seeds.clear();
+    build_node(xs.slice_mut(offsets[b], offsets[b + 1]), 0, shapes.as_slice(), &mut scratch,
+               &mut seeds);
+
+    starts.push(codes.len);
+    for &(seed, rice) in seeds.iter() {
+      codes.push(seed, rice);
+    }
+    for &(seed, rice) in seeds.iter() {
+      codes.push_unary(seed >> rice);
+    }
+  }
+  // Keep one word past the end, so reads never run off it.
+  codes.words.push(0);
+
+  Ok(Some(Labeling {
+    buckets: buckets,
+    offsets: EliasFano::new(offsets.as_slice()),
+    starts:  EliasFano::new(starts.as_slice()),
+    codes:   codes.words,
+    shapes:  shapes,
+  }))
+}
+
+impl Labeling {
+  /// The index `k` would have if it were a known key, or `None` if it
+  /// certainly isn't.
+  pub fn index<K, H: KeyHasher<K>>(&self, hasher: &H, k: &K) -> Option<uint> {
+    let (x, b) =
+      match hasher.vertices(k, HASH_RANGE) {
+        Some((u, v)) => split(u, v, self.buckets),
+        None         => return None,
+      };
+
+    let mut index = self.offsets.get(b);
+    let mut m     = self.offsets.get(b + 1) - index;
+
+    // No known key lands in an empty bucket.
+    if m == 0 {
+      return None;
+    }
+
+    let codes     = self.codes.as_slice();
+    let mut fixed = self.starts.get(b);
+    let mut unary = fixed + self.shapes[m].fixed;
+    let mut depth = 0;
+    while m > 1 {
+      let shape = &self.shapes[m];
+      let low   = read_bits(codes, fixed, shape.rice);
+      let high  = read_unary(codes, unary);
+      fixed += shape.rice;
+      unary += high + 1;
+
+      let seed = (high as u64) << shape.rice | low;
+      let slot = reduce(remix(x, seed, depth), m);
+      if shape.unit == 0 {
+        return Some(index + slot);
+      }
+
+      // Skip the parts before this key's, which are all full.
+      let part = slot / shape.unit;
+      let skip = &self.shapes[shape.unit];
+      fixed += part * skip.fixed;
+      unary  = skip_ones(codes, unary, part * skip.nodes);
+
+      index += part * shape.unit;
+      m      = cmp::min(shape.unit, m - part * shape.unit);
+      depth += 1;
+    }
+    Some(index)
+  }
+
+  /// Checks that the buckets hold `m` keys between them, that every bucket
+  /// size has a shape, and that every bucket's codes are all there, so
+  /// `index` stays in bounds.
+  pub fn verify(&self, m: uint) -> Result<(), Corruption> {
+    if !self.starts.is_consistent(self.buckets) {
+      return Err(BadLength("starts", self.buckets));
+    }
+    if !self.offsets.is_consistent(self.buckets + 1)
+    || self.offsets.get(0) != 0 || self.offsets.get(self.buckets) != m {
+      return Err(BadLength("offsets", self.buckets + 1));
+    }
+    if !shapes_are_consistent(self.shapes.as_slice()) {
+      return Err(BadLength("shapes", self.shapes.len()));
+    }
+
+    let codes = self.codes.as_slice();
+    for b in range(0, self.buckets) {
+      let size = self.offsets.get(b + 1) - self.offsets.get(b);
+      if size >= self.shapes.len() {
+        return Err(OutOfBounds("offsets", b + 1, self.offsets.get(b + 1)));
+      }
+
+      // A bucket's fixed-width codes, and then one unary code per node.
+      let shape = &self.shapes[size];
+      let unary = self.starts.get(b) + shape.fixed;
+      if size > 1 && (unary > codes.len() * 64 || !has_ones(codes, unary, shape.nodes)) {
+        return Err(OutOfBounds("starts", b, self.starts.get(b)));
+      }
+    }
+    Ok(())
+  }
+
+  /// The range `hasher.vertices` is asked for.
+  pub fn hash_range(&self) -> uint {
+    HASH_RANGE
+  }
+
+  /// The number of keys, which is also the number of slots.
+  pub fn len(&self) -> uint {
+    self.offsets.get(self.buckets)
+  }
+
+  /// The bytes of the codes, the bucket directory, and the table of node
+  /// shapes.
+  pub fn heap_size(&self) -> uint {
+    self.codes.len() * 8 + self.offsets.heap_size() + self.starts.heap_size()
+      + self.shapes.len() * 4 * 8
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{shapes, lower_fanout, upper_fanout};
+  use super::super::builder::RECSPLIT_LEAF_SIZE;
+
+  #[test]
+  fn splits_nodes_into_parts_of_the_next_size_down() {
+    let leaf   = RECSPLIT_LEAF_SIZE;
+    let lower  = leaf * lower_fanout(leaf);
+    let upper  = lower * upper_fanout(leaf);
+    let shapes = shapes(leaf, 4 * upper);
+
+    for m in range(2, shapes.len()) {
+      let unit = shapes[m].unit;
+      if m <= leaf {
+        assert_eq!(unit, 0);
+        continue;
+      }
+
+      let parts = (m + unit - 1) / unit;
+      if m <= lower {
+        assert!(unit == leaf && parts <= lower_fanout(leaf), "{} keys split by {}", m, unit);
+      } else if m <= upper {
+        assert!(unit == lower && parts <= upper_fanout(leaf), "{} keys split by {}", m, unit);
+      } else {
+        // In two, the first part a whole number of upper nodes.
+        assert!(unit % upper == 0 && parts == 2, "{} keys split by {}", m, unit);
+      }
+    }
+  }
+}
@@ -0,0 +1,218 @@
+//! `Encodable` and `Decodable` for `HashMap`, behind the `serialize` feature.
+//!
+//! Construction is the expensive part of a table, so it's often worth doing
+//! once, offline. The encoding holds the raw tables rather than just the
+//! entries, and decoding checks that they're internally consistent before
+//! handing back a map. Tables built with every `Algorithm` can be encoded:
+//! their labels are encoded as whichever algorithm's they are. So are the
+//! seed they were built from, their order, and their fingerprints, so a
+//! decoded map rejects the unknown keys it rejected before.
+
+use std::hash;
+use std::kinds::marker;
+use serialize::{Encodable, Decodable, Encoder, Decoder};
+
+use super::{HashMap, PerfectHashFn, PerfectHashState, ByteCounter, Positional, Labels};
+use super::{BackupStore, Fingerprints, SeededBackup};
+use super::CheckBackup;
+use super::slots::Slots;
+
+impl<E,
+     S: Encoder<E>,
+     K: Encodable<S, E> + Eq + hash::Hash,
+     V: Encodable<S, E>>
+    Encodable<S, E> for HashMap<K, V> {
+  fn encode(&self, s: &mut S) -> Result<(), E> {
+    // The weights are interleaved in memory, but encoded as separate tables.
+    let weights = self.func.hasher.weights.as_slice();
+    let t1: Vec<u32> = weights.iter().map(|&(w1, _)| w1).collect();
+    let t2: Vec<u32> = weights.iter().map(|&(_, w2)| w2).collect();
+
+    s.emit_struct("HashMap", 9, |s| {
+      try!(s.emit_struct_field("labels", 0, |s| self.func.labels.encode(s)));
+      try!(s.emit_struct_field("t1",     1, |s| t1.encode(s)));
+      try!(s.emit_struct_field("t2",     2, |s| t2.encode(s)));
+      try!(s.emit_struct_field("keys",   3, |s| self.keys.encode(s)));
+      // Encoded as a `Vec<Option<V>>`.
+      try!(s.emit_struct_field("table",  4, |s| s.emit_seq(self.table.len(), |s| {
+        for (i, v) in self.table.iter().enumerate() {
+          try!(s.emit_seq_elt(i, |s| v.encode(s)));
+        }
+        Ok(())
+      })));
+      // An empty backup is encoded as `None`, as it was when the backup was
+      // allocated lazily, and a full one as a map.
+      try!(s.emit_struct_field("backup", 5, |s| s.emit_option(|s| {
+        if self.backup.len() == 0 {
+          s.emit_option_none()
+        } else {
+          s.emit_option_some(|s| s.emit_map(self.backup.len(), |s| {
+            for (i, (k, v)) in self.backup.iter().enumerate() {
+              try!(s.emit_map_elt_key(i, |s| k.encode(s)));
+              try!(s.emit_map_elt_val(i, |s| v.encode(s)));
+            }
+            Ok(())
+          }))
+        }
+      })));
+      try!(s.emit_struct_field("order",        6, |s| self.func.order.encode(s)));
+      try!(s.emit_struct_field("fingerprints", 7, |s| self.func.fingerprints.encode(s)));
+      s.emit_struct_field("seed", 8, |s| self.func.seed.encode(s))
+    })
+  }
+}
+
+impl<'a,
+     E,
+     D: Decoder<E>,
+     K: Decodable<D, E>
+      + Eq
+      + hash::Hash
+      + hash::Hash<PerfectHashState<'a>>
+      + hash::Hash<ByteCounter>,
+     V: Decodable<D, E>>
+    Decodable<D, E> for HashMap<K, V> {
+  fn decode(d: &mut D) -> Result<HashMap<K, V>, E> {
+    d.read_struct("HashMap", 9, |d| {
+      let labels: Labels
+                 = try!(d.read_struct_field("labels", 0, |d| Decodable::decode(d)));
+      let t1: Vec<u32>
+                 = try!(d.read_struct_field("t1",     1, |d| Decodable::decode(d)));
+      let t2: Vec<u32>
+                 = try!(d.read_struct_field("t2",     2, |d| Decodable::decode(d)));
+
+      if t1.len() != t2.len() {
+        return Err(d.error("perfect::HashMap: t1 and t2 differ in length"));
+      }
+      let keys: Vec<K>
+                 = try!(d.read_struct_field("keys",   3, |d| Decodable::decode(d)));
+      let table: Vec<Option<V>>
+                 = try!(d.read_struct_field("table",  4, |d| Decodable::decode(d)));
+      let backup: Option<Vec<(K, V)>>
+                 = try!(d.read_struct_field("backup", 5, |d| d.read_option(|d, some| {
+                     if some { decode_entries(d).map(Some) } else { Ok(None) }
+                   })));
+      let order: Option<Vec<uint>>
+                 = try!(d.read_struct_field("order",        6, |d| Decodable::decode(d)));
+      let fingerprints: Option<Fingerprints>
+                 = try!(d.read_struct_field("fingerprints", 7, |d| Decodable::decode(d)));
+      let seed: Option<u64>
+                 = try!(d.read_struct_field("seed",         8, |d| Decodable::decode(d)));
+
+      let table  = Slots::from_options(table);
+      let size   = table.count();
+      let mut store = SeededBackup::new(seed.unwrap_or(0));
+      for (k, v) in backup.unwrap_or(Vec::new()).into_iter() {
+        // A `SeededBackup` takes every pair.
+        let _ = store.insert(k, v);
+      }
+
+      let func = PerfectHashFn {
+        labels:       labels,
+        hasher:       Positional { weights: t1.into_iter().zip(t2.into_iter()).collect() },
+        m:            keys.len(),
+        order:        order,
+        fingerprints: fingerprints,
+        seed:         seed,
+        stats:        None,
+        marker:       marker::CovariantType,
+      };
+
+      let settings = func.builder();
+      let map = HashMap {
+        func:       func,
+        keys:       keys,
+        table:      table,
+        size:       size,
+        backup:     store,
+        rebuild_at: None,
+        unknown:    CheckBackup,
+        sorted:     None,
+        resort:     None,
+        counters:   None,
+        sampler:    None,
+        settings:   settings,
+      };
+
+      if map.verify().is_ok() {
+        Ok(map)
+      } else {
+        Err(d.error("perfect::HashMap: decoded tables are inconsistent"))
+      }
+    })
+  }
+}
+
+/// The pairs of an encoded map, in the order they were encoded.
+fn decode_entries<E, D: Decoder<E>, K: Decodable<D, E>, V: Decodable<D, E>>(d: &mut D)
+    -> Result<Vec<(K, V)>, E> {
+  d.read_map(|d, len| {
+    let mut entries = Vec::with_capacity(len);
+    for i in range(0, len) {
+      let k = try!(d.read_map_elt_key(i, |d| Decodable::decode(d)));
+      let v = try!(d.read_map_elt_val(i, |d| Decodable::decode(d)));
+      entries.push((k, v));
+    }
+    Ok(entries)
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use serialize::json;
+
+  use super::super::{HashMap, HashMapBuilder, Chm, Bdz, Chd, RecSplit, PtHash, Fks};
+  use super::super::testing::keys;
+
+  /// Half the known keys with values, and one unknown key in the backup.
+  fn map(builder: HashMapBuilder) -> HashMap<String, uint> {
+    let keys = keys(100);
+    let mut map = builder.seed(1).build(keys.clone()).unwrap();
+    for (i, k) in keys.into_iter().enumerate().filter(|&(i, _)| i % 2 == 0) {
+      map.insert(k, i);
+    }
+    map.insert("unknown".to_string(), 1000);
+    map
+  }
+
+  fn decode(text: &str) -> Option<HashMap<String, uint>> {
+    json::decode(text).ok()
+  }
+
+  #[test]
+  fn round_trip_every_algorithm() {
+    let algorithms = [Chm, Bdz, Chd(5.0), RecSplit(8, 100), PtHash(5.0), Fks];
+    for &algorithm in algorithms.iter() {
+      let builders = [
+        HashMapBuilder::new(),
+        HashMapBuilder::new().fingerprints(true).order_preserving(true),
+      ];
+      for builder in builders.iter() {
+        let map     = map(builder.clone().algorithm(algorithm));
+        let decoded = decode(json::encode(&map).as_slice()).unwrap();
+        assert_eq!(decoded, map);
+        assert_eq!(decoded.seed(), map.seed());
+        assert!(decoded.verify().is_ok());
+      }
+    }
+  }
+
+  #[test]
+  fn rejects_misplaced_keys() {
+    let text = json::encode(&map(HashMapBuilder::new()));
+    let swapped =
+      text.replace("\"key 10\"", "\"swap\"")
+          .replace("\"key 11\"", "\"key 10\"")
+          .replace("\"swap\"", "\"key 11\"");
+    assert!(swapped != text);
+    assert!(decode(swapped.as_slice()).is_none());
+  }
+
+  #[test]
+  fn rejects_truncated_text() {
+    let text = json::encode(&map(HashMapBuilder::new()));
+    for len in range(0, text.len()) {
+      assert!(decode(text.as_slice().slice_to(len)).is_none(), "{} bytes were decoded", len);
+    }
+  }
+}
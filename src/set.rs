@@ -0,0 +1,129 @@
+//! A set built on the same perfect hashing scheme as `HashMap`.
+//!
+//! This is just a `HashMap<K, ()>` with a set-shaped interface, for users who
+//! only need to know whether a key is present (keyword recognizers, reserved
+//! word checks, and so on).
+
+use std::hash;
+use std::iter;
+
+use super::{HashMap, Keys, MoveEntries, PerfectHashState, ByteCounter};
+
+/// A hashset specialized to a set of keys known at construction time.
+///
+/// Known keys are tested without collisions. Other keys are kept in a lazily
+/// allocated backup set, exactly as with `HashMap`.
+pub struct HashSet<K> {
+  map: HashMap<K, ()>,
+}
+
+/// HashSet iterator.
+pub type SetItems<'a, K> = Keys<'a, K, ()>;
+
+/// HashSet move iterator.
+pub type SetMoveItems<K> =
+  iter::Map<'static, (K, ()), K, MoveEntries<K, ()>>;
+
+/// Set operations iterator.
+pub type SetAlgebraItems<'a, K> =
+  iter::FilterMap<'static, (&'a HashSet<K>, &'a K), &'a K,
+                  iter::Zip<iter::Repeat<&'a HashSet<K>>, SetItems<'a, K>>>;
+
+impl<'a,
+     K: Eq
+      + hash::Hash
+      + hash::Hash<PerfectHashState<'a>>
+      + hash::Hash<ByteCounter>>
+    HashSet<K> {
+
+  /// Builds an empty set optimized for holding `known_vals`.
+  pub fn new(known_vals: Vec<K>) -> HashSet<K> {
+    HashSet { map: HashMap::new(known_vals) }
+  }
+
+  /// Returns true if the set contains a value.
+  pub fn contains(&self, value: &K) -> bool {
+    self.map.contains_key(value)
+  }
+
+  /// Adds a value to the set. Returns true if the value was not already
+  /// present in the set.
+  pub fn insert(&mut self, value: K) -> bool {
+    self.map.insert(value, ()).is_none()
+  }
+
+  /// Removes a value from the set. Returns true if the value was present in
+  /// the set.
+  pub fn remove(&mut self, value: &K) -> bool {
+    self.map.remove(value).is_some()
+  }
+
+  /// Visits the values representing the difference, i.e. the values that are
+  /// in `self` but not in `other`.
+  pub fn difference<'b>(&'b self, other: &'b HashSet<K>) -> SetAlgebraItems<'b, K> {
+    iter::Repeat::new(other).zip(self.iter())
+      .filter_map(|(other, elt)| {
+        if !other.contains(elt) { Some(elt) } else { None }
+      })
+  }
+
+  /// Visits the values representing the symmetric difference, i.e. the
+  /// values that are in `self` or in `other` but not in both.
+  pub fn symmetric_difference<'b>(&'b self, other: &'b HashSet<K>)
+      -> iter::Chain<SetAlgebraItems<'b, K>, SetAlgebraItems<'b, K>> {
+    self.difference(other).chain(other.difference(self))
+  }
+
+  /// Visits the values representing the intersection, i.e. the values that
+  /// are both in `self` and `other`.
+  pub fn intersection<'b>(&'b self, other: &'b HashSet<K>) -> SetAlgebraItems<'b, K> {
+    iter::Repeat::new(other).zip(self.iter())
+      .filter_map(|(other, elt)| {
+        if other.contains(elt) { Some(elt) } else { None }
+      })
+  }
+
+  /// Visits the values representing the union, i.e. all the values in `self`
+  /// or `other`, without duplicates.
+  pub fn union<'b>(&'b self, other: &'b HashSet<K>)
+      -> iter::Chain<SetItems<'b, K>, SetAlgebraItems<'b, K>> {
+    self.iter().chain(other.difference(self))
+  }
+
+  /// Returns true if the set has no elements in common with `other`.
+  pub fn is_disjoint(&self, other: &HashSet<K>) -> bool {
+    self.iter().all(|v| !other.contains(v))
+  }
+
+  /// Returns true if every element of `self` is also in `other`.
+  pub fn is_subset(&self, other: &HashSet<K>) -> bool {
+    self.iter().all(|v| other.contains(v))
+  }
+
+  /// Returns true if every element of `other` is also in `self`.
+  pub fn is_superset(&self, other: &HashSet<K>) -> bool {
+    other.is_subset(self)
+  }
+}
+
+impl<K> HashSet<K> {
+  /// An iterator visiting all elements in arbitrary order.
+  pub fn iter<'a>(&'a self) -> SetItems<'a, K> {
+    self.map.keys()
+  }
+
+  /// Creates a consuming iterator over all elements in arbitrary order.
+  pub fn into_iter(self) -> SetMoveItems<K> {
+    self.map.into_iter().map(|(k, ())| k)
+  }
+
+  /// Returns the number of elements in the set.
+  pub fn len(&self) -> uint {
+    self.map.len()
+  }
+
+  /// Returns true if the set contains no elements.
+  pub fn is_empty(&self) -> bool {
+    self.map.is_empty()
+  }
+}
@@ -0,0 +1,256 @@
+//! Two-level functions, for keysets too large to build in one piece.
+//!
+//! Keys are split into buckets by a seeded SipHash, buckets are grouped into
+//! shards, and each shard gets its own `PerfectHashFn`, built from only its
+//! own keys. A key's index is its shard's offset plus its index within the
+//! shard, so the whole thing is still minimal: `m` keys hash onto `[0, m)`.
+//! Lookups cost one extra hash, but a failed attempt only retries one
+//! shard, and shards can be built at the same time.
+//!
+//! When the keys don't fit in memory, each is spilled to one of `BUCKETS`
+//! files as it's streamed in, and consecutive buckets are grouped into
+//! shards small enough to build within the memory budget. Only one shard's
+//! keys are ever in memory at once. The finished functions of every shard
+//! are, but with `Bdz` or `Chd` those take a few bits per key.
+
+use std::cmp;
+use std::hash;
+use std::hash::sip;
+use std::io;
+use std::io::{BufferedReader, BufferedWriter, File, IoError, IoResult, TempDir};
+use std::io::fs;
+use std::mem;
+use std::rand::Rng;
+use std::task;
+
+use super::{HashMapBuilder, PerfectHashFn, KeyHasher, Positional, Persist, BuildError};
+
+/// The number of files keys are spilled into. Every shard is a run of
+/// consecutive buckets, so no shard can be smaller than one bucket.
+pub static BUCKETS: uint = 256;
+
+/// About how many bytes construction needs per key, on top of the key
+/// itself, for the graph and the labels under construction.
+pub static BYTES_PER_KEY: uint = 64;
+
+/// A perfect hash function split into shards, from
+/// `HashMapBuilder::build_fn_sharded` or `HashMapBuilder::build_sharded`.
+pub struct ShardedHashFn<K, H = Positional> {
+  k0:       u64,
+  k1:       u64,
+  /// The shard each bucket went to. There's one bucket per entry.
+  shard_of: Vec<uint>,
+  /// The index of each shard's first key, and then the number of keys.
+  offsets:  Vec<uint>,
+  shards:   Vec<PerfectHashFn<K, H>>,
+}
+
+fn bucket<K: hash::Hash>(k0: u64, k1: u64, buckets: uint, k: &K) -> uint {
+  (sip::hash_with_keys(k0, k1, k) % buckets as u64) as uint
+}
+
+fn other(desc: &'static str, detail: Option<String>) -> IoError {
+  IoError {
+    kind:   io::OtherIoError,
+    desc:   desc,
+    detail: detail,
+  }
+}
+
+/// Splits `keys` into `shards`, and builds a function for each with
+/// `builder` and `hasher`, on `workers` tasks.
+pub fn construct<R: Rng,
+                 K: Clone + Send + Eq + hash::Hash,
+                 H: KeyHasher<K> + Send>(
+    rng: &mut R,
+    builder: &HashMapBuilder,
+    hasher: H,
+    keys: &[K],
+    shards: uint,
+    workers: uint) -> Result<ShardedHashFn<K, H>, BuildError> {
+  let (k0, k1) = (rng.gen(), rng.gen());
+  let shards   = cmp::max(shards, 1);
+  let workers  = cmp::min(cmp::max(workers, 1), shards);
+
+  let mut split = Vec::from_fn(shards, |_| Vec::new());
+  for k in keys.iter() {
+    split.get_mut(bucket(k0, k1, shards, k)).push(k.clone());
+  }
+
+  // Worker `w` builds every shard `s` with `s % workers == w`.
+  let mut jobs = Vec::from_fn(workers, |_| Vec::new());
+  for (s, shard_keys) in split.into_iter().enumerate() {
+    jobs.get_mut(s % workers).push((s, shard_keys));
+  }
+
+  let (tx, rx) = channel();
+  for job in jobs.into_iter() {
+    let builder = builder.clone();
+    let hasher  = hasher.clone();
+    let tx      = tx.clone();
+
+    task::spawn(proc() {
+      for (s, shard_keys) in job.into_iter() {
+        let func = builder.build_fn_with_hasher(hasher.clone(), shard_keys.as_slice());
+        let failed = func.is_err();
+        // The receiver is gone if another shard already failed.
+        if tx.send_opt((s, func)).is_err() || failed {
+          return;
+        }
+      }
+    });
+  }
+
+  drop(tx);
+
+  let mut funcs: Vec<Option<PerfectHashFn<K, H>>> = Vec::from_fn(shards, |_| None);
+  for (s, func) in rx.iter() {
+    *funcs.get_mut(s) = Some(try!(func));
+  }
+
+  let funcs =
+    funcs.into_iter().map(|func| {
+        match func {
+          Some(func) => func,
+          None       => fail!("perfect: a construction worker failed"),
+        }
+      }).collect();
+
+  Ok(ShardedHashFn::from_shards(k0, k1, range(0, shards).collect(), funcs))
+}
+
+/// Spills `keys` into buckets under `dir`, then builds a function for each
+/// shard with `builder` and `hasher`. No shard needs more than `budget`
+/// bytes.
+pub fn construct_spilled<R: Rng,
+                 K: Persist + Eq + hash::Hash,
+                 H: KeyHasher<K>,
+                 I: Iterator<K>>(
+    rng: &mut R,
+    builder: &HashMapBuilder,
+    hasher: H,
+    mut keys: I,
+    budget: uint,
+    dir: &Path) -> IoResult<ShardedHashFn<K, H>> {
+  let (k0, k1) = (rng.gen(), rng.gen());
+  let spill    = try!(TempDir::new_in(dir, "perfect"));
+  let path     = |b: uint| spill.path().join(format!("bucket-{}", b));
+
+  // Spill every key. Nothing but the writers' buffers is held meanwhile.
+  let mut counts = Vec::from_elem(BUCKETS, 0u);
+  {
+    let mut writers = Vec::with_capacity(BUCKETS);
+    for b in range(0, BUCKETS) {
+      writers.push(BufferedWriter::new(try!(File::create(&path(b)))));
+    }
+
+    for k in keys {
+      let b = bucket(k0, k1, BUCKETS, &k);
+      try!(k.persist(writers.get_mut(b)));
+      *counts.get_mut(b) += 1;
+    }
+
+    for w in writers.iter_mut() {
+      try!(w.flush());
+    }
+  }
+
+  // Group runs of buckets into shards, by what they'd cost in memory.
+  let mut shard_of = Vec::with_capacity(BUCKETS);
+  let mut groups: Vec<Vec<uint>> = vec![Vec::new()];
+  let mut used = 0;
+  for b in range(0, BUCKETS) {
+    let bytes = try!(fs::stat(&path(b))).size as uint;
+    let cost  = bytes + counts[b] * (mem::size_of::<K>() + BYTES_PER_KEY);
+
+    if cost > budget {
+      return Err(other("a bucket doesn't fit in the memory budget",
+                       Some(format!("bucket {} needs about {} bytes", b, cost))));
+    }
+
+    if used + cost > budget && !groups.last().unwrap().is_empty() {
+      groups.push(Vec::new());
+      used = 0;
+    }
+
+    used += cost;
+    groups.last_mut().unwrap().push(b);
+    shard_of.push(groups.len() - 1);
+  }
+
+  let mut shards = Vec::with_capacity(groups.len());
+  for group in groups.iter() {
+    let mut shard_keys = Vec::with_capacity(group.iter().fold(0, |n, &b| n + counts[b]));
+    for &b in group.iter() {
+      let mut r = BufferedReader::new(try!(File::open(&path(b))));
+      for _ in range(0, counts[b]) {
+        shard_keys.push(try!(Persist::restore(&mut r)));
+      }
+    }
+
+    let func =
+      match builder.build_fn_with_hasher(hasher.clone(), shard_keys.as_slice()) {
+        Ok(func) => func,
+        Err(e)   => return Err(other("couldn't build a perfect table", Some(e.to_string()))),
+      };
+
+    shards.push(func);
+  }
+
+  Ok(ShardedHashFn::from_shards(k0, k1, shard_of, shards))
+}
+
+impl<K: Eq + hash::Hash, H: KeyHasher<K>> ShardedHashFn<K, H> {
+  fn from_shards(k0: u64, k1: u64, shard_of: Vec<uint>, shards: Vec<PerfectHashFn<K, H>>)
+      -> ShardedHashFn<K, H> {
+    let mut offsets = Vec::with_capacity(shards.len() + 1);
+    offsets.push(0u);
+    for func in shards.iter() {
+      let end = *offsets.last().unwrap() + func.len();
+      offsets.push(end);
+    }
+
+    ShardedHashFn {
+      k0:       k0,
+      k1:       k1,
+      shard_of: shard_of,
+      offsets:  offsets,
+      shards:   shards,
+    }
+  }
+
+  fn shard(&self, k: &K) -> uint {
+    self.shard_of[bucket(self.k0, self.k1, self.shard_of.len(), k)]
+  }
+
+  /// The index of `k`, which must be one of the keys. Other keys hash to an
+  /// arbitrary index.
+  pub fn hash(&self, k: &K) -> uint {
+    let s = self.shard(k);
+    self.offsets[s] + self.shards[s].hash(k)
+  }
+
+  /// The index of `k`, or `None` if its shard's function can tell it isn't
+  /// one of the keys.
+  pub fn try_hash(&self, k: &K) -> Option<uint> {
+    let s = self.shard(k);
+    self.shards[s].try_hash(k).map(|i| self.offsets[s] + i)
+  }
+}
+
+impl<K, H> ShardedHashFn<K, H> {
+  /// The number of keys, which is also the number of distinct indices.
+  pub fn len(&self) -> uint {
+    *self.offsets.last().unwrap()
+  }
+
+  /// The number of shards.
+  pub fn shard_count(&self) -> uint {
+    self.shards.len()
+  }
+
+  /// The function for each shard.
+  pub fn shards(&self) -> &[PerfectHashFn<K, H>] {
+    self.shards.as_slice()
+  }
+}
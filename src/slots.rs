@@ -0,0 +1,401 @@
+//! Storage for the values of known keys.
+//!
+//! A `Vec<Option<V>>` spends a discriminant on every slot, padded out to
+//! `V`'s alignment, which for word-sized values doubles the table. Here the
+//! values sit in one uninitialized array and a bitmap says which slots hold
+//! one, so an empty slot costs a single bit.
+//!
+//! Large values can live out of line instead, each in a box of its own, so
+//! the slot array is a word per slot however big `V` is. Lookups then take
+//! one more load, but the slots stay dense, and so do the cache lines a scan
+//! over them touches.
+
+use std::kinds::marker;
+use std::mem;
+use std::ptr;
+
+use super::prefetch;
+
+/// A fixed number of slots, each holding a `V` or nothing.
+pub struct Slots<V> {
+  /// Always has length zero. Its buffer holds `len` slots, of which only the
+  /// ones marked in `present` are initialized. Boxed slots leave it empty.
+  values:  Vec<V>,
+  /// The box of each full slot, when the values are out of line. The rest
+  /// are null.
+  boxes:   Vec<*mut V>,
+  boxed:   bool,
+  len:     uint,
+  present: Vec<u64>,
+}
+
+impl<V> Slots<V> {
+  /// `len` empty slots.
+  pub fn new(len: uint) -> Slots<V> {
+    Slots::with_layout(len, false)
+  }
+
+  /// `len` empty slots, whose values are boxed if `boxed` is true.
+  pub fn with_layout(len: uint, boxed: bool) -> Slots<V> {
+    Slots {
+      values:  Vec::with_capacity(if boxed { 0 } else { len }),
+      boxes:   if boxed { Vec::from_elem(len, ptr::null_mut()) } else { Vec::new() },
+      boxed:   boxed,
+      len:     len,
+      present: Vec::from_elem((len + 63) / 64, 0u64),
+    }
+  }
+
+  /// A slot for every element of `values`, filled where it's `Some`.
+  pub fn from_options(values: Vec<Option<V>>) -> Slots<V> {
+    Slots::from_options_in(values, false)
+  }
+
+  /// Like `from_options`, but boxing the values if `boxed` is true.
+  pub fn from_options_in(values: Vec<Option<V>>, boxed: bool) -> Slots<V> {
+    let mut slots = Slots::with_layout(values.len(), boxed);
+    for (i, v) in values.into_iter().enumerate() {
+      slots.replace(i, v);
+    }
+    slots
+  }
+
+  /// True if the values are out of line.
+  pub fn is_boxed(&self) -> bool {
+    self.boxed
+  }
+
+  /// Moves the values into boxes, or back into the slots.
+  pub fn set_boxed(&mut self, boxed: bool) {
+    if boxed != self.boxed {
+      let values = mem::replace(self, Slots::new(0)).into_options();
+      *self = Slots::from_options_in(values, boxed);
+    }
+  }
+
+  /// The number of slots, full or not.
+  pub fn len(&self) -> uint {
+    self.len
+  }
+
+  /// The bytes of the values, full or not, and the bitmap. Boxed values
+  /// count a pointer per slot, and a box per full one.
+  pub fn heap_size(&self) -> uint {
+    let values =
+      if self.boxed {
+        self.len * mem::size_of::<*mut V>() + self.count() * mem::size_of::<V>()
+      } else {
+        self.len * mem::size_of::<V>()
+      };
+    values + self.present.len() * mem::size_of::<u64>()
+  }
+
+  /// The number of full slots.
+  pub fn count(&self) -> uint {
+    self.present.iter().fold(0, |n, &word| n + word.count_ones())
+  }
+
+  /// True if slot `i` holds a value.
+  pub fn is_full(&self, i: uint) -> bool {
+    assert!(i < self.len);
+    self.present[i / 64] & (1 << (i % 64)) != 0
+  }
+
+  fn slot(&self, i: uint) -> *mut V {
+    if self.boxed {
+      self.boxes[i]
+    } else {
+      unsafe { self.values.as_ptr().offset(i as int) as *mut V }
+    }
+  }
+
+  /// Prefetches slot `i`, and the word of the bitmap that says whether it's
+  /// full. A boxed slot only prefetches its pointer: the box is only known
+  /// once that's been read.
+  pub fn prefetch(&self, i: uint) {
+    if i < self.len {
+      let slot =
+        if self.boxed {
+          &self.boxes[i] as *const *mut V as *const u8
+        } else {
+          self.slot(i) as *const u8
+        };
+      prefetch(slot);
+      prefetch(&self.present[i / 64] as *const u64);
+    }
+  }
+
+  /// The value in slot `i`.
+  pub fn get<'a>(&'a self, i: uint) -> Option<&'a V> {
+    if self.is_full(i) {
+      Some(unsafe { &*self.slot(i) })
+    } else {
+      None
+    }
+  }
+
+  /// The value in slot `i`, by mutable reference.
+  pub fn get_mut<'a>(&'a mut self, i: uint) -> Option<&'a mut V> {
+    if self.is_full(i) {
+      Some(unsafe { &mut *self.slot(i) })
+    } else {
+      None
+    }
+  }
+
+  /// The value in slot `i`, which must be in range and full.
+  pub unsafe fn get_unchecked<'a>(&'a self, i: uint) -> &'a V {
+    &*self.slot(i)
+  }
+
+  /// The value in slot `i`, which must be in range and full, by mutable
+  /// reference.
+  pub unsafe fn get_unchecked_mut<'a>(&'a mut self, i: uint) -> &'a mut V {
+    &mut *self.slot(i)
+  }
+
+  /// Puts `v` in slot `i`, returning what was there before.
+  pub fn replace(&mut self, i: uint, v: Option<V>) -> Option<V> {
+    let old =
+      if !self.is_full(i) {
+        None
+      } else if self.boxed {
+        let b: Box<V> = unsafe { mem::transmute(self.boxes[i]) };
+        *self.boxes.get_mut(i) = ptr::null_mut();
+        Some(*b)
+      } else {
+        Some(unsafe { ptr::read(self.slot(i) as *const V) })
+      };
+
+    match v {
+      Some(v) => {
+        if self.boxed {
+          *self.boxes.get_mut(i) = unsafe { mem::transmute(box v) };
+        } else {
+          unsafe { ptr::write(self.slot(i), v); }
+        }
+        *self.present.get_mut(i / 64) |= 1 << (i % 64);
+      }
+      None => *self.present.get_mut(i / 64) &= !(1 << (i % 64)),
+    }
+
+    old
+  }
+
+  /// Empties slot `i`, returning its value.
+  pub fn take(&mut self, i: uint) -> Option<V> {
+    self.replace(i, None)
+  }
+
+  /// Empties every slot, keeping the buffer for new values.
+  pub fn clear(&mut self) {
+    for i in range(0, self.len) {
+      self.take(i);
+    }
+  }
+
+  /// Every slot, in order, as an `Option`.
+  pub fn into_options(mut self) -> Vec<Option<V>> {
+    Vec::from_fn(self.len, |i| self.take(i))
+  }
+
+  /// Like `into_options`, but leaves no slots behind, laid out as before.
+  pub fn drain_options(&mut self) -> Vec<Option<V>> {
+    let boxed = self.boxed;
+    mem::replace(self, Slots::with_layout(0, boxed)).into_options()
+  }
+
+  /// Visits the slots in order.
+  pub fn iter<'a>(&'a self) -> Items<'a, V> {
+    Items { slots: self, next: 0 }
+  }
+
+  /// Visits the slots in order, with mutable references to the values.
+  pub fn iter_mut<'a>(&'a mut self) -> MutItems<'a, V> {
+    MutItems {
+      slots:  self as *mut Slots<V>,
+      next:   0,
+      marker: marker::ContravariantLifetime,
+    }
+  }
+
+  /// Empties the slots in order.
+  pub fn into_iter(self) -> MoveItems<V> {
+    MoveItems { slots: self, next: 0 }
+  }
+}
+
+impl<V: Clone> Clone for Slots<V> {
+  fn clone(&self) -> Slots<V> {
+    Slots::from_options_in(self.iter().map(|v| v.map(|v| v.clone())).collect(), self.boxed)
+  }
+}
+
+#[unsafe_destructor]
+impl<V> Drop for Slots<V> {
+  fn drop(&mut self) {
+    for i in range(0, self.len) {
+      self.take(i);
+    }
+  }
+}
+
+/// Slots iterator.
+pub struct Items<'a, V: 'a> {
+  slots: &'a Slots<V>,
+  next:  uint,
+}
+
+/// Slots mutable iterator.
+pub struct MutItems<'a, V: 'a> {
+  slots:  *mut Slots<V>,
+  next:   uint,
+  marker: marker::ContravariantLifetime<'a>,
+}
+
+/// Slots move iterator.
+pub struct MoveItems<V> {
+  slots: Slots<V>,
+  next:  uint,
+}
+
+impl<'a, V> Iterator<Option<&'a V>> for Items<'a, V> {
+  fn next(&mut self) -> Option<Option<&'a V>> {
+    if self.next == self.slots.len {
+      return None;
+    }
+
+    self.next += 1;
+    Some(self.slots.get(self.next - 1))
+  }
+}
+
+impl<'a, V> Iterator<Option<&'a mut V>> for MutItems<'a, V> {
+  fn next(&mut self) -> Option<Option<&'a mut V>> {
+    // Each slot is handed out once, so the references never alias.
+    let slots = unsafe { &mut *self.slots };
+
+    if self.next == slots.len {
+      return None;
+    }
+
+    self.next += 1;
+    Some(slots.get_mut(self.next - 1))
+  }
+}
+
+impl<V> Iterator<Option<V>> for MoveItems<V> {
+  fn next(&mut self) -> Option<Option<V>> {
+    if self.next == self.slots.len {
+      return None;
+    }
+
+    self.next += 1;
+    Some(self.slots.take(self.next - 1))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::cell::Cell;
+  use std::rc::Rc;
+
+  use super::Slots;
+
+  /// A value that counts its drops.
+  struct Tracked {
+    id:    uint,
+    drops: Rc<Cell<uint>>,
+  }
+
+  impl Drop for Tracked {
+    fn drop(&mut self) {
+      self.drops.set(self.drops.get() + 1);
+    }
+  }
+
+  fn tracked(id: uint, drops: &Rc<Cell<uint>>) -> Option<Tracked> {
+    Some(Tracked { id: id, drops: drops.clone() })
+  }
+
+  fn insert_take_and_drop(boxed: bool) {
+    let drops = Rc::new(Cell::new(0u));
+    {
+      let mut slots = Slots::with_layout(200, boxed);
+      for i in range(0u, 200).filter(|i| i % 3 != 0) {
+        assert!(slots.replace(i, tracked(i, &drops)).is_none());
+      }
+      assert_eq!(slots.count(), 133);
+      for i in range(0u, 200) {
+        assert_eq!(slots.is_full(i), i % 3 != 0);
+        assert_eq!(slots.get(i).map(|v| v.id), if i % 3 != 0 { Some(i) } else { None });
+      }
+
+      // Replacing and taking hand the old values back, still alive.
+      let old = slots.replace(1, tracked(1000, &drops));
+      assert_eq!(old.as_ref().map(|v| v.id), Some(1));
+      assert_eq!(slots.get(1).map(|v| v.id), Some(1000));
+      assert_eq!(drops.get(), 0);
+      drop(old);
+      assert_eq!(drops.get(), 1);
+
+      assert_eq!(slots.take(2).map(|v| v.id), Some(2));
+      assert_eq!(drops.get(), 2);
+      assert!(slots.take(2).is_none());
+      assert!(slots.take(3).is_none());
+      assert_eq!(slots.count(), 132);
+    }
+    // The values left are each dropped once with the slots.
+    assert_eq!(drops.get(), 134);
+  }
+
+  #[test]
+  fn insert_take_and_drop_inline() {
+    insert_take_and_drop(false);
+  }
+
+  #[test]
+  fn insert_take_and_drop_boxed() {
+    insert_take_and_drop(true);
+  }
+
+  fn move_iterator_drops_the_rest(boxed: bool) {
+    let drops = Rc::new(Cell::new(0u));
+    {
+      let slots = Slots::from_options_in(Vec::from_fn(10, |i| tracked(i, &drops)), boxed);
+      let mut iter = slots.into_iter();
+      assert_eq!(iter.next().unwrap().map(|v| v.id), Some(0));
+      assert_eq!(drops.get(), 1);
+    }
+    assert_eq!(drops.get(), 10);
+  }
+
+  #[test]
+  fn move_iterator_drops_the_rest_inline() {
+    move_iterator_drops_the_rest(false);
+  }
+
+  #[test]
+  fn move_iterator_drops_the_rest_boxed() {
+    move_iterator_drops_the_rest(true);
+  }
+
+  #[test]
+  fn set_boxed_moves_without_dropping() {
+    let drops = Rc::new(Cell::new(0u));
+    let values = Vec::from_fn(10, |i| if i % 2 == 0 { tracked(i, &drops) } else { None });
+    let mut slots = Slots::from_options(values);
+
+    for &boxed in [true, false].iter() {
+      slots.set_boxed(boxed);
+      assert_eq!(slots.is_boxed(), boxed);
+      assert_eq!(drops.get(), 0);
+      for i in range(0u, 10) {
+        assert_eq!(slots.get(i).map(|v| v.id), if i % 2 == 0 { Some(i) } else { None });
+      }
+    }
+
+    drop(slots);
+    assert_eq!(drops.get(), 5);
+  }
+}
@@ -0,0 +1,172 @@
+//! Checks the unit tests of more than one module share, and the ones every
+//! algorithm has to pass.
+
+use super::{Algorithm, HashMap, HashMapBuilder, DuplicateKey};
+use super::{Chm, Bdz, Chd, RecSplit, PtHash, Fks};
+use super::builder::{DEFAULT_CHD_LAMBDA, DEFAULT_PTHASH_LAMBDA};
+use super::builder::{RECSPLIT_LEAF_SIZE, RECSPLIT_BUCKET_SIZE};
+
+/// `m` distinct keys.
+pub fn keys(m: uint) -> Vec<String> {
+  range(0, m).map(|i| format!("key {}", i)).collect()
+}
+
+/// Builds a function with `algorithm` over `m` keys, and checks that it
+/// gives them distinct indices in `[0, m)`.
+fn check_indices(algorithm: Algorithm, m: uint) {
+  let keys = keys(m);
+  let func =
+    HashMapBuilder::new()
+      .algorithm(algorithm)
+      .seed(m as u64)
+      .build_fn(keys.as_slice())
+      .unwrap();
+
+  let mut seen = Vec::from_elem(m, false);
+  for k in keys.iter() {
+    let i = func.hash(k);
+    assert!(i < m, "{}: {} hashed to {}, with {} keys", algorithm, k, i, m);
+    assert!(!seen[i], "{}: {} hashed to {}, as another key did", algorithm, k, i);
+    *seen.get_mut(i) = true;
+  }
+  assert!(func.verify().is_ok(), "{}: {} keys", algorithm, m);
+}
+
+/// Builds a map with `algorithm` over half of `m` keys, inserts them all,
+/// and checks that a rebuild keeps the algorithm and every value.
+fn check_rebuild(algorithm: Algorithm, m: uint) {
+  let keys = keys(m);
+  let mut map: HashMap<String, uint> =
+    HashMapBuilder::new()
+      .algorithm(algorithm)
+      .seed(m as u64)
+      .build(keys.slice_to(m / 2).to_vec())
+      .unwrap();
+  for (i, k) in keys.iter().enumerate() {
+    map.insert(k.clone(), i);
+  }
+
+  map.rebuild();
+  assert_eq!(map.func.algorithm(), algorithm);
+  assert_eq!(map.capacity(), m);
+  for (i, k) in keys.iter().enumerate() {
+    assert!(map.get(k) == Some(&i), "{}: {} lost its value", algorithm, k);
+  }
+  assert!(map.verify().is_ok(), "{}: {} keys", algorithm, m);
+}
+
+/// Every algorithm, with its default parameters.
+fn algorithms() -> Vec<Algorithm> {
+  vec![
+    Chm,
+    Bdz,
+    Chd(DEFAULT_CHD_LAMBDA),
+    RecSplit(RECSPLIT_LEAF_SIZE, RECSPLIT_BUCKET_SIZE),
+    PtHash(DEFAULT_PTHASH_LAMBDA),
+    Fks,
+  ]
+}
+
+#[test]
+fn every_algorithm_gives_distinct_indices() {
+  for &algorithm in algorithms().iter() {
+    for &m in [0u, 1, 2, 1000].iter() {
+      check_indices(algorithm, m);
+    }
+  }
+}
+
+#[test]
+fn every_algorithm_rebuilds_with_itself() {
+  for &algorithm in algorithms().iter() {
+    for &m in [1u, 2, 1000].iter() {
+      check_rebuild(algorithm, m);
+    }
+  }
+}
+
+#[test]
+fn sorted_maps_stay_sorted_through_rebuilds() {
+  let keys = keys(100);
+  let mut map: HashMap<String, uint> =
+    HashMapBuilder::new()
+      .seed(100)
+      .build_sorted(keys.slice_to(50).to_vec())
+      .unwrap();
+  map.set_rebuild_threshold(Some(1));
+  for (i, k) in keys.iter().enumerate() {
+    map.insert(k.clone(), i);
+  }
+
+  let mut expected = keys.clone();
+  expected.sort();
+  let sorted: Vec<String> = map.iter_sorted().map(|(k, _v)| k.clone()).collect();
+  assert_eq!(map.capacity(), 100);
+  assert_eq!(sorted, expected);
+}
+
+#[test]
+fn partition_keeps_the_settings_on_both_sides() {
+  let keys = keys(100);
+  let mut map: HashMap<String, uint> =
+    HashMapBuilder::new()
+      .algorithm(Fks)
+      .seed(100)
+      .sample_lookups(1)
+      .build_sorted(keys.slice_to(50).to_vec())
+      .unwrap();
+  for (i, k) in keys.iter().enumerate() {
+    map.insert(k.clone(), i);
+  }
+
+  let (ours, rest) = map.partition(|k| k.len() % 2 == 0);
+  for half in [&ours, &rest].iter() {
+    assert_eq!(half.func.algorithm(), Fks);
+    assert!(half.sample_stats().is_some());
+    let sorted: Vec<&String> = half.iter_sorted().map(|(k, _v)| k).collect();
+    let mut expected = sorted.clone();
+    expected.sort();
+    assert_eq!(sorted, expected);
+  }
+  for (i, k) in keys.iter().enumerate() {
+    let half = if k.len() % 2 == 0 { &ours } else { &rest };
+    assert!(half.get(k) == Some(&i), "{} lost its value", k);
+  }
+}
+
+/// A source of entropy that always gives the same seed.
+fn fixed_entropy() -> u64 {
+  42
+}
+
+#[test]
+fn builds_with_entropy_seed_the_tables_and_the_backup_from_it() {
+  let keys = keys(100);
+  let builder = HashMapBuilder::new().entropy(fixed_entropy);
+  let mut map: HashMap<String, uint> = builder.build(keys.slice_to(50).to_vec()).unwrap();
+  assert_eq!(map.seed(), Some(42));
+
+  // The unknown half goes to the backup, and then into the slots.
+  for (i, k) in keys.iter().enumerate() {
+    map.insert(k.clone(), i);
+  }
+  map.rebuild();
+  assert_eq!(map.seed(), Some(42));
+  for (i, k) in keys.iter().enumerate() {
+    assert!(map.get(k) == Some(&i), "{} lost its value", k);
+  }
+
+  let a = builder.build_fn(keys.as_slice()).unwrap();
+  let b = builder.build_fn(keys.as_slice()).unwrap();
+  assert!(keys.iter().all(|k| a.hash(k) == b.hash(k)));
+}
+
+#[test]
+fn duplicates_are_reported_where_a_scan_finds_them() {
+  let keys: Vec<String> =
+    ["a", "b", "a", "c", "b", "a"].iter().map(|k| k.to_string()).collect();
+  match HashMapBuilder::new().seed(1).build_fn(keys.as_slice()) {
+    Err(DuplicateKey(first, second)) => assert_eq!((first, second), (0, 2)),
+    _                                => fail!("the duplicates weren't found"),
+  }
+}
@@ -0,0 +1,128 @@
+//! A map that trusts its callers to only look up known keys.
+//!
+//! A `HashMap` stores every known key so it can tell them from unknown ones.
+//! When every lookup is known to be for a known key, those keys are dead
+//! weight: for `String` keys they're often most of the table. A
+//! `TrustedHashMap` keeps only the hash function and one value per key.
+
+use std::hash;
+use std::mem;
+use std::ops;
+
+use super::{PerfectHashFn, PerfectHashState, ByteCounter, KeyHasher, Positional};
+use super::{HashMapBuilder, BuildError, MemoryBreakdown};
+
+/// A hashtable from a fixed set of keys to values, without the keys.
+///
+/// Looking up a key that wasn't one of the known keys returns the value of
+/// some arbitrary known key, or fails if the hash function can tell the key
+/// wasn't known. Nothing can be inserted or removed.
+pub struct TrustedHashMap<K, V, H = Positional> {
+  func:   PerfectHashFn<K, H>,
+  values: Vec<V>,
+}
+
+impl<'a,
+     K: Eq
+      + hash::Hash
+      + hash::Hash<PerfectHashState<'a>>
+      + hash::Hash<ByteCounter>,
+     V>
+    TrustedHashMap<K, V> {
+
+  /// Builds a map holding `pairs`.
+  ///
+  /// Fails if the same key appears twice. This uses the default
+  /// `HashMapBuilder` settings; see `HashMapBuilder::build_trusted` to tune
+  /// them.
+  pub fn new(pairs: Vec<(K, V)>) -> TrustedHashMap<K, V> {
+    match HashMapBuilder::new().build_trusted(pairs) {
+      Ok(map)  => map,
+      Err(err) => fail!("perfect::TrustedHashMap::new: {}", err),
+    }
+  }
+}
+
+impl<K: Eq + hash::Hash, V, H: KeyHasher<K>> TrustedHashMap<K, V, H> {
+  /// Puts each value in the slot `func` gives its key, then forgets the
+  /// keys.
+  ///
+  /// Fails unless `func` was built from exactly the keys of `pairs`.
+  pub fn from_fn(func: PerfectHashFn<K, H>, pairs: Vec<(K, V)>) -> TrustedHashMap<K, V, H> {
+    let m = func.len();
+    if pairs.len() != m {
+      fail!("perfect::TrustedHashMap::from_fn: {} pairs for {} keys", pairs.len(), m);
+    }
+
+    let mut slots: Vec<Option<V>> = Vec::from_fn(m, |_| None);
+    for (k, v) in pairs.into_iter() {
+      let slot = slots.get_mut(func.hash(&k));
+      if slot.is_some() {
+        fail!("perfect::TrustedHashMap::from_fn: two keys share a slot");
+      }
+      *slot = Some(v);
+    }
+
+    TrustedHashMap {
+      func:   func,
+      values: slots.into_iter().map(|v| v.unwrap()).collect(),
+    }
+  }
+
+  /// The value for `k`, which must be a known key.
+  pub fn get(&self, k: &K) -> &V {
+    &self.values[self.func.hash(k)]
+  }
+
+  /// A mutable reference to the value for `k`, which must be a known key.
+  pub fn get_mut(&mut self, k: &K) -> &mut V {
+    let i = self.func.hash(k);
+    self.values.get_mut(i)
+  }
+
+  /// The bytes each part of the map takes up. There are no keys or backup
+  /// store, so those are always zero.
+  pub fn memory_usage(&self) -> MemoryBreakdown {
+    MemoryBreakdown {
+      hasher: self.func.hasher.heap_size(),
+      labels: self.func.labels_size(),
+      slots:  self.values.capacity() * mem::size_of::<V>(),
+      keys:   0,
+      backup: 0,
+    }
+  }
+}
+
+impl<K, V, H> TrustedHashMap<K, V, H> {
+  /// Returns the number of elements in the map.
+  pub fn len(&self) -> uint {
+    self.values.len()
+  }
+
+  /// Returns true if the map contains no elements.
+  pub fn is_empty(&self) -> bool {
+    self.values.is_empty()
+  }
+
+  /// The values, in slot order.
+  pub fn values(&self) -> &[V] {
+    self.values.as_slice()
+  }
+
+  /// The values, in slot order, by mutable reference.
+  pub fn values_mut(&mut self) -> &mut [V] {
+    self.values.as_mut_slice()
+  }
+}
+
+impl<K: Eq + hash::Hash, V, H: KeyHasher<K>> ops::Index<K, V> for TrustedHashMap<K, V, H> {
+  fn index<'a>(&'a self, k: &K) -> &'a V {
+    self.get(k)
+  }
+}
+
+impl<K: Eq + hash::Hash, V, H: KeyHasher<K>> ops::IndexMut<K, V> for TrustedHashMap<K, V, H> {
+  fn index_mut<'a>(&'a mut self, k: &K) -> &'a mut V {
+    self.get_mut(k)
+  }
+}
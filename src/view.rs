@@ -0,0 +1,431 @@
+//! Zero-copy lookups against a table image in borrowed memory.
+//!
+//! `HashMap::write_image` lays a table out exactly as it sits in memory:
+//! native-endian words, with keys and values as raw bytes. `HashMapRef` then
+//! does lookups directly against a byte slice holding that image, typically
+//! a memory-mapped file, without copying anything into `Vec`s.
+//!
+//! Because nothing is converted, an image can only be read on a target with
+//! the same word size and endianness as the one that wrote it, and the keys
+//! and values must be plain data. Use the `binary` module for a portable
+//! format.
+//!
+//! The layout is a header of eight words (magic, word size, n, m, max_length,
+//! key size, value size, and the number of full slots), then the positional
+//! weights as interleaved pairs of `u32`s, nodes as words, then the keys, one
+//! presence byte per slot, and the values. Every section after the header is
+//! padded to a multiple of eight bytes.
+//!
+//! `write_image_in` is for memory the caller manages: it writes the image
+//! into a given buffer, say carved out of an arena or a hugepage mapping,
+//! and views it there, so the table's arrays never touch the global heap
+//! once the `HashMap` it came from is dropped. `image_len` says how big the
+//! buffer has to be. An image is limited to `Pod` keys and values and a `Chm`
+//! function; for other keys, such as strings, `HashMapBuilder::build_in`
+//! moves a table's keys and values into a `Region` instead (see the `arena`
+//! module).
+//!
+//! `StaticMap` is the same idea for tables compiled into the binary by
+//! `codegen`, and `StaticSet` is the matching set for `perfect_set!`.
+//!
+//! Everything here but `write_image` works without the `std` feature, so
+//! tables built offline can be looked up from firmware or a kernel.
+
+#[cfg(not(feature = "std"))] use core::prelude::*;
+
+use std::hash;
+#[cfg(feature = "std")] use std::io;
+#[cfg(feature = "std")] use std::io::{IoError, IoResult};
+use std::iter;
+use std::mem;
+use std::num::{CheckedAdd, CheckedMul};
+use std::raw;
+use std::slice;
+
+#[cfg(feature = "std")] use super::HashMap;
+use super::{PerfectHashState, candidate_slot};
+use super::{Corruption, BadLength, OutOfBounds, MisplacedKey, BadCount};
+
+/// Changed whenever the layout changes, so older images are rejected rather
+/// than misread.
+static IMAGE_MAGIC: uint = 0x50524636;
+
+static HEADER_WORDS: uint = 8;
+
+/// Types that are safe to read straight out of arbitrary bytes: they're
+/// `Copy`, contain no pointers, and every bit pattern is a valid value.
+///
+/// This is a promise made by the implementor. `HashMapRef` trusts it.
+pub trait Pod: Copy {}
+
+impl Pod for u8 {}
+impl Pod for u16 {}
+impl Pod for u32 {}
+impl Pod for u64 {}
+impl Pod for uint {}
+impl Pod for i8 {}
+impl Pod for i16 {}
+impl Pod for i32 {}
+impl Pod for i64 {}
+impl Pod for int {}
+
+fn padded(len: uint) -> uint {
+  (len + 7) & !7
+}
+
+/// The offset just past `len` elements of `size` bytes, padded, starting at
+/// byte `at`, or `None` if it doesn't fit in a `uint`.
+fn section_end(at: uint, len: uint, size: uint) -> Option<uint> {
+  len.checked_mul(&size)
+     .and_then(|bytes| bytes.checked_add(&7))
+     .and_then(|bytes| at.checked_add(&(bytes & !7)))
+}
+
+#[cfg(feature = "std")]
+fn write_raw<W: Writer, T>(w: &mut W, xs: &[T]) -> IoResult<()> {
+  let len = xs.len() * mem::size_of::<T>();
+  try!(unsafe {
+    slice::raw::buf_as_slice(xs.as_ptr() as *const u8, len, |bytes| w.write(bytes))
+  });
+  write_padding(w, len)
+}
+
+#[cfg(feature = "std")]
+fn write_padding<W: Writer>(w: &mut W, len: uint) -> IoResult<()> {
+  for _ in range(len, padded(len)) {
+    try!(w.write_u8(0));
+  }
+  Ok(())
+}
+
+/// Reinterprets the `len` `T`s starting at byte `offset` of `bytes`.
+unsafe fn cast<'a, T>(bytes: &'a [u8], offset: uint, len: uint) -> &'a [T] {
+  mem::transmute(raw::Slice {
+    data: bytes.as_ptr().offset(offset as int) as *const T,
+    len:  len,
+  })
+}
+
+#[cfg(feature = "std")]
+impl<K: Pod, V: Pod> HashMap<K, V> {
+  /// Writes an image of the table that `HashMapRef` can use in place.
+  ///
+  /// Only the perfect slots are written. Entries in the backup table aren't
+  /// part of the image. Only tables built with `Chm` can be written.
+  pub fn write_image<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+    let nodes =
+      match self.func.chm_nodes() {
+        Some(nodes) => nodes,
+        None        => return Err(IoError {
+          kind:   io::InvalidInput,
+          desc:   "only CHM tables can be written",
+          detail: None,
+        }),
+      };
+
+    let header = [
+      IMAGE_MAGIC,
+      mem::size_of::<uint>(),
+      nodes.len(),
+      self.keys.len(),
+      self.func.hasher.weights.len(),
+      mem::size_of::<K>(),
+      mem::size_of::<V>(),
+      self.size,
+    ];
+
+    try!(write_raw(w, header.as_slice()));
+    try!(write_raw(w, self.func.hasher.weights.as_slice()));
+    try!(write_raw(w, nodes.to_vec().as_slice()));
+    try!(write_raw(w, self.keys.as_slice()));
+
+    let present: Vec<u8> = self.table.iter().map(|v| v.is_some() as u8).collect();
+    try!(write_raw(w, present.as_slice()));
+
+    // Empty slots are zeroed. They're never read.
+    let values: Vec<V> =
+      self.table.iter().map(|v| {
+          match v {
+            Some(&v) => v,
+            None    => unsafe { mem::zeroed() },
+          }
+        }).collect();
+    write_raw(w, values.as_slice())
+  }
+
+  /// The number of bytes `write_image` writes, or `None` if the table
+  /// wasn't built with `Chm`.
+  pub fn image_len(&self) -> Option<uint> {
+    let word = mem::size_of::<uint>();
+    let m    = self.keys.len();
+
+    self.func.chm_nodes().map(|nodes| {
+      padded(HEADER_WORDS * word)
+      + self.func.hasher.weights.len() * 8
+      + padded(nodes.len() * word)
+      + padded(m * mem::size_of::<K>())
+      + padded(m)
+      + padded(m * mem::size_of::<V>())
+    })
+  }
+
+  /// Writes an image of the table into the start of `buf`, and views it
+  /// there. `buf` must be word-aligned and at least `image_len` bytes.
+  pub fn write_image_in<'a>(&self, buf: &'a mut [u8]) -> IoResult<HashMapRef<'a, K, V>> {
+    try!(self.write_image(&mut io::BufWriter::new(buf)));
+
+    let buf: &'a [u8] = buf;
+    match unsafe { HashMapRef::from_bytes(buf) } {
+      Some(view) => Ok(view),
+      None       => Err(IoError {
+        kind:   io::InvalidInput,
+        desc:   "the buffer isn't word-aligned",
+        detail: None,
+      }),
+    }
+  }
+}
+
+/// A read-only table whose arrays all live in borrowed memory.
+pub struct HashMapRef<'a, K: 'a, V: 'a> {
+  /// The number of full slots, from the header.
+  len:     uint,
+  nodes:   &'a [uint],
+  weights: &'a [(u32, u32)],
+  keys:    &'a [K],
+  present: &'a [u8],
+  values:  &'a [V],
+}
+
+impl<'a, K: Pod, V: Pod> HashMapRef<'a, K, V> {
+  /// Views an image written by `HashMap::write_image`.
+  ///
+  /// Returns `None` if the header doesn't match this target and these types,
+  /// if its lengths don't fit each other or `bytes`, or if `bytes` isn't
+  /// word-aligned. Whatever the header says, every array lies inside
+  /// `bytes`.
+  ///
+  /// This is unsafe because the contents of each array are trusted. Corrupt
+  /// tables can only cause wrong answers or a failed bounds check, which
+  /// `verify` rules out, but the caller is responsible for `bytes` really
+  /// being an image.
+  pub unsafe fn from_bytes(bytes: &'a [u8]) -> Option<HashMapRef<'a, K, V>> {
+    let word = mem::size_of::<uint>();
+
+    if bytes.as_ptr() as uint % word != 0
+    || mem::min_align_of::<K>() > 8
+    || mem::min_align_of::<V>() > 8
+    || bytes.len() < HEADER_WORDS * word {
+      return None;
+    }
+
+    let header: &[uint] = cast(bytes, 0, HEADER_WORDS);
+
+    if header[0] != IMAGE_MAGIC
+    || header[1] != word
+    || header[5] != mem::size_of::<K>()
+    || header[6] != mem::size_of::<V>() {
+      return None;
+    }
+
+    let (n, m, max_length, len) = (header[2], header[3], header[4], header[7]);
+
+    // A key graph has more vertices than keys, unless it has neither.
+    if n < m || len > m {
+      return None;
+    }
+
+    // The weights, nodes, keys, presence bytes, and values, in that order.
+    let sections = [(max_length, 8), (n, word), (m, mem::size_of::<K>()), (m, 1),
+                    (m, mem::size_of::<V>())];
+
+    let mut starts = [0u, ..5];
+    let mut end    = padded(HEADER_WORDS * word);
+    for (i, &(len, size)) in sections.iter().enumerate() {
+      starts[i] = end;
+      end =
+        match section_end(end, len, size) {
+          Some(next) => next,
+          None       => return None,
+        };
+    }
+
+    if bytes.len() < end {
+      return None;
+    }
+
+    Some(HashMapRef {
+      len:     len,
+      nodes:   cast(bytes, starts[1], n),
+      weights: cast(bytes, starts[0], max_length),
+      keys:    cast(bytes, starts[2], m),
+      present: cast(bytes, starts[3], m),
+      values:  cast(bytes, starts[4], m),
+    })
+  }
+}
+
+impl<'a, 'b, K: Pod + Eq + hash::Hash<PerfectHashState<'b>>, V: Pod> HashMapRef<'a, K, V> {
+  fn find_slot(&self, k: &K) -> Option<uint> {
+    match candidate_slot(self.nodes, self.weights, self.keys.len(), k) {
+      Some(i) if self.keys[i] == *k && self.present[i] != 0 => Some(i),
+      _                                                     => None,
+    }
+  }
+
+  /// Returns a reference to the value corresponding to the key.
+  pub fn get(&self, k: &K) -> Option<&'a V> {
+    let values = self.values;
+    self.find_slot(k).map(|i| &values[i])
+  }
+
+  /// Returns true if the map contains a value for the specified key.
+  pub fn contains_key(&self, k: &K) -> bool {
+    self.find_slot(k).is_some()
+  }
+
+  /// Checks the image that `from_bytes` trusted: every weight and node is in
+  /// bounds, every key hashes back to its own slot, and the header counts the
+  /// full slots right.
+  pub fn verify(&self) -> Result<(), Corruption> {
+    let (n, m) = (self.nodes.len(), self.keys.len());
+
+    if m > 0 && n == 0 {
+      return Err(BadLength("nodes", 0));
+    }
+
+    for (i, &(w1, w2)) in self.weights.iter().enumerate() {
+      if w1 as uint >= n {
+        return Err(OutOfBounds("t1", i, w1 as uint));
+      }
+      if w2 as uint >= n {
+        return Err(OutOfBounds("t2", i, w2 as uint));
+      }
+    }
+
+    match self.nodes.iter().position(|&g| g >= m) {
+      Some(i) => return Err(OutOfBounds("nodes", i, self.nodes[i])),
+      None    => {}
+    }
+
+    for (i, k) in self.keys.iter().enumerate() {
+      match candidate_slot(self.nodes, self.weights, m, k) {
+        Some(j) if j == i => {}
+        found             => return Err(MisplacedKey(i, found)),
+      }
+    }
+
+    let full = self.present.iter().filter(|&&p| p != 0).count();
+    if full != self.len {
+      return Err(BadCount(self.len, full));
+    }
+
+    Ok(())
+  }
+}
+
+impl<'a, K, V> HashMapRef<'a, K, V> {
+  /// Returns the number of elements in the map.
+  pub fn len(&self) -> uint {
+    self.len
+  }
+
+  /// Returns true if the map contains no elements.
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+}
+
+/// A read-only table whose arrays are `'static`, as emitted by `codegen`.
+///
+/// The fields are public only so generated code can build one in a static
+/// initializer. Don't fill them in by hand.
+pub struct StaticMap<K: 'static, V: 'static> {
+  #[doc(hidden)]
+  pub nodes:   &'static [uint],
+  #[doc(hidden)]
+  pub weights: &'static [(u32, u32)],
+  #[doc(hidden)]
+  pub keys:    &'static [K],
+  #[doc(hidden)]
+  pub values:  &'static [V],
+}
+
+impl<'a, K: Eq + hash::Hash<PerfectHashState<'a>>, V> StaticMap<K, V> {
+  /// Returns a reference to the value corresponding to the key.
+  pub fn get(&self, k: &K) -> Option<&'static V> {
+    match candidate_slot(self.nodes, self.weights, self.keys.len(), k) {
+      Some(i) if self.keys[i] == *k => Some(&self.values[i]),
+      _                             => None,
+    }
+  }
+
+  /// Like `get`, but looks up any type that hashes the same way as `K`, such
+  /// as a borrowed `&str` for a table of `&'static str`.
+  pub fn get_equiv<Q: hash::Hash<PerfectHashState<'a>> + Equiv<K>>(&self, k: &Q)
+      -> Option<&'static V> {
+    match candidate_slot(self.nodes, self.weights, self.keys.len(), k) {
+      Some(i) if k.equiv(&self.keys[i]) => Some(&self.values[i]),
+      _                                 => None,
+    }
+  }
+
+  /// Returns true if the map contains a value for the specified key.
+  pub fn contains_key(&self, k: &K) -> bool {
+    self.get(k).is_some()
+  }
+}
+
+impl<K, V> StaticMap<K, V> {
+  /// Returns the number of elements in the map.
+  pub fn len(&self) -> uint {
+    self.keys.len()
+  }
+
+  /// Returns true if the map contains no elements.
+  pub fn is_empty(&self) -> bool {
+    self.keys.is_empty()
+  }
+
+  /// An iterator visiting all key-value pairs, in the order the keys were
+  /// given to the generator.
+  pub fn iter(&self) -> iter::Zip<slice::Items<'static, K>, slice::Items<'static, V>> {
+    self.keys.iter().zip(self.values.iter())
+  }
+}
+
+/// A read-only set whose arrays are `'static`, as emitted by `perfect_set!`.
+pub struct StaticSet<K: 'static> {
+  #[doc(hidden)]
+  pub map: StaticMap<K, ()>,
+}
+
+impl<'a, K: Eq + hash::Hash<PerfectHashState<'a>>> StaticSet<K> {
+  /// Returns true if the set contains a value.
+  pub fn contains(&self, value: &K) -> bool {
+    self.map.contains_key(value)
+  }
+
+  /// Like `contains`, but tests any type that hashes the same way as `K`.
+  pub fn contains_equiv<Q: hash::Hash<PerfectHashState<'a>> + Equiv<K>>(&self, value: &Q)
+      -> bool {
+    self.map.get_equiv(value).is_some()
+  }
+}
+
+impl<K> StaticSet<K> {
+  /// Returns the number of elements in the set.
+  pub fn len(&self) -> uint {
+    self.map.len()
+  }
+
+  /// Returns true if the set contains no elements.
+  pub fn is_empty(&self) -> bool {
+    self.map.is_empty()
+  }
+
+  /// An iterator visiting all elements, in the order they were given.
+  pub fn iter(&self) -> slice::Items<'static, K> {
+    self.map.keys.iter()
+  }
+}